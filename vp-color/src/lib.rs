@@ -0,0 +1,42 @@
+//! Colorimetry caps shared by vp-sndr and vp-rcvr.
+//!
+//! The sender writes --colorimetry and --color-range into the caps in front of its
+//! encoder and the receiver into the caps of its v4l2 outputs; both go through
+//! [`colorimetry_field`] so the two ends name the same colorimetry.
+
+use gstreamer_video as gst_video;
+use gstreamer_video::{VideoColorMatrix, VideoColorPrimaries, VideoTransferFunction};
+
+/// The `,colorimetry=` caps field for --colorimetry and --color-range, or nothing
+/// when neither is set. Either one alone takes bt709 or limited range for the other.
+pub fn colorimetry_field(colorimetry: Option<&str>, range: Option<&str>) -> String {
+    if colorimetry.is_none() && range.is_none() {
+        return String::new();
+    }
+    let (matrix, transfer, primaries) = match colorimetry.unwrap_or("bt709") {
+        "bt601" => (
+            VideoColorMatrix::Bt601,
+            VideoTransferFunction::Bt709,
+            VideoColorPrimaries::Smpte170m,
+        ),
+        "bt2020" => (
+            VideoColorMatrix::Bt2020,
+            VideoTransferFunction::Bt202010,
+            VideoColorPrimaries::Bt2020,
+        ),
+        _ => (
+            VideoColorMatrix::Bt709,
+            VideoTransferFunction::Bt709,
+            VideoColorPrimaries::Bt709,
+        ),
+    };
+    let range = if range == Some("full") {
+        gst_video::VideoColorRange::Range0255
+    } else {
+        gst_video::VideoColorRange::Range16235
+    };
+    format!(
+        ",colorimetry={}",
+        gst_video::VideoColorimetry::new(range, matrix, transfer, primaries)
+    )
+}
@@ -0,0 +1,80 @@
+//! Config key editing shared by vp-sndr and vp-rcvr.
+//!
+//! `config get/set/unset` address a setting by its dotted TOML key. Each program
+//! serializes its config to a [`serde_json::Value`], edits it here and deserializes
+//! the result, so the value is checked by the program's own types and checks before
+//! the file is written.
+
+use serde_json::Value;
+
+/// JSON pointer of a dotted key: `profile.travel.fps` is `/profile/travel/fps`.
+pub fn config_pointer(key: &str) -> String {
+    format!("/{}", key.replace('.', "/"))
+}
+
+/// The value under `key` as `config get` prints it; unset optional settings are an
+/// empty string.
+pub fn config_value(doc: &Value, key: &str) -> Result<String, String> {
+    match doc.pointer(&config_pointer(key)) {
+        Some(Value::String(text)) => Ok(text.clone()),
+        Some(Value::Null) => Ok(String::new()),
+        Some(other) => Ok(other.to_string()),
+        None => Err(format!("unknown config key: {key}")),
+    }
+}
+
+/// Sets or removes `key` in the serialized config. Top-level keys must exist (unset
+/// options are `null`); below them, new entries can be added to tables.
+pub fn edit_config_value(doc: &mut Value, key: &str, value: Option<&str>) -> Result<(), String> {
+    let unknown = || format!("unknown config key: {key}");
+    let (parent, name) = match key.rsplit_once('.') {
+        Some((parent, name)) => (config_pointer(parent), name),
+        None => (String::new(), key),
+    };
+    let table = doc.pointer_mut(&parent).and_then(Value::as_object_mut);
+    match (table, value) {
+        (Some(table), Some(value)) => {
+            if parent.is_empty() && !table.contains_key(name) {
+                return Err(unknown());
+            }
+            let slot = table.entry(name).or_insert(Value::Null);
+            *slot = parse_config_value(key, slot, value)?;
+        }
+        // Removed keys take their default on load.
+        (Some(table), None) => {
+            table.remove(name).ok_or_else(unknown)?;
+        }
+        // Lists are addressed by index, e.g. viewports.0.port.
+        (None, Some(value)) => {
+            let slot = doc.pointer_mut(&config_pointer(key)).ok_or_else(unknown)?;
+            *slot = parse_config_value(key, slot, value)?;
+        }
+        (None, None) => return Err(unknown()),
+    }
+    Ok(())
+}
+
+/// Reads VALUE as the type already stored under the key. Unset keys take a number
+/// or true/false when VALUE looks like one, and a string otherwise.
+fn parse_config_value(key: &str, current: &Value, value: &str) -> Result<Value, String> {
+    match current {
+        Value::String(_) => Ok(Value::String(value.to_string())),
+        Value::Bool(_) => value
+            .parse::<bool>()
+            .map(Value::Bool)
+            .map_err(|_| format!("invalid value for {key}: {value} (expected true or false)")),
+        Value::Number(_) => match serde_json::from_str::<Value>(value) {
+            Ok(number @ Value::Number(_)) => Ok(number),
+            _ => Err(format!(
+                "invalid value for {key}: {value} (expected a number)"
+            )),
+        },
+        Value::Null => match serde_json::from_str::<Value>(value) {
+            Ok(parsed @ (Value::Number(_) | Value::Bool(_))) => Ok(parsed),
+            _ => Ok(Value::String(value.to_string())),
+        },
+        Value::Array(_) | Value::Object(_) => Err(format!(
+            "{key} is a table or list; set one of its keys instead"
+        )),
+    }
+}
@@ -0,0 +1,202 @@
+//! Control socket shared by vp-sndr and vp-rcvr.
+//!
+//! Each connection carries newline-delimited requests. A line starting with `{` is a
+//! JSON-RPC 2.0 request (`{"jsonrpc":"2.0","id":1,"method":"get-status"}`) and gets a
+//! JSON-RPC response line; anything else is a plain text command answered with
+//! `ok ...` or `error ...`, which is what the tray and the CLI subcommands send.
+//! Requests are handed to the streaming loop over a channel and answered there.
+
+use serde_json::{Value, json};
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::Duration;
+
+/// What the streaming loop answers: a JSON value on success, a message on failure.
+pub type Reply = Result<Value, String>;
+
+// JSON-RPC 2.0 error codes.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+pub struct Request<C> {
+    pub command: C,
+    pub reply: mpsc::Sender<Reply>,
+}
+
+/// How one program maps requests onto its own command type.
+pub struct Protocol<C> {
+    /// "sender" or "receiver", for error messages.
+    pub name: &'static str,
+    /// Plain text line such as `latency +10`.
+    pub parse_line: fn(&str) -> Result<C, String>,
+    /// JSON-RPC method and params; `None` for an unknown method.
+    pub parse_method: fn(&str, &Value) -> Option<Result<C, String>>,
+    pub reply_timeout: Duration,
+}
+
+/// `$XDG_RUNTIME_DIR/<file_name>`, or the temp dir outside a session.
+pub fn socket_path(file_name: &str) -> PathBuf {
+    let dir = env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir);
+    dir.join(file_name)
+}
+
+/// Binds the socket (replacing a stale one) and serves each client on its own thread.
+pub fn listen<C: Send + 'static>(
+    path: &Path,
+    protocol: Protocol<C>,
+    tx: mpsc::Sender<Request<C>>,
+) -> Result<(), String> {
+    let _ = fs::remove_file(path);
+    let listener = UnixListener::bind(path).map_err(|e| format!("bind {}: {e}", path.display()))?;
+    let protocol = Arc::new(protocol);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let tx = tx.clone();
+            let protocol = Arc::clone(&protocol);
+            thread::spawn(move || handle_client(stream, &protocol, &tx));
+        }
+    });
+    Ok(())
+}
+
+/// Hands a command to the streaming loop and waits for its answer.
+pub fn forward<C>(
+    tx: &mpsc::Sender<Request<C>>,
+    command: C,
+    name: &str,
+    timeout: Duration,
+) -> Reply {
+    let (reply_tx, reply_rx) = mpsc::channel();
+    tx.send(Request {
+        command,
+        reply: reply_tx,
+    })
+    .map_err(|_| format!("{name} is shutting down"))?;
+    reply_rx
+        .recv_timeout(timeout)
+        .map_err(|_| format!("timed out waiting for {name}"))?
+}
+
+fn handle_client<C>(stream: UnixStream, protocol: &Protocol<C>, tx: &mpsc::Sender<Request<C>>) {
+    let mut writer = match stream.try_clone() {
+        Ok(v) => v,
+        Err(_) => return,
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let reply = if line.starts_with('{') {
+            match handle_json(line, protocol, tx) {
+                Some(response) => format!("{response}\n"),
+                // Notifications get no response.
+                None => continue,
+            }
+        } else {
+            let result = (protocol.parse_line)(line)
+                .and_then(|command| forward(tx, command, protocol.name, protocol.reply_timeout));
+            match result {
+                Ok(value) => format!("ok {}\n", text_of(&value)),
+                Err(err) => format!("error {err}\n"),
+            }
+        };
+        if writer.write_all(reply.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+fn handle_json<C>(
+    line: &str,
+    protocol: &Protocol<C>,
+    tx: &mpsc::Sender<Request<C>>,
+) -> Option<Value> {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(v) => v,
+        Err(e) => return Some(error_response(Value::Null, PARSE_ERROR, &e.to_string())),
+    };
+    let id = request.get("id").cloned();
+    let Some(method) = request.get("method").and_then(Value::as_str) else {
+        return Some(error_response(
+            id.unwrap_or(Value::Null),
+            INVALID_REQUEST,
+            "missing method",
+        ));
+    };
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+    let outcome = match (protocol.parse_method)(method, &params) {
+        None => Err((METHOD_NOT_FOUND, format!("unknown method: {method}"))),
+        Some(Err(err)) => Err((INVALID_PARAMS, err)),
+        Some(Ok(command)) => forward(tx, command, protocol.name, protocol.reply_timeout)
+            .map_err(|err| (SERVER_ERROR, err)),
+    };
+    let id = id?;
+    Some(match outcome {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message)) => error_response(id, code, &message),
+    })
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+/// Strings go out as-is on the text protocol; anything else as compact JSON.
+fn text_of(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Sends one text command and returns the `ok` message.
+pub fn send_line(path: &Path, name: &str, line: &str) -> Result<String, String> {
+    let mut stream = UnixStream::connect(path).map_err(|e| {
+        format!(
+            "cannot connect to {}: {e} (is the {name} running?)",
+            path.display()
+        )
+    })?;
+    stream
+        .write_all(format!("{line}\n").as_bytes())
+        .map_err(|e| format!("write control command: {e}"))?;
+    let mut reply = String::new();
+    BufReader::new(stream)
+        .read_line(&mut reply)
+        .map_err(|e| format!("read control reply: {e}"))?;
+    let reply = reply.trim();
+    if let Some(msg) = reply.strip_prefix("ok") {
+        Ok(msg.trim().to_string())
+    } else if let Some(err) = reply.strip_prefix("error") {
+        Err(err.trim().to_string())
+    } else {
+        Err(format!("unexpected control reply: {reply}"))
+    }
+}
+
+/// Reads a `u32` field of JSON-RPC params.
+pub fn param_u32(params: &Value, key: &str) -> Result<u32, String> {
+    params
+        .get(key)
+        .and_then(Value::as_u64)
+        .and_then(|v| u32::try_from(v).ok())
+        .ok_or_else(|| format!("params.{key} must be an unsigned integer"))
+}
@@ -0,0 +1,126 @@
+//! Prometheus metrics shared by vp-sndr and vp-rcvr.
+//!
+//! The streaming loop sets counters and gauges on a [`Metrics`] handle about once a
+//! second; [`Metrics::serve`] answers `GET /metrics` with the Prometheus text format
+//! from a small HTTP listener thread. No labels, no histograms: one value per name.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const READ_TIMEOUT_SECS: u64 = 5;
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Counter,
+    Gauge,
+}
+
+struct Sample {
+    kind: Kind,
+    help: &'static str,
+    value: f64,
+}
+
+/// Cheap to clone; every clone sets and serves the same values.
+#[derive(Clone)]
+pub struct Metrics {
+    prefix: &'static str,
+    samples: Arc<Mutex<BTreeMap<&'static str, Sample>>>,
+}
+
+impl Metrics {
+    /// `prefix` is prepended to every name, e.g. `vp_sndr` gives `vp_sndr_fps`.
+    pub fn new(prefix: &'static str) -> Self {
+        Self {
+            prefix,
+            samples: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// A total that only grows; by convention the name ends in `_total`.
+    pub fn counter(&self, name: &'static str, help: &'static str, value: u64) {
+        self.set(name, Kind::Counter, help, value as f64);
+    }
+
+    pub fn gauge(&self, name: &'static str, help: &'static str, value: f64) {
+        self.set(name, Kind::Gauge, help, value);
+    }
+
+    fn set(&self, name: &'static str, kind: Kind, help: &'static str, value: f64) {
+        if let Ok(mut samples) = self.samples.lock() {
+            samples.insert(name, Sample { kind, help, value });
+        }
+    }
+
+    /// The Prometheus text exposition format (version 0.0.4).
+    pub fn render(&self) -> String {
+        let Ok(samples) = self.samples.lock() else {
+            return String::new();
+        };
+        let mut out = String::new();
+        for (name, sample) in samples.iter() {
+            let kind = match sample.kind {
+                Kind::Counter => "counter",
+                Kind::Gauge => "gauge",
+            };
+            out.push_str(&format!(
+                "# HELP {prefix}_{name} {help}\n# TYPE {prefix}_{name} {kind}\n{prefix}_{name} {value}\n",
+                prefix = self.prefix,
+                help = sample.help,
+                value = sample.value,
+            ));
+        }
+        out
+    }
+
+    /// Binds `addr` (e.g. `127.0.0.1:9321`) and answers scrapes on a background thread.
+    pub fn serve(&self, addr: &str) -> Result<SocketAddr, String> {
+        let listener = TcpListener::bind(addr).map_err(|e| format!("bind {addr}: {e}"))?;
+        let local = listener
+            .local_addr()
+            .map_err(|e| format!("bind {addr}: {e}"))?;
+        let metrics = self.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                // Scrapes are rare and small; one at a time is enough.
+                let _ = metrics.answer(stream);
+            }
+        });
+        Ok(local)
+    }
+
+    fn answer(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(READ_TIMEOUT_SECS)))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // Skip the headers; nothing in them changes the answer.
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 0 && !header.trim_end().is_empty() {
+            header.clear();
+        }
+        let mut parts = request_line.split_whitespace();
+        let (status, content_type, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/metrics")) => (
+                "200 OK",
+                "text/plain; version=0.0.4; charset=utf-8",
+                self.render(),
+            ),
+            (Some("GET"), _) => ("404 Not Found", "text/plain", "try /metrics\n".to_string()),
+            _ => (
+                "405 Method Not Allowed",
+                "text/plain",
+                "only GET is supported\n".to_string(),
+            ),
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    }
+}
@@ -0,0 +1,591 @@
+//! The receive side of vp-link as a library: RTP in, decoded frames out.
+//!
+//! [`Receiver`] runs the same udpsrc → rtpbin → depayloader → decoder chain as the
+//! vp-rcvr binary and hands each decoded frame to a callback ([`Receiver::on_frame`])
+//! or to a channel ([`Receiver::frames`]). The pieces it is built from are public as
+//! well; vp-rcvr puts its preview, recording and other outputs behind them.
+
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use gstreamer_app as gst_app;
+use gstreamer_net as gst_net;
+use gstreamer_video as gst_video;
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::Duration;
+
+/// Highest jitterbuffer latency vp-rcvr accepts.
+pub const MAX_LATENCY_MS: u32 = 2000;
+// Dynamic payload type of the sender's ULPFEC packets (vp-sndr --fec-percent).
+const FEC_PAYLOAD_TYPE: u32 = 122;
+// Payload type of retransmitted packets (vp-sndr --rtx).
+const RTX_PAYLOAD_TYPE: u32 = 97;
+// Frames Receiver::frames holds for a slow reader before it drops new ones.
+const FRAME_QUEUE: usize = 4;
+
+/// What to receive. The defaults match vp-rcvr's, except that frames are handed
+/// over in the decoder's own format unless `format` asks for another.
+#[derive(Clone, Debug)]
+pub struct ReceiverSettings {
+    /// `h264`, `h265`, `av1`, `vp9` or `mjpeg`.
+    pub codec: String,
+    /// `auto`, `vaapi`, `nvdec` or `software`.
+    pub decoder: String,
+    pub bind_ip: String,
+    /// RTP port; RTCP arrives on the next one.
+    pub port: u16,
+    pub payload: u32,
+    pub clock_rate: u32,
+    pub latency_ms: u32,
+    /// Ask the sender to retransmit lost packets (vp-sndr --rtx).
+    pub rtx: bool,
+    /// Raw video format of the frames, e.g. `RGBA` or `NV12`.
+    pub format: Option<String>,
+}
+
+impl Default for ReceiverSettings {
+    fn default() -> Self {
+        Self {
+            codec: "h265".to_string(),
+            decoder: "auto".to_string(),
+            bind_ip: "0.0.0.0".to_string(),
+            port: 5000,
+            payload: 96,
+            clock_rate: 90_000,
+            latency_ms: 25,
+            rtx: false,
+            format: None,
+        }
+    }
+}
+
+/// One decoded picture. The pixels stay in the decoder's buffer; `planes` locates
+/// each plane in [`Frame::data`].
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    /// GStreamer's name for the pixel format, e.g. `I420` or `RGBA`.
+    pub format: String,
+    /// Presentation time, in the pipeline's running time.
+    pub pts: Option<Duration>,
+    /// (offset, stride) in bytes of each plane.
+    pub planes: Vec<(usize, usize)>,
+    buffer: gst::MappedBuffer<gst::buffer::Readable>,
+}
+
+impl Frame {
+    pub fn data(&self) -> &[u8] {
+        self.buffer.as_slice()
+    }
+
+    fn from_sample(sample: &gst::Sample) -> Option<Self> {
+        let info = gst_video::VideoInfo::from_caps(sample.caps()?).ok()?;
+        let buffer = sample.buffer_owned()?;
+        let pts = buffer.pts().map(|pts| Duration::from_nanos(pts.nseconds()));
+        // Mapped as a video frame, the layout follows the buffer's VideoMeta, so the
+        // padded strides of hardware decoders are located correctly.
+        let frame = gst_video::VideoFrame::from_buffer_readable(buffer, &info).ok()?;
+        let planes = frame
+            .info()
+            .offset()
+            .iter()
+            .zip(frame.info().stride())
+            .take(frame.n_planes() as usize)
+            .map(|(&offset, &stride)| (offset, stride.max(0) as usize))
+            .collect();
+        Some(Self {
+            width: info.width(),
+            height: info.height(),
+            format: info.format().to_str().to_string(),
+            pts,
+            planes,
+            buffer: frame.into_buffer().into_mapped_buffer_readable().ok()?,
+        })
+    }
+}
+
+/// A running (or ready to run) receive pipeline that ends in an appsink.
+pub struct Receiver {
+    pipeline: gst::Pipeline,
+    sink: gst_app::AppSink,
+    decoder: &'static str,
+    missing_decoder: Option<&'static str>,
+    sender: Arc<Mutex<Option<String>>>,
+}
+
+impl Receiver {
+    /// Builds the pipeline for SETTINGS; nothing is received before [`Receiver::start`].
+    pub fn new(settings: &ReceiverSettings) -> Result<Self, String> {
+        gst::init().map_err(|e| format!("gstreamer init failed: {e}"))?;
+        let (encoding_name, depay_parse) = depay_stage(&settings.codec)
+            .ok_or_else(|| format!("unsupported codec '{}'", settings.codec))?;
+        let (decoder, missing_decoder) = select_decoder(&settings.codec, &settings.decoder)?;
+        let format = settings
+            .format
+            .as_ref()
+            .map(|format| format!(" ! video/x-raw,format={format}"))
+            .unwrap_or_default();
+        let (bind_ip, port) = (&settings.bind_ip, settings.port);
+        // The appsink keeps only the newest frames, so a slow callback costs frames
+        // rather than latency.
+        let description = format!(
+            "udpsrc name=src address={bind_ip} port={port} buffer-size=4194304 \
+             caps=\"application/x-rtp,media=video,encoding-name={encoding_name},payload={},clock-rate={}\" ! \
+             queue name=rtp_in \
+             udpsrc name=rtcpsrc address={bind_ip} port={} caps=application/x-rtcp \
+             multiudpsink name=rtcpout sync=false async=false \
+             queue name=depay_in ! {depay_parse} ! {}{format} ! \
+             appsink name=frames sync=false max-buffers=2 drop=true",
+            settings.payload,
+            settings.clock_rate,
+            port.saturating_add(1),
+            decode_stage(decoder)
+        );
+        let pipeline = gst::parse::launch(&description)
+            .map_err(|e| format!("could not build receive pipeline: {e}"))?
+            .downcast::<gst::Pipeline>()
+            .map_err(|_| "receive pipeline is not a gst::Pipeline".to_string())?;
+        let rtp_caps = gst::Caps::builder("application/x-rtp")
+            .field("media", "video")
+            .field("encoding-name", encoding_name)
+            .field("payload", settings.payload as i32)
+            .field("clock-rate", settings.clock_rate as i32)
+            .build();
+        attach_rtp_session(
+            &pipeline,
+            rtp_caps,
+            settings.payload,
+            settings.latency_ms,
+            settings.rtx,
+        )?;
+        let sender = Arc::new(Mutex::new(None));
+        let found = Arc::clone(&sender);
+        return_rtcp_to_sender(&pipeline, port, move |addr| {
+            if let Ok(mut sender) = found.lock() {
+                *sender = Some(addr.to_string());
+            }
+        });
+        let sink = pipeline
+            .by_name("frames")
+            .and_then(|sink| sink.downcast::<gst_app::AppSink>().ok())
+            .ok_or_else(|| "receive pipeline has no appsink".to_string())?;
+        Ok(Self {
+            pipeline,
+            sink,
+            decoder,
+            missing_decoder,
+            sender,
+        })
+    }
+
+    /// The decoder element in use, e.g. `vah265dec`.
+    pub fn decoder(&self) -> &'static str {
+        self.decoder
+    }
+
+    /// The element of the requested [`ReceiverSettings::decoder`] kind when it is not
+    /// installed and [`Receiver::decoder`] stands in for it.
+    pub fn missing_decoder(&self) -> Option<&'static str> {
+        self.missing_decoder
+    }
+
+    /// Where receiver reports and NACKs go, as `HOST:PORT`; None until the first RTP
+    /// packet has arrived.
+    pub fn sender_address(&self) -> Option<String> {
+        self.sender.lock().ok().and_then(|sender| sender.clone())
+    }
+
+    /// The underlying pipeline, for properties and bus messages the type does not cover.
+    pub fn pipeline(&self) -> &gst::Pipeline {
+        &self.pipeline
+    }
+
+    /// Calls CALLBACK on the streaming thread for every decoded frame. Replaces an
+    /// earlier callback or [`Receiver::frames`] channel.
+    pub fn on_frame<F>(&self, mut callback: F)
+    where
+        F: FnMut(&Frame) + Send + 'static,
+    {
+        self.sink.set_callbacks(
+            gst_app::AppSinkCallbacks::builder()
+                .new_sample(move |sink| {
+                    let sample = sink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                    if let Some(frame) = Frame::from_sample(&sample) {
+                        callback(&frame);
+                    }
+                    Ok(gst::FlowSuccess::Ok)
+                })
+                .build(),
+        );
+    }
+
+    /// Frames as a channel instead of a callback. A reader that falls behind misses
+    /// frames; the channel closes when the pipeline stops.
+    pub fn frames(&self) -> mpsc::Receiver<Frame> {
+        let (tx, rx) = mpsc::sync_channel(FRAME_QUEUE);
+        self.sink.set_callbacks(
+            gst_app::AppSinkCallbacks::builder()
+                .new_sample(move |sink| {
+                    let sample = sink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                    if let Some(frame) = Frame::from_sample(&sample)
+                        && let Err(mpsc::TrySendError::Disconnected(_)) = tx.try_send(frame)
+                    {
+                        return Err(gst::FlowError::Flushing);
+                    }
+                    Ok(gst::FlowSuccess::Ok)
+                })
+                .build(),
+        );
+        rx
+    }
+
+    pub fn start(&self) -> Result<(), String> {
+        self.pipeline
+            .set_state(gst::State::Playing)
+            .map(|_| ())
+            .map_err(|e| format!("could not start receive pipeline: {e}"))
+    }
+
+    pub fn stop(&self) {
+        let _ = self.pipeline.set_state(gst::State::Null);
+    }
+
+    /// Blocks until the pipeline fails or ends. Start it first.
+    pub fn run(&self) -> Result<(), String> {
+        let bus = self
+            .pipeline
+            .bus()
+            .ok_or_else(|| "receive pipeline has no bus".to_string())?;
+        for msg in bus.iter_timed(gst::ClockTime::NONE) {
+            match msg.view() {
+                gst::MessageView::Eos(..) => return Ok(()),
+                gst::MessageView::Error(e) => {
+                    return Err(format!(
+                        "receive pipeline error from {}: {}",
+                        e.src()
+                            .map(|s| s.path_string())
+                            .unwrap_or_else(|| "<unknown>".into()),
+                        e.error()
+                    ));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// The RTP encoding name and the depayload/parse stage for CODEC.
+pub fn depay_stage(codec: &str) -> Option<(&'static str, &'static str)> {
+    match codec {
+        // After a loss the H.264/H.265 depayloaders hold the picture and ask the
+        // sender for a keyframe (RTCP PLI) instead of decoding a smeared one.
+        "h264" => Some((
+            "H264",
+            "rtph264depay request-keyframe=true wait-for-keyframe=true ! h264parse",
+        )),
+        "h265" => Some((
+            "H265",
+            "rtph265depay request-keyframe=true wait-for-keyframe=true ! h265parse",
+        )),
+        "av1" => Some(("AV1", "rtpav1depay ! av1parse")),
+        "vp9" => Some(("VP9", "rtpvp9depay ! vp9parse")),
+        "mjpeg" => Some(("JPEG", "rtpjpegdepay")),
+        _ => None,
+    }
+}
+
+/// Decoder elements for CODEC as (kind, factory) in the order auto tries them.
+fn decoder_candidates(codec: &str) -> [(&'static str, &'static str); 3] {
+    match codec {
+        "h264" => [
+            ("vaapi", "vah264dec"),
+            ("nvdec", "nvh264dec"),
+            ("software", "avdec_h264"),
+        ],
+        "h265" => [
+            ("vaapi", "vah265dec"),
+            ("nvdec", "nvh265dec"),
+            ("software", "avdec_h265"),
+        ],
+        "av1" => [
+            ("vaapi", "vaav1dec"),
+            ("nvdec", "nvav1dec"),
+            ("software", "dav1ddec"),
+        ],
+        "vp9" => [
+            ("vaapi", "vavp9dec"),
+            ("nvdec", "nvvp9dec"),
+            ("software", "vp9dec"),
+        ],
+        _ => [
+            ("vaapi", "vajpegdec"),
+            ("nvdec", "nvjpegdec"),
+            ("software", "jpegdec"),
+        ],
+    }
+}
+
+/// Picks the decoder element for CODEC. The VA and NV plugins only register the
+/// decoders the installed driver and GPU can run, so a present factory is a usable
+/// decoder; a requested one that is missing falls back to the auto order. Returns
+/// the element, and the missing one of the requested kind when it fell back, for the
+/// caller to report.
+pub fn select_decoder(
+    codec: &str,
+    decoder: &str,
+) -> Result<(&'static str, Option<&'static str>), String> {
+    let candidates = decoder_candidates(codec);
+    let available = |name: &str| gst::ElementFactory::find(name).is_some();
+    let missing = match candidates.iter().find(|(kind, _)| *kind == decoder) {
+        Some(&(_, name)) if available(name) => return Ok((name, None)),
+        Some(&(_, name)) => Some(name),
+        None => None,
+    };
+    candidates
+        .iter()
+        .map(|(_, name)| *name)
+        .find(|name| available(name))
+        .map(|name| (name, missing))
+        .ok_or_else(|| {
+            let names: Vec<&str> = candidates.iter().map(|(_, name)| *name).collect();
+            format!("no {codec} decoder found (looked for {})", names.join(", "))
+        })
+}
+
+/// The first installed decoder for CODEC after CURRENT in the auto order.
+pub fn next_decoder(codec: &str, current: &str) -> Option<&'static str> {
+    decoder_candidates(codec)
+        .iter()
+        .map(|(_, name)| *name)
+        .skip_while(|name| *name != current)
+        .skip(1)
+        .find(|name| gst::ElementFactory::find(name).is_some())
+}
+
+/// NVDEC hands over CUDA memory unless something downloads it first. The names let
+/// swap_decoder replace the decoder in a built pipeline.
+pub fn decode_stage(element: &str) -> String {
+    if element.starts_with("nv") {
+        format!("{element} name=dec ! cudadownload name=decdl ! videoconvert name=decconv")
+    } else {
+        format!("{element} name=dec ! videoconvert name=decconv")
+    }
+}
+
+/// Replaces the decoder (and its CUDA download) of a pipeline in NULL with ELEMENT.
+pub fn swap_decoder(pipeline: &gst::Pipeline, element: &str) -> Result<(), String> {
+    let old = pipeline
+        .by_name("dec")
+        .ok_or_else(|| "receive pipeline has no decoder".to_string())?;
+    let convert = pipeline
+        .by_name("decconv")
+        .ok_or_else(|| "receive pipeline has no decoder output".to_string())?;
+    let upstream = old
+        .static_pad("sink")
+        .and_then(|pad| pad.peer())
+        .ok_or_else(|| "decoder input is not linked".to_string())?;
+    // Removing the elements also unlinks them.
+    for stale in [Some(old), pipeline.by_name("decdl")].into_iter().flatten() {
+        pipeline
+            .remove(&stale)
+            .map_err(|_| "could not remove the old decoder".to_string())?;
+    }
+    let make = |factory: &str, name: &str| {
+        gst::ElementFactory::make(factory)
+            .name(name)
+            .build()
+            .map_err(|_| format!("could not create {factory}"))
+    };
+    let mut chain = vec![make(element, "dec")?];
+    if element.starts_with("nv") {
+        chain.push(make("cudadownload", "decdl")?);
+    }
+    pipeline
+        .add_many(&chain)
+        .map_err(|_| format!("could not add {element}"))?;
+    let sink = chain[0]
+        .static_pad("sink")
+        .ok_or_else(|| format!("{element} has no sink pad"))?;
+    upstream
+        .link(&sink)
+        .map_err(|e| format!("could not link {element}: {e}"))?;
+    chain.push(convert);
+    gst::Element::link_many(&chain).map_err(|_| format!("could not link {element}"))
+}
+
+/// Receives through an rtpbin session: jitterbuffer, RTCP from the sender on port + 1,
+/// ULPFEC recovery when the sender adds FEC packets, and with `rtx`, NACKs for lost
+/// packets that the sender answers on its RTX stream. PIPELINE can also be a bin
+/// holding one of several streams.
+pub fn attach_rtp_session(
+    pipeline: &impl IsA<gst::Bin>,
+    rtp_caps: gst::Caps,
+    payload: u32,
+    latency_ms: u32,
+    rtx: bool,
+) -> Result<gst::Element, String> {
+    let rtpbin = gst::ElementFactory::make("rtpbin")
+        .name("rtpbin")
+        .property("latency", latency_ms)
+        .property("drop-on-latency", true)
+        .property("do-lost", true)
+        .property("do-retransmission", rtx)
+        .build()
+        .map_err(|e| format!("create rtpbin: {e}"))?;
+    // AVPF sends keyframe requests right away rather than with the next regular report.
+    rtpbin.set_property_from_str("rtp-profile", "avpf");
+    if rtx {
+        rtpbin.connect("request-aux-receiver", false, move |values| {
+            let session = values[1].get::<u32>().ok()?;
+            let pt_map = gst::Structure::builder("application/x-rtp-pt-map")
+                .field(payload.to_string(), RTX_PAYLOAD_TYPE)
+                .build();
+            let rtx_receive = gst::ElementFactory::make("rtprtxreceive")
+                .property("payload-type-map", pt_map)
+                .build()
+                .ok()?;
+            wrap_aux_element(rtx_receive, session).map(|bin| bin.to_value())
+        });
+    }
+
+    // FEC packets share the media SSRC but use their own payload type, which the
+    // udpsrc caps do not describe.
+    let fec_caps = gst::Caps::builder("application/x-rtp")
+        .field("media", "video")
+        .field("encoding-name", "ULPFEC")
+        .field("payload", FEC_PAYLOAD_TYPE as i32)
+        .field("clock-rate", 90000i32)
+        .build();
+    rtpbin.connect("request-pt-map", false, move |values| {
+        let pt = values[2].get::<u32>().ok()?;
+        if pt == FEC_PAYLOAD_TYPE {
+            Some(fec_caps.to_value())
+        } else {
+            Some(rtp_caps.to_value())
+        }
+    });
+    rtpbin.connect("request-fec-decoder", false, |values| {
+        let rtpbin = values[0].get::<gst::Element>().ok()?;
+        let session = values[1].get::<u32>().ok()?;
+        let storage = rtpbin
+            .emit_by_name::<Option<gst::glib::Object>>("get-internal-storage", &[&session])?;
+        let decoder = gst::ElementFactory::make("rtpulpfecdec")
+            .property("pt", FEC_PAYLOAD_TYPE)
+            .property("storage", &storage)
+            .build()
+            .ok()?;
+        Some(decoder.to_value())
+    });
+
+    let depay_in = pipeline
+        .by_name("depay_in")
+        .ok_or_else(|| "receive pipeline has no depay_in queue".to_string())?;
+    rtpbin.connect_pad_added(move |rtpbin, pad| {
+        if !pad.name().starts_with("recv_rtp_src_") {
+            return;
+        }
+        let Some(sink) = depay_in.static_pad("sink") else {
+            return;
+        };
+        // A restarted sender shows up as a new SSRC; follow it.
+        if let Some(old) = sink.peer() {
+            let _ = old.unlink(&sink);
+        }
+        // Posted as a warning on the bus, for the caller to report.
+        if let Err(err) = pad.link(&sink) {
+            gst::element_warning!(
+                rtpbin,
+                gst::StreamError::Failed,
+                ["could not link RTP stream {}: {:?}", pad.name(), err]
+            );
+        }
+    });
+
+    pipeline
+        .add(&rtpbin)
+        .map_err(|e| format!("add rtpbin: {e}"))?;
+    let rtp_in = pipeline
+        .by_name("rtp_in")
+        .ok_or_else(|| "receive pipeline has no rtp_in queue".to_string())?;
+    rtp_in
+        .link_pads(Some("src"), &rtpbin, Some("recv_rtp_sink_0"))
+        .map_err(|e| format!("link rtpbin input: {e}"))?;
+    let rtcp_src = pipeline
+        .by_name("rtcpsrc")
+        .ok_or_else(|| "receive pipeline has no RTCP udpsrc".to_string())?;
+    rtcp_src
+        .link_pads(Some("src"), &rtpbin, Some("recv_rtcp_sink_0"))
+        .map_err(|e| format!("link rtpbin RTCP input: {e}"))?;
+    let rtcp_out = pipeline
+        .by_name("rtcpout")
+        .ok_or_else(|| "receive pipeline has no RTCP sink".to_string())?;
+    rtpbin
+        .link_pads(Some("send_rtcp_src_0"), &rtcp_out, None)
+        .map_err(|e| format!("link rtpbin RTCP output: {e}"))?;
+
+    // FEC can only rebuild packets that are still stored, so keep as much history as
+    // the jitterbuffer can ever hold.
+    if let Some(storage) = rtpbin.emit_by_name::<Option<gst::Element>>("get-storage", &[&0u32]) {
+        storage.set_property("size-time", MAX_LATENCY_MS as u64 * 1_000_000);
+    }
+    Ok(rtpbin)
+}
+
+/// rtpbin wants aux elements as a bin with `sink_N` / `src_N` pads for session N.
+fn wrap_aux_element(element: gst::Element, session: u32) -> Option<gst::Element> {
+    let bin = gst::Bin::new();
+    bin.add(&element).ok()?;
+    for (pad_name, ghost_name) in [
+        ("sink", format!("sink_{session}")),
+        ("src", format!("src_{session}")),
+    ] {
+        let target = element.static_pad(pad_name)?;
+        let ghost = gst::GhostPad::builder_with_target(&target)
+            .ok()?
+            .name(ghost_name)
+            .build();
+        bin.add_pad(&ghost).ok()?;
+    }
+    Some(bin.upcast())
+}
+
+/// Receiver reports and NACKs go back to the sender on port + 2. The sender's address
+/// is taken from the first RTP packet, so it does not have to be configured; ON_SENDER
+/// gets it as `HOST:PORT` once it is known.
+pub fn return_rtcp_to_sender<F>(pipeline: &impl IsA<gst::Bin>, port: u16, on_sender: F)
+where
+    F: Fn(&str) + Send + Sync + 'static,
+{
+    let (Some(rtcp_out), Some(pad)) = (
+        pipeline.by_name("rtcpout"),
+        pipeline
+            .by_name("src")
+            .and_then(|src| src.static_pad("src")),
+    ) else {
+        return;
+    };
+    let return_port = i32::from(port.saturating_add(2));
+    pad.add_probe(gst::PadProbeType::BUFFER, move |_, info| {
+        let sender = info
+            .buffer()
+            .and_then(|buffer| buffer.meta::<gst_net::NetAddressMeta>())
+            .and_then(|meta| {
+                meta.addr()
+                    .downcast::<gst_net::gio::InetSocketAddress>()
+                    .ok()
+            })
+            .map(|addr| addr.address().to_str().to_string());
+        let Some(host) = sender else {
+            return gst::PadProbeReturn::Ok;
+        };
+        rtcp_out.emit_by_name::<()>("add", &[&host, &return_port]);
+        on_sender(&format!("{host}:{return_port}"));
+        gst::PadProbeReturn::Remove
+    });
+}
@@ -1,14 +1,127 @@
+mod whep;
+
+use clap::{CommandFactory, Parser, Subcommand};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use gstreamer_app as gst_app;
+use gstreamer_video as gst_video;
 use ksni::menu::{MenuItem, StandardItem};
 use ksni::{Tray, TrayService};
 use serde::{Deserialize, Serialize};
 use std::env;
-use std::fs;
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
 use std::process::{Command, ExitCode, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use vp_color::colorimetry_field;
+use vp_config::{config_value, edit_config_value};
+use vp_control as control;
+use vp_link::{
+    MAX_LATENCY_MS, attach_rtp_session, decode_stage, depay_stage, next_decoder,
+    return_rtcp_to_sender, select_decoder, swap_decoder,
+};
+use vp_metrics::Metrics;
+
+const PREVIEW_WINDOW_TITLE: &str = "vp-rcvr-preview";
+const PREVIEW_WINDOW_WAIT_SECS: u64 = 10;
+const RTP_CAPTURE_MAGIC: &[u8; 8] = b"VPRTP001";
+const RTP_RECORD_REPORT_SECS: u64 = 5;
+const REPLAY_START_DELAY_MS: u64 = 300;
+const REPLAY_DRAIN_MS: u64 = 500;
+const SENDER_STATS_PORT_OFFSET: u16 = 3;
+const SENDER_STATS_LOG_SECS: u64 = 10;
+const LATENCY_STEP_MS: i64 = 5;
+const OSD_MESSAGE_SECS: u64 = 2;
+const METRICS_INTERVAL_MS: u64 = 1000;
+const STATS_OSD_INTERVAL_MS: u64 = 1000;
+const CONTROL_REPLY_TIMEOUT_SECS: u64 = 5;
+const IDLE_TIMEOUT_EXIT_CODE: u8 = 3;
+const NO_SIGNAL_TEXT: &str = "No signal - waiting for sender";
+// --standby: output format without --v4l2-width/--v4l2-height/--v4l2-fps, and how
+// long the stream may pause before the bars come up.
+const STANDBY_WIDTH: u32 = 1920;
+const STANDBY_HEIGHT: u32 = 1080;
+const STANDBY_FPS: u32 = 60;
+const STANDBY_AFTER_MS: u64 = 1000;
+// How long --record may take to write the Matroska index on the way out.
+const RECORD_FINISH_TIMEOUT_MS: u64 = 3000;
+const SNAPSHOT_TIMEOUT_SECS: u64 = 2;
+// Keyframe interval in frames when --restream or --hls-addr re-encode (2 s at 60 fps,
+// as streaming services ask for).
+const REENCODE_KEYINT: u32 = 120;
+// Pause before a dropped --restream connection is retried.
+const RESTREAM_RETRY_SECS: u64 = 5;
+// Buffers the --restream appsrc holds for a slow connection (2 s at 60 fps).
+const RESTREAM_QUEUE_BUFFERS: u64 = 120;
+// Pause before a lost --transport srt, --rtsp-url or --whep-url connection is set up again.
+const RECONNECT_SECS: u64 = 2;
+// Same SRT retransmission window as vp-sndr --srt-latency-ms.
+const DEFAULT_SRT_LATENCY_MS: u32 = 120;
+const LATENCY_FIXED: &str =
+    "latency is fixed per connection with --transport srt, --rtsp-url or --whep-url";
+// --hls-addr: segment length, segments in the playlist, and how long a viewer may
+// take to send its request.
+const HLS_TARGET_SECS: u32 = 2;
+const HLS_PLAYLIST_LENGTH: u32 = 5;
+const HLS_READ_TIMEOUT_SECS: u64 = 5;
+// --shm-socket: shared memory area for the frames in flight (several 4K RGBA frames).
+const SHM_SIZE_BYTES: u32 = 128 * 1024 * 1024;
+const HLS_PLAYLIST: &str = "index.m3u8";
+const HLS_PAGE: &str = "index.html";
+const HLS_PAGE_HTML: &str = "<!doctype html>
+<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">
+<title>vp-rcvr</title>
+<style>body{margin:0;background:#000}video{width:100vw;height:100vh}</style>
+<video src=\"index.m3u8\" autoplay muted playsinline controls></video>
+";
+// How long --v4l2-modprobe waits for the loopback device to appear.
+const LOOPBACK_LOAD_TIMEOUT_SECS: u64 = 3;
+// SRTP master key (16 bytes) + salt (14 bytes) for AES_CM_128_HMAC_SHA1_80.
+const SRTP_KEY_LEN: usize = 30;
+// Opus audio from vp-sndr --with-audio arrives on RTP port + 2.
+const AUDIO_PORT_OFFSET: u16 = 2;
+const AUDIO_PAYLOAD_TYPE: u32 = 111;
+// PipeWire node names of --audio-output virtual-mic and --virtual-camera.
+const VIRTUAL_MIC_NODE: &str = "vp-rcvr-mic";
+const VIRTUAL_CAMERA_NODE: &str = "vp-rcvr-camera";
+// RFC 6051 header extension with the sender's capture time; the ID must match vp-sndr.
+const CAPTURE_TIME_EXT_ID: u32 = 1;
+const CAPTURE_TIME_EXT_URI: &str = "urn:ietf:params:rtp-hdrext:ntp-64";
+const NTP_UNIX_OFFSET_SECS: u64 = 2_208_988_800;
+const LATENCY_LOG_SECS: u64 = 10;
+// Latency samples are kept as a 1 ms histogram; slower frames share the last bucket.
+const LATENCY_HISTOGRAM_MS: usize = 5000;
+
+/// An extra output for the decoded stream, next to the preview and --v4l2-device:
+/// a `[[sink]]` table in the config or a `--sink` flag.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct SinkSpec {
+    kind: String,
+    #[serde(default)]
+    device: Option<String>,
+    #[serde(default)]
+    path: Option<String>,
+    #[serde(default)]
+    width: Option<u32>,
+    #[serde(default)]
+    height: Option<u32>,
+    #[serde(default)]
+    fps: Option<u32>,
+    #[serde(default)]
+    format: Option<String>,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 struct ReceiverConfig {
     codec: String,
+    decoder: String,
     bind_ip: String,
     port: u16,
     payload: u8,
@@ -16,15 +129,57 @@ struct ReceiverConfig {
     latency_ms: u32,
     no_preview: bool,
     v4l2_device: Option<String>,
+    v4l2_modprobe: bool,
     v4l2_width: Option<u32>,
     v4l2_height: Option<u32>,
     v4l2_fps: Option<u32>,
+    virtual_camera: bool,
+    shm_socket: Option<String>,
+    preview_position: Option<String>,
+    preview_monitor: Option<u32>,
+    preview_on_top: bool,
+    osd: bool,
+    standby: bool,
+    idle_timeout_secs: Option<u64>,
+    on_timeout: String,
+    on_decode_error: String,
+    stall_timeout_secs: Option<u64>,
+    srtp_key: Option<String>,
+    rtx: bool,
+    transport: String,
+    srt_mode: String,
+    srt_latency_ms: u32,
+    srt_passphrase: Option<String>,
+    sender_ip: Option<String>,
+    rtsp_url: Option<String>,
+    whep_url: Option<String>,
+    whep_token: Option<String>,
+    with_audio: bool,
+    audio_latency_ms: Option<u32>,
+    audio_delay_ms: Option<u32>,
+    audio_output: String,
+    measure_latency: bool,
+    metrics_addr: Option<String>,
+    insert_after_depay: Option<String>,
+    insert_after_decode: Option<String>,
+    bit_depth: u32,
+    colorimetry: Option<String>,
+    color_range: Option<String>,
+    record: Option<String>,
+    restream: Option<String>,
+    restream_bitrate_kbps: Option<u32>,
+    hls_addr: Option<String>,
+    hls_bitrate_kbps: Option<u32>,
+    #[serde(rename = "sink")]
+    sinks: Vec<SinkSpec>,
+    snapshot_dir: Option<String>,
 }
 
 impl Default for ReceiverConfig {
     fn default() -> Self {
         Self {
             codec: "h265".to_string(),
+            decoder: "auto".to_string(),
             bind_ip: "0.0.0.0".to_string(),
             port: 5000,
             payload: 96,
@@ -32,9 +187,49 @@ impl Default for ReceiverConfig {
             latency_ms: 25,
             no_preview: false,
             v4l2_device: None,
+            v4l2_modprobe: false,
             v4l2_width: None,
             v4l2_height: None,
             v4l2_fps: None,
+            virtual_camera: false,
+            shm_socket: None,
+            preview_position: None,
+            preview_monitor: None,
+            preview_on_top: false,
+            osd: false,
+            standby: false,
+            idle_timeout_secs: None,
+            on_timeout: "exit".to_string(),
+            on_decode_error: "fallback".to_string(),
+            stall_timeout_secs: None,
+            srtp_key: None,
+            rtx: false,
+            transport: "udp".to_string(),
+            srt_mode: "listener".to_string(),
+            srt_latency_ms: DEFAULT_SRT_LATENCY_MS,
+            srt_passphrase: None,
+            sender_ip: None,
+            rtsp_url: None,
+            whep_url: None,
+            whep_token: None,
+            with_audio: false,
+            audio_latency_ms: None,
+            audio_delay_ms: None,
+            audio_output: "default".to_string(),
+            measure_latency: false,
+            metrics_addr: None,
+            insert_after_depay: None,
+            insert_after_decode: None,
+            bit_depth: 8,
+            colorimetry: None,
+            color_range: None,
+            record: None,
+            restream: None,
+            restream_bitrate_kbps: None,
+            hls_addr: None,
+            hls_bitrate_kbps: None,
+            sinks: Vec::new(),
+            snapshot_dir: None,
         }
     }
 }
@@ -77,31 +272,263 @@ fn save_config(cfg: &ReceiverConfig) -> Result<(), String> {
     Ok(())
 }
 
-fn cfg_from_receive(
-    codec: &str,
-    bind_ip: &str,
-    port: u16,
-    payload: u8,
-    clock_rate: u32,
-    latency_ms: u32,
-    no_preview: bool,
-    v4l2_device: Option<&str>,
-    v4l2_width: Option<u32>,
-    v4l2_height: Option<u32>,
-    v4l2_fps: Option<u32>,
-) -> ReceiverConfig {
+/// `config get KEY`. Keys are the names in the TOML file.
+fn config_get(key: &str) -> Result<String, String> {
+    let value =
+        serde_json::to_value(load_config()).map_err(|e| format!("serialize config: {e}"))?;
+    config_value(&value, key)
+}
+
+/// `config set KEY VALUE`, or `config unset KEY` with no value, which puts the
+/// default back. The edited config is checked like the `receive` flags before
+/// it is written. A file that does not parse is left as it is rather than
+/// replaced with the defaults.
+fn config_set(key: &str, value: Option<&str>) -> Result<(), String> {
+    let path = config_path()?;
+    let cfg = match fs::read_to_string(&path) {
+        Ok(data) => toml::from_str::<ReceiverConfig>(&data)
+            .map_err(|e| format!("parse {}: {e}", path.display()))?,
+        Err(err) if err.kind() == ErrorKind::NotFound => ReceiverConfig::default(),
+        Err(err) => return Err(format!("read {}: {err}", path.display())),
+    };
+    let mut doc = serde_json::to_value(cfg).map_err(|e| format!("serialize config: {e}"))?;
+    edit_config_value(&mut doc, key, value)?;
+    let cfg = serde_json::from_value::<ReceiverConfig>(doc)
+        .map_err(|e| format!("invalid value for {key}: {e}"))?;
+    check_config(&cfg)?;
+    save_config(&cfg)
+}
+
+/// The value checks of the `receive` flags, for settings written by `config set`.
+fn check_config(cfg: &ReceiverConfig) -> Result<(), String> {
+    if !matches!(
+        cfg.codec.as_str(),
+        "h264" | "h265" | "av1" | "vp9" | "mjpeg"
+    ) {
+        return Err(format!(
+            "invalid codec: {} (expected h264, h265, av1, vp9 or mjpeg)",
+            cfg.codec
+        ));
+    }
+    if cfg.clock_rate == 0 {
+        return Err("clock_rate must be > 0".to_string());
+    }
+    for (key, value) in [
+        ("v4l2_width", cfg.v4l2_width),
+        ("v4l2_height", cfg.v4l2_height),
+        ("v4l2_fps", cfg.v4l2_fps),
+    ] {
+        if value == Some(0) {
+            return Err(format!("{key} must be > 0"));
+        }
+    }
+    let has_output = cfg.v4l2_device.is_some()
+        || cfg.virtual_camera
+        || cfg.shm_socket.is_some()
+        || !cfg.sinks.is_empty();
+    if cfg.no_preview && !has_output {
+        return Err(
+            "nothing to do: no_preview needs v4l2_device, virtual_camera, shm_socket or a sink"
+                .to_string(),
+        );
+    }
+    for sink in &cfg.sinks {
+        check_sink(sink)?;
+    }
+    if let Some(url) = &cfg.restream {
+        parse_restream_url(url)?;
+    }
+    if cfg.restream_bitrate_kbps == Some(0) {
+        return Err("restream_bitrate_kbps must be > 0".to_string());
+    }
+    if let Some(addr) = &cfg.hls_addr {
+        addr.parse::<std::net::SocketAddr>()
+            .map_err(|_| format!("invalid hls_addr: {addr}"))?;
+    }
+    if cfg.hls_bitrate_kbps == Some(0) {
+        return Err("hls_bitrate_kbps must be > 0".to_string());
+    }
+    if cfg.idle_timeout_secs == Some(0) {
+        return Err("idle_timeout_secs must be > 0".to_string());
+    }
+    if cfg.stall_timeout_secs == Some(0) {
+        return Err("stall_timeout_secs must be > 0".to_string());
+    }
+    parse_decoder(&cfg.decoder)?;
+    parse_on_timeout(&cfg.on_timeout)?;
+    parse_on_decode_error(&cfg.on_decode_error)?;
+    parse_audio_output(&cfg.audio_output)?;
+    if let Some(position) = &cfg.preview_position {
+        parse_preview_position(position)?;
+    }
+    if let Some(key) = &cfg.srtp_key {
+        parse_srtp_key(key)?;
+    }
+    parse_transport(&cfg.transport)?;
+    parse_srt_mode(&cfg.srt_mode)?;
+    if let Some(passphrase) = &cfg.srt_passphrase {
+        parse_srt_passphrase(passphrase)?;
+    }
+    if let Some(url) = &cfg.rtsp_url {
+        parse_rtsp_url(url)?;
+    }
+    if let Some(url) = &cfg.whep_url {
+        parse_whep_url(url)?;
+    }
+    check_transport(cfg)?;
+    if let Some(addr) = &cfg.metrics_addr {
+        addr.parse::<std::net::SocketAddr>()
+            .map_err(|_| format!("invalid metrics_addr: {addr}"))?;
+    }
+    for fragment in [&cfg.insert_after_depay, &cfg.insert_after_decode]
+        .into_iter()
+        .flatten()
+    {
+        parse_pipeline_fragment(fragment)?;
+    }
+    if cfg.bit_depth != 8 && cfg.bit_depth != 10 {
+        return Err(format!(
+            "invalid bit_depth: {} (expected 8 or 10)",
+            cfg.bit_depth
+        ));
+    }
+    check_bit_depth(&cfg.codec, cfg.bit_depth)?;
+    for (key, value, choices) in [
+        (
+            "colorimetry",
+            &cfg.colorimetry,
+            &["bt709", "bt601", "bt2020"][..],
+        ),
+        ("color_range", &cfg.color_range, &["full", "limited"][..]),
+    ] {
+        if let Some(value) = value
+            && !choices.contains(&value.as_str())
+        {
+            return Err(format!(
+                "invalid {key}: {value} (expected {})",
+                choices.join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// SRT brings only the MPEG-TS video of vp-sndr --transport srt, and rtspsrc and
+/// webrtcbin run their own RTP session; the extras of vp-sndr's RTP stream (SRTP,
+/// RTX, audio, capture times) have nothing to work on in any of them.
+fn check_transport(cfg: &ReceiverConfig) -> Result<(), String> {
+    if cfg.whep_token.is_some() && cfg.whep_url.is_none() {
+        return Err("--whep-token needs --whep-url".to_string());
+    }
+    if cfg.whep_token.is_some()
+        && let Some(url) = &cfg.whep_url
+        && !whep::is_loopback(url)
+    {
+        return Err(
+            "--whep-token would be sent unencrypted over http://; reach the endpoint through a local TLS proxy (http://127.0.0.1:...)"
+                .to_string(),
+        );
+    }
+    let pulled = match (&cfg.rtsp_url, &cfg.whep_url) {
+        (Some(_), Some(_)) => {
+            return Err("--rtsp-url and --whep-url are separate sources; pick one".to_string());
+        }
+        (Some(_), None) => Some("--rtsp-url"),
+        (None, Some(_)) => Some("--whep-url"),
+        (None, None) => None,
+    };
+    let source = match (cfg.transport.as_str(), pulled) {
+        ("srt", Some(flag)) => {
+            return Err(format!(
+                "{flag} pulls the stream itself; drop --transport srt"
+            ));
+        }
+        ("srt", None) => "--transport srt",
+        (_, Some(flag)) => flag,
+        (_, None) => return Ok(()),
+    };
+    for (flag, set) in [
+        ("--srtp-key", cfg.srtp_key.is_some()),
+        ("--rtx", cfg.rtx),
+        ("--with-audio", cfg.with_audio),
+        ("--measure-latency", cfg.measure_latency),
+    ] {
+        if set {
+            return Err(format!("{flag} does not apply to {source}"));
+        }
+    }
+    if cfg.whep_url.is_some() && cfg.codec == "mjpeg" {
+        return Err("--whep-url carries h264, h265, av1 or vp9, not mjpeg".to_string());
+    }
+    if pulled.is_some() {
+        return Ok(());
+    }
+    if !matches!(cfg.codec.as_str(), "h264" | "h265") {
+        return Err(format!(
+            "--transport srt carries h264 or h265, not {}",
+            cfg.codec
+        ));
+    }
+    if cfg.srt_mode == "caller" && cfg.sender_ip.is_none() {
+        return Err("--srt-mode caller needs --sender-ip".to_string());
+    }
+    Ok(())
+}
+
+fn cfg_from_receive(cfg: &ReceiveCfg) -> ReceiverConfig {
     ReceiverConfig {
-        codec: codec.to_string(),
-        bind_ip: bind_ip.to_string(),
-        port,
-        payload,
-        clock_rate,
-        latency_ms,
-        no_preview,
-        v4l2_device: v4l2_device.map(|v| v.to_string()),
-        v4l2_width,
-        v4l2_height,
-        v4l2_fps,
+        codec: cfg.codec.clone(),
+        decoder: cfg.decoder.clone(),
+        bind_ip: cfg.bind_ip.clone(),
+        port: cfg.port,
+        payload: cfg.payload,
+        clock_rate: cfg.clock_rate,
+        latency_ms: cfg.latency_ms,
+        no_preview: cfg.no_preview,
+        v4l2_device: cfg.v4l2_device.clone(),
+        v4l2_modprobe: cfg.v4l2_modprobe,
+        v4l2_width: cfg.v4l2_width,
+        v4l2_height: cfg.v4l2_height,
+        v4l2_fps: cfg.v4l2_fps,
+        virtual_camera: cfg.virtual_camera,
+        shm_socket: cfg.shm_socket.clone(),
+        preview_position: cfg.preview_position.clone(),
+        preview_monitor: cfg.preview_monitor,
+        preview_on_top: cfg.preview_on_top,
+        osd: cfg.osd,
+        standby: cfg.standby,
+        idle_timeout_secs: cfg.idle_timeout_secs,
+        on_timeout: cfg.on_timeout.clone(),
+        on_decode_error: cfg.on_decode_error.clone(),
+        stall_timeout_secs: cfg.stall_timeout_secs,
+        srtp_key: cfg.srtp_key.clone(),
+        rtx: cfg.rtx,
+        transport: cfg.transport.clone(),
+        srt_mode: cfg.srt_mode.clone(),
+        srt_latency_ms: cfg.srt_latency_ms,
+        srt_passphrase: cfg.srt_passphrase.clone(),
+        sender_ip: cfg.sender_ip.clone(),
+        rtsp_url: cfg.rtsp_url.clone(),
+        whep_url: cfg.whep_url.clone(),
+        whep_token: cfg.whep_token.clone(),
+        with_audio: cfg.with_audio,
+        audio_latency_ms: cfg.audio_latency_ms,
+        audio_delay_ms: cfg.audio_delay_ms,
+        audio_output: cfg.audio_output.clone(),
+        measure_latency: cfg.measure_latency,
+        metrics_addr: cfg.metrics_addr.clone(),
+        insert_after_depay: cfg.insert_after_depay.clone(),
+        insert_after_decode: cfg.insert_after_decode.clone(),
+        bit_depth: cfg.bit_depth,
+        colorimetry: cfg.colorimetry.clone(),
+        color_range: cfg.color_range.clone(),
+        record: cfg.record.clone(),
+        restream: cfg.restream.clone(),
+        restream_bitrate_kbps: cfg.restream_bitrate_kbps,
+        hls_addr: cfg.hls_addr.clone(),
+        hls_bitrate_kbps: cfg.hls_bitrate_kbps,
+        sinks: cfg.sinks.clone(),
+        snapshot_dir: cfg.snapshot_dir.clone(),
     }
 }
 
@@ -216,415 +643,3492 @@ fn tray_open_config() {
 }
 
 fn main() -> ExitCode {
-    let args: Vec<String> = env::args().collect();
-    match parse_cli(&args) {
-        Ok(Cli::Help) => {
-            print_help();
-            ExitCode::SUCCESS
-        }
-        Ok(Cli::ConfigPath) => {
+    let Some(command) = Cli::parse().command else {
+        let _ = Cli::command().print_help();
+        return ExitCode::SUCCESS;
+    };
+    match command {
+        CliCommand::Config { action: None } => {
             match config_path() {
                 Ok(path) => println!("{}", path.display()),
                 Err(err) => eprintln!("error: {err}"),
             }
             ExitCode::SUCCESS
         }
-        Ok(Cli::Tray) => run_tray(),
-        Ok(Cli::RunSaved) => {
+        CliCommand::Config {
+            action: Some(ConfigAction::Get { key }),
+        } => match config_get(&key) {
+            Ok(value) => {
+                println!("{value}");
+                ExitCode::SUCCESS
+            }
+            Err(err) => {
+                eprintln!("error: {err}");
+                ExitCode::from(2)
+            }
+        },
+        CliCommand::Config {
+            action: Some(action),
+        } => {
+            let result = match action {
+                ConfigAction::Set { key, value } => config_set(&key, Some(&value)),
+                ConfigAction::Unset { key } => config_set(&key, None),
+                ConfigAction::Get { .. } => unreachable!(),
+            };
+            match result {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(err) => {
+                    eprintln!("error: {err}");
+                    ExitCode::from(2)
+                }
+            }
+        }
+        CliCommand::Tray => run_tray(),
+        CliCommand::RunSaved { dry_run } => {
             let cfg = load_config();
-            run_receive(
-                &cfg.codec,
-                &cfg.bind_ip,
-                cfg.port,
-                cfg.payload,
-                cfg.clock_rate,
-                cfg.latency_ms,
-                !cfg.no_preview,
-                None,
-                None,
-                cfg.v4l2_device.as_deref(),
-                cfg.v4l2_width,
-                cfg.v4l2_height,
-                cfg.v4l2_fps,
-            )
+            let receive_cfg = ReceiveCfg {
+                codec: cfg.codec,
+                decoder: cfg.decoder,
+                bind_ip: cfg.bind_ip,
+                port: cfg.port,
+                payload: cfg.payload,
+                clock_rate: cfg.clock_rate,
+                latency_ms: cfg.latency_ms,
+                no_preview: cfg.no_preview,
+                preview_width: None,
+                preview_height: None,
+                preview_position: cfg.preview_position,
+                preview_monitor: cfg.preview_monitor,
+                preview_on_top: cfg.preview_on_top,
+                osd: cfg.osd,
+                standby: cfg.standby,
+                v4l2_device: cfg.v4l2_device,
+                v4l2_modprobe: cfg.v4l2_modprobe,
+                v4l2_width: cfg.v4l2_width,
+                v4l2_height: cfg.v4l2_height,
+                v4l2_fps: cfg.v4l2_fps,
+                virtual_camera: cfg.virtual_camera,
+                shm_socket: cfg.shm_socket,
+                idle_timeout_secs: cfg.idle_timeout_secs,
+                on_timeout: cfg.on_timeout,
+                on_decode_error: cfg.on_decode_error,
+                stall_timeout_secs: cfg.stall_timeout_secs,
+                srtp_key: cfg.srtp_key,
+                rtx: cfg.rtx,
+                transport: cfg.transport,
+                srt_mode: cfg.srt_mode,
+                srt_latency_ms: cfg.srt_latency_ms,
+                srt_passphrase: cfg.srt_passphrase,
+                sender_ip: cfg.sender_ip,
+                rtsp_url: cfg.rtsp_url,
+                whep_url: cfg.whep_url,
+                whep_token: cfg.whep_token,
+                with_audio: cfg.with_audio,
+                audio_latency_ms: cfg.audio_latency_ms,
+                audio_delay_ms: cfg.audio_delay_ms,
+                audio_output: cfg.audio_output,
+                measure_latency: cfg.measure_latency,
+                metrics_addr: cfg.metrics_addr,
+                insert_after_depay: cfg.insert_after_depay,
+                insert_after_decode: cfg.insert_after_decode,
+                bit_depth: cfg.bit_depth,
+                colorimetry: cfg.colorimetry,
+                color_range: cfg.color_range,
+                record: cfg.record,
+                restream: cfg.restream,
+                restream_bitrate_kbps: cfg.restream_bitrate_kbps,
+                hls_addr: cfg.hls_addr,
+                hls_bitrate_kbps: cfg.hls_bitrate_kbps,
+                sinks: cfg.sinks,
+                snapshot_dir: cfg.snapshot_dir,
+                dry_run,
+            };
+            run_receive(receive_cfg, None)
         }
-        Ok(Cli::Receive {
-            codec,
-            bind_ip,
-            port,
-            payload,
-            clock_rate,
-            latency_ms,
-            no_preview,
-            preview_width,
-            preview_height,
-            v4l2_device,
-            v4l2_width,
-            v4l2_height,
-            v4l2_fps,
-        }) => {
-            if let Err(err) = save_config(&cfg_from_receive(
-                &codec,
-                &bind_ip,
-                port,
-                payload,
-                clock_rate,
-                latency_ms,
-                no_preview,
-                v4l2_device.as_deref(),
-                v4l2_width,
-                v4l2_height,
-                v4l2_fps,
-            )) {
+        CliCommand::Receive(receive_cfg) => {
+            if !receive_cfg.dry_run
+                && let Err(err) = save_config(&cfg_from_receive(&receive_cfg))
+            {
                 eprintln!("WARN: {err}");
             }
-            run_receive(
-                &codec,
-                &bind_ip,
-                port,
-                payload,
-                clock_rate,
-                latency_ms,
-                !no_preview,
-                preview_width,
-                preview_height,
-                v4l2_device.as_deref(),
-                v4l2_width,
-                v4l2_height,
-                v4l2_fps,
-            )
+            run_receive(*receive_cfg, None)
         }
-        Err(err) => {
-            eprintln!("error: {err}");
-            print_help();
-            ExitCode::from(2)
+        CliCommand::Tile(tile_cfg) => run_tile(*tile_cfg),
+        CliCommand::RecordRtp {
+            path,
+            bind_ip,
+            port,
+        } => run_record_rtp(&path, &bind_ip, port),
+        CliCommand::Replay { path, mut cfg } => {
+            // Replayed packets are sent over loopback, never from the network.
+            cfg.bind_ip = "127.0.0.1".to_string();
+            cfg.transport = "udp".to_string();
+            cfg.rtsp_url = None;
+            cfg.whep_url = None;
+            cfg.whep_token = None;
+            run_receive(*cfg, Some(&path))
+        }
+        CliCommand::Latency { value } => {
+            let line = match value {
+                Some(v) => format!("latency {v}"),
+                None => "latency".to_string(),
+            };
+            match send_control_command(&line) {
+                Ok(reply) => {
+                    println!("{reply}");
+                    ExitCode::SUCCESS
+                }
+                Err(err) => {
+                    eprintln!("error: {err}");
+                    ExitCode::from(1)
+                }
+            }
         }
+        CliCommand::Snapshot => match send_control_command("snapshot") {
+            Ok(reply) => {
+                println!("{reply}");
+                ExitCode::SUCCESS
+            }
+            Err(err) => {
+                eprintln!("error: {err}");
+                ExitCode::from(1)
+            }
+        },
     }
 }
 
-enum Cli {
-    Help,
-    Tray,
-    ConfigPath,
-    RunSaved,
-    Receive {
-        codec: String,
+const EXAMPLES: &str = "\
+Examples:
+  vp-rcvr receive --port 5000
+  vp-rcvr receive --port 5000 --preview-width 1280 --preview-height 720
+  vp-rcvr receive --port 5000 --osd
+  vp-rcvr receive --port 5000 --v4l2-device /dev/video10 --standby
+  vp-rcvr receive --port 5000 --no-preview --v4l2-device /dev/video10 --stall-timeout-secs 5
+  vp-rcvr receive --port 5000 --preview-position bottom-right --preview-monitor 1 --preview-on-top
+  vp-rcvr tile --stream 5000=desk --stream 5010=laptop --stream 5020=render
+  vp-rcvr receive --port 5000 --v4l2-device /dev/video10
+  vp-rcvr receive --port 5000 --no-preview --v4l2-device /dev/video10
+  vp-rcvr receive --port 5000 --no-preview --virtual-camera
+  vp-rcvr receive --port 5000 --no-preview --shm-socket /tmp/vp.sock
+  vp-rcvr receive --port 5000 --no-preview --v4l2-device auto --v4l2-modprobe
+  vp-rcvr receive --port 5000 --v4l2-device /dev/video10 --sink kind=v4l2,device=/dev/video11,w=1280,h=720
+  vp-rcvr receive --codec h264 --port 5000 --no-preview --v4l2-device /dev/video10 --v4l2-width 1280 --v4l2-height 720 --v4l2-fps 60
+  vp-rcvr receive --codec av1 --port 5000
+  vp-rcvr receive --port 5000 --decoder vaapi
+  vp-rcvr receive --port 5000 --decoder vaapi --on-decode-error exit
+  vp-rcvr receive --port 5000 --latency-ms 25 --rtx
+  vp-rcvr receive --port 5000 --codec h264 --transport srt --srt-latency-ms 200
+  vp-rcvr receive --codec h264 --rtsp-url rtsp://camera.local:8554/stream --no-preview --v4l2-device auto
+  vp-rcvr receive --codec h264 --whep-url http://media.local:8889/cam/whep
+  vp-rcvr receive --port 5000 --with-audio
+  vp-rcvr receive --port 5000 --no-preview --v4l2-device /dev/video10 --with-audio --audio-output virtual-mic --audio-delay-ms 40
+  vp-rcvr receive --port 5000 --srtp-key \"$(cat ~/.config/vp-link/srtp.key)\"
+  vp-rcvr receive --port 5000 --no-preview --v4l2-device /dev/video10 --idle-timeout-secs 30 --on-timeout restart
+  vp-rcvr receive --port 5000 --measure-latency
+  vp-rcvr receive --port 5000 --bit-depth 10
+  vp-rcvr receive --port 5000 --v4l2-device /dev/video10 --colorimetry bt709 --color-range limited
+  vp-rcvr receive --port 5000 --metrics-addr 127.0.0.1:9322
+  vp-rcvr receive --port 5000 --insert-after-decode \"gamma gamma=1.2 ! videobalance contrast=1.1\"
+  vp-rcvr receive --codec h264 --port 5000 --v4l2-device /dev/video10 --dry-run
+  vp-rcvr receive --port 5000 --record ~/Videos/monitoring.mkv
+  vp-rcvr receive --codec h264 --port 5000 --restream rtmp://live.twitch.tv/app/KEY
+  vp-rcvr receive --codec h264 --port 5000 --hls-addr 0.0.0.0:8080
+  VP_RCVR_LATENCY_MS=40 vp-rcvr receive --port 5000
+  vp-rcvr record-rtp field-issue.vprtp --port 5000
+  vp-rcvr replay field-issue.vprtp --latency-ms 50
+  vp-rcvr latency +5
+  vp-rcvr snapshot
+  vp-rcvr tray
+  vp-rcvr config
+  vp-rcvr config get latency_ms
+  vp-rcvr config set latency_ms 40
+  vp-rcvr run-saved
+";
+
+#[derive(Parser)]
+#[command(name = "vp-rcvr", about = "vp-rcvr: HEVC viewport receiver", after_help = EXAMPLES)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+    /// Receive and show a stream; the settings are saved for run-saved.
+    Receive(Box<ReceiveCfg>),
+    /// Store received RTP packets with their arrival times until Ctrl-C.
+    RecordRtp {
+        path: PathBuf,
+        /// Address to listen on.
+        #[arg(
+            long,
+            value_name = "IP",
+            env = "VP_RCVR_BIND_IP",
+            default_value = "0.0.0.0"
+        )]
         bind_ip: String,
+        /// RTP port.
+        #[arg(long, value_name = "N", env = "VP_RCVR_PORT", default_value_t = 5000)]
         port: u16,
-        payload: u8,
-        clock_rate: u32,
-        latency_ms: u32,
-        no_preview: bool,
-        preview_width: Option<u32>,
-        preview_height: Option<u32>,
-        v4l2_device: Option<String>,
-        v4l2_width: Option<u32>,
-        v4l2_height: Option<u32>,
-        v4l2_fps: Option<u32>,
+    },
+    /// Receive several streams and show them as a grid in one window.
+    Tile(Box<TileCfg>),
+    /// Play a record-rtp capture through the receive pipeline.
+    Replay {
+        path: PathBuf,
+        #[command(flatten)]
+        cfg: Box<ReceiveCfg>,
+    },
+    /// Save the running receiver's current frame as a PNG.
+    Snapshot,
+    /// Print or change the jitter-buffer latency of the running receiver.
+    Latency {
+        #[arg(value_name = "MS|+MS|-MS", allow_hyphen_values = true, value_parser = parse_latency_value)]
+        value: Option<String>,
+    },
+    /// Show the tray icon.
+    Tray,
+    /// Print the config path, or read and change saved settings.
+    Config {
+        #[command(subcommand)]
+        action: Option<ConfigAction>,
+    },
+    /// Receive with the saved settings.
+    RunSaved {
+        /// Print the pipeline and exit.
+        #[arg(long, env = "VP_RCVR_DRY_RUN")]
+        dry_run: bool,
     },
 }
 
-fn parse_cli(args: &[String]) -> Result<Cli, String> {
-    if args.len() <= 1 {
-        return Ok(Cli::Help);
-    }
-    match args[1].as_str() {
-        "-h" | "--help" | "help" => Ok(Cli::Help),
-        "tray" => Ok(Cli::Tray),
-        "config" => Ok(Cli::ConfigPath),
-        "run-saved" => Ok(Cli::RunSaved),
-        "receive" => {
-            let mut bind_ip = String::from("0.0.0.0");
-            let mut codec = String::from("h265");
-            let mut port = 5000u16;
-            let mut payload = 96u8;
-            let mut clock_rate = 90_000u32;
-            let mut latency_ms = 25u32;
-            let mut no_preview = false;
-            let mut preview_width: Option<u32> = None;
-            let mut preview_height: Option<u32> = None;
-            let mut v4l2_device: Option<String> = None;
-            let mut v4l2_width: Option<u32> = None;
-            let mut v4l2_height: Option<u32> = None;
-            let mut v4l2_fps: Option<u32> = None;
-
-            let mut i = 2usize;
-            while i < args.len() {
-                match args[i].as_str() {
-                    "--bind-ip" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --bind-ip".to_string())?;
-                        bind_ip = next.clone();
-                        i += 2;
-                    }
-                    "--codec" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --codec".to_string())?;
-                        let next_lc = next.to_ascii_lowercase();
-                        if next_lc != "h264" && next_lc != "h265" {
-                            return Err(format!("invalid --codec value: {next} (expected h264 or h265)"));
-                        }
-                        codec = next_lc;
-                        i += 2;
-                    }
-                    "--port" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --port".to_string())?;
-                        port = next
-                            .parse::<u16>()
-                            .map_err(|_| format!("invalid --port value: {next}"))?;
-                        i += 2;
-                    }
-                    "--payload" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --payload".to_string())?;
-                        payload = next
-                            .parse::<u8>()
-                            .map_err(|_| format!("invalid --payload value: {next}"))?;
-                        i += 2;
-                    }
-                    "--clock-rate" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --clock-rate".to_string())?;
-                        clock_rate = next
-                            .parse::<u32>()
-                            .map_err(|_| format!("invalid --clock-rate value: {next}"))?;
-                        i += 2;
-                    }
-                    "--latency-ms" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --latency-ms".to_string())?;
-                        latency_ms = next
-                            .parse::<u32>()
-                            .map_err(|_| format!("invalid --latency-ms value: {next}"))?;
-                        i += 2;
-                    }
-                    "--no-preview" => {
-                        no_preview = true;
-                        i += 1;
-                    }
-                    "--preview-width" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --preview-width".to_string())?;
-                        let val = next
-                            .parse::<u32>()
-                            .map_err(|_| format!("invalid --preview-width value: {next}"))?;
-                        if val == 0 {
-                            return Err("--preview-width must be > 0".to_string());
-                        }
-                        preview_width = Some(val);
-                        i += 2;
-                    }
-                    "--preview-height" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --preview-height".to_string())?;
-                        let val = next
-                            .parse::<u32>()
-                            .map_err(|_| format!("invalid --preview-height value: {next}"))?;
-                        if val == 0 {
-                            return Err("--preview-height must be > 0".to_string());
-                        }
-                        preview_height = Some(val);
-                        i += 2;
-                    }
-                    "--v4l2-device" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --v4l2-device".to_string())?;
-                        v4l2_device = Some(next.clone());
-                        i += 2;
-                    }
-                    "--v4l2-width" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --v4l2-width".to_string())?;
-                        let val = next
-                            .parse::<u32>()
-                            .map_err(|_| format!("invalid --v4l2-width value: {next}"))?;
-                        if val == 0 {
-                            return Err("--v4l2-width must be > 0".to_string());
-                        }
-                        v4l2_width = Some(val);
-                        i += 2;
-                    }
-                    "--v4l2-height" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --v4l2-height".to_string())?;
-                        let val = next
-                            .parse::<u32>()
-                            .map_err(|_| format!("invalid --v4l2-height value: {next}"))?;
-                        if val == 0 {
-                            return Err("--v4l2-height must be > 0".to_string());
-                        }
-                        v4l2_height = Some(val);
-                        i += 2;
-                    }
-                    "--v4l2-fps" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --v4l2-fps".to_string())?;
-                        let val = next
-                            .parse::<u32>()
-                            .map_err(|_| format!("invalid --v4l2-fps value: {next}"))?;
-                        if val == 0 {
-                            return Err("--v4l2-fps must be > 0".to_string());
-                        }
-                        v4l2_fps = Some(val);
-                        i += 2;
-                    }
-                    other => return Err(format!("unknown argument: {other}")),
-                }
-            }
-
-            if no_preview && v4l2_device.is_none() {
-                return Err(
-                    "nothing to do: provide preview or --v4l2-device when using --no-preview"
-                        .to_string(),
-                );
-            }
-
-            Ok(Cli::Receive {
-                codec,
-                bind_ip,
-                port,
-                payload,
-                clock_rate,
-                latency_ms,
-                no_preview,
-                preview_width,
-                preview_height,
-                v4l2_device,
-                v4l2_width,
-                v4l2_height,
-                v4l2_fps,
-            })
-        }
-        other => Err(format!("unknown command: {other}")),
-    }
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print a setting.
+    Get { key: String },
+    /// Change a setting after checking it like the matching flag.
+    Set { key: String, value: String },
+    /// Put a setting back to its default.
+    Unset { key: String },
 }
 
-fn run_receive(
-    codec: &str,
-    bind_ip: &str,
+/// `receive` and `replay` flags; `run-saved` fills it from the config instead.
+#[derive(clap::Args)]
+#[command(group(clap::ArgGroup::new("output").multiple(true)))]
+struct ReceiveCfg {
+    /// Codec the sender encodes with.
+    #[arg(
+        long,
+        value_name = "h264|h265|av1|vp9|mjpeg",
+        env = "VP_RCVR_CODEC",
+        default_value = "h265",
+        value_parser = parse_codec
+    )]
+    codec: String,
+    /// Decoder to use; auto tries VA-API, then NVDEC, then software.
+    #[arg(
+        long,
+        value_name = "auto|vaapi|nvdec|software",
+        env = "VP_RCVR_DECODER",
+        default_value = "auto",
+        value_parser = parse_decoder
+    )]
+    decoder: String,
+    /// Address to listen on.
+    #[arg(
+        long,
+        value_name = "IP",
+        env = "VP_RCVR_BIND_IP",
+        default_value = "0.0.0.0"
+    )]
+    bind_ip: String,
+    /// RTP port; the sender's RTCP, audio and stats use the next three.
+    #[arg(long, value_name = "N", env = "VP_RCVR_PORT", default_value_t = 5000)]
     port: u16,
+    /// RTP payload type.
+    #[arg(long, value_name = "N", env = "VP_RCVR_PAYLOAD", default_value_t = 96)]
     payload: u8,
+    /// RTP clock rate.
+    #[arg(
+        long,
+        value_name = "N",
+        env = "VP_RCVR_CLOCK_RATE",
+        default_value_t = 90_000,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
     clock_rate: u32,
+    /// Jitter-buffer latency.
+    #[arg(
+        long,
+        value_name = "N",
+        env = "VP_RCVR_LATENCY_MS",
+        default_value_t = 25
+    )]
     latency_ms: u32,
-    preview: bool,
+    /// No preview window; needs --v4l2-device or --virtual-camera.
+    #[arg(long, env = "VP_RCVR_NO_PREVIEW", requires = "output")]
+    no_preview: bool,
+    /// Preview window width.
+    #[arg(long, value_name = "N", env = "VP_RCVR_PREVIEW_WIDTH", value_parser = clap::value_parser!(u32).range(1..))]
     preview_width: Option<u32>,
+    /// Preview window height.
+    #[arg(long, value_name = "N", env = "VP_RCVR_PREVIEW_HEIGHT", value_parser = clap::value_parser!(u32).range(1..))]
     preview_height: Option<u32>,
-    v4l2_device: Option<&str>,
+    /// Where to put the preview on its monitor (X11 only).
+    #[arg(
+        long,
+        value_name = "top-left|top-right|bottom-left|bottom-right|center|X,Y",
+        env = "VP_RCVR_PREVIEW_POSITION",
+        value_parser = parse_preview_position
+    )]
+    preview_position: Option<String>,
+    /// Monitor index from `xrandr --listactivemonitors`.
+    #[arg(long, value_name = "N", env = "VP_RCVR_PREVIEW_MONITOR")]
+    preview_monitor: Option<u32>,
+    /// Keep the preview above other windows.
+    #[arg(long, env = "VP_RCVR_PREVIEW_ON_TOP")]
+    preview_on_top: bool,
+    /// Show bitrate, fps, jitter, loss and uptime in the preview.
+    #[arg(long, env = "VP_RCVR_OSD", conflicts_with = "no_preview")]
+    osd: bool,
+    /// Show color bars on the preview and V4L2 outputs while no stream comes in.
+    #[arg(long, env = "VP_RCVR_STANDBY")]
+    standby: bool,
+    /// V4L2 loopback device to write frames to, for OBS, or auto for a free one.
+    #[arg(
+        long,
+        value_name = "/dev/videoN",
+        env = "VP_RCVR_V4L2_DEVICE",
+        group = "output"
+    )]
+    v4l2_device: Option<String>,
+    /// Load v4l2loopback (with sudo -n) when --v4l2-device auto finds no device.
+    #[arg(long, env = "VP_RCVR_V4L2_MODPROBE", requires = "v4l2_device")]
+    v4l2_modprobe: bool,
+    /// Force the loopback width.
+    #[arg(long, value_name = "N", env = "VP_RCVR_V4L2_WIDTH", value_parser = clap::value_parser!(u32).range(1..))]
     v4l2_width: Option<u32>,
+    /// Force the loopback height.
+    #[arg(long, value_name = "N", env = "VP_RCVR_V4L2_HEIGHT", value_parser = clap::value_parser!(u32).range(1..))]
     v4l2_height: Option<u32>,
+    /// Force the loopback frame rate.
+    #[arg(long, value_name = "N", env = "VP_RCVR_V4L2_FPS", value_parser = clap::value_parser!(u32).range(1..))]
     v4l2_fps: Option<u32>,
-) -> ExitCode {
-    let (encoding_name, depay_parse, decode_chain) = match codec {
-        "h264" => ("H264", "rtph264depay ! h264parse", "decodebin"),
-        "h265" => (
-            "H265",
-            "rtph265depay ! h265parse",
-            "nvh265dec ! cudadownload ! videoconvert",
-        ),
-        other => {
-            eprintln!("FAIL: unsupported codec '{other}'");
-            return ExitCode::from(2);
-        }
-    };
-    let caps = format!(
-        "application/x-rtp,media=video,encoding-name={encoding_name},payload={payload},clock-rate={clock_rate}"
-    );
+    /// Offer the stream as a PipeWire camera, for portals and browsers.
+    #[arg(long, env = "VP_RCVR_VIRTUAL_CAMERA", group = "output")]
+    virtual_camera: bool,
+    /// Share the decoded frames with local processes through shmsink on this socket.
+    #[arg(
+        long,
+        value_name = "PATH",
+        env = "VP_RCVR_SHM_SOCKET",
+        group = "output"
+    )]
+    shm_socket: Option<String>,
+    /// Act when no packet arrives for this long.
+    #[arg(long, value_name = "N", env = "VP_RCVR_IDLE_TIMEOUT_SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    idle_timeout_secs: Option<u64>,
+    /// What to do on an idle timeout.
+    #[arg(
+        long,
+        value_name = "exit|placeholder|restart",
+        env = "VP_RCVR_ON_TIMEOUT",
+        default_value = "exit",
+        value_parser = parse_on_timeout,
+        requires = "idle_timeout_secs"
+    )]
+    on_timeout: String,
+    /// What to do when the decoder fails.
+    #[arg(
+        long,
+        value_name = "fallback|restart|exit",
+        env = "VP_RCVR_ON_DECODE_ERROR",
+        default_value = "fallback",
+        value_parser = parse_on_decode_error
+    )]
+    on_decode_error: String,
+    /// Restart the pipeline when packets arrive but no frame is decoded for this long.
+    #[arg(long, value_name = "N", env = "VP_RCVR_STALL_TIMEOUT_SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    stall_timeout_secs: Option<u64>,
+    /// Decrypt the stream with this pre-shared key.
+    #[arg(long, value_name = "HEX", env = "VP_RCVR_SRTP_KEY", value_parser = parse_srtp_key_arg)]
+    srtp_key: Option<String>,
+    /// NACK lost packets so the sender resends them.
+    #[arg(long, env = "VP_RCVR_RTX")]
+    rtx: bool,
+    /// RTP over UDP, or MPEG-TS over SRT from vp-sndr --transport srt.
+    #[arg(
+        long,
+        value_name = "udp|srt",
+        env = "VP_RCVR_TRANSPORT",
+        default_value = "udp",
+        value_parser = parse_transport
+    )]
+    transport: String,
+    /// SRT connection direction; a listener waits for a sender in caller mode.
+    #[arg(
+        long,
+        value_name = "listener|caller",
+        env = "VP_RCVR_SRT_MODE",
+        default_value = "listener",
+        value_parser = parse_srt_mode
+    )]
+    srt_mode: String,
+    /// SRT retransmission window; the larger of this and the sender's is used.
+    #[arg(
+        long,
+        alias = "srt-latency",
+        value_name = "N",
+        env = "VP_RCVR_SRT_LATENCY_MS",
+        default_value_t = DEFAULT_SRT_LATENCY_MS
+    )]
+    srt_latency_ms: u32,
+    /// Decrypt the SRT stream; the sender's --srt-passphrase.
+    #[arg(
+        long,
+        alias = "passphrase",
+        value_name = "P",
+        env = "VP_RCVR_SRT_PASSPHRASE",
+        value_parser = parse_srt_passphrase
+    )]
+    srt_passphrase: Option<String>,
+    /// Sender to connect to with --srt-mode caller.
+    #[arg(long, value_name = "IP", env = "VP_RCVR_SENDER_IP")]
+    sender_ip: Option<String>,
+    /// Pull the stream from an RTSP server or camera instead of listening for it.
+    #[arg(long, value_name = "URL", env = "VP_RCVR_RTSP_URL", value_parser = parse_rtsp_url)]
+    rtsp_url: Option<String>,
+    /// Pull the stream from a WHEP (WebRTC-HTTP egress) endpoint.
+    #[arg(long, value_name = "URL", env = "VP_RCVR_WHEP_URL", value_parser = parse_whep_url)]
+    whep_url: Option<String>,
+    /// Bearer token for the --whep-url endpoint (loopback URLs only).
+    #[arg(long, value_name = "TOKEN", env = "VP_RCVR_WHEP_TOKEN")]
+    whep_token: Option<String>,
+    /// Play the sender's Opus audio.
+    #[arg(long, env = "VP_RCVR_WITH_AUDIO")]
+    with_audio: bool,
+    /// Jitterbuffer latency of the audio stream (default: --latency-ms).
+    #[arg(
+        long,
+        value_name = "N",
+        env = "VP_RCVR_AUDIO_LATENCY_MS",
+        requires = "with_audio"
+    )]
+    audio_latency_ms: Option<u32>,
+    /// Hold the audio back this long to line it up with the video.
+    #[arg(
+        long,
+        value_name = "N",
+        env = "VP_RCVR_AUDIO_DELAY_MS",
+        requires = "with_audio"
+    )]
+    audio_delay_ms: Option<u32>,
+    /// Play the audio on the default output, or offer it as a PipeWire microphone.
+    #[arg(
+        long,
+        value_name = "default|virtual-mic",
+        env = "VP_RCVR_AUDIO_OUTPUT",
+        default_value = "default",
+        value_parser = parse_audio_output,
+        requires = "with_audio"
+    )]
+    audio_output: String,
+    /// Log capture-to-decode latency from the sender's timestamps (needs NTP-synced clocks).
+    #[arg(long, env = "VP_RCVR_MEASURE_LATENCY")]
+    measure_latency: bool,
+    /// Keep decoded frames at 10 bits up to the preview and V4L2 conversions.
+    #[arg(
+        long,
+        value_name = "8|10",
+        env = "VP_RCVR_BIT_DEPTH",
+        default_value = "8",
+        value_parser = parse_bit_depth
+    )]
+    bit_depth: u32,
+    /// Color matrix and primaries of the V4L2 output (match the sender's --colorimetry).
+    #[arg(long, value_name = "bt709|bt601|bt2020", env = "VP_RCVR_COLORIMETRY", value_parser = ["bt709", "bt601", "bt2020"])]
+    colorimetry: Option<String>,
+    /// Full-range (0-255) or limited-range (16-235) V4L2 output.
+    #[arg(long, value_name = "full|limited", env = "VP_RCVR_COLOR_RANGE", value_parser = ["full", "limited"])]
+    color_range: Option<String>,
+    /// Serve Prometheus metrics on this address.
+    #[arg(long, value_name = "ADDR", env = "VP_RCVR_METRICS_ADDR", value_parser = parse_socket_addr)]
+    metrics_addr: Option<String>,
+    /// Extra elements for the encoded stream between the depayloader and the decoder.
+    #[arg(long, value_name = "FRAGMENT", env = "VP_RCVR_INSERT_AFTER_DEPAY", value_parser = parse_pipeline_fragment)]
+    insert_after_depay: Option<String>,
+    /// Extra elements for the decoded frames ahead of the preview and V4L2 sinks,
+    /// e.g. "gamma gamma=1.2".
+    #[arg(long, value_name = "FRAGMENT", env = "VP_RCVR_INSERT_AFTER_DECODE", value_parser = parse_pipeline_fragment)]
+    insert_after_decode: Option<String>,
+    /// Also write the received stream, as sent, to this Matroska file.
+    #[arg(long, value_name = "PATH", env = "VP_RCVR_RECORD")]
+    record: Option<String>,
+    /// Also send the stream to an RTMP(S) or SRT server, e.g. rtmp://host/app/KEY.
+    #[arg(long, value_name = "URL", env = "VP_RCVR_RESTREAM", value_parser = parse_restream_url)]
+    restream: Option<String>,
+    /// Re-encode the restream as H.264 at this bitrate instead of passing it through.
+    #[arg(
+        long,
+        value_name = "KBPS",
+        env = "VP_RCVR_RESTREAM_BITRATE_KBPS",
+        value_parser = clap::value_parser!(u32).range(1..),
+        requires = "restream"
+    )]
+    restream_bitrate_kbps: Option<u32>,
+    /// Serve the stream as HLS for browsers on this address, e.g. 0.0.0.0:8080.
+    #[arg(long, value_name = "ADDR", env = "VP_RCVR_HLS_ADDR", value_parser = parse_socket_addr)]
+    hls_addr: Option<String>,
+    /// Re-encode the HLS stream as H.264 at this bitrate instead of passing it through.
+    #[arg(
+        long,
+        value_name = "KBPS",
+        env = "VP_RCVR_HLS_BITRATE_KBPS",
+        value_parser = clap::value_parser!(u32).range(1..),
+        requires = "hls_addr"
+    )]
+    hls_bitrate_kbps: Option<u32>,
+    /// Extra output for the decoded stream; repeat for more.
+    #[arg(
+        long = "sink",
+        value_name = "kind=preview|v4l2|record[,device=DEV][,path=PATH][,w=N,h=N][,fps=N][,format=F]",
+        value_parser = parse_sink,
+        group = "output"
+    )]
+    sinks: Vec<SinkSpec>,
+    /// Where snapshots (S in the preview, SIGUSR1, `vp-rcvr snapshot`) are saved
+    /// (default: the Pictures directory).
+    #[arg(long, value_name = "DIR", env = "VP_RCVR_SNAPSHOT_DIR")]
+    snapshot_dir: Option<String>,
+    /// Print the pipeline and exit without receiving or saving the settings.
+    #[arg(long, env = "VP_RCVR_DRY_RUN")]
+    dry_run: bool,
+}
 
-    let mut pipeline = format!(
-        "udpsrc address={bind_ip} port={port} buffer-size=4194304 caps=\"{caps}\" ! \
-         queue ! rtpjitterbuffer latency={latency_ms} drop-on-latency=true ! \
-         {depay_parse} ! {decode_chain} ! tee name=t"
-    );
+/// `tile` flags: several senders, one window.
+#[derive(clap::Args)]
+struct TileCfg {
+    /// RTP port of a stream and its label in the grid; repeat for each sender.
+    #[arg(
+        long = "stream",
+        value_name = "PORT[=LABEL]",
+        required = true,
+        value_parser = parse_tile_stream
+    )]
+    streams: Vec<TileStream>,
+    /// Codec the senders encode with.
+    #[arg(
+        long,
+        value_name = "h264|h265|av1|vp9|mjpeg",
+        env = "VP_RCVR_CODEC",
+        default_value = "h265",
+        value_parser = parse_codec
+    )]
+    codec: String,
+    /// Decoder to use; auto tries VA-API, then NVDEC, then software.
+    #[arg(
+        long,
+        value_name = "auto|vaapi|nvdec|software",
+        env = "VP_RCVR_DECODER",
+        default_value = "auto",
+        value_parser = parse_decoder
+    )]
+    decoder: String,
+    /// Address to listen on.
+    #[arg(
+        long,
+        value_name = "IP",
+        env = "VP_RCVR_BIND_IP",
+        default_value = "0.0.0.0"
+    )]
+    bind_ip: String,
+    /// RTP payload type.
+    #[arg(long, value_name = "N", env = "VP_RCVR_PAYLOAD", default_value_t = 96)]
+    payload: u8,
+    /// Jitter-buffer latency of each stream.
+    #[arg(
+        long,
+        value_name = "N",
+        env = "VP_RCVR_LATENCY_MS",
+        default_value_t = 25
+    )]
+    latency_ms: u32,
+    /// Tiles per row (default: as square a grid as fits the streams).
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    columns: Option<u32>,
+    /// Width of each tile; streams are scaled into it with black bars.
+    #[arg(long, value_name = "N", default_value_t = 960, value_parser = clap::value_parser!(u32).range(1..))]
+    tile_width: u32,
+    /// Height of each tile.
+    #[arg(long, value_name = "N", default_value_t = 540, value_parser = clap::value_parser!(u32).range(1..))]
+    tile_height: u32,
+}
 
-    if preview {
-        let mut preview_scale_caps = String::new();
-        if preview_width.is_some() || preview_height.is_some() {
-            preview_scale_caps.push_str(" ! videoscale ! video/x-raw");
-            if let Some(w) = preview_width {
-                preview_scale_caps.push_str(&format!(",width={w}"));
-            }
-            if let Some(h) = preview_height {
-                preview_scale_caps.push_str(&format!(",height={h}"));
-            }
-        }
-        pipeline.push_str(&format!(
-            " t. ! queue ! videoconvert{} ! fpsdisplaysink text-overlay=false video-sink=autovideosink sync=false",
-            preview_scale_caps
+#[derive(Clone)]
+struct TileStream {
+    port: u16,
+    label: String,
+}
+
+fn parse_codec(value: &str) -> Result<String, String> {
+    let codec = value.to_ascii_lowercase();
+    match codec.as_str() {
+        "h264" | "h265" | "av1" | "vp9" | "mjpeg" => Ok(codec),
+        _ => Err(format!(
+            "invalid codec: {value} (expected h264, h265, av1, vp9 or mjpeg)"
+        )),
+    }
+}
+
+fn parse_bit_depth(value: &str) -> Result<u32, String> {
+    match value {
+        "8" => Ok(8),
+        "10" => Ok(10),
+        _ => Err(format!("invalid bit depth: {value} (expected 8 or 10)")),
+    }
+}
+
+/// The decoder select_decoder picked, after warning when the --decoder kind asked
+/// for is not installed.
+fn warn_missing_decoder(
+    requested: &str,
+    (decoder, missing): (&'static str, Option<&'static str>),
+) -> &'static str {
+    if let Some(missing) = missing {
+        eprintln!(
+            "WARN: --decoder {requested} needs {missing}, which is not available; trying the others"
+        );
+    }
+    decoder
+}
+
+/// H.264 and MJPEG streams from vp-sndr are always 8-bit.
+fn check_bit_depth(codec: &str, bit_depth: u32) -> Result<(), String> {
+    if bit_depth == 10 && !matches!(codec, "h265" | "av1" | "vp9") {
+        return Err(format!(
+            "--bit-depth 10 needs --codec h265, av1 or vp9, not {codec}"
         ));
     }
+    Ok(())
+}
 
-    if let Some(device) = v4l2_device {
-        let mut v4l2_caps = String::from("video/x-raw,format=I420");
-        if let Some(w) = v4l2_width {
-            v4l2_caps.push_str(&format!(",width={w}"));
+fn parse_latency_value(value: &str) -> Result<String, String> {
+    parse_latency_adjust(value).map(|_| value.to_string())
+}
+
+fn parse_socket_addr(value: &str) -> Result<String, String> {
+    value
+        .parse::<std::net::SocketAddr>()
+        .map(|_| value.to_string())
+        .map_err(|_| format!("invalid address: {value} (expected IP:PORT)"))
+}
+
+/// Builds the fragment on its own, so a typo is reported against the flag instead
+/// of as a parse error somewhere in the whole receive pipeline.
+fn parse_pipeline_fragment(value: &str) -> Result<String, String> {
+    gst::init().map_err(|e| format!("gstreamer init failed: {e}"))?;
+    gst::parse::bin_from_description(value, true)
+        .map_err(|e| format!("invalid pipeline fragment \"{value}\": {e}"))?;
+    Ok(value.trim().to_string())
+}
+
+fn parse_srtp_key_arg(value: &str) -> Result<String, String> {
+    parse_srtp_key(value)?;
+    Ok(value.to_ascii_lowercase())
+}
+
+fn parse_transport(value: &str) -> Result<String, String> {
+    let transport = value.to_ascii_lowercase();
+    match transport.as_str() {
+        "udp" | "srt" => Ok(transport),
+        _ => Err(format!(
+            "invalid --transport value: {value} (expected udp or srt)"
+        )),
+    }
+}
+
+fn parse_srt_mode(value: &str) -> Result<String, String> {
+    let mode = value.to_ascii_lowercase();
+    match mode.as_str() {
+        "listener" | "caller" => Ok(mode),
+        _ => Err(format!(
+            "invalid --srt-mode value: {value} (expected listener or caller)"
+        )),
+    }
+}
+
+fn parse_rtsp_url(value: &str) -> Result<String, String> {
+    if ["rtsp://", "rtsps://", "rtspt://"]
+        .iter()
+        .any(|scheme| value.starts_with(scheme))
+    {
+        Ok(value.to_string())
+    } else {
+        Err(format!(
+            "invalid --rtsp-url: {value} (expected rtsp://, rtsps:// or rtspt://)"
+        ))
+    }
+}
+
+fn parse_whep_url(value: &str) -> Result<String, String> {
+    whep::split_url(value).map(|_| value.to_string())
+}
+
+fn parse_srt_passphrase(value: &str) -> Result<String, String> {
+    if !(10..=79).contains(&value.len()) {
+        return Err("--srt-passphrase must be 10 to 79 characters".to_string());
+    }
+    Ok(value.to_string())
+}
+
+fn parse_on_timeout(value: &str) -> Result<String, String> {
+    let action = value.to_ascii_lowercase();
+    match action.as_str() {
+        "exit" | "placeholder" | "restart" => Ok(action),
+        _ => Err(format!(
+            "invalid --on-timeout value: {value} (expected exit, placeholder, or restart)"
+        )),
+    }
+}
+
+fn parse_decoder(value: &str) -> Result<String, String> {
+    let decoder = value.to_ascii_lowercase();
+    match decoder.as_str() {
+        "auto" | "vaapi" | "nvdec" | "software" => Ok(decoder),
+        _ => Err(format!(
+            "invalid --decoder value: {value} (expected auto, vaapi, nvdec, or software)"
+        )),
+    }
+}
+
+fn parse_on_decode_error(value: &str) -> Result<String, String> {
+    let action = value.to_ascii_lowercase();
+    match action.as_str() {
+        "fallback" | "restart" | "exit" => Ok(action),
+        _ => Err(format!(
+            "invalid --on-decode-error value: {value} (expected fallback, restart, or exit)"
+        )),
+    }
+}
+
+fn parse_audio_output(value: &str) -> Result<String, String> {
+    let output = value.to_ascii_lowercase();
+    match output.as_str() {
+        "default" | "virtual-mic" => Ok(output),
+        _ => Err(format!(
+            "invalid --audio-output value: {value} (expected default or virtual-mic)"
+        )),
+    }
+}
+
+/// Parses `kind=preview|v4l2|record` with `device=DEV` (v4l2), `path=PATH` (record),
+/// and `w=N,h=N,fps=N,format=F` for the preview and v4l2 kinds.
+fn parse_sink(value: &str) -> Result<SinkSpec, String> {
+    let mut spec = SinkSpec::default();
+    for part in value.split(',').filter(|part| !part.is_empty()) {
+        let (key, val) = part
+            .split_once('=')
+            .ok_or_else(|| format!("invalid --sink value: {value} (expected key=value pairs)"))?;
+        let number = |val: &str| {
+            val.parse::<u32>()
+                .map_err(|_| format!("invalid --sink {key} value: {val}"))
+        };
+        match key {
+            "kind" => spec.kind = val.to_ascii_lowercase(),
+            "device" => spec.device = Some(val.to_string()),
+            "path" => spec.path = Some(val.to_string()),
+            "w" | "width" => spec.width = Some(number(val)?),
+            "h" | "height" => spec.height = Some(number(val)?),
+            "fps" => spec.fps = Some(number(val)?),
+            "format" => spec.format = Some(val.to_string()),
+            other => return Err(format!("unknown --sink key: {other}")),
         }
-        if let Some(h) = v4l2_height {
-            v4l2_caps.push_str(&format!(",height={h}"));
+    }
+    check_sink(&spec).map_err(|err| format!("--sink {value}: {err}"))?;
+    Ok(spec)
+}
+
+fn check_sink(spec: &SinkSpec) -> Result<(), String> {
+    match spec.kind.as_str() {
+        "preview" | "record" | "v4l2" => {}
+        "" => return Err("sink needs kind=preview, v4l2 or record".to_string()),
+        other => {
+            return Err(format!(
+                "invalid sink kind: {other} (expected preview, v4l2 or record)"
+            ));
         }
-        if let Some(fps) = v4l2_fps {
-            v4l2_caps.push_str(&format!(",framerate={fps}/1"));
+    }
+    if (spec.kind == "v4l2") != spec.device.is_some() {
+        return Err("device is for, and required by, v4l2 sinks".to_string());
+    }
+    if spec.device.as_deref() == Some("auto") {
+        return Err("v4l2 sinks need a fixed device; auto is only for --v4l2-device".to_string());
+    }
+    if (spec.kind == "record") != spec.path.is_some() {
+        return Err("path is for, and required by, record sinks".to_string());
+    }
+    // The recording is the stream as sent, so there is nothing to scale or convert.
+    if spec.kind == "record"
+        && (spec.width.is_some()
+            || spec.height.is_some()
+            || spec.fps.is_some()
+            || spec.format.is_some())
+    {
+        return Err("record sinks store the stream as sent; drop w/h/fps/format".to_string());
+    }
+    for (key, value) in [("w", spec.width), ("h", spec.height), ("fps", spec.fps)] {
+        if value == Some(0) {
+            return Err(format!("sink {key} must be > 0"));
         }
-        pipeline.push_str(&format!(
-            " t. ! queue ! videoconvert ! {} ! v4l2sink device={} io-mode=rw sync=false",
-            v4l2_caps, device
-        ));
     }
+    Ok(())
+}
 
-    println!("Starting {} receiver on {}:{}...", encoding_name, bind_ip, port);
-    println!("Pipeline: {}", pipeline);
+/// Caps of a preview or v4l2 sink: its own size, rate and format, plus COLORIMETRY
+/// for loopback devices.
+fn sink_caps(spec: &SinkSpec, colorimetry: &str) -> String {
+    let mut caps = String::from("video/x-raw");
+    // Loopback readers want a fixed format; the preview takes whatever it gets.
+    match (&spec.format, spec.kind.as_str()) {
+        (Some(format), _) => caps.push_str(&format!(",format={format}")),
+        (None, "v4l2") => caps.push_str(",format=I420"),
+        _ => {}
+    }
+    if let Some(w) = spec.width {
+        caps.push_str(&format!(",width={w}"));
+    }
+    if let Some(h) = spec.height {
+        caps.push_str(&format!(",height={h}"));
+    }
+    if let Some(fps) = spec.fps {
+        caps.push_str(&format!(",framerate={fps}/1"));
+    }
+    if spec.device.is_some() {
+        caps.push_str(colorimetry);
+    }
+    caps
+}
 
-    let cmd = format!("gst-launch-1.0 -e -v {pipeline}");
-    let status = Command::new("bash")
-        .args(["-lc", &cmd])
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .status();
+/// The tee branch of a preview or v4l2 sink; the sink element is named NAME so
+/// restarts keep it open.
+fn sink_branch(spec: &SinkSpec, name: &str, caps: &str) -> String {
+    let sink = match spec.device.as_deref() {
+        Some(device) => format!("v4l2sink name={name} device={device} io-mode=rw sync=false"),
+        None => format!("autovideosink name={name} sync=false"),
+    };
+    format!(" t. ! queue ! videoconvert ! videoscale ! videorate ! {caps} ! {sink}")
+}
 
-    match status {
-        Ok(s) if s.success() => ExitCode::SUCCESS,
-        Ok(s) => {
-            eprintln!(
-                "FAIL: gst-launch-1.0 exited with code {}",
-                s.code().unwrap_or(-1)
-            );
-            ExitCode::from(1)
-        }
-        Err(err) => {
-            eprintln!("FAIL: could not start gst-launch-1.0: {err}");
-            ExitCode::from(1)
-        }
-    }
-}
-
-fn print_help() {
-    println!("vp-rcvr: HEVC viewport receiver");
-    println!();
-    println!("Usage:");
-    println!("  vp-rcvr receive [--codec h264|h265] [--bind-ip IP] [--port N] [--payload N] [--clock-rate N] [--latency-ms N] [--no-preview] [--preview-width N] [--preview-height N] [--v4l2-device /dev/videoN] [--v4l2-width N] [--v4l2-height N] [--v4l2-fps N]");
-    println!("  vp-rcvr tray");
-    println!("  vp-rcvr config");
-    println!("  vp-rcvr run-saved");
-    println!();
-    println!("Examples:");
-    println!("  vp-rcvr receive --port 5000");
-    println!("  vp-rcvr receive --port 5000 --preview-width 1280 --preview-height 720");
-    println!("  vp-rcvr receive --port 5000 --v4l2-device /dev/video10");
-    println!("  vp-rcvr receive --port 5000 --no-preview --v4l2-device /dev/video10");
-    println!("  vp-rcvr receive --codec h264 --port 5000 --no-preview --v4l2-device /dev/video10 --v4l2-width 1280 --v4l2-height 720 --v4l2-fps 60");
-    println!("  vp-rcvr tray");
-    println!("  vp-rcvr config");
-    println!("  vp-rcvr run-saved");
+fn parse_restream_url(value: &str) -> Result<String, String> {
+    if ["rtmp://", "rtmps://", "srt://"]
+        .iter()
+        .any(|scheme| value.starts_with(scheme))
+    {
+        Ok(value.to_string())
+    } else {
+        Err(format!(
+            "invalid --restream URL: {value} (expected rtmp://, rtmps:// or srt://)"
+        ))
+    }
+}
+
+/// The head of a branch that feeds a muxer, with its queue named NAME: the parsed
+/// stream from the `rec` tee as it came in, when the container takes CODEC (H.264,
+/// and H.265 with ALLOW_H265), or the decoded frames encoded again as H.264 at
+/// BITRATE_KBPS. The parsers repeat SPS/PPS before every keyframe, so a server or
+/// player that joins mid-GOP can start at the next one.
+fn mux_feed(
+    name: &str,
+    codec: &str,
+    bitrate_kbps: Option<u32>,
+    allow_h265: bool,
+) -> Option<String> {
+    match (bitrate_kbps, codec) {
+        (Some(kbps), _) => Some(format!(
+            " t. ! queue name={name} leaky=downstream max-size-buffers=2 ! videoconvert ! \
+             x264enc tune=zerolatency speed-preset=veryfast bitrate={kbps} key-int-max={REENCODE_KEYINT} ! \
+             video/x-h264,profile=high ! h264parse config-interval=-1"
+        )),
+        (None, "h264") => Some(format!(
+            " rec. ! queue name={name} ! h264parse config-interval=-1"
+        )),
+        (None, "h265") if allow_h265 => Some(format!(
+            " rec. ! queue name={name} ! h265parse config-interval=-1"
+        )),
+        _ => None,
+    }
+}
+
+/// `--restream`: the tee branch that hands the stream to the `restreamout` appsink,
+/// and the description of the pipeline that muxes and sends it to URL (see
+/// [`Restream`]). FLV (RTMP) only carries H.264 as received, MPEG-TS (SRT) H.264 and
+/// H.265.
+fn restream_branch(
+    url: &str,
+    codec: &str,
+    bitrate_kbps: Option<u32>,
+) -> Result<(String, String), String> {
+    let srt = url.starts_with("srt://");
+    let feed = mux_feed("restreamq", codec, bitrate_kbps, srt).ok_or_else(|| {
+        format!(
+            "{} cannot carry {codec} as received; pass --restream-bitrate-kbps to re-encode it as H.264",
+            if srt { "SRT (MPEG-TS)" } else { "RTMP (FLV)" }
+        )
+    })?;
+    let branch = format!("{feed} ! appsink name=restreamout sync=false async=false");
+    // The buffers keep the receive pipeline's timestamps, so the sink does not sync
+    // them to a clock of its own. A connection that cannot keep up loses the oldest
+    // RESTREAM_QUEUE_BUFFERS onwards rather than piling them up.
+    let src = format!(
+        "appsrc name=restreamsrc is-live=true format=time leaky-type=downstream max-buffers={RESTREAM_QUEUE_BUFFERS}"
+    );
+    let url = url.replace('"', "\\\"");
+    let sender = if srt {
+        format!(
+            "{src} ! mpegtsmux name=restreammux alignment=7 ! srtsink name=restream uri=\"{url}\" wait-for-connection=false sync=false"
+        )
+    } else {
+        format!(
+            "{src} ! flvmux name=restreammux streamable=true ! rtmp2sink name=restream location=\"{url}\" sync=false"
+        )
+    };
+    Ok((branch, sender))
+}
+
+/// The `--restream` connection. The muxer and the sink run in a pipeline of their
+/// own, fed from the receive pipeline's `restreamout` appsink, so a dropped
+/// connection neither stalls the tee nor touches the other outputs: only this
+/// pipeline is stopped and, RESTREAM_RETRY_SECS later, started again.
+struct Restream {
+    pipeline: gst::Pipeline,
+    src: gst_app::AppSrc,
+    retry_at: Option<Instant>,
+}
+
+impl Restream {
+    fn start(receive: &gst::Pipeline, desc: &str) -> Result<Self, String> {
+        let pipeline = gst::parse::launch(desc)
+            .map_err(|e| format!("could not build restream pipeline: {e}"))?
+            .downcast::<gst::Pipeline>()
+            .map_err(|_| "restream pipeline is not a gst::Pipeline".to_string())?;
+        let src = pipeline
+            .by_name("restreamsrc")
+            .and_then(|src| src.downcast::<gst_app::AppSrc>().ok())
+            .ok_or_else(|| "restream pipeline has no appsrc".to_string())?;
+        let out = receive
+            .by_name("restreamout")
+            .and_then(|sink| sink.downcast::<gst_app::AppSink>().ok())
+            .ok_or_else(|| "receive pipeline has no restreamout appsink".to_string())?;
+        let feed = src.clone();
+        out.set_callbacks(
+            gst_app::AppSinkCallbacks::builder()
+                .new_sample(move |sink| {
+                    let sample = sink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                    // Refused while the connection is down; the receive pipeline
+                    // carries on either way.
+                    let _ = feed.push_sample(&sample);
+                    Ok(gst::FlowSuccess::Ok)
+                })
+                .build(),
+        );
+        let mut restream = Restream {
+            pipeline,
+            src,
+            retry_at: None,
+        };
+        if let Err(err) = restream.connect() {
+            restream.failed(&err);
+        }
+        Ok(restream)
+    }
+
+    fn connect(&mut self) -> Result<(), String> {
+        let _ = self.pipeline.set_state(gst::State::Null);
+        // The fresh muxer needs the stream caps again; the next sample brings them.
+        self.src.set_caps(None::<&gst::Caps>);
+        self.pipeline
+            .set_state(gst::State::Playing)
+            .map(|_| ())
+            .map_err(|_| "could not start restream pipeline".to_string())
+    }
+
+    fn failed(&mut self, err: &str) {
+        let _ = self.pipeline.set_state(gst::State::Null);
+        if self.retry_at.is_none() {
+            eprintln!("WARN: restream failed: {err}; reconnecting in {RESTREAM_RETRY_SECS}s");
+            self.retry_at = Some(Instant::now() + Duration::from_secs(RESTREAM_RETRY_SECS));
+        }
+    }
+
+    /// Takes note of a dropped connection and reconnects once the pause is over.
+    /// True when it reconnected, so the caller can ask for a keyframe to start on.
+    fn poll(&mut self) -> bool {
+        if let Some(bus) = self.pipeline.bus()
+            && let Some(msg) = bus.pop_filtered(&[gst::MessageType::Error, gst::MessageType::Eos])
+        {
+            let err = match msg.view() {
+                gst::MessageView::Error(e) => e.error().to_string(),
+                _ => "the server closed the connection".to_string(),
+            };
+            self.failed(&err);
+        }
+        if !self.retry_at.is_some_and(|at| Instant::now() >= at) {
+            return false;
+        }
+        self.retry_at = None;
+        println!("Reconnecting restream...");
+        match self.connect() {
+            Ok(()) => true,
+            Err(err) => {
+                self.failed(&err);
+                false
+            }
+        }
+    }
+
+    fn stop(&self) {
+        let _ = self.pipeline.set_state(gst::State::Null);
+    }
+}
+
+/// `--hls-addr`: the tee branch that writes MPEG-TS segments and the playlist into
+/// DIR. Phone browsers play H.264 from MPEG-TS segments, not H.265.
+fn hls_branch(dir: &Path, codec: &str, bitrate_kbps: Option<u32>) -> Result<String, String> {
+    let feed = mux_feed("hlsq", codec, bitrate_kbps, false)
+        .ok_or_else(|| format!("HLS needs H.264; pass --hls-bitrate-kbps to re-encode {codec}"))?;
+    let dir = dir.display().to_string().replace('"', "\\\"");
+    Ok(format!(
+        "{feed} ! hlssink2 name=hls location=\"{dir}/segment%05d.ts\" playlist-location=\"{dir}/{HLS_PLAYLIST}\" \
+         target-duration={HLS_TARGET_SECS} playlist-length={HLS_PLAYLIST_LENGTH} max-files={}",
+        HLS_PLAYLIST_LENGTH * 2
+    ))
+}
+
+/// `--hls-addr`: serves the player page, the playlist and the segments in DIR on a
+/// background thread.
+fn serve_hls(addr: &str, dir: PathBuf) -> Result<SocketAddr, String> {
+    let listener = TcpListener::bind(addr).map_err(|e| format!("bind {addr}: {e}"))?;
+    let local = listener
+        .local_addr()
+        .map_err(|e| format!("bind {addr}: {e}"))?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let dir = dir.clone();
+            // Segments take a moment over Wi-Fi; a thread each keeps the playlist
+            // answering for the other viewers.
+            thread::spawn(move || {
+                let _ = answer_hls(stream, &dir);
+            });
+        }
+    });
+    Ok(local)
+}
+
+fn answer_hls(mut stream: TcpStream, dir: &Path) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(HLS_READ_TIMEOUT_SECS)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers; nothing in them changes the answer.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && !header.trim_end().is_empty() {
+        header.clear();
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next();
+    let path = parts.next().unwrap_or("/");
+    let name = match path.split('?').next().unwrap_or("").trim_start_matches('/') {
+        "" => HLS_PAGE,
+        name => name,
+    };
+    let content_type = match Path::new(name).extension().and_then(|ext| ext.to_str()) {
+        Some("m3u8") => "application/vnd.apple.mpegurl",
+        Some("ts") => "video/mp2t",
+        Some("html") => "text/html; charset=utf-8",
+        _ => "",
+    };
+    // Only plain file names from the HLS directory.
+    let body = if method == Some("GET")
+        && !content_type.is_empty()
+        && !name.contains('/')
+        && !name.contains("..")
+    {
+        fs::read(dir.join(name)).ok()
+    } else {
+        None
+    };
+    match body {
+        Some(body) => {
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+                body.len()
+            )?;
+            stream.write_all(&body)
+        }
+        None => write!(
+            stream,
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        ),
+    }
+}
+
+/// `--shm-socket`: removes a socket left behind by a receiver that did not exit
+/// cleanly, which shmsink cannot bind over. One that accepts connections is in use.
+fn clear_stale_socket(path: &str) -> Result<(), String> {
+    if !Path::new(path).exists() {
+        return Ok(());
+    }
+    if UnixStream::connect(path).is_ok() {
+        return Err(format!("{path} is in use by another process"));
+    }
+    fs::remove_file(path).map_err(|e| format!("could not remove stale {path}: {e}"))
+}
+
+fn parse_srtp_key(value: &str) -> Result<Vec<u8>, String> {
+    if value.len() != SRTP_KEY_LEN * 2 || !value.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!(
+            "--srtp-key must be {} hex characters (e.g. from `openssl rand -hex {}`)",
+            SRTP_KEY_LEN * 2,
+            SRTP_KEY_LEN
+        ));
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&value[i..i + 2], 16).map_err(|e| e.to_string()))
+        .collect()
+}
+
+fn parse_preview_position(value: &str) -> Result<String, String> {
+    let pos = value.to_ascii_lowercase();
+    match pos.as_str() {
+        "top-left" | "top-right" | "bottom-left" | "bottom-right" | "center" => Ok(pos),
+        _ => match pos.split_once(',') {
+            Some((x, y)) if x.parse::<i32>().is_ok() && y.parse::<i32>().is_ok() => Ok(pos),
+            _ => Err(format!(
+                "invalid --preview-position value: {value} (expected top-left, top-right, bottom-left, bottom-right, center, or X,Y)"
+            )),
+        },
+    }
+}
+
+fn parse_tile_stream(value: &str) -> Result<TileStream, String> {
+    let (port, label) = match value.split_once('=') {
+        Some((port, label)) => (port, label.to_string()),
+        None => (value, format!("port {value}")),
+    };
+    let port = port
+        .parse::<u16>()
+        .map_err(|_| format!("invalid --stream value: {value} (expected PORT or PORT=LABEL)"))?;
+    Ok(TileStream { port, label })
+}
+
+fn run_receive(cfg: ReceiveCfg, replay: Option<&Path>) -> ExitCode {
+    let Some((encoding_name, depay_parse)) = depay_stage(&cfg.codec) else {
+        eprintln!("FAIL: unsupported codec '{}'", cfg.codec);
+        return ExitCode::from(2);
+    };
+    if let Err(err) = check_bit_depth(&cfg.codec, cfg.bit_depth)
+        .and_then(|()| check_transport(&cfg_from_receive(&cfg)))
+    {
+        eprintln!("FAIL: {err}");
+        return ExitCode::from(2);
+    }
+    let srt = cfg.transport == "srt";
+    // Sources that connect somewhere, and are set up again when the connection ends.
+    let connection = if srt {
+        Some("SRT")
+    } else if cfg.rtsp_url.is_some() {
+        Some("RTSP")
+    } else if cfg.whep_url.is_some() {
+        Some("WHEP")
+    } else {
+        None
+    };
+    // The decoder is picked from the installed plugins, so gstreamer is up before
+    // the pipeline is described (also for --dry-run).
+    if let Err(err) = gst::init() {
+        eprintln!("FAIL: gstreamer init failed: {err}");
+        return ExitCode::from(1);
+    }
+    let mut decoder = match select_decoder(&cfg.codec, &cfg.decoder) {
+        Ok(v) => warn_missing_decoder(&cfg.decoder, v),
+        Err(err) => {
+            eprintln!("FAIL: {err}");
+            return ExitCode::from(1);
+        }
+    };
+    let decode_chain = decode_stage(decoder);
+    // The decoders output 10-bit frames for Main 10 (or VP9/AV1 10-bit) streams on
+    // their own; these caps keep the videoconvert behind them from reducing them to
+    // 8 bits before the user elements and the tee.
+    let decode_caps = if cfg.bit_depth == 10 {
+        " ! video/x-raw,format={P010_10LE,I420_10LE}"
+    } else {
+        ""
+    };
+    let srtp_key = match cfg.srtp_key.as_deref().map(parse_srtp_key).transpose() {
+        Ok(v) => v,
+        Err(err) => {
+            eprintln!("FAIL: {err}");
+            return ExitCode::from(2);
+        }
+    };
+    let bind_ip = &cfg.bind_ip;
+    let port = cfg.port;
+    let (caps_name, srtp_stage) = if srtp_key.is_some() {
+        ("application/x-srtp", "srtpdec name=srtp ! ")
+    } else {
+        ("application/x-rtp", "")
+    };
+    let mut caps = format!(
+        "{caps_name},media=video,encoding-name={encoding_name},payload={},clock-rate={}",
+        cfg.payload, cfg.clock_rate
+    );
+    // The depayloader reads the capture time into a reference timestamp meta on
+    // each frame, which the decoder passes on.
+    if cfg.measure_latency {
+        caps.push_str(&format!(
+            ",extmap-{CAPTURE_TIME_EXT_ID}={CAPTURE_TIME_EXT_URI}"
+        ));
+    }
+
+    let after_depay = cfg
+        .insert_after_depay
+        .as_ref()
+        .map(|fragment| format!(" ! {fragment}"))
+        .unwrap_or_default();
+    // Decoders hand over whatever format they produce; videoconvert lets the user
+    // elements pick theirs.
+    let after_decode = cfg
+        .insert_after_decode
+        .as_ref()
+        .map(|fragment| format!(" ! videoconvert ! {fragment}"))
+        .unwrap_or_default();
+
+    // The recording takes the parsed stream ahead of the decoder, so nothing is
+    // encoded twice; a restart or a second run writes a new numbered file.
+    // A record sink is another way to ask for --record; there is one recording.
+    let record_sinks: Vec<&str> = cfg
+        .sinks
+        .iter()
+        .filter_map(|sink| sink.path.as_deref())
+        .collect();
+    let record = match (cfg.record.as_deref(), record_sinks.as_slice()) {
+        (None, []) => None,
+        (Some(path), []) | (None, [path]) => Some(path.to_string()),
+        _ => {
+            eprintln!("FAIL: only one recording at a time (--record or one record sink)");
+            return ExitCode::from(2);
+        }
+    };
+    let mut record_path = record.as_deref().map(free_file_path);
+    let (restream, restream_desc) = match cfg
+        .restream
+        .as_deref()
+        .map(|url| restream_branch(url, &cfg.codec, cfg.restream_bitrate_kbps))
+        .transpose()
+    {
+        Ok(v) => v.unzip(),
+        Err(err) => {
+            eprintln!("FAIL: {err}");
+            return ExitCode::from(2);
+        }
+    };
+    // HLS files go to a directory of their own for the run, removed on the way out.
+    let hls_dir = cfg
+        .hls_addr
+        .as_ref()
+        .map(|_| env::temp_dir().join(format!("vp-rcvr-hls-{}", std::process::id())));
+    let hls = match hls_dir
+        .as_deref()
+        .map(|dir| hls_branch(dir, &cfg.codec, cfg.hls_bitrate_kbps))
+        .transpose()
+    {
+        Ok(v) => v,
+        Err(err) => {
+            eprintln!("FAIL: {err}");
+            return ExitCode::from(2);
+        }
+    };
+    let from_rec = [&restream, &hls]
+        .into_iter()
+        .flatten()
+        .any(|branch| branch.starts_with(" rec."));
+    let record_tee = if record_path.is_some() || from_rec {
+        " ! tee name=rec ! queue"
+    } else {
+        ""
+    };
+
+    let input = if let Some(url) = &cfg.rtsp_url {
+        // rtspsrc runs the RTP session and jitterbuffer; the identity stands in for
+        // udpsrc, where the data probes look for arriving packets. The caps keep
+        // a camera's audio stream away from the video depayloader.
+        format!(
+            "rtspsrc name=rtspsrc location=\"{}\" latency={} ! application/x-rtp,media=video ! \
+             identity name=src ! queue name=depay_in ! {depay_parse}",
+            url.replace('"', "\\\""),
+            cfg.latency_ms
+        )
+    } else if cfg.whep_url.is_some() {
+        // Each WHEP session gets a fresh webrtcbin once the pipeline plays (see
+        // whep.rs); its stream links to webrtc_in, and the identity stands in for
+        // udpsrc as with rtspsrc.
+        format!(
+            "capsfilter name=webrtc_in caps=\"application/x-rtp,media=video\" ! \
+             identity name=src ! queue name=depay_in ! {depay_parse}"
+        )
+    } else if srt {
+        // vp-sndr muxes the parsed stream into MPEG-TS; the same parser picks it up.
+        let host = if cfg.srt_mode == "caller" {
+            cfg.sender_ip.as_deref().unwrap_or_default()
+        } else {
+            bind_ip.as_str()
+        };
+        format!(
+            "srtsrc name=src uri=srt://{host}:{port} mode={} latency={} ! tsdemux ! \
+             queue name=depay_in ! {}parse",
+            cfg.srt_mode, cfg.srt_latency_ms, cfg.codec
+        )
+    } else {
+        // rtp_in and depay_in are joined through rtpbin once the pipeline is built.
+        format!(
+            "udpsrc name=src address={bind_ip} port={port} buffer-size=4194304 caps=\"{caps}\" ! \
+             {srtp_stage}queue name=rtp_in \
+             udpsrc name=rtcpsrc address={bind_ip} port={} caps=application/x-rtcp \
+             multiudpsink name=rtcpout sync=false async=false \
+             queue name=depay_in ! {depay_parse}",
+            port.saturating_add(1)
+        )
+    };
+    let mut pipeline = format!(
+        "{input}{after_depay}{record_tee} ! {decode_chain}{decode_caps}{after_decode} ! {}tee name=t",
+        standby_stage(&cfg)
+    );
+    if let Some(path) = &record_path {
+        pipeline.push_str(&format!(
+            " rec. ! queue name=recq ! matroskamux ! filesink name=recsink location=\"{}\"",
+            path.replace('"', "\\\"")
+        ));
+    }
+    for branch in [&restream, &hls].into_iter().flatten() {
+        pipeline.push_str(branch);
+    }
+
+    if cfg.with_audio {
+        let (audio_caps_name, audio_srtp_stage) = if srtp_key.is_some() {
+            ("application/x-srtp", "srtpdec name=audiosrtp ! ")
+        } else {
+            ("application/x-rtp", "")
+        };
+        // The sinks play as frames arrive, so the delay is a queue that only lets
+        // audio through once that much is buffered.
+        let audio_delay = cfg
+            .audio_delay_ms
+            .filter(|ms| *ms > 0)
+            .map(|ms| {
+                let delay_ns = u64::from(ms) * 1_000_000;
+                format!(
+                    " ! queue max-size-buffers=0 max-size-bytes=0 max-size-time={} min-threshold-time={delay_ns}",
+                    delay_ns + 1_000_000_000
+                )
+            })
+            .unwrap_or_default();
+        // A virtual source node shows up as a microphone in PipeWire/PulseAudio clients.
+        let audio_sink = if cfg.audio_output == "virtual-mic" {
+            format!(
+                "pipewiresink mode=provide sync=false \
+                 stream-properties=\"props,media.class=Audio/Source/Virtual,node.name={VIRTUAL_MIC_NODE},node.description=vp-rcvr\""
+            )
+        } else {
+            "autoaudiosink sync=false".to_string()
+        };
+        pipeline.push_str(&format!(
+            " udpsrc name=audiosrc address={bind_ip} port={} \
+             caps=\"{audio_caps_name},media=audio,encoding-name=OPUS,payload={AUDIO_PAYLOAD_TYPE},clock-rate=48000\" ! \
+             {audio_srtp_stage}rtpjitterbuffer latency={} ! rtpopusdepay ! opusdec ! \
+             audioconvert ! audioresample{audio_delay} ! {audio_sink}",
+            port.saturating_add(AUDIO_PORT_OFFSET),
+            cfg.audio_latency_ms.unwrap_or(cfg.latency_ms)
+        ));
+    }
+
+    // Window placement needs a sink that opens its own X11 window with a known title,
+    // so wmctrl can find it; Wayland does not let clients position their windows.
+    let mut place_preview = !cfg.no_preview
+        && (cfg.preview_position.is_some() || cfg.preview_monitor.is_some() || cfg.preview_on_top);
+    if place_preview && env::var_os("DISPLAY").is_none() {
+        eprintln!("WARN: preview placement needs X11/XWayland (DISPLAY is not set); ignoring --preview-position/--preview-monitor/--preview-on-top");
+        place_preview = false;
+    }
+
+    if cfg.osd && cfg.no_preview {
+        eprintln!("WARN: --osd draws on the preview, which is off; ignoring it");
+    }
+    if !cfg.no_preview {
+        let mut preview_scale_caps = String::new();
+        if cfg.preview_width.is_some() || cfg.preview_height.is_some() {
+            preview_scale_caps.push_str(" ! videoscale ! video/x-raw");
+            if let Some(w) = cfg.preview_width {
+                preview_scale_caps.push_str(&format!(",width={w}"));
+            }
+            if let Some(h) = cfg.preview_height {
+                preview_scale_caps.push_str(&format!(",height={h}"));
+            }
+        }
+        let preview_sink = if place_preview {
+            format!("taginject tags=\"title={PREVIEW_WINDOW_TITLE}\" ! fpsdisplaysink name=preview text-overlay=false video-sink=xvimagesink")
+        } else {
+            "fpsdisplaysink name=preview text-overlay=false video-sink=autovideosink".to_string()
+        };
+        let stats_overlay = if cfg.osd {
+            " ! textoverlay name=statsosd text=\"\" valignment=bottom halignment=left shaded-background=true font-desc=\"Monospace 11\""
+        } else {
+            ""
+        };
+        pipeline.push_str(&format!(
+            " t. ! queue ! videoconvert{} ! textoverlay name=osd text=\"\" valignment=top halignment=left shaded-background=true font-desc=\"Sans 14\"{} ! {} sync=false",
+            preview_scale_caps, stats_overlay, preview_sink
+        ));
+    }
+
+    // Resolved once, so restarts keep writing to the same device.
+    let v4l2_device = match cfg.v4l2_device.as_deref() {
+        Some("auto") => match find_free_loopback(cfg.v4l2_modprobe && !cfg.dry_run) {
+            Ok(device) => {
+                println!("V4L2 loopback: {device}");
+                Some(device)
+            }
+            Err(err) => {
+                eprintln!("FAIL: {err}");
+                return ExitCode::from(1);
+            }
+        },
+        other => other.map(str::to_string),
+    };
+    if let Some(device) = &v4l2_device {
+        let mut v4l2_caps = String::from("video/x-raw,format=I420");
+        if let Some(w) = cfg.v4l2_width {
+            v4l2_caps.push_str(&format!(",width={w}"));
+        }
+        if let Some(h) = cfg.v4l2_height {
+            v4l2_caps.push_str(&format!(",height={h}"));
+        }
+        if let Some(fps) = cfg.v4l2_fps {
+            v4l2_caps.push_str(&format!(",framerate={fps}/1"));
+        }
+        // Without this the loopback carries no colorimetry and videoconvert keeps
+        // whatever the decoder reported, which OBS may read with the other matrix.
+        v4l2_caps.push_str(&colorimetry_field(
+            cfg.colorimetry.as_deref(),
+            cfg.color_range.as_deref(),
+        ));
+        // A dry run leaves the device alone; it may be in use by a running receiver.
+        if !cfg.dry_run
+            && let Err(err) = check_v4l2_device(device, &v4l2_caps)
+        {
+            eprintln!("FAIL: {err}");
+            return ExitCode::from(1);
+        }
+        pipeline.push_str(&format!(
+            " t. ! queue ! videoconvert ! {} ! v4l2sink name=v4l2out device={} io-mode=rw sync=false",
+            v4l2_caps, device
+        ));
+    }
+
+    // A Video/Source node with the Camera role is what the camera portal lists, so
+    // browsers and PipeWire-aware apps see it without the v4l2loopback module.
+    if cfg.virtual_camera {
+        pipeline.push_str(&format!(
+            " t. ! queue ! videoconvert ! video/x-raw,format=I420{} ! pipewiresink name=pwcam mode=provide sync=false \
+             stream-properties=\"props,media.class=Video/Source,media.role=Camera,node.name={VIRTUAL_CAMERA_NODE},node.description=vp-rcvr\"",
+            colorimetry_field(cfg.colorimetry.as_deref(), cfg.color_range.as_deref())
+        ));
+    }
+
+    // Frames go out in the format the decoder produced, so readers convert only if
+    // they need to; a slow reader drops frames instead of holding up the others.
+    if let Some(path) = &cfg.shm_socket {
+        pipeline.push_str(&format!(
+            " t. ! queue leaky=downstream max-size-buffers=2 ! shmsink name=shmout socket-path=\"{}\" \
+             shm-size={SHM_SIZE_BYTES} wait-for-connection=false sync=false",
+            path.replace('"', "\\\"")
+        ));
+    }
+
+    // Extra sinks are out0, out1, ... in config order, not counting the recording.
+    let colorimetry = colorimetry_field(cfg.colorimetry.as_deref(), cfg.color_range.as_deref());
+    for (index, sink) in cfg
+        .sinks
+        .iter()
+        .filter(|sink| sink.kind != "record")
+        .enumerate()
+    {
+        let caps = sink_caps(sink, &colorimetry);
+        if let Some(device) = &sink.device
+            && !cfg.dry_run
+            && let Err(err) = check_v4l2_device(device, &caps)
+        {
+            eprintln!("FAIL: {err}");
+            return ExitCode::from(1);
+        }
+        pipeline.push_str(&sink_branch(sink, &format!("out{index}"), &caps));
+    }
+
+    if cfg.dry_run {
+        println!("Receive pipeline:\n  {pipeline}\n");
+        if srt {
+            println!(
+                "SRT {} on port {port} (latency {} ms).",
+                cfg.srt_mode, cfg.srt_latency_ms
+            );
+        } else if cfg.rtsp_url.is_some() {
+            println!(
+                "rtspsrc runs the RTP session (latency {} ms).",
+                cfg.latency_ms
+            );
+        } else if cfg.whep_url.is_some() {
+            println!(
+                "webrtcbin runs the RTP session (latency {} ms) and links to webrtc_in.",
+                cfg.latency_ms
+            );
+        } else {
+            println!(
+                "rtp_in and depay_in are linked through rtpbin (latency {} ms{}).",
+                cfg.latency_ms,
+                if cfg.rtx { ", RTX" } else { "" }
+            );
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    if let (Some(addr), Some(dir)) = (&cfg.hls_addr, &hls_dir) {
+        let served = fs::create_dir_all(dir)
+            .and_then(|()| fs::write(dir.join(HLS_PAGE), HLS_PAGE_HTML))
+            .map_err(|e| format!("could not create {}: {e}", dir.display()))
+            .and_then(|()| serve_hls(addr, dir.clone()));
+        match served {
+            Ok(local) => println!("HLS: http://{local}/"),
+            Err(err) => {
+                eprintln!("FAIL: {err}");
+                return ExitCode::from(1);
+            }
+        }
+    }
+
+    if let Some(path) = &cfg.shm_socket
+        && let Err(err) = clear_stale_socket(path)
+    {
+        eprintln!("FAIL: {err}");
+        return ExitCode::from(1);
+    }
+
+    let replay_reader = match replay.map(open_rtp_capture).transpose() {
+        Ok(reader) => reader,
+        Err(err) => {
+            eprintln!("FAIL: {err}");
+            return ExitCode::from(1);
+        }
+    };
+
+    match cfg.rtsp_url.as_ref().or(cfg.whep_url.as_ref()) {
+        Some(url) => println!("Starting {encoding_name} receiver from {url}..."),
+        None => println!("Starting {encoding_name} receiver on {bind_ip}:{port}..."),
+    }
+    println!("Decoder: {decoder}");
+    println!("Pipeline: {}", pipeline);
+    if let Some(path) = &record_path {
+        println!("Recording to {path}.");
+    }
+    if let Some(url) = &cfg.restream {
+        println!("Restreaming to {url}.");
+    }
+
+    let pipeline = match gst::parse::launch(&pipeline) {
+        Ok(p) => match p.downcast::<gst::Pipeline>() {
+            Ok(v) => v,
+            Err(_) => {
+                eprintln!("FAIL: receive pipeline is not a gst::Pipeline");
+                return ExitCode::from(1);
+            }
+        },
+        Err(err) => {
+            eprintln!("FAIL: could not build receive pipeline: {err}");
+            return ExitCode::from(1);
+        }
+    };
+    let rtp_caps = gst::Caps::builder("application/x-rtp")
+        .field("media", "video")
+        .field("encoding-name", encoding_name)
+        .field("payload", cfg.payload as i32)
+        .field("clock-rate", cfg.clock_rate as i32)
+        .field_if_some(
+            format!("extmap-{CAPTURE_TIME_EXT_ID}").as_str(),
+            cfg.measure_latency.then_some(CAPTURE_TIME_EXT_URI),
+        )
+        .build();
+    let mut whep_session = cfg.whep_url.as_deref().map(|url| {
+        whep::Session::new(
+            url,
+            cfg.whep_token.as_deref(),
+            rtp_caps.clone(),
+            cfg.latency_ms,
+        )
+    });
+    // rtpbin owns the jitterbuffer, so it is also where latency is read and set. SRT,
+    // rtspsrc and webrtcbin buffer on their own, sized once per connection.
+    let jitter = if connection.is_some() {
+        None
+    } else {
+        match attach_rtp_session(
+            &pipeline,
+            rtp_caps,
+            cfg.payload.into(),
+            cfg.latency_ms,
+            cfg.rtx,
+        ) {
+            Ok(v) => Some(v),
+            Err(err) => {
+                eprintln!("FAIL: {err}");
+                return ExitCode::from(1);
+            }
+        }
+    };
+    if let Some(passphrase) = cfg.srt_passphrase.as_deref().filter(|_| srt) {
+        match pipeline.by_name("src") {
+            Some(src) => src.set_property("passphrase", passphrase),
+            None => {
+                eprintln!("FAIL: could not find srtsrc in receive pipeline");
+                return ExitCode::from(1);
+            }
+        }
+    }
+    let osd = pipeline.by_name("osd");
+    // srtpdec asks for the key of each new SSRC; every sender shares the one master key.
+    if let Some(key) = srtp_key {
+        let Some(srtp) = pipeline.by_name("srtp") else {
+            eprintln!("FAIL: receive pipeline has no srtpdec");
+            return ExitCode::from(1);
+        };
+        let key_caps = gst::Caps::builder("application/x-srtp")
+            .field("srtp-key", gst::Buffer::from_slice(key))
+            .field("srtp-cipher", "aes-128-icm")
+            .field("srtp-auth", "hmac-sha1-80")
+            .field("srtcp-cipher", "aes-128-icm")
+            .field("srtcp-auth", "hmac-sha1-80")
+            .build();
+        if let Some(audio_srtp) = pipeline.by_name("audiosrtp") {
+            let key_caps = key_caps.clone();
+            audio_srtp.connect("request-key", false, move |_| Some(key_caps.to_value()));
+        }
+        srtp.connect("request-key", false, move |_| Some(key_caps.to_value()));
+    }
+    let bus = match pipeline.bus() {
+        Some(v) => v,
+        None => {
+            eprintln!("FAIL: could not get pipeline bus");
+            return ExitCode::from(1);
+        }
+    };
+
+    // Key presses in the preview window travel upstream as navigation events.
+    let (key_tx, key_rx) = mpsc::channel::<PreviewKey>();
+    if let Some(pad) = osd.as_ref().and_then(|o| o.static_pad("src")) {
+        pad.add_probe(gst::PadProbeType::EVENT_UPSTREAM, move |_, info| {
+            if let Some(gst::PadProbeData::Event(ref event)) = info.data
+                && let Ok(gst_video::NavigationEvent::KeyPress { key, .. }) =
+                    gst_video::NavigationEvent::parse(event)
+            {
+                match key.as_str() {
+                    "plus" | "equal" | "KP_Add" => {
+                        let _ = key_tx.send(PreviewKey::Latency(LATENCY_STEP_MS));
+                    }
+                    "minus" | "KP_Subtract" => {
+                        let _ = key_tx.send(PreviewKey::Latency(-LATENCY_STEP_MS));
+                    }
+                    "s" | "S" => {
+                        let _ = key_tx.send(PreviewKey::Snapshot);
+                    }
+                    _ => {}
+                }
+            }
+            gst::PadProbeReturn::Ok
+        });
+    }
+
+    // Milliseconds since `watch_base` at which the last RTP packet left udpsrc.
+    let watch_base = Instant::now();
+    let last_data_ms = Arc::new(AtomicU64::new(0));
+    if (cfg.idle_timeout_secs.is_some() || cfg.stall_timeout_secs.is_some())
+        && let Some(pad) = pipeline
+            .by_name("src")
+            .and_then(|src| src.static_pad("src"))
+    {
+        let last_data_ms = Arc::clone(&last_data_ms);
+        pad.add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+            last_data_ms.store(watch_base.elapsed().as_millis() as u64, Ordering::Relaxed);
+            gst::PadProbeReturn::Ok
+        });
+    }
+
+    let metrics = cfg.metrics_addr.as_deref().and_then(|addr| {
+        let metrics = Metrics::new("vp_rcvr");
+        match metrics.serve(addr) {
+            Ok(local) => {
+                println!("Metrics: http://{local}/metrics");
+                Some(metrics)
+            }
+            Err(err) => {
+                eprintln!("WARN: metrics endpoint unavailable: {err}");
+                None
+            }
+        }
+    });
+    let counters = Arc::new(ReceiveCounters::default());
+    if metrics.is_some()
+        || cfg.osd
+        || cfg.stall_timeout_secs.is_some()
+        || cfg.standby
+        || cfg.on_decode_error != "exit"
+    {
+        if let Some(pad) = pipeline
+            .by_name("src")
+            .and_then(|src| src.static_pad("src"))
+        {
+            let counters = Arc::clone(&counters);
+            pad.add_probe(gst::PadProbeType::BUFFER, move |_, info| {
+                if let Some(buffer) = info.buffer() {
+                    counters
+                        .bytes
+                        .fetch_add(buffer.size() as u64, Ordering::Relaxed);
+                }
+                gst::PadProbeReturn::Ok
+            });
+        }
+        // Frames come out of the decoder into the tee in front of every sink.
+        if let Some(pad) = decoded_pad(&pipeline) {
+            let counters = Arc::clone(&counters);
+            pad.add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+                counters.frames.fetch_add(1, Ordering::Relaxed);
+                counters
+                    .last_frame_ms
+                    .store(watch_base.elapsed().as_millis() as u64, Ordering::Relaxed);
+                gst::PadProbeReturn::Ok
+            });
+        }
+    }
+
+    // shmsrc has no caps of its own; readers need these to parse the frames.
+    if let Some(pad) = pipeline
+        .by_name("shmout")
+        .and_then(|sink| sink.static_pad("sink"))
+    {
+        pad.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, |_, info| {
+            if let Some(gst::EventView::Caps(caps)) = info.event().map(|event| event.view()) {
+                println!("Shared memory frames: {}", caps.caps());
+            }
+            gst::PadProbeReturn::Ok
+        });
+    }
+
+    // A reference to the newest decoded frame, for snapshots.
+    let last_frame = Arc::new(Mutex::new(None::<gst::Buffer>));
+    if let Some(pad) = decoded_pad(&pipeline) {
+        let last_frame = Arc::clone(&last_frame);
+        pad.add_probe(gst::PadProbeType::BUFFER, move |_, info| {
+            if let Some(buffer) = info.buffer()
+                && let Ok(mut last) = last_frame.lock()
+            {
+                *last = Some(buffer.clone());
+            }
+            gst::PadProbeReturn::Ok
+        });
+    }
+    let snapshot_dir = cfg
+        .snapshot_dir
+        .as_ref()
+        .map(PathBuf::from)
+        .or_else(dirs::picture_dir)
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    let latency_stats = Arc::new(Mutex::new(LatencyStats::default()));
+    if cfg.measure_latency
+        && let Some(pad) = decoded_pad(&pipeline)
+    {
+        let latency_stats = Arc::clone(&latency_stats);
+        let ntp_caps = gst::Caps::new_empty_simple("timestamp/x-ntp");
+        pad.add_probe(gst::PadProbeType::BUFFER, move |_, info| {
+            let captured = info
+                .buffer()
+                .and_then(|buffer| {
+                    buffer
+                        .iter_meta::<gst::ReferenceTimestampMeta>()
+                        .find(|meta| meta.reference().can_intersect(&ntp_caps))
+                })
+                .map(|meta| meta.timestamp());
+            if let Some(captured) = captured
+                && let Ok(mut stats) = latency_stats.lock()
+            {
+                stats.record(captured);
+            }
+            gst::PadProbeReturn::Ok
+        });
+    }
+    let mut latency_logged_at = Instant::now();
+
+    return_rtcp_to_sender(&pipeline, port, |addr| println!("RTCP feedback to {addr}"));
+
+    let mut restream = match restream_desc
+        .as_deref()
+        .map(|desc| Restream::start(&pipeline, desc))
+        .transpose()
+    {
+        Ok(v) => v,
+        Err(err) => {
+            eprintln!("FAIL: {err}");
+            return ExitCode::from(1);
+        }
+    };
+
+    if pipeline.set_state(gst::State::Playing).is_err() {
+        let _ = pipeline.set_state(gst::State::Null);
+        eprintln!("FAIL: could not set receive pipeline to Playing");
+        return ExitCode::from(1);
+    }
+    if let Some(session) = &mut whep_session
+        && let Err(err) = session.connect(&pipeline)
+    {
+        let _ = pipeline.set_state(gst::State::Null);
+        eprintln!("FAIL: {err}");
+        return ExitCode::from(1);
+    }
+
+    if replay.is_none()
+        && let Err(err) = start_sender_stats_listener(bind_ip, port)
+    {
+        eprintln!("WARN: sender stats unavailable: {err}");
+    }
+
+    if let Some(reader) = replay_reader {
+        let pipeline_weak = pipeline.downgrade();
+        let drain = Duration::from_millis(u64::from(cfg.latency_ms) + REPLAY_DRAIN_MS);
+        thread::spawn(move || {
+            // Give udpsrc a moment to start reading before the first packet goes out.
+            thread::sleep(Duration::from_millis(REPLAY_START_DELAY_MS));
+            match replay_rtp_capture(reader, port) {
+                Ok(packets) => println!("Replay finished ({packets} packets)."),
+                Err(err) => eprintln!("WARN: replay stopped: {err}"),
+            }
+            thread::sleep(drain);
+            if let Some(pipeline) = pipeline_weak.upgrade() {
+                pipeline.send_event(gst::event::Eos::new());
+            }
+        });
+    }
+
+    if place_preview {
+        let position = cfg.preview_position.clone();
+        let monitor = cfg.preview_monitor;
+        let on_top = cfg.preview_on_top;
+        thread::spawn(move || place_preview_window(position.as_deref(), monitor, on_top));
+    }
+
+    let control_rx = match start_control_listener() {
+        Ok(rx) => Some(rx),
+        Err(err) => {
+            eprintln!("WARN: control socket unavailable: {err}");
+            None
+        }
+    };
+
+    let terminate = register_terminate_flag();
+    let snapshot_signal = Arc::new(AtomicBool::new(false));
+    if let Err(err) =
+        signal_hook::flag::register(signal_hook::consts::SIGUSR1, Arc::clone(&snapshot_signal))
+    {
+        eprintln!("WARN: SIGUSR1 snapshots unavailable: {err}");
+    }
+    let mut ended = false;
+    let mut latency_ms = if srt {
+        cfg.srt_latency_ms
+    } else {
+        cfg.latency_ms
+    };
+    let mut quitting = false;
+    let mut osd_clear_at: Option<Instant> = None;
+    let idle_timeout = cfg.idle_timeout_secs.map(Duration::from_secs);
+    let stall_timeout = cfg.stall_timeout_secs.map(Duration::from_secs);
+    // Frames are only expected from this point on: the start, or the last restart.
+    let mut stall_base_ms = 0;
+    // When the current decoder was (re)started, to tell whether it decoded anything.
+    let mut decoder_base_ms = 0;
+    let mut reconnect_at: Option<Instant> = None;
+    let mut idle = false;
+    let mut exit = ExitCode::SUCCESS;
+    let mut metrics_report = metrics.map(MetricsReport::new);
+    let standby = pipeline.by_name("standby");
+    let mut on_standby = false;
+    let mut stats_osd = pipeline
+        .by_name("statsosd")
+        .map(|overlay| StatsOsd::new(overlay, cfg.clock_rate));
+    loop {
+        if let Some(msg) = bus.timed_pop(gst::ClockTime::from_mseconds(50)) {
+            match msg.view() {
+                // A sender or server that stops, or a dropped network, ends the SRT,
+                // RTSP or WHEP connection; a fresh source connects again or listens
+                // for it.
+                gst::MessageView::Error(e)
+                    if connection.is_some()
+                        && e.src().is_some_and(|src| {
+                            matches!(src.name().as_str(), "src" | "rtspsrc" | "webrtc")
+                        }) =>
+                {
+                    if reconnect_at.is_none() {
+                        eprintln!(
+                            "WARN: {} connection failed: {}; reconnecting in {RECONNECT_SECS}s",
+                            connection.unwrap_or_default(),
+                            e.error()
+                        );
+                        reconnect_at = Some(Instant::now() + Duration::from_secs(RECONNECT_SECS));
+                    }
+                }
+                gst::MessageView::Eos(..) if connection.is_some() && !quitting => {
+                    if reconnect_at.is_none() {
+                        eprintln!(
+                            "WARN: {} connection closed; reconnecting in {RECONNECT_SECS}s",
+                            connection.unwrap_or_default()
+                        );
+                        reconnect_at = Some(Instant::now() + Duration::from_secs(RECONNECT_SECS));
+                    }
+                }
+                gst::MessageView::Error(e) => {
+                    let from_decoder = e
+                        .src()
+                        .is_some_and(|src| matches!(src.name().as_str(), "dec" | "decdl"));
+                    if !from_decoder || cfg.on_decode_error == "exit" {
+                        eprintln!(
+                            "FAIL: receive pipeline error from {}: {}",
+                            e.src().map(|s| s.path_string()).unwrap_or_else(|| "<unknown>".into()),
+                            e.error()
+                        );
+                        exit = ExitCode::from(1);
+                        break;
+                    }
+                    // A decoder that fails before its first frame cannot handle this
+                    // stream (e.g. its profile); one that fails later hit a corrupted
+                    // frame and only needs fresh state and a keyframe.
+                    let decoded = counters.last_frame_ms.load(Ordering::Relaxed) > decoder_base_ms;
+                    let replacement = match cfg.on_decode_error.as_str() {
+                        "fallback" if !decoded => next_decoder(&cfg.codec, decoder),
+                        _ => None,
+                    };
+                    if !decoded && replacement.is_none() {
+                        eprintln!(
+                            "FAIL: decoder {decoder} failed before its first frame: {}",
+                            e.error()
+                        );
+                        exit = ExitCode::from(1);
+                        break;
+                    }
+                    eprintln!("WARN: decoder {decoder} failed: {}", e.error());
+                    match replacement {
+                        Some(next) => println!("Switching to decoder {next}..."),
+                        None => println!("Restarting receive pipeline..."),
+                    }
+                    let now_ms = watch_base.elapsed().as_millis() as u64;
+                    decoder_base_ms = now_ms;
+                    stall_base_ms = now_ms;
+                    if let Err(err) = restart_pipeline(
+                        &pipeline,
+                        record.as_deref(),
+                        &mut record_path,
+                        replacement,
+                        whep_session.as_mut(),
+                    ) {
+                        eprintln!("FAIL: {err}");
+                        exit = ExitCode::from(1);
+                        break;
+                    }
+                    if let Some(next) = replacement {
+                        decoder = next;
+                    }
+                    let _ = request_keyframe(&pipeline);
+                }
+                gst::MessageView::Eos(..) => {
+                    ended = true;
+                    break;
+                }
+                gst::MessageView::Warning(w) if is_rtpbin(w.src()) => {
+                    eprintln!("WARN: {}", w.error());
+                }
+                gst::MessageView::Latency(..) => {
+                    let _ = pipeline.recalculate_latency();
+                }
+                _ => {}
+            }
+        }
+        if terminate.load(Ordering::Relaxed) {
+            println!("Stopping...");
+            break;
+        }
+        if restream.as_mut().is_some_and(Restream::poll) {
+            let _ = request_keyframe(&pipeline);
+        }
+        if reconnect_at.is_some_and(|at| Instant::now() >= at) {
+            reconnect_at = None;
+            println!("Reconnecting {}...", connection.unwrap_or_default());
+            stall_base_ms = watch_base.elapsed().as_millis() as u64;
+            if let Err(err) = restart_pipeline(
+                &pipeline,
+                record.as_deref(),
+                &mut record_path,
+                None,
+                whep_session.as_mut(),
+            ) {
+                eprintln!("FAIL: {err}");
+                exit = ExitCode::from(1);
+                break;
+            }
+        }
+        let mut snapshot = snapshot_signal.swap(false, Ordering::Relaxed);
+        while let Ok(key) = key_rx.try_recv() {
+            match key {
+                PreviewKey::Latency(step) => {
+                    let Some(jitter) = &jitter else {
+                        eprintln!("WARN: {LATENCY_FIXED}");
+                        continue;
+                    };
+                    latency_ms = adjusted_latency(latency_ms, LatencyAdjust::Delta(step));
+                    apply_latency(jitter, osd.as_ref(), latency_ms);
+                }
+                PreviewKey::Snapshot => snapshot = true,
+            }
+            osd_clear_at = Some(Instant::now() + Duration::from_secs(OSD_MESSAGE_SECS));
+        }
+        if snapshot {
+            if let Err(err) = save_snapshot(&pipeline, &last_frame, &snapshot_dir, osd.as_ref()) {
+                eprintln!("WARN: snapshot failed: {err}");
+            }
+            osd_clear_at = Some(Instant::now() + Duration::from_secs(OSD_MESSAGE_SECS));
+        }
+        if let Some(rx) = &control_rx {
+            while let Ok(req) = rx.try_recv() {
+                let result = match req.command {
+                    ControlCommand::GetLatency => Ok(format!("latency {latency_ms} ms").into()),
+                    ControlCommand::SetLatency(_) if jitter.is_none() => {
+                        Err(LATENCY_FIXED.to_string())
+                    }
+                    ControlCommand::SetLatency(adjust) => {
+                        latency_ms = adjusted_latency(latency_ms, adjust);
+                        if let Some(jitter) = &jitter {
+                            apply_latency(jitter, osd.as_ref(), latency_ms);
+                        }
+                        osd_clear_at = Some(Instant::now() + Duration::from_secs(OSD_MESSAGE_SECS));
+                        Ok(format!("latency {latency_ms} ms").into())
+                    }
+                    ControlCommand::Status => Ok(serde_json::json!({
+                        "state": if idle { "idle" } else { "receiving" },
+                        "codec": cfg.codec,
+                        "decoder": decoder,
+                        "bind_ip": cfg.bind_ip,
+                        "transport": cfg.transport,
+                        "rtsp_url": cfg.rtsp_url,
+                        "whep_url": cfg.whep_url,
+                        "port": cfg.port,
+                        "latency_ms": latency_ms,
+                        "preview": !cfg.no_preview,
+                        "v4l2_device": v4l2_device,
+                        "virtual_camera": cfg.virtual_camera,
+                        "shm_socket": cfg.shm_socket,
+                        "audio": cfg.with_audio,
+                        "record": record_path,
+                        "restream": cfg.restream,
+                    })),
+                    ControlCommand::Keyframe => {
+                        request_keyframe(&pipeline).map(|()| "keyframe requested".into())
+                    }
+                    ControlCommand::Snapshot => {
+                        osd_clear_at = Some(Instant::now() + Duration::from_secs(OSD_MESSAGE_SECS));
+                        save_snapshot(&pipeline, &last_frame, &snapshot_dir, osd.as_ref())
+                            .map(|path| format!("snapshot {}", path.display()).into())
+                    }
+                    ControlCommand::Quit => {
+                        quitting = true;
+                        println!("Quit requested; stopping...");
+                        pipeline.send_event(gst::event::Eos::new());
+                        Ok("stopping".into())
+                    }
+                };
+                let _ = req.reply.send(result);
+            }
+        }
+        if let Some(timeout) = idle_timeout {
+            let since_data = watch_base.elapsed().saturating_sub(Duration::from_millis(
+                last_data_ms.load(Ordering::Relaxed),
+            ));
+            if !idle && since_data >= timeout {
+                eprintln!("WARN: no RTP data for {}s", since_data.as_secs());
+                match cfg.on_timeout.as_str() {
+                    "restart" => {
+                        println!("Restarting receive pipeline...");
+                        last_data_ms.store(watch_base.elapsed().as_millis() as u64, Ordering::Relaxed);
+                        stall_base_ms = watch_base.elapsed().as_millis() as u64;
+                        if let Err(err) = restart_pipeline(
+                            &pipeline,
+                            record.as_deref(),
+                            &mut record_path,
+                            None,
+                            whep_session.as_mut(),
+                        ) {
+                            eprintln!("FAIL: {err}");
+                            exit = ExitCode::from(1);
+                            break;
+                        }
+                    }
+                    "placeholder" => {
+                        idle = true;
+                        if let Some(selector) = &standby {
+                            on_standby = true;
+                            select_standby_input(selector, true);
+                        }
+                    }
+                    _ => {
+                        exit = ExitCode::from(IDLE_TIMEOUT_EXIT_CODE);
+                        break;
+                    }
+                }
+            } else if idle && since_data < timeout {
+                idle = false;
+                println!("RTP data resumed.");
+                if let Some(selector) = &standby {
+                    on_standby = false;
+                    select_standby_input(selector, false);
+                }
+            }
+        }
+        if let Some(timeout) = stall_timeout {
+            let now_ms = watch_base.elapsed().as_millis() as u64;
+            let last_frame_ms = counters.last_frame_ms.load(Ordering::Relaxed);
+            let since_frame =
+                Duration::from_millis(now_ms.saturating_sub(last_frame_ms.max(stall_base_ms)));
+            let since_data =
+                Duration::from_millis(now_ms.saturating_sub(last_data_ms.load(Ordering::Relaxed)));
+            // Without packets there is nothing to decode; that is --idle-timeout-secs.
+            if since_frame >= timeout && since_data < timeout {
+                eprintln!(
+                    "WARN: packets arrive but no frame was decoded for {}s; restarting the receive pipeline",
+                    since_frame.as_secs()
+                );
+                stall_base_ms = now_ms;
+                if let Err(err) = restart_pipeline(
+                    &pipeline,
+                    record.as_deref(),
+                    &mut record_path,
+                    None,
+                    whep_session.as_mut(),
+                ) {
+                    eprintln!("FAIL: {err}");
+                    exit = ExitCode::from(1);
+                    break;
+                }
+                let _ = request_keyframe(&pipeline);
+            }
+        }
+        if cfg.measure_latency
+            && latency_logged_at.elapsed() >= Duration::from_secs(LATENCY_LOG_SECS)
+        {
+            latency_logged_at = Instant::now();
+            if let Ok(mut stats) = latency_stats.lock() {
+                match stats.window.summary() {
+                    Some(summary) => println!("Latency: {summary}"),
+                    None if !idle => println!(
+                        "Latency: no capture times from the sender (it needs RTP over UDP and GStreamer 1.22+)"
+                    ),
+                    None => {}
+                }
+                stats.window = LatencyHistogram::default();
+            }
+        }
+        if let Some(report) = &mut metrics_report {
+            report.tick(
+                &counters,
+                &pipeline,
+                jitter.as_ref(),
+                watch_base,
+                idle,
+                latency_ms,
+            );
+        }
+        if let Some(stats_osd) = &mut stats_osd {
+            stats_osd.tick(&counters, jitter.as_ref(), idle, latency_ms);
+        }
+        if let Some(selector) = standby.as_ref().filter(|_| cfg.standby) {
+            let now_ms = watch_base.elapsed().as_millis() as u64;
+            let last_frame_ms = counters.last_frame_ms.load(Ordering::Relaxed);
+            let streaming = counters.frames.load(Ordering::Relaxed) > 0
+                && now_ms.saturating_sub(last_frame_ms) < STANDBY_AFTER_MS;
+            if streaming == on_standby {
+                on_standby = !streaming;
+                select_standby_input(selector, on_standby);
+            }
+        }
+        if let Some(at) = osd_clear_at
+            && Instant::now() >= at
+        {
+            if let Some(osd) = &osd {
+                osd.set_property("text", "");
+            }
+            osd_clear_at = None;
+        }
+    }
+
+    if !ended {
+        finish_recording(&pipeline);
+    }
+    if let Some(restream) = &restream {
+        restream.stop();
+    }
+    let _ = pipeline.set_state(gst::State::Null);
+    if let Some(session) = &mut whep_session {
+        session.close(&pipeline);
+    }
+    if control_rx.is_some() {
+        let _ = fs::remove_file(control_socket_path());
+    }
+    if let Some(dir) = &hls_dir {
+        let _ = fs::remove_dir_all(dir);
+    }
+    if let Ok(stats) = latency_stats.lock()
+        && let Some(summary) = stats.total.summary()
+    {
+        println!("Capture-to-decode latency over the run: {summary}");
+    }
+    if let Ok(stats) = latency_stats.lock()
+        && stats.sender_ahead > 0
+    {
+        eprintln!(
+            "WARN: {} frames were stamped later than the local clock; sync both machines with NTP",
+            stats.sender_ahead
+        );
+    }
+    if jitter.is_some() && latency_ms != cfg.latency_ms {
+        println!("Latency was tuned to {latency_ms} ms; pass --latency-ms {latency_ms} to keep it.");
+    }
+    exit
+}
+
+/// `tile`: one receive session per stream, each in its own bin so the element names
+/// attach_rtp_session looks for stay unique, scaled into a compositor grid.
+fn run_tile(cfg: TileCfg) -> ExitCode {
+    let mut ports: Vec<u16> = cfg.streams.iter().map(|s| s.port).collect();
+    ports.sort_unstable();
+    // Each stream also uses the next port for RTCP.
+    if let Some(pair) = ports.windows(2).find(|pair| pair[1] - pair[0] < 2) {
+        eprintln!(
+            "FAIL: --stream ports {} and {} overlap (each stream also uses port + 1)",
+            pair[0], pair[1]
+        );
+        return ExitCode::from(2);
+    }
+    let Some((encoding_name, depay_parse)) = depay_stage(&cfg.codec) else {
+        eprintln!("FAIL: unsupported codec '{}'", cfg.codec);
+        return ExitCode::from(2);
+    };
+    if let Err(err) = gst::init() {
+        eprintln!("FAIL: gstreamer init failed: {err}");
+        return ExitCode::from(1);
+    }
+    let decoder = match select_decoder(&cfg.codec, &cfg.decoder) {
+        Ok(v) => warn_missing_decoder(&cfg.decoder, v),
+        Err(err) => {
+            eprintln!("FAIL: {err}");
+            return ExitCode::from(1);
+        }
+    };
+    let count = cfg.streams.len() as u32;
+    let columns = cfg
+        .columns
+        .unwrap_or_else(|| (count as f64).sqrt().ceil() as u32)
+        .min(count);
+    let (width, height) = (cfg.tile_width, cfg.tile_height);
+
+    // Streams that have not started yet leave their tile black instead of holding
+    // up the others.
+    let pipeline = match gst::parse::launch(
+        "compositor name=grid background=black ignore-inactive-pads=true ! videoconvert ! \
+         fpsdisplaysink name=preview text-overlay=false video-sink=autovideosink sync=false",
+    ) {
+        Ok(p) => match p.downcast::<gst::Pipeline>() {
+            Ok(v) => v,
+            Err(_) => {
+                eprintln!("FAIL: tile pipeline is not a gst::Pipeline");
+                return ExitCode::from(1);
+            }
+        },
+        Err(err) => {
+            eprintln!("FAIL: could not build tile pipeline: {err}");
+            return ExitCode::from(1);
+        }
+    };
+    let Some(grid) = pipeline.by_name("grid") else {
+        eprintln!("FAIL: tile pipeline has no compositor");
+        return ExitCode::from(1);
+    };
+    let bind_ip = &cfg.bind_ip;
+    for (index, stream) in cfg.streams.iter().enumerate() {
+        let port = stream.port;
+        let description = format!(
+            "udpsrc name=src address={bind_ip} port={port} buffer-size=4194304 \
+             caps=\"application/x-rtp,media=video,encoding-name={encoding_name},payload={},clock-rate=90000\" ! \
+             queue name=rtp_in \
+             udpsrc name=rtcpsrc address={bind_ip} port={} caps=application/x-rtcp \
+             multiudpsink name=rtcpout sync=false async=false \
+             queue name=depay_in ! {depay_parse} ! {} ! \
+             videoscale add-borders=true ! video/x-raw,width={width},height={height},pixel-aspect-ratio=1/1 ! \
+             textoverlay text=\"{}\" valignment=top halignment=left shaded-background=true font-desc=\"Sans 14\" ! \
+             queue name=out",
+            cfg.payload,
+            port.saturating_add(1),
+            decode_stage(decoder),
+            stream.label.replace('"', "\\\"")
+        );
+        let rtp_caps = gst::Caps::builder("application/x-rtp")
+            .field("media", "video")
+            .field("encoding-name", encoding_name)
+            .field("payload", cfg.payload as i32)
+            .field("clock-rate", 90_000i32)
+            .build();
+        let column = index as u32 % columns;
+        let row = index as u32 / columns;
+        let added = gst::parse::bin_from_description(&description, false)
+            .map_err(|e| format!("could not build stream on port {port}: {e}"))
+            .and_then(|bin| {
+                pipeline
+                    .add(&bin)
+                    .map_err(|e| format!("add stream on port {port}: {e}"))?;
+                attach_rtp_session(&bin, rtp_caps, cfg.payload.into(), cfg.latency_ms, false)?;
+                return_rtcp_to_sender(&bin, port, |addr| println!("RTCP feedback to {addr}"));
+                let out = bin
+                    .by_name("out")
+                    .and_then(|out| out.static_pad("src"))
+                    .ok_or_else(|| format!("stream on port {port} has no output"))?;
+                let ghost = gst::GhostPad::builder_with_target(&out)
+                    .map_err(|e| format!("stream on port {port}: {e}"))?
+                    .name("src")
+                    .build();
+                bin.add_pad(&ghost)
+                    .map_err(|e| format!("stream on port {port}: {e}"))?;
+                let tile = grid
+                    .request_pad_simple("sink_%u")
+                    .ok_or_else(|| "compositor has no free input".to_string())?;
+                tile.set_property("xpos", (column * width) as i32);
+                tile.set_property("ypos", (row * height) as i32);
+                ghost
+                    .link(&tile)
+                    .map(|_| ())
+                    .map_err(|e| format!("link stream on port {port}: {e}"))
+            });
+        if let Err(err) = added {
+            eprintln!("FAIL: {err}");
+            return ExitCode::from(1);
+        }
+        println!("Tile {}: {} from {bind_ip}:{port}", index + 1, stream.label);
+    }
+    println!("Decoder: {decoder}");
+
+    let bus = match pipeline.bus() {
+        Some(bus) => bus,
+        None => {
+            eprintln!("FAIL: could not get pipeline bus");
+            return ExitCode::from(1);
+        }
+    };
+    if let Err(err) = pipeline.set_state(gst::State::Playing) {
+        eprintln!("FAIL: could not start tile pipeline: {err}");
+        return ExitCode::from(1);
+    }
+    let terminate = register_terminate_flag();
+    let mut exit = ExitCode::SUCCESS;
+    while !terminate.load(Ordering::Relaxed) {
+        let Some(msg) = bus.timed_pop(gst::ClockTime::from_mseconds(100)) else {
+            continue;
+        };
+        match msg.view() {
+            gst::MessageView::Error(e) => {
+                eprintln!(
+                    "FAIL: tile pipeline error from {}: {}",
+                    e.src()
+                        .map(|s| s.path_string())
+                        .unwrap_or_else(|| "<unknown>".into()),
+                    e.error()
+                );
+                exit = ExitCode::from(1);
+                break;
+            }
+            gst::MessageView::Eos(..) => break,
+            gst::MessageView::Warning(w) if is_rtpbin(w.src()) => {
+                eprintln!("WARN: {}", w.error());
+            }
+            _ => {}
+        }
+    }
+    let _ = pipeline.set_state(gst::State::Null);
+    exit
+}
+
+/// attach_rtp_session reports an RTP stream it cannot link as a warning of rtpbin.
+fn is_rtpbin(src: Option<&gst::Object>) -> bool {
+    src.is_some_and(|src| src.name() == "rtpbin")
+}
+
+/// The /dev/videoN nodes of v4l2loopback, in number order. Its devices are virtual,
+/// unlike those of real capture hardware.
+fn loopback_devices() -> Vec<(u32, PathBuf)> {
+    let Ok(entries) = fs::read_dir("/sys/class/video4linux") else {
+        return Vec::new();
+    };
+    let mut devices: Vec<(u32, PathBuf)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let number = name.strip_prefix("video")?.parse().ok()?;
+            let real = fs::canonicalize(entry.path()).ok()?;
+            real.starts_with("/sys/devices/virtual")
+                .then_some((number, entry.path()))
+        })
+        .collect();
+    devices.sort();
+    devices
+}
+
+/// `--v4l2-device auto`: the first loopback device no producer is writing to; its
+/// sysfs `format` stays empty until one is. With LOAD_MODULE and v4l2loopback not
+/// loaded yet, loads it with a `vp-rcvr` card label first.
+fn find_free_loopback(load_module: bool) -> Result<String, String> {
+    if load_module && !Path::new("/sys/module/v4l2loopback").exists() {
+        println!("Loading v4l2loopback...");
+        let status = Command::new("sudo")
+            .args([
+                "-n",
+                "modprobe",
+                "v4l2loopback",
+                "card_label=vp-rcvr",
+                "exclusive_caps=1",
+            ])
+            .status()
+            .map_err(|e| format!("could not run sudo modprobe: {e}"))?;
+        if !status.success() {
+            return Err(
+                "sudo -n modprobe v4l2loopback failed (needs passwordless sudo for modprobe)"
+                    .to_string(),
+            );
+        }
+        // udev creates the /dev node a moment after the module registers it.
+        let deadline = Instant::now() + Duration::from_secs(LOOPBACK_LOAD_TIMEOUT_SECS);
+        let created = || {
+            loopback_devices()
+                .iter()
+                .any(|(number, _)| Path::new(&format!("/dev/video{number}")).exists())
+        };
+        while !created() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+    let devices = loopback_devices();
+    if devices.is_empty() {
+        return Err(
+            "no v4l2loopback device found (load the module, or pass --v4l2-modprobe)".to_string(),
+        );
+    }
+    devices
+        .iter()
+        .find(|(_, sys)| {
+            fs::read_to_string(sys.join("format")).is_ok_and(|format| format.trim().is_empty())
+        })
+        .map(|(number, _)| format!("/dev/video{number}"))
+        .ok_or_else(|| {
+            let names: Vec<String> = devices
+                .iter()
+                .map(|(number, _)| format!("/dev/video{number}"))
+                .collect();
+            format!(
+                "every v4l2loopback device already has a producer ({})",
+                names.join(", ")
+            )
+        })
+}
+
+/// Opens DEVICE the way the pipeline will and checks that it takes CAPS, so a wrong
+/// device number or an unsupported mode fails here with a readable message.
+fn check_v4l2_device(device: &str, caps: &str) -> Result<(), String> {
+    if !Path::new(device).exists() {
+        let names: Vec<String> = loopback_devices()
+            .iter()
+            .map(|(number, _)| format!("/dev/video{number}"))
+            .collect();
+        return Err(format!(
+            "{device} does not exist (v4l2loopback devices: {})",
+            if names.is_empty() {
+                "none".to_string()
+            } else {
+                names.join(", ")
+            }
+        ));
+    }
+    let wanted = caps
+        .parse::<gst::Caps>()
+        .map_err(|_| format!("invalid V4L2 caps: {caps}"))?;
+    let sink = gst::ElementFactory::make("v4l2sink")
+        .property("device", device)
+        .build()
+        .map_err(|_| "v4l2sink is not available (gstreamer1.0-plugins-good)".to_string())?;
+    let opened = sink.set_state(gst::State::Ready);
+    let offered = sink.static_pad("sink").map(|pad| pad.query_caps(None));
+    let _ = sink.set_state(gst::State::Null);
+    if opened.is_err() {
+        return Err(format!(
+            "could not open {device} for output; is it a v4l2loopback device?"
+        ));
+    }
+    match offered {
+        Some(offered) if !offered.can_intersect(&wanted) => Err(format!(
+            "{device} does not accept {caps} (it offers {offered})"
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// `--standby` and `--on-timeout placeholder`: an input-selector between the decoded
+/// stream (`sink_0`) and SMPTE bars with the no-signal text (`sink_1`), ending with
+/// its ` ! `. Both inputs are brought to the same size, rate and format, so switching
+/// never renegotiates the sinks; a V4L2 loopback cannot change its format while OBS
+/// reads it.
+fn standby_stage(cfg: &ReceiveCfg) -> String {
+    if !cfg.standby && cfg.on_timeout != "placeholder" {
+        return String::new();
+    }
+    let width = cfg.v4l2_width.unwrap_or(STANDBY_WIDTH);
+    let height = cfg.v4l2_height.unwrap_or(STANDBY_HEIGHT);
+    let fps = cfg.v4l2_fps.unwrap_or(STANDBY_FPS);
+    let format = if cfg.bit_depth == 10 {
+        "I420_10LE"
+    } else {
+        "I420"
+    };
+    let caps = format!(
+        "video/x-raw,format={format},width={width},height={height},framerate={fps}/1,pixel-aspect-ratio=1/1"
+    );
+    // The receive loop picks the input: with --standby the bars until frames arrive,
+    // and again whenever they stop for STANDBY_AFTER_MS; with --on-timeout
+    // placeholder the bars from the idle timeout until packets resume.
+    format!(
+        "videoscale name=standbyscale add-borders=true ! videorate ! videoconvert ! {caps} ! standby.sink_0 \
+         videotestsrc is-live=true pattern=smpte ! video/x-raw,width={width},height={height},framerate={fps}/1 ! \
+         textoverlay text=\"{NO_SIGNAL_TEXT}\" valignment=center halignment=center shaded-background=true font-desc=\"Sans 32\" ! \
+         videoconvert ! {caps} ! standby.sink_1 \
+         input-selector name=standby sync-streams=false ! "
+    )
+}
+
+fn select_standby_input(selector: &gst::Element, bars: bool) {
+    if let Some(pad) = selector.static_pad(if bars { "sink_1" } else { "sink_0" }) {
+        selector.set_property("active-pad", &pad);
+    }
+}
+
+/// The pad that sees each decoded frame of the stream: the tee, or with the standby
+/// slate the scaler in front of its selector, so the bars are not counted as frames
+/// and frames, caps and timestamps are still the decoder's own.
+fn decoded_pad(pipeline: &gst::Pipeline) -> Option<gst::Pad> {
+    match pipeline.by_name("standbyscale") {
+        Some(scale) => scale.static_pad("sink"),
+        None => pipeline.by_name("t").and_then(|tee| tee.static_pad("sink")),
+    }
+}
+
+/// Takes the receive pipeline through NULL and back to PLAYING with fresh depayloader
+/// and decoder state, switching to the DECODER element if one is given. The preview,
+/// V4L2, PipeWire camera and `--sink` sinks are locked in their state for the restart,
+/// so the windows, the loopback devices and the camera node stay open and OBS keeps
+/// its source; they show the last frame until the new stream arrives. A WHEP session
+/// is ended and negotiated again once the pipeline plays.
+fn restart_pipeline(
+    pipeline: &gst::Pipeline,
+    record: Option<&str>,
+    record_path: &mut Option<String>,
+    decoder: Option<&str>,
+    mut whep_session: Option<&mut whep::Session>,
+) -> Result<(), String> {
+    finish_recording(pipeline);
+    if let Some(session) = whep_session.as_deref_mut() {
+        session.close(pipeline);
+    }
+    let extra = (0..).map_while(|index| pipeline.by_name(&format!("out{index}")));
+    let kept: Vec<gst::Element> = ["preview", "v4l2out", "pwcam", "shmout"]
+        .into_iter()
+        .filter_map(|name| pipeline.by_name(name))
+        .chain(extra)
+        .collect();
+    for sink in &kept {
+        sink.set_locked_state(true);
+    }
+    let _ = pipeline.set_state(gst::State::Null);
+    let swapped = decoder.map_or(Ok(()), |element| swap_decoder(pipeline, element));
+    if let (Some(base), Some(sink)) = (record, pipeline.by_name("recsink")) {
+        let path = free_file_path(base);
+        sink.set_property("location", &path);
+        println!("Recording to {path}.");
+        *record_path = Some(path);
+    }
+    let restarted = pipeline.set_state(gst::State::Playing);
+    for sink in &kept {
+        sink.set_locked_state(false);
+    }
+    swapped?;
+    restarted.map_err(|_| "could not restart receive pipeline".to_string())?;
+    whep_session.map_or(Ok(()), |session| session.connect(pipeline))
+}
+
+/// SIGTERM/SIGINT set the flag so the loop can close the recording; a second
+/// signal exits right away.
+fn register_terminate_flag() -> Arc<AtomicBool> {
+    let terminate = Arc::new(AtomicBool::new(false));
+    for signal in [signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+        let registered =
+            signal_hook::flag::register_conditional_shutdown(signal, 1, Arc::clone(&terminate))
+                .and_then(|_| signal_hook::flag::register(signal, Arc::clone(&terminate)));
+        if let Err(err) = registered {
+            eprintln!("WARN: graceful shutdown on signal {signal} unavailable: {err}");
+        }
+    }
+    terminate
+}
+
+/// `path`, or the first of `NAME-1.EXT`, `NAME-2.EXT`, ... that does not exist yet,
+/// so recordings and snapshots never overwrite earlier ones.
+fn free_file_path(path: &str) -> String {
+    let original = Path::new(path);
+    if !original.exists() {
+        return path.to_string();
+    }
+    let stem = original
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ext = original
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| original.with_file_name(format!("{stem}-{n}{ext}")))
+        .find(|candidate| !candidate.exists())
+        .map(|candidate| candidate.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_string())
+}
+
+/// Sends EOS down the --record branch alone and waits for it to reach the file, so
+/// matroskamux writes its index and duration before the pipeline stops. The rest of
+/// the pipeline is left alone: its sinks may never see an EOS when no stream came in.
+fn finish_recording(pipeline: &gst::Pipeline) {
+    let (Some(queue), Some(sink)) = (
+        pipeline.by_name("recq").and_then(|q| q.static_pad("sink")),
+        pipeline
+            .by_name("recsink")
+            .and_then(|s| s.static_pad("sink")),
+    ) else {
+        return;
+    };
+    let (tx, rx) = mpsc::channel();
+    sink.add_probe(
+        gst::PadProbeType::EVENT_DOWNSTREAM,
+        move |_, info| match info.event() {
+            Some(event) if event.type_() == gst::EventType::Eos => {
+                let _ = tx.send(());
+                gst::PadProbeReturn::Remove
+            }
+            _ => gst::PadProbeReturn::Ok,
+        },
+    );
+    queue.send_event(gst::event::Eos::new());
+    if rx
+        .recv_timeout(Duration::from_millis(RECORD_FINISH_TIMEOUT_MS))
+        .is_err()
+    {
+        eprintln!("WARN: the recording was not finished in time; it may lack its index");
+    }
+}
+
+/// Capture-to-decode latency for `--measure-latency`: the current log interval and
+/// the whole run.
+#[derive(Default)]
+struct LatencyStats {
+    window: LatencyHistogram,
+    total: LatencyHistogram,
+    // Frames whose capture time is later than the local clock.
+    sender_ahead: u64,
+}
+
+impl LatencyStats {
+    /// `captured` is the sender's capture time as NTP time.
+    fn record(&mut self, captured: gst::ClockTime) {
+        let Ok(since_unix) = SystemTime::now().duration_since(UNIX_EPOCH) else {
+            return;
+        };
+        let now = since_unix.as_nanos() as u64 + NTP_UNIX_OFFSET_SECS * 1_000_000_000;
+        let Some(latency) = now.checked_sub(captured.nseconds()) else {
+            self.sender_ahead += 1;
+            return;
+        };
+        let ms = (latency / 1_000_000) as usize;
+        self.window.record(ms);
+        self.total.record(ms);
+    }
+}
+
+struct LatencyHistogram {
+    counts: Vec<u64>,
+    samples: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; LATENCY_HISTOGRAM_MS],
+            samples: 0,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    fn record(&mut self, ms: usize) {
+        self.counts[ms.min(LATENCY_HISTOGRAM_MS - 1)] += 1;
+        self.samples += 1;
+    }
+
+    /// The smallest latency in ms that `percent` of the samples do not exceed.
+    fn percentile(&self, percent: u64) -> usize {
+        let target = (self.samples * percent).div_ceil(100).max(1);
+        let mut seen = 0;
+        for (ms, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return ms;
+            }
+        }
+        LATENCY_HISTOGRAM_MS - 1
+    }
+
+    fn summary(&self) -> Option<String> {
+        if self.samples == 0 {
+            return None;
+        }
+        Some(format!(
+            "p50 {} ms, p95 {} ms, p99 {} ms, max {} ms ({} frames)",
+            self.percentile(50),
+            self.percentile(95),
+            self.percentile(99),
+            self.percentile(100),
+            self.samples
+        ))
+    }
+}
+
+/// Updated from pad probes for `--metrics-addr`.
+#[derive(Default)]
+struct ReceiveCounters {
+    // UDP payload bytes of the video stream.
+    bytes: AtomicU64,
+    frames: AtomicU64,
+    // Milliseconds since the loop's watch base at which the last frame was decoded.
+    last_frame_ms: AtomicU64,
+}
+
+/// Turns the counters into rates once a second and exports them.
+struct MetricsReport {
+    metrics: Metrics,
+    last_at: Instant,
+    last_bytes: u64,
+    last_frames: u64,
+}
+
+impl MetricsReport {
+    fn new(metrics: Metrics) -> Self {
+        Self {
+            metrics,
+            last_at: Instant::now(),
+            last_bytes: 0,
+            last_frames: 0,
+        }
+    }
+
+    fn tick(
+        &mut self,
+        counters: &ReceiveCounters,
+        pipeline: &gst::Pipeline,
+        rtpbin: Option<&gst::Element>,
+        watch_base: Instant,
+        idle: bool,
+        latency_ms: u32,
+    ) {
+        let elapsed = self.last_at.elapsed();
+        if elapsed < Duration::from_millis(METRICS_INTERVAL_MS) {
+            return;
+        }
+        let metrics = &self.metrics;
+        let secs = elapsed.as_secs_f64();
+        let bytes = counters.bytes.load(Ordering::Relaxed);
+        let frames = counters.frames.load(Ordering::Relaxed);
+        metrics.counter("frames_decoded_total", "Frames out of the decoder.", frames);
+        metrics.counter("bytes_received_total", "RTP bytes received.", bytes);
+        metrics.gauge(
+            "fps",
+            "Frames decoded per second.",
+            (frames - self.last_frames) as f64 / secs,
+        );
+        metrics.gauge(
+            "bitrate_kbps",
+            "Received video bitrate.",
+            (bytes - self.last_bytes) as f64 * 8.0 / 1000.0 / secs,
+        );
+        if let Some(lost) = rtpbin.and_then(packets_lost) {
+            metrics.counter(
+                "packets_lost_total",
+                "RTP packets that never arrived.",
+                lost,
+            );
+        }
+        metrics.gauge(
+            "pipeline_playing",
+            "1 while the receive pipeline is playing.",
+            (pipeline.current_state() == gst::State::Playing) as u8 as f64,
+        );
+        metrics.gauge(
+            "idle",
+            "1 while showing the no-signal placeholder.",
+            idle as u8 as f64,
+        );
+        metrics.gauge("latency_ms", "Jitterbuffer latency.", latency_ms as f64);
+        if frames > 0 {
+            let since_start_ms = watch_base.elapsed().as_millis() as u64;
+            let last_frame_ms = counters.last_frame_ms.load(Ordering::Relaxed);
+            metrics.gauge(
+                "last_frame_age_seconds",
+                "Seconds since the last decoded frame.",
+                since_start_ms.saturating_sub(last_frame_ms) as f64 / 1000.0,
+            );
+        }
+        self.last_at = Instant::now();
+        self.last_bytes = bytes;
+        self.last_frames = frames;
+    }
+}
+
+/// `--osd`: a once-a-second summary in the bottom corner of the preview, from the
+/// same counters as the metrics plus the session's view of the sender's stream.
+struct StatsOsd {
+    overlay: gst::Element,
+    clock_rate: u32,
+    last_at: Instant,
+    last_bytes: u64,
+    last_frames: u64,
+    // When frames started coming in; cleared while the stream is idle.
+    started: Option<Instant>,
+}
+
+impl StatsOsd {
+    fn new(overlay: gst::Element, clock_rate: u32) -> Self {
+        Self {
+            overlay,
+            clock_rate,
+            last_at: Instant::now(),
+            last_bytes: 0,
+            last_frames: 0,
+            started: None,
+        }
+    }
+
+    fn tick(
+        &mut self,
+        counters: &ReceiveCounters,
+        rtpbin: Option<&gst::Element>,
+        idle: bool,
+        latency_ms: u32,
+    ) {
+        let elapsed = self.last_at.elapsed();
+        if elapsed < Duration::from_millis(STATS_OSD_INTERVAL_MS) {
+            return;
+        }
+        let secs = elapsed.as_secs_f64();
+        let bytes = counters.bytes.load(Ordering::Relaxed);
+        let frames = counters.frames.load(Ordering::Relaxed);
+        let fps = (frames - self.last_frames) as f64 / secs;
+        let mbps = (bytes - self.last_bytes) as f64 * 8.0 / 1_000_000.0 / secs;
+        if idle || fps == 0.0 {
+            self.started = None;
+        } else if self.started.is_none() {
+            self.started = Some(Instant::now());
+        }
+        let uptime = self.started.map_or(0, |at| at.elapsed().as_secs());
+        let source = rtpbin.and_then(remote_source_stats);
+        let received = source
+            .as_ref()
+            .and_then(|s| s.get::<u64>("packets-received").ok())
+            .unwrap_or(0);
+        let lost = source
+            .as_ref()
+            .and_then(|s| s.get::<i32>("packets-lost").ok())
+            .map_or(0, |lost| lost.max(0) as u64);
+        let loss_pct = if received + lost > 0 {
+            lost as f64 * 100.0 / (received + lost) as f64
+        } else {
+            0.0
+        };
+        // Interarrival jitter in RTP clock units.
+        let jitter_ms = source
+            .as_ref()
+            .and_then(|s| s.get::<u32>("jitter").ok())
+            .map_or(0.0, |jitter| {
+                jitter as f64 * 1000.0 / self.clock_rate.max(1) as f64
+            });
+        let text = format!(
+            "{mbps:.1} Mbit/s  {fps:.1} fps\n\
+             jitter {jitter_ms:.1} ms of {latency_ms} ms buffer\n\
+             lost {lost} ({loss_pct:.2}%)\n\
+             up {:02}:{:02}:{:02}",
+            uptime / 3600,
+            uptime / 60 % 60,
+            uptime % 60
+        );
+        self.overlay.set_property("text", text);
+        self.last_at = Instant::now();
+        self.last_bytes = bytes;
+        self.last_frames = frames;
+    }
+}
+
+/// The session's statistics for the sender's stream (the first non-internal source).
+fn remote_source_stats(rtpbin: &gst::Element) -> Option<gst::Structure> {
+    let session =
+        rtpbin.emit_by_name::<Option<gst::glib::Object>>("get-internal-session", &[&0u32])?;
+    let stats = session.property::<gst::Structure>("stats");
+    let sources = stats.get::<gst::glib::ValueArray>("source-stats").ok()?;
+    sources.iter().find_map(|value| {
+        let source = value.get::<gst::Structure>().ok()?;
+        (!source.get::<bool>("internal").unwrap_or(true)).then_some(source)
+    })
+}
+
+/// Packets the sender's stream lost so far, from the session's view of it.
+fn packets_lost(rtpbin: &gst::Element) -> Option<u64> {
+    remote_source_stats(rtpbin)?
+        .get::<i32>("packets-lost")
+        .ok()
+        .map(|lost| lost.max(0) as u64)
+}
+
+#[derive(Clone, Copy)]
+enum LatencyAdjust {
+    Set(u32),
+    Delta(i64),
+}
+
+fn parse_latency_adjust(value: &str) -> Result<LatencyAdjust, String> {
+    let invalid = || format!("invalid latency value: {value} (expected MS, +MS, or -MS)");
+    if value.starts_with('+') || value.starts_with('-') {
+        value.parse::<i64>().map(LatencyAdjust::Delta).map_err(|_| invalid())
+    } else {
+        value.parse::<u32>().map(LatencyAdjust::Set).map_err(|_| invalid())
+    }
+}
+
+fn adjusted_latency(current: u32, adjust: LatencyAdjust) -> u32 {
+    match adjust {
+        LatencyAdjust::Set(ms) => ms.min(MAX_LATENCY_MS),
+        LatencyAdjust::Delta(step) => (current as i64 + step).clamp(0, MAX_LATENCY_MS as i64) as u32,
+    }
+}
+
+/// What a key press in the preview window asks for.
+enum PreviewKey {
+    Latency(i64),
+    Snapshot,
+}
+
+/// Writes the newest decoded frame to `dir` as `vp-rcvr-YYYYMMDD-HHMMSS.png`.
+fn save_snapshot(
+    pipeline: &gst::Pipeline,
+    last_frame: &Mutex<Option<gst::Buffer>>,
+    dir: &Path,
+    osd: Option<&gst::Element>,
+) -> Result<PathBuf, String> {
+    let buffer = last_frame
+        .lock()
+        .map_err(|_| "frame store is poisoned".to_string())?
+        .clone()
+        .ok_or_else(|| "no frame has been decoded yet".to_string())?;
+    let caps = decoded_pad(pipeline)
+        .and_then(|pad| pad.current_caps())
+        .ok_or_else(|| "the decoder has not negotiated a format yet".to_string())?;
+    let sample = gst::Sample::builder().buffer(&buffer).caps(&caps).build();
+    let png = gst_video::convert_sample(
+        &sample,
+        &gst::Caps::new_empty_simple("image/png"),
+        gst::ClockTime::from_seconds(SNAPSHOT_TIMEOUT_SECS),
+    )
+    .map_err(|e| format!("PNG conversion failed: {e}"))?;
+    let data = png
+        .buffer()
+        .and_then(|b| b.map_readable().ok())
+        .ok_or_else(|| "PNG conversion returned no data".to_string())?;
+    let stamp = gst::glib::DateTime::now_local()
+        .and_then(|now| now.format("%Y%m%d-%H%M%S"))
+        .map_err(|e| format!("could not read the local time: {e}"))?;
+    fs::create_dir_all(dir).map_err(|e| format!("create {}: {e}", dir.display()))?;
+    let name = dir.join(format!("vp-rcvr-{stamp}.png"));
+    let path = PathBuf::from(free_file_path(&name.to_string_lossy()));
+    fs::write(&path, data.as_slice()).map_err(|e| format!("write {}: {e}", path.display()))?;
+    println!("Snapshot saved to {}.", path.display());
+    if let Some(osd) = osd {
+        osd.set_property("text", "snapshot saved");
+    }
+    Ok(path)
+}
+
+fn apply_latency(jitter: &gst::Element, osd: Option<&gst::Element>, latency_ms: u32) {
+    // rtpbin hands the latency to its jitterbuffer, which posts a latency message on
+    // change; the bus loop recalculates.
+    jitter.set_property("latency", latency_ms);
+    println!("Latency: {latency_ms} ms");
+    if let Some(osd) = osd {
+        osd.set_property("text", format!("latency {latency_ms} ms"));
+    }
+}
+
+enum ControlCommand {
+    GetLatency,
+    SetLatency(LatencyAdjust),
+    Status,
+    Keyframe,
+    Snapshot,
+    Quit,
+}
+
+type ControlRequest = control::Request<ControlCommand>;
+
+fn control_socket_path() -> PathBuf {
+    control::socket_path("vp-rcvr.sock")
+}
+
+fn parse_control_command(line: &str) -> Result<ControlCommand, String> {
+    let mut parts = line.split_whitespace();
+    match parts.next() {
+        Some("latency") => match parts.next() {
+            Some(value) => Ok(ControlCommand::SetLatency(parse_latency_adjust(value)?)),
+            None => Ok(ControlCommand::GetLatency),
+        },
+        Some("status") => Ok(ControlCommand::Status),
+        Some("keyframe") => Ok(ControlCommand::Keyframe),
+        Some("snapshot") => Ok(ControlCommand::Snapshot),
+        Some("quit") => Ok(ControlCommand::Quit),
+        Some(other) => Err(format!("unknown command: {other}")),
+        None => Err("empty command".to_string()),
+    }
+}
+
+/// JSON-RPC methods of the control socket.
+fn parse_control_method(
+    method: &str,
+    params: &serde_json::Value,
+) -> Option<Result<ControlCommand, String>> {
+    let command = match method {
+        "get-status" => Ok(ControlCommand::Status),
+        "get-latency" => Ok(ControlCommand::GetLatency),
+        "set-latency" => match (params.get("ms"), params.get("delta")) {
+            (Some(_), _) => control::param_u32(params, "ms")
+                .map(|ms| ControlCommand::SetLatency(LatencyAdjust::Set(ms))),
+            (None, Some(delta)) => delta
+                .as_i64()
+                .map(|step| ControlCommand::SetLatency(LatencyAdjust::Delta(step)))
+                .ok_or_else(|| "params.delta must be an integer".to_string()),
+            (None, None) => Err("set-latency needs params.ms or params.delta".to_string()),
+        },
+        "request-keyframe" => Ok(ControlCommand::Keyframe),
+        "snapshot" => Ok(ControlCommand::Snapshot),
+        "quit" => Ok(ControlCommand::Quit),
+        _ => return None,
+    };
+    Some(command)
+}
+
+fn start_control_listener() -> Result<mpsc::Receiver<ControlRequest>, String> {
+    let path = control_socket_path();
+    let (tx, rx) = mpsc::channel::<ControlRequest>();
+    control::listen(
+        &path,
+        control::Protocol {
+            name: "receiver",
+            parse_line: parse_control_command,
+            parse_method: parse_control_method,
+            reply_timeout: Duration::from_secs(CONTROL_REPLY_TIMEOUT_SECS),
+        },
+        tx,
+    )?;
+    println!("Control socket: {}", path.display());
+    Ok(rx)
+}
+
+fn send_control_command(line: &str) -> Result<String, String> {
+    control::send_line(&control_socket_path(), "receiver", line)
+}
+
+/// Asks upstream for a keyframe; rtpbin turns the request into an RTCP PLI that the
+/// sender answers with an IDR frame.
+fn request_keyframe(pipeline: &gst::Pipeline) -> Result<(), String> {
+    let pad = pipeline
+        .by_name("depay_in")
+        .and_then(|queue| queue.static_pad("src"))
+        .ok_or_else(|| "receive pipeline has no depay_in queue".to_string())?;
+    let event = gst_video::UpstreamForceKeyUnitEvent::builder()
+        .all_headers(true)
+        .build();
+    if pad.send_event(event) {
+        Ok(())
+    } else {
+        Err("nothing upstream handled the keyframe request".to_string())
+    }
+}
+
+#[derive(Default)]
+struct SenderStats {
+    fps: f64,
+    capture_fps: f64,
+    target_fps: u32,
+    bitrate_kbps: u32,
+    encoder: String,
+    drops: u64,
+    drops_total: u64,
+    follow: String,
+}
+
+fn parse_sender_stats(line: &str) -> Option<SenderStats> {
+    let mut fields = line.split_whitespace();
+    if fields.next()? != "vp-stats" {
+        return None;
+    }
+    let mut stats = SenderStats::default();
+    for field in fields {
+        let Some((key, value)) = field.split_once('=') else {
+            continue;
+        };
+        match key {
+            "fps" => stats.fps = value.parse().ok()?,
+            "capture_fps" => stats.capture_fps = value.parse().ok()?,
+            "target_fps" => stats.target_fps = value.parse().ok()?,
+            "bitrate_kbps" => stats.bitrate_kbps = value.parse().ok()?,
+            "encoder" => stats.encoder = value.to_string(),
+            "drops" => stats.drops = value.parse().ok()?,
+            "drops_total" => stats.drops_total = value.parse().ok()?,
+            "follow" => stats.follow = value.to_string(),
+            _ => {}
+        }
+    }
+    Some(stats)
+}
+
+fn sender_bottleneck(stats: &SenderStats) -> Option<&'static str> {
+    let target = stats.target_fps as f64;
+    if target > 0.0 && stats.capture_fps < target * 0.9 {
+        Some("capture-limited")
+    } else if stats.fps < stats.capture_fps * 0.9 || stats.drops > 0 {
+        Some("crop/encode-limited")
+    } else {
+        None
+    }
+}
+
+fn start_sender_stats_listener(bind_ip: &str, rtp_port: u16) -> Result<(), String> {
+    let port = rtp_port
+        .checked_add(SENDER_STATS_PORT_OFFSET)
+        .ok_or_else(|| format!("no stats port above RTP port {rtp_port}"))?;
+    let socket = UdpSocket::bind((bind_ip, port)).map_err(|e| format!("bind {bind_ip}:{port}: {e}"))?;
+    thread::spawn(move || {
+        let mut buf = [0u8; 1500];
+        let mut last_log: Option<Instant> = None;
+        loop {
+            let Ok(len) = socket.recv(&mut buf) else {
+                return;
+            };
+            let Some(stats) = parse_sender_stats(&String::from_utf8_lossy(&buf[..len])) else {
+                continue;
+            };
+            if last_log.is_some_and(|t| t.elapsed() < Duration::from_secs(SENDER_STATS_LOG_SECS)) {
+                continue;
+            }
+            last_log = Some(Instant::now());
+            let mut line = format!(
+                "Sender: {:.1}/{} fps (capture {:.1}), {} kbps {}, drops {} (total {}), follow {}",
+                stats.fps,
+                stats.target_fps,
+                stats.capture_fps,
+                stats.bitrate_kbps,
+                stats.encoder,
+                stats.drops,
+                stats.drops_total,
+                stats.follow
+            );
+            if let Some(hint) = sender_bottleneck(&stats) {
+                line.push_str(&format!(" [{hint}]"));
+            }
+            println!("{line}");
+        }
+    });
+    Ok(())
+}
+
+fn run_record_rtp(path: &Path, bind_ip: &str, port: u16) -> ExitCode {
+    let socket = match UdpSocket::bind((bind_ip, port)) {
+        Ok(socket) => socket,
+        Err(err) => {
+            eprintln!("FAIL: could not bind {bind_ip}:{port}: {err}");
+            return ExitCode::from(1);
+        }
+    };
+    let mut file = match File::create(path) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("FAIL: could not create {}: {err}", path.display());
+            return ExitCode::from(1);
+        }
+    };
+    if let Err(err) = file.write_all(RTP_CAPTURE_MAGIC) {
+        eprintln!("FAIL: write {}: {err}", path.display());
+        return ExitCode::from(1);
+    }
+
+    println!(
+        "Recording RTP from {bind_ip}:{port} to {} (Ctrl-C to stop)...",
+        path.display()
+    );
+    // Record layout: [elapsed_us u64 LE][len u16 LE][packet], timed from the first packet.
+    let mut buf = [0u8; 65536];
+    let mut record = Vec::with_capacity(buf.len() + 10);
+    let mut started: Option<Instant> = None;
+    let mut last_report = Instant::now();
+    let mut packets = 0u64;
+    let mut bytes = 0u64;
+    loop {
+        let len = match socket.recv(&mut buf) {
+            Ok(len) => len,
+            Err(err) => {
+                eprintln!("FAIL: receive on {bind_ip}:{port}: {err}");
+                return ExitCode::from(1);
+            }
+        };
+        let elapsed_us = started.get_or_insert_with(Instant::now).elapsed().as_micros() as u64;
+        record.clear();
+        record.extend_from_slice(&elapsed_us.to_le_bytes());
+        record.extend_from_slice(&(len as u16).to_le_bytes());
+        record.extend_from_slice(&buf[..len]);
+        if let Err(err) = file.write_all(&record) {
+            eprintln!("FAIL: write {}: {err}", path.display());
+            return ExitCode::from(1);
+        }
+        packets += 1;
+        bytes += len as u64;
+        if last_report.elapsed() >= Duration::from_secs(RTP_RECORD_REPORT_SECS) {
+            println!("Recorded {packets} packets ({} KiB).", bytes / 1024);
+            last_report = Instant::now();
+        }
+    }
+}
+
+fn open_rtp_capture(path: &Path) -> Result<BufReader<File>, String> {
+    let file = File::open(path).map_err(|e| format!("open {}: {e}", path.display()))?;
+    let mut reader = BufReader::new(file);
+    let mut magic = [0u8; 8];
+    reader
+        .read_exact(&mut magic)
+        .map_err(|e| format!("read {}: {e}", path.display()))?;
+    if &magic != RTP_CAPTURE_MAGIC {
+        return Err(format!("{} is not a vp-rcvr RTP capture", path.display()));
+    }
+    Ok(reader)
+}
+
+fn read_rtp_record(reader: &mut BufReader<File>, packet: &mut Vec<u8>) -> Result<Option<u64>, String> {
+    let mut header = [0u8; 10];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(format!("read capture: {err}")),
+    }
+    let elapsed_us = u64::from_le_bytes([
+        header[0], header[1], header[2], header[3], header[4], header[5], header[6], header[7],
+    ]);
+    let len = u16::from_le_bytes([header[8], header[9]]) as usize;
+    packet.resize(len, 0);
+    reader
+        .read_exact(packet)
+        .map_err(|e| format!("truncated capture record: {e}"))?;
+    Ok(Some(elapsed_us))
+}
+
+fn replay_rtp_capture(mut reader: BufReader<File>, port: u16) -> Result<u64, String> {
+    let socket = UdpSocket::bind(("127.0.0.1", 0)).map_err(|e| format!("bind replay socket: {e}"))?;
+    let started = Instant::now();
+    let mut packet = Vec::new();
+    let mut packets = 0u64;
+    while let Some(elapsed_us) = read_rtp_record(&mut reader, &mut packet)? {
+        let due = started + Duration::from_micros(elapsed_us);
+        let now = Instant::now();
+        if due > now {
+            thread::sleep(due - now);
+        }
+        socket
+            .send_to(&packet, ("127.0.0.1", port))
+            .map_err(|e| format!("send to 127.0.0.1:{port}: {e}"))?;
+        packets += 1;
+    }
+    Ok(packets)
+}
+
+struct PreviewWindow {
+    id: String,
+    width: i32,
+    height: i32,
+}
+
+struct MonitorRect {
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
+fn place_preview_window(position: Option<&str>, monitor: Option<u32>, on_top: bool) {
+    let deadline = Instant::now() + Duration::from_secs(PREVIEW_WINDOW_WAIT_SECS);
+    let window = loop {
+        match find_preview_window() {
+            Ok(Some(window)) => break window,
+            Ok(None) => {}
+            Err(err) => {
+                eprintln!("WARN: preview placement unavailable: {err}");
+                return;
+            }
+        }
+        if Instant::now() >= deadline {
+            eprintln!("WARN: preview window did not appear within {PREVIEW_WINDOW_WAIT_SECS}s; skipping placement");
+            return;
+        }
+        thread::sleep(Duration::from_millis(200));
+    };
+
+    if position.is_some() || monitor.is_some() {
+        let monitors = list_monitors();
+        let index = monitor.unwrap_or(0) as usize;
+        match monitors.get(index) {
+            Some(mon) => {
+                let (x, y) = preview_origin(position.unwrap_or("center"), mon, &window);
+                run_wmctrl(&["-i", "-r", &window.id, "-e", &format!("0,{x},{y},-1,-1")]);
+                println!("Preview: placed at {x},{y} on monitor {index}");
+            }
+            None => eprintln!(
+                "WARN: monitor {index} not found ({} active); leaving preview where it is",
+                monitors.len()
+            ),
+        }
+    }
+    if on_top {
+        run_wmctrl(&["-i", "-r", &window.id, "-b", "add,above"]);
+    }
+}
+
+fn find_preview_window() -> Result<Option<PreviewWindow>, String> {
+    let output = Command::new("wmctrl")
+        .arg("-lG")
+        .output()
+        .map_err(|e| format!("could not run wmctrl: {e}"))?;
+    if !output.status.success() {
+        return Err("wmctrl -lG failed".to_string());
+    }
+    // Columns: id desktop x y width height host title...
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let cols: Vec<&str> = line.split_whitespace().collect();
+        if cols.len() < 8 || cols[7..].join(" ") != PREVIEW_WINDOW_TITLE {
+            continue;
+        }
+        let (Ok(width), Ok(height)) = (cols[4].parse::<i32>(), cols[5].parse::<i32>()) else {
+            continue;
+        };
+        return Ok(Some(PreviewWindow {
+            id: cols[0].to_string(),
+            width,
+            height,
+        }));
+    }
+    Ok(None)
+}
+
+fn list_monitors() -> Vec<MonitorRect> {
+    let output = match Command::new("xrandr").arg("--listactivemonitors").output() {
+        Ok(output) => output,
+        Err(err) => {
+            eprintln!("WARN: could not run xrandr: {err}");
+            return Vec::new();
+        }
+    };
+    // Lines look like: " 0: +*DP-1 2560/597x1440/336+0+0  DP-1"
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .skip(1)
+        .filter_map(|line| parse_monitor_geometry(line.split_whitespace().nth(2)?))
+        .collect()
+}
+
+fn parse_monitor_geometry(geom: &str) -> Option<MonitorRect> {
+    let (w, rest) = geom.split_once('x')?;
+    let width = w.split('/').next()?.parse().ok()?;
+    let (h, rest) = rest.split_once('/')?;
+    let mut offsets = rest.split('+').skip(1);
+    Some(MonitorRect {
+        x: offsets.next()?.parse().ok()?,
+        y: offsets.next()?.parse().ok()?,
+        width,
+        height: h.parse().ok()?,
+    })
+}
+
+fn preview_origin(position: &str, mon: &MonitorRect, window: &PreviewWindow) -> (i32, i32) {
+    let right = mon.x + mon.width - window.width;
+    let bottom = mon.y + mon.height - window.height;
+    match position {
+        "top-left" => (mon.x, mon.y),
+        "top-right" => (right, mon.y),
+        "bottom-left" => (mon.x, bottom),
+        "bottom-right" => (right, bottom),
+        "center" => (
+            mon.x + (mon.width - window.width) / 2,
+            mon.y + (mon.height - window.height) / 2,
+        ),
+        other => {
+            // X,Y was validated in parse_preview_position; it is relative to the monitor.
+            let (x, y) = other.split_once(',').unwrap_or(("0", "0"));
+            (
+                mon.x + x.parse::<i32>().unwrap_or(0),
+                mon.y + y.parse::<i32>().unwrap_or(0),
+            )
+        }
+    }
+}
+
+fn run_wmctrl(args: &[&str]) {
+    match Command::new("wmctrl").args(args).status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => eprintln!(
+            "WARN: wmctrl {} exited with code {}",
+            args.join(" "),
+            status.code().unwrap_or(-1)
+        ),
+        Err(err) => eprintln!("WARN: could not run wmctrl: {err}"),
+    }
 }
@@ -0,0 +1,312 @@
+//! WHEP client for `receive --whep-url`.
+//!
+//! webrtcbin makes a receive-only offer and gathers all of its ICE candidates into
+//! it before the offer is POSTed, so the endpoint's answer completes the exchange
+//! and no trickle ICE is needed. The session resource named by the `Location` header
+//! is DELETEd when the session ends. webrtcbin takes a single offer/answer, so every
+//! new session gets a fresh one, linked to the `webrtc_in` capsfilter of the pipeline.
+//! Only plain `http://` endpoints are supported.
+
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use gstreamer_sdp as gst_sdp;
+use gstreamer_webrtc as gst_webrtc;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const HTTP_TIMEOUT_SECS: u64 = 10;
+const ICE_GATHER_TIMEOUT_SECS: u64 = 5;
+const ICE_GATHER_POLL_MS: u64 = 50;
+
+pub struct Session {
+    url: String,
+    token: Option<String>,
+    caps: gst::Caps,
+    latency_ms: u32,
+    webrtc: Option<gst::Element>,
+    resource: Option<String>,
+}
+
+struct Reply {
+    status: u16,
+    location: Option<String>,
+    body: String,
+}
+
+impl Session {
+    pub fn new(url: &str, token: Option<&str>, mut caps: gst::Caps, latency_ms: u32) -> Self {
+        // WHEP servers expect packetization-mode=1 in an H.264 offer, as browsers send it.
+        if let Some(s) = caps.make_mut().structure_mut(0)
+            && s.get::<&str>("encoding-name")
+                .is_ok_and(|name| name == "H264")
+        {
+            s.set("packetization-mode", "1");
+        }
+        Session {
+            url: url.to_string(),
+            token: token.map(str::to_string),
+            caps,
+            latency_ms,
+            webrtc: None,
+            resource: None,
+        }
+    }
+
+    /// Ends any earlier session and starts a new one. Failures of the endpoint or
+    /// of ICE are posted as errors of the `webrtc` element, like a dropped SRT or
+    /// RTSP connection, so the receive loop retries them.
+    pub fn connect(&mut self, pipeline: &gst::Pipeline) -> Result<(), String> {
+        self.close(pipeline);
+        let sink = pipeline
+            .by_name("webrtc_in")
+            .and_then(|filter| filter.static_pad("sink"))
+            .ok_or_else(|| "receive pipeline has no webrtc_in".to_string())?;
+        let webrtc = gst::ElementFactory::make("webrtcbin")
+            .name("webrtc")
+            .property_from_str("bundle-policy", "max-bundle")
+            .property("latency", self.latency_ms)
+            .build()
+            .map_err(|_| "webrtcbin is not installed (gst-plugins-bad)".to_string())?;
+        webrtc.connect_pad_added(move |_, pad| {
+            if pad.direction() == gst::PadDirection::Src
+                && !sink.is_linked()
+                && let Err(err) = pad.link(&sink)
+            {
+                eprintln!("WARN: could not link webrtcbin: {err:?}");
+            }
+        });
+        webrtc.connect_notify(Some("connection-state"), |webrtc, _| {
+            let state =
+                webrtc.property::<gst_webrtc::WebRTCPeerConnectionState>("connection-state");
+            if state == gst_webrtc::WebRTCPeerConnectionState::Failed {
+                gst::element_error!(webrtc, gst::ResourceError::Read, ["ICE connection failed"]);
+            }
+        });
+        pipeline
+            .add(&webrtc)
+            .map_err(|_| "could not add webrtcbin to the receive pipeline".to_string())?;
+        webrtc
+            .sync_state_with_parent()
+            .map_err(|_| "could not start webrtcbin".to_string())?;
+        self.webrtc = Some(webrtc.clone());
+        match negotiate(&webrtc, &self.url, self.token.as_deref(), &self.caps) {
+            Ok(resource) => self.resource = resource,
+            Err(err) => gst::element_error!(webrtc, gst::ResourceError::OpenRead, ["{}", err]),
+        }
+        Ok(())
+    }
+
+    /// Tells the endpoint the session is over and takes webrtcbin out of the pipeline.
+    pub fn close(&mut self, pipeline: &gst::Pipeline) {
+        if let Some(resource) = self.resource.take() {
+            match request("DELETE", &resource, self.token.as_deref(), None) {
+                Ok(reply) if reply.status < 300 => {}
+                Ok(reply) => eprintln!("WARN: WHEP endpoint answered {} to DELETE", reply.status),
+                Err(err) => eprintln!("WARN: could not end WHEP session: {err}"),
+            }
+        }
+        if let Some(webrtc) = self.webrtc.take() {
+            let _ = webrtc.set_state(gst::State::Null);
+            let _ = pipeline.remove(&webrtc);
+        }
+    }
+}
+
+fn negotiate(
+    webrtc: &gst::Element,
+    url: &str,
+    token: Option<&str>,
+    caps: &gst::Caps,
+) -> Result<Option<String>, String> {
+    webrtc.emit_by_name::<gst_webrtc::WebRTCRTPTransceiver>(
+        "add-transceiver",
+        &[&gst_webrtc::WebRTCRTPTransceiverDirection::Recvonly, caps],
+    );
+    let promise = gst::Promise::new();
+    webrtc.emit_by_name::<()>("create-offer", &[&None::<gst::Structure>, &promise]);
+    let offer = match promise.wait() {
+        gst::PromiseResult::Replied => promise.get_reply().and_then(|reply| {
+            reply
+                .get::<gst_webrtc::WebRTCSessionDescription>("offer")
+                .ok()
+        }),
+        _ => None,
+    }
+    .ok_or_else(|| "webrtcbin could not create an offer".to_string())?;
+    webrtc.emit_by_name::<()>("set-local-description", &[&offer, &None::<gst::Promise>]);
+
+    let deadline = Instant::now() + Duration::from_secs(ICE_GATHER_TIMEOUT_SECS);
+    while webrtc.property::<gst_webrtc::WebRTCICEGatheringState>("ice-gathering-state")
+        != gst_webrtc::WebRTCICEGatheringState::Complete
+    {
+        if Instant::now() >= deadline {
+            eprintln!(
+                "WARN: ICE gathering did not finish within {ICE_GATHER_TIMEOUT_SECS}s; offering the candidates found so far"
+            );
+            break;
+        }
+        thread::sleep(Duration::from_millis(ICE_GATHER_POLL_MS));
+    }
+    // The local description now carries the gathered candidates; the offer does not.
+    let sdp = webrtc
+        .property::<Option<gst_webrtc::WebRTCSessionDescription>>("local-description")
+        .unwrap_or(offer)
+        .sdp()
+        .as_text()
+        .map_err(|e| format!("could not write the offer: {e}"))?;
+
+    let reply = request("POST", url, token, Some(&sdp))?;
+    if !matches!(reply.status, 200 | 201) {
+        return Err(format!(
+            "WHEP endpoint answered {}: {}",
+            reply.status,
+            reply.body.trim()
+        ));
+    }
+    let answer = gst_sdp::SDPMessage::parse_buffer(reply.body.as_bytes())
+        .map_err(|_| "WHEP endpoint did not answer with SDP".to_string())?;
+    let answer =
+        gst_webrtc::WebRTCSessionDescription::new(gst_webrtc::WebRTCSDPType::Answer, answer);
+    webrtc.emit_by_name::<()>("set-remote-description", &[&answer, &None::<gst::Promise>]);
+    Ok(reply.location.map(|location| resolve(url, &location)))
+}
+
+/// Splits `http://host[:port]/path` into the authority and the path.
+pub fn split_url(url: &str) -> Result<(String, String), String> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("invalid WHEP URL: {url} (expected http://HOST[:PORT]/PATH)"))?;
+    let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    if authority.is_empty() {
+        return Err(format!("invalid WHEP URL: {url} (no host)"));
+    }
+    let path = if path.is_empty() { "/" } else { path };
+    Ok((authority.to_string(), path.to_string()))
+}
+
+/// Whether URL names this machine, where a bearer token over plain HTTP does not
+/// leave it (e.g. a local TLS proxy in front of an `https://` endpoint).
+pub fn is_loopback(url: &str) -> bool {
+    let Ok((authority, _)) = split_url(url) else {
+        return false;
+    };
+    let host = match authority.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => authority.split(':').next().unwrap_or_default(),
+    };
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+/// The Location header may be absolute, host-relative or relative to the endpoint.
+fn resolve(url: &str, location: &str) -> String {
+    if location.starts_with("http://") || location.starts_with("https://") {
+        return location.to_string();
+    }
+    let Ok((authority, path)) = split_url(url) else {
+        return location.to_string();
+    };
+    if location.starts_with('/') {
+        format!("http://{authority}{location}")
+    } else {
+        let dir = path.rsplit_once('/').map_or("", |(dir, _)| dir);
+        format!("http://{authority}{dir}/{location}")
+    }
+}
+
+fn request(
+    method: &str,
+    url: &str,
+    token: Option<&str>,
+    sdp: Option<&str>,
+) -> Result<Reply, String> {
+    let (authority, path) = split_url(url)?;
+    // A port is present unless the authority is a bare name or a bracketed IPv6 address.
+    let addr = match authority.rsplit_once(':') {
+        Some((_, port)) if !port.ends_with(']') => authority.clone(),
+        _ => format!("{authority}:80"),
+    };
+    let mut stream =
+        TcpStream::connect(&addr).map_err(|e| format!("could not connect to {addr}: {e}"))?;
+    stream
+        .set_read_timeout(Some(Duration::from_secs(HTTP_TIMEOUT_SECS)))
+        .map_err(|e| format!("{addr}: {e}"))?;
+    let body = sdp.unwrap_or_default();
+    let mut head =
+        format!("{method} {path} HTTP/1.1\r\nHost: {authority}\r\nConnection: close\r\n");
+    if let Some(token) = token {
+        head.push_str(&format!("Authorization: Bearer {token}\r\n"));
+    }
+    if sdp.is_some() {
+        head.push_str("Content-Type: application/sdp\r\n");
+    }
+    head.push_str(&format!("Content-Length: {}\r\n\r\n", body.len()));
+    stream
+        .write_all(head.as_bytes())
+        .and_then(|()| stream.write_all(body.as_bytes()))
+        .map_err(|e| format!("could not send to {addr}: {e}"))?;
+
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader
+        .read_line(&mut line)
+        .map_err(|e| format!("no reply from {addr}: {e}"))?;
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| format!("bad reply from {addr}: {}", line.trim()))?;
+    let mut location = None;
+    let mut chunked = false;
+    loop {
+        line.clear();
+        reader
+            .read_line(&mut line)
+            .map_err(|e| format!("bad reply from {addr}: {e}"))?;
+        let Some((name, value)) = line.trim_end().split_once(':') else {
+            break;
+        };
+        match name.to_ascii_lowercase().as_str() {
+            "location" => location = Some(value.trim().to_string()),
+            "transfer-encoding" => chunked = value.to_ascii_lowercase().contains("chunked"),
+            _ => {}
+        }
+    }
+    // Connection: close, so the body runs to the end of the stream.
+    let mut body = String::new();
+    reader
+        .read_to_string(&mut body)
+        .map_err(|e| format!("bad reply from {addr}: {e}"))?;
+    if chunked {
+        body = dechunk(&body);
+    }
+    Ok(Reply {
+        status,
+        location,
+        body,
+    })
+}
+
+fn dechunk(body: &str) -> String {
+    let mut out = String::new();
+    let mut rest = body;
+    while let Some((size, tail)) = rest.split_once("\r\n") {
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let Ok(size) = usize::from_str_radix(size, 16) else {
+            break;
+        };
+        let (Some(chunk), Some(next)) = (tail.get(..size), tail.get(size..)) else {
+            break;
+        };
+        if size == 0 {
+            break;
+        }
+        out.push_str(chunk);
+        rest = next.trim_start_matches("\r\n");
+    }
+    out
+}
@@ -1,5 +1,11 @@
-use ashpd::desktop::screencast::{CursorMode, Screencast, SourceType};
-use ashpd::desktop::PersistMode;
+mod screencopy;
+mod signaling;
+
+use ashpd::desktop::global_shortcuts::{GlobalShortcuts, NewShortcut};
+use ashpd::desktop::screencast::{CursorMode, Screencast, SourceType, Stream};
+use ashpd::desktop::{PersistMode, Session};
+use clap::{CommandFactory, Parser, Subcommand};
+use cosmic_client_toolkit::cosmic_protocols::toplevel_info::v1::client::zcosmic_toplevel_handle_v1;
 use cosmic_client_toolkit::screencopy::{
     CaptureCursorSession, CaptureFrame, CaptureSession, CaptureSource, FailureReason, Formats,
     Frame, ScreencopyCursorSessionData, ScreencopyCursorSessionDataExt, ScreencopyHandler,
@@ -10,41 +16,200 @@ use cosmic_client_toolkit::sctk::output::{OutputHandler, OutputState};
 use cosmic_client_toolkit::sctk::registry::{ProvidesRegistryState, RegistryState};
 use cosmic_client_toolkit::sctk::seat::pointer::{PointerEvent, PointerHandler};
 use cosmic_client_toolkit::sctk::seat::{Capability, SeatHandler, SeatState};
+use cosmic_client_toolkit::toplevel_info::{ToplevelInfoHandler, ToplevelInfoState};
 use cosmic_client_toolkit::wayland_client::globals::registry_queue_init as wl_registry_queue_init;
 use cosmic_client_toolkit::wayland_client::protocol::{wl_buffer, wl_output, wl_pointer, wl_seat};
 use cosmic_client_toolkit::wayland_client::{
     Connection as WlConnection, QueueHandle as WlQueueHandle, WEnum,
 };
-use cosmic_client_toolkit::{delegate_screencopy, wayland_client::delegate_noop};
-use evdev::{Device, EventSummary, EventType, RelativeAxisCode};
+use cosmic_client_toolkit::wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_handle_v1;
+use cosmic_client_toolkit::{
+    delegate_screencopy, delegate_toplevel_info, wayland_client::delegate_noop,
+};
+use futures_util::StreamExt;
 use gstreamer as gst;
 use gstreamer::prelude::*;
 use gstreamer_app::{AppSink, AppSinkCallbacks, AppSrc};
+use gstreamer_rtp as gst_rtp;
+use gstreamer_rtsp_server as gst_rtsp_server;
+use gstreamer_rtsp_server::prelude::*;
+use gstreamer_sdp as gst_sdp;
 use gstreamer_video as gst_video;
+use gstreamer_webrtc as gst_webrtc;
+use inotify::{Inotify, WatchMask};
+use input::event::pointer::{ButtonState, PointerEvent as LibinputPointerEvent};
+use input::event::{Event as LibinputEvent, EventTrait};
+use input::{DeviceCapability, Libinput, LibinputInterface};
 use ksni::menu::{MenuItem, StandardItem};
 use ksni::{Icon, Tray, TrayService};
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
+use signaling::SignalEvent;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::fs;
+use std::net::UdpSocket;
+use std::os::fd::OwnedFd;
+use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::net::UnixStream;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::{Command, ExitCode, Stdio};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::mpsc;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use vp_color::colorimetry_field;
+use vp_config::{config_value, edit_config_value};
+use vp_control as control;
+use vp_metrics::Metrics;
 
 const PORTAL_TIMEOUT_SECS: u64 = 15;
 const DEFAULT_WIDTH: u32 = 1280;
 const DEFAULT_HEIGHT: u32 = 720;
 const DEFAULT_QUEUE_BUFFERS: u32 = 8;
+const DEFAULT_CLOCK_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+/// Gap between a watermark and the edges of the frame.
+const WATERMARK_MARGIN_PX: i32 = 16;
 const DEFAULT_MOUSE_SMOOTHING: f64 = 8.0;
 const DEFAULT_CURSOR_CHANGE_EPSILON_PX: f64 = 0.25;
 const DEFAULT_SETTLE_EPSILON_PX: f64 = 0.75;
+const CONTROL_REPLY_TIMEOUT_SECS: u64 = 60;
+const DBUS_NAME: &str = "org.vplink.Sender1";
+const DBUS_PATH: &str = "/org/vplink/Sender1";
+// Raw formats the CPU crop copies as they arrive, plane by plane, so videoconvert
+// only runs on the cropped frame; other sources are converted to one of them. The
+// cursor and click overlays draw into RGBA.
+const CROP_FORMATS: &str = "{BGRx,BGRA,RGBx,RGBA,xRGB,ARGB,xBGR,ABGR,NV12,I420}";
+// With --bit-depth 10 the 10-bit layouts come first, so a 10-bit source is kept
+// as it is; an 8-bit one still passes through in its own format.
+const CROP_FORMATS_10BIT: &str =
+    "{P010_10LE,I420_10LE,BGR10A2_LE,RGB10A2_LE,BGRx,BGRA,RGBx,RGBA,xRGB,ARGB,xBGR,ABGR,NV12,I420}";
+// Crops of at least this many bytes are copied in bands on a pool of up to
+// CROP_COPY_THREADS threads; below it handing out the bands costs more than it saves.
+const CROP_PARALLEL_MIN_BYTES: usize = 1 << 20;
+const CROP_COPY_THREADS: usize = 4;
+// While paused the last frame is repeated this often so the receiver keeps a picture.
+const PAUSE_FRAME_INTERVAL_MS: u64 = 1000;
+// --idle-fps: how long the viewport must stay unchanged before the rate drops.
+const IDLE_AFTER_MS: u64 = 2000;
+// GlobalShortcuts trigger in the xdg shortcuts format; the id names it in the
+// desktop's shortcut settings.
+const DEFAULT_FOLLOW_SHORTCUT: &str = "CTRL+ALT+f";
+const FOLLOW_SHORTCUT_ID: &str = "toggle-follow-mouse";
+// `calibrate`: sampling interval, default run time, and the least cursor travel a
+// device needs before its scale is trusted.
+const CALIBRATE_SAMPLE_MS: u64 = 50;
+const DEFAULT_CALIBRATE_SECS: u64 = 15;
+const CALIBRATE_MIN_TRAVEL_PX: f64 = 2000.0;
+const MAX_TEMPORAL_LAYERS: u32 = 4;
+// Stats go to the receiver on RTP port + 3; +1/+2 stay free for RTCP.
+const STATS_PORT_OFFSET: u16 = 3;
+const STATS_INTERVAL_MS: u64 = 1000;
+const EOS_FLUSH_TIMEOUT_SECS: u64 = 5;
+// Restart after a pipeline error: the wait doubles from MIN to MAX and starts over
+// once a run has streamed for STABLE seconds.
+const RESTART_BACKOFF_MIN_SECS: u64 = 1;
+const RESTART_BACKOFF_MAX_SECS: u64 = 60;
+const RESTART_STABLE_SECS: u64 = 60;
+const DEFAULT_SRT_LATENCY_MS: u32 = 120;
+const RTSP_MOUNT_PATH: &str = "/viewport";
+// SRTP master key (16 bytes) + salt (14 bytes) for AES_CM_128_HMAC_SHA1_80.
+const SRTP_KEY_LEN: usize = 30;
+// Dynamic payload type for ULPFEC packets; must match vp-rcvr.
+const FEC_PAYLOAD_TYPE: u32 = 122;
+// RTX (RFC 4588) payload type for retransmissions of payload type 96.
+const MEDIA_PAYLOAD_TYPE: u32 = 96;
+const RTX_PAYLOAD_TYPE: u32 = 97;
+// RFC 6051 64-bit NTP header extension carrying each frame's capture time, so
+// vp-rcvr can measure glass-to-glass latency; the ID must match vp-rcvr.
+const CAPTURE_TIME_EXT_ID: u32 = 1;
+const NTP_UNIX_OFFSET_SECS: u64 = 2_208_988_800;
+// How long sent packets are kept for retransmission.
+const RTX_HISTORY_MS: u32 = 1000;
+// The Opus stream goes to the receiver on RTP port + 2; the sender's own RTCP
+// socket on that port is on the other host.
+const AUDIO_PORT_OFFSET: u16 = 2;
+const AUDIO_PAYLOAD_TYPE: u32 = 111;
+const AUDIO_BITRATE_BPS: u32 = 128_000;
+// --follow-predict-ms: cursor velocity is an exponential average of per-frame
+// motion; below the minimum speed the prediction is treated as zero.
+const MAX_FOLLOW_PREDICT_MS: u32 = 500;
+const FOLLOW_VELOCITY_SMOOTHING: f64 = 0.5;
+const FOLLOW_PREDICT_MIN_SPEED_PX_S: f64 = 30.0;
+// --click-highlight: a ring that widens and fades out over this time after each
+// button press.
+const CLICK_HIGHLIGHT_MS: u64 = 400;
+const CLICK_HIGHLIGHT_RADIUS_PX: f64 = 24.0;
+const CLICK_HIGHLIGHT_THICKNESS_PX: f64 = 4.0;
+const CLICK_HIGHLIGHT_RGB: [u8; 3] = [255, 210, 0];
+/// Side of the squares a `blur` mask is pixelated into; large enough that text in
+/// it cannot be read back.
+const MASK_BLOCK_PX: usize = 24;
+// --roi-radius: QP offset for the square around the cursor. The encoder's rate
+// control takes the bits back from the rest of the frame.
+const ROI_DELTA_QP: i32 = -8;
+const MIN_ZOOM: f64 = 0.25;
+const MAX_ZOOM: f64 = 8.0;
+// --encoder auto: tried in this order; each must preroll a test frame at the stream
+// size within the timeout.
+const AUTO_ENCODERS: &[&str] = &[
+    "nvh265enc",
+    "vaapih265enc",
+    "vah265enc",
+    "qsvh265enc",
+    "x265enc",
+    "x264enc",
+];
+const ENCODER_PROBE_TIMEOUT_SECS: u64 = 5;
+// Adaptive bitrate: react to each new receiver report, cut 20% on loss or jitter,
+// creep back up 5% per clean report, never below a quarter of --bitrate-kbps.
+const ABR_POLL_MS: u64 = 500;
+const ABR_LOSS_THRESHOLD_PCT: f64 = 2.0;
+const ABR_JITTER_THRESHOLD_MS: u32 = 30;
+const ABR_STEP_DOWN: f64 = 0.8;
+const ABR_STEP_UP: f64 = 1.05;
+const ABR_MIN_DIVISOR: u32 = 4;
+
+/// A region hidden in every frame before encoding, in source pixels: pixelated
+/// (`blur`) or painted over (`black`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct MaskSpec {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+    mode: String,
+}
+
+impl Default for MaskSpec {
+    fn default() -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            w: 0,
+            h: 0,
+            mode: "black".to_string(),
+        }
+    }
+}
+
+/// An extra crop of the same capture, encoded and sent as its own RTP stream.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ViewportSpec {
+    name: String,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    port: u16,
+    #[serde(default)]
+    receiver_ip: Option<String>,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 struct SenderConfig {
     receiver_ip: String,
     port: u16,
@@ -56,8 +221,72 @@ struct SenderConfig {
     follow_mouse: bool,
     smoothing: f64,
     deadzone: f64,
+    follow_margin: u32,
     encoder: String,
     bitrate_kbps: u32,
+    cursor_mode: String,
+    temporal_layers: u32,
+    transport: String,
+    srt_mode: String,
+    srt_latency_ms: u32,
+    srt_passphrase: Option<String>,
+    rtsp_port: Option<u16>,
+    srtp_key: Option<String>,
+    fec_percent: u32,
+    rtx: bool,
+    adaptive_bitrate: bool,
+    output: Option<String>,
+    source: String,
+    backend: String,
+    dmabuf: bool,
+    crop: String,
+    viewports: Vec<ViewportSpec>,
+    with_audio: bool,
+    audio_node: Option<String>,
+    zoom: f64,
+    cursor_backend: String,
+    follow_window: bool,
+    follow_predict_ms: u32,
+    roi_radius: u32,
+    click_highlight: bool,
+    draw_cursor: bool,
+    deadzone_y: Option<f64>,
+    follow_margin_y: Option<u32>,
+    follow_shortcut: Option<String>,
+    no_restart: bool,
+    stats_interval: Option<u64>,
+    metrics_addr: Option<String>,
+    insert_before_encoder: Option<String>,
+    va_device: Option<String>,
+    #[serde(deserialize_with = "deserialize_enc_opts")]
+    enc_opts: BTreeMap<String, String>,
+    rt_priority: Option<u32>,
+    cpu_affinity: Option<String>,
+    queue_buffers: u32,
+    leaky: String,
+    overlay_clock: bool,
+    overlay_clock_position: String,
+    overlay_clock_format: String,
+    watermark: Option<String>,
+    watermark_pos: String,
+    watermark_opacity: f64,
+    /// `[[mask]]` tables in the file.
+    #[serde(rename = "mask")]
+    masks: Vec<MaskSpec>,
+    fit: String,
+    lock_aspect: bool,
+    scale_width: Option<u32>,
+    scale_height: Option<u32>,
+    bit_depth: u32,
+    colorimetry: Option<String>,
+    color_range: Option<String>,
+    fps_mode: String,
+    idle_fps: Option<u32>,
+    idle_bitrate_kbps: Option<u32>,
+    delta_scale: BTreeMap<String, f64>,
+    /// `[profile.NAME]` tables: only the keys that differ from the settings above.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    profile: BTreeMap<String, toml::Table>,
 }
 
 impl Default for SenderConfig {
@@ -73,12 +302,100 @@ impl Default for SenderConfig {
             follow_mouse: false,
             smoothing: DEFAULT_MOUSE_SMOOTHING,
             deadzone: 0.0,
-            encoder: "x265enc".to_string(),
+            follow_margin: 0,
+            encoder: "auto".to_string(),
             bitrate_kbps: 8000,
+            cursor_mode: "auto".to_string(),
+            temporal_layers: 1,
+            transport: "udp".to_string(),
+            srt_mode: "caller".to_string(),
+            srt_latency_ms: DEFAULT_SRT_LATENCY_MS,
+            srt_passphrase: None,
+            rtsp_port: None,
+            srtp_key: None,
+            fec_percent: 0,
+            rtx: false,
+            adaptive_bitrate: false,
+            output: None,
+            source: "monitor".to_string(),
+            backend: "portal".to_string(),
+            dmabuf: false,
+            crop: "cpu".to_string(),
+            viewports: Vec::new(),
+            with_audio: false,
+            audio_node: None,
+            zoom: 1.0,
+            cursor_backend: "auto".to_string(),
+            follow_window: false,
+            follow_predict_ms: 0,
+            roi_radius: 0,
+            click_highlight: false,
+            draw_cursor: false,
+            deadzone_y: None,
+            follow_margin_y: None,
+            follow_shortcut: None,
+            no_restart: false,
+            stats_interval: None,
+            metrics_addr: None,
+            insert_before_encoder: None,
+            va_device: None,
+            enc_opts: BTreeMap::new(),
+            rt_priority: None,
+            cpu_affinity: None,
+            queue_buffers: DEFAULT_QUEUE_BUFFERS,
+            leaky: "none".to_string(),
+            overlay_clock: false,
+            overlay_clock_position: "top-left".to_string(),
+            overlay_clock_format: DEFAULT_CLOCK_FORMAT.to_string(),
+            watermark: None,
+            watermark_pos: "top-right".to_string(),
+            watermark_opacity: 1.0,
+            masks: Vec::new(),
+            fit: "shrink".to_string(),
+            lock_aspect: false,
+            scale_width: None,
+            scale_height: None,
+            bit_depth: 8,
+            colorimetry: None,
+            color_range: None,
+            fps_mode: "passthrough".to_string(),
+            idle_fps: None,
+            idle_bitrate_kbps: None,
+            delta_scale: BTreeMap::new(),
+            profile: BTreeMap::new(),
         }
     }
 }
 
+/// `[enc_opts]` values may be written as numbers or booleans in the file (or by
+/// `config set`); the encoder gets them as text either way.
+fn deserialize_enc_opts<'de, D>(deserializer: D) -> Result<BTreeMap<String, String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Scalar {
+        Text(String),
+        Int(i64),
+        Float(f64),
+        Bool(bool),
+    }
+    let raw = BTreeMap::<String, Scalar>::deserialize(deserializer)?;
+    Ok(raw
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Scalar::Text(text) => text,
+                Scalar::Int(n) => n.to_string(),
+                Scalar::Float(x) => x.to_string(),
+                Scalar::Bool(b) => b.to_string(),
+            };
+            (key, value)
+        })
+        .collect())
+}
+
 fn config_path() -> Result<PathBuf, String> {
     let mut dir = dirs::config_dir().ok_or_else(|| "could not resolve config directory".to_string())?;
     dir.push("vp-link");
@@ -86,6 +403,48 @@ fn config_path() -> Result<PathBuf, String> {
     Ok(dir)
 }
 
+/// The portal grants one restore token per selection, so the file is keyed by what
+/// was selected; a token for a window must not be replayed for `--source monitor`.
+fn restore_token_path(source: &str, output: Option<&str>) -> Result<PathBuf, String> {
+    let mut dir = dirs::config_dir().ok_or_else(|| "could not resolve config directory".to_string())?;
+    dir.push("vp-link");
+    let name = match output {
+        Some(output) => {
+            let output: String = output
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || c == '-' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+            format!("vp-sndr-{source}-{output}.restore-token")
+        }
+        None => format!("vp-sndr-{source}.restore-token"),
+    };
+    dir.push(name);
+    Ok(dir)
+}
+
+fn load_restore_token(path: &Path) -> Option<String> {
+    let token = fs::read_to_string(path).ok()?;
+    let token = token.trim();
+    if token.is_empty() {
+        None
+    } else {
+        Some(token.to_string())
+    }
+}
+
+fn save_restore_token(path: &Path, token: &str) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("create dir {}: {e}", parent.display()))?;
+    }
+    fs::write(path, format!("{token}\n")).map_err(|e| format!("write {}: {e}", path.display()))
+}
+
 fn load_config() -> SenderConfig {
     let path = match config_path() {
         Ok(p) => p,
@@ -117,6 +476,259 @@ fn save_config(cfg: &SenderConfig) -> Result<(), String> {
     Ok(())
 }
 
+/// The saved settings with `[profile.NAME]` laid over them, or just the saved
+/// settings without a profile.
+fn load_profile(name: Option<&str>) -> Result<SenderConfig, String> {
+    let cfg = load_config();
+    match name {
+        Some(name) => apply_profile(&cfg, name),
+        None => Ok(cfg),
+    }
+}
+
+fn apply_profile(cfg: &SenderConfig, name: &str) -> Result<SenderConfig, String> {
+    let overrides = cfg.profile.get(name).cloned().ok_or_else(|| {
+        let known: Vec<&str> = cfg.profile.keys().map(String::as_str).collect();
+        if known.is_empty() {
+            format!("unknown profile: {name} (the config has no [profile.*] tables)")
+        } else {
+            format!("unknown profile: {name} (known: {})", known.join(", "))
+        }
+    })?;
+    let mut table = toml::Table::try_from(cfg).map_err(|e| format!("serialize config: {e}"))?;
+    table.extend(overrides);
+    table
+        .try_into::<SenderConfig>()
+        .map_err(|e| format!("invalid [profile.{name}]: {e}"))
+}
+
+/// `config get KEY`. Keys are the names in the TOML file.
+fn config_get(key: &str) -> Result<String, String> {
+    let value =
+        serde_json::to_value(load_config()).map_err(|e| format!("serialize config: {e}"))?;
+    config_value(&value, key)
+}
+
+/// `config set KEY VALUE`, or `config unset KEY` with no value, which puts the
+/// default back. The edited config is checked like the `send` flags before
+/// it is written. A file that does not parse is left as it is rather than
+/// replaced with the defaults.
+fn config_set(key: &str, value: Option<&str>) -> Result<(), String> {
+    let path = config_path()?;
+    let cfg = match fs::read_to_string(&path) {
+        Ok(data) => toml::from_str::<SenderConfig>(&data)
+            .map_err(|e| format!("parse {}: {e}", path.display()))?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => SenderConfig::default(),
+        Err(err) => return Err(format!("read {}: {err}", path.display())),
+    };
+    let mut doc = serde_json::to_value(cfg).map_err(|e| format!("serialize config: {e}"))?;
+    edit_config_value(&mut doc, key, value)?;
+    let cfg = serde_json::from_value::<SenderConfig>(doc)
+        .map_err(|e| format!("invalid value for {key}: {e}"))?;
+    check_config(&cfg)?;
+    save_config(&cfg)
+}
+
+/// The value checks of the `send` flags, for settings written by `config set`.
+/// Profiles are checked as they would be run, on top of the top-level settings.
+fn check_config(cfg: &SenderConfig) -> Result<(), String> {
+    check_settings(cfg)?;
+    for name in cfg.profile.keys() {
+        check_settings(&apply_profile(cfg, name)?).map_err(|e| format!("[profile.{name}]: {e}"))?;
+    }
+    Ok(())
+}
+
+fn check_settings(cfg: &SenderConfig) -> Result<(), String> {
+    for (key, value) in [
+        ("width", cfg.width),
+        ("height", cfg.height),
+        ("fps", cfg.fps),
+        ("bitrate_kbps", cfg.bitrate_kbps),
+        ("queue_buffers", cfg.queue_buffers),
+    ] {
+        if value == 0 {
+            return Err(format!("{key} must be > 0"));
+        }
+    }
+    for (key, value, choices) in [
+        (
+            "colorimetry",
+            &cfg.colorimetry,
+            &["bt709", "bt601", "bt2020"][..],
+        ),
+        ("color_range", &cfg.color_range, &["full", "limited"][..]),
+    ] {
+        if let Some(value) = value
+            && !choices.contains(&value.as_str())
+        {
+            return Err(format!(
+                "invalid {key}: {value} (expected {})",
+                choices.join(", ")
+            ));
+        }
+    }
+    for (key, value) in [
+        ("idle_fps", cfg.idle_fps),
+        ("idle_bitrate_kbps", cfg.idle_bitrate_kbps),
+    ] {
+        if value == Some(0) {
+            return Err(format!("{key} must be > 0"));
+        }
+    }
+    if cfg.idle_bitrate_kbps.is_some() && cfg.idle_fps.is_none() {
+        return Err("idle_bitrate_kbps needs idle_fps".to_string());
+    }
+    if cfg.bit_depth != 8 && cfg.bit_depth != 10 {
+        return Err(format!(
+            "invalid bit_depth: {} (expected 8 or 10)",
+            cfg.bit_depth
+        ));
+    }
+    for (key, value, choices) in [
+        ("transport", &cfg.transport, &["udp", "srt", "webrtc"][..]),
+        ("srt_mode", &cfg.srt_mode, &["caller", "listener"][..]),
+        ("source", &cfg.source, &["monitor", "window"][..]),
+        ("backend", &cfg.backend, &["portal", "screencopy"][..]),
+        ("crop", &cfg.crop, &["cpu", "gl", "element"][..]),
+        ("leaky", &cfg.leaky, &["upstream", "downstream", "none"][..]),
+        ("fit", &cfg.fit, &["shrink", "letterbox"][..]),
+        (
+            "fps_mode",
+            &cfg.fps_mode,
+            &["drop", "duplicate", "passthrough"][..],
+        ),
+        (
+            "overlay_clock_position",
+            &cfg.overlay_clock_position,
+            &["top-left", "top-right", "bottom-left", "bottom-right"][..],
+        ),
+        (
+            "watermark_pos",
+            &cfg.watermark_pos,
+            &["top-left", "top-right", "bottom-left", "bottom-right"][..],
+        ),
+        (
+            "cursor_backend",
+            &cfg.cursor_backend,
+            &["auto", "wayland", "libinput"][..],
+        ),
+    ] {
+        if !choices.contains(&value.as_str()) {
+            return Err(format!(
+                "invalid {key}: {value} (expected {})",
+                choices.join(", ")
+            ));
+        }
+    }
+    parse_cursor_mode(&cfg.cursor_mode)?;
+    if cfg.smoothing <= 0.0 {
+        return Err("smoothing must be > 0".to_string());
+    }
+    if !(0.0..=100.0).contains(&cfg.deadzone)
+        || !(0.0..=100.0).contains(&cfg.deadzone_y.unwrap_or(cfg.deadzone))
+    {
+        return Err("deadzone must be between 0 and 100".to_string());
+    }
+    if !(MIN_ZOOM..=MAX_ZOOM).contains(&cfg.zoom) {
+        return Err(format!("zoom must be between {MIN_ZOOM} and {MAX_ZOOM}"));
+    }
+    match (cfg.scale_width, cfg.scale_height) {
+        (Some(0), _) | (_, Some(0)) => {
+            return Err("scale_width and scale_height must be > 0".to_string());
+        }
+        (Some(_), None) | (None, Some(_)) => {
+            return Err("scale_width and scale_height must be set together".to_string());
+        }
+        _ => {}
+    }
+    if !(0.0..=1.0).contains(&cfg.watermark_opacity) {
+        return Err("watermark_opacity must be between 0 and 1".to_string());
+    }
+    for (i, mask) in cfg.masks.iter().enumerate() {
+        if mask.w == 0 || mask.h == 0 {
+            return Err(format!("mask.{i}: w and h must be > 0"));
+        }
+        if !["blur", "black"].contains(&mask.mode.as_str()) {
+            return Err(format!(
+                "invalid mask.{i}.mode: {} (expected blur, black)",
+                mask.mode
+            ));
+        }
+    }
+    if !(1..=MAX_TEMPORAL_LAYERS).contains(&cfg.temporal_layers) {
+        return Err(format!(
+            "temporal_layers must be between 1 and {MAX_TEMPORAL_LAYERS}"
+        ));
+    }
+    if cfg.follow_predict_ms > MAX_FOLLOW_PREDICT_MS {
+        return Err(format!(
+            "follow_predict_ms must be at most {MAX_FOLLOW_PREDICT_MS}"
+        ));
+    }
+    if cfg.fec_percent > 100 {
+        return Err("fec_percent must be 0-100".to_string());
+    }
+    if cfg.stats_interval == Some(0) {
+        return Err("stats_interval must be > 0".to_string());
+    }
+    if let Some(addr) = &cfg.metrics_addr {
+        addr.parse::<std::net::SocketAddr>()
+            .map_err(|_| format!("invalid metrics_addr: {addr}"))?;
+    }
+    if let Some(fragment) = &cfg.insert_before_encoder {
+        parse_pipeline_fragment(fragment)?;
+    }
+    if let Some(device) = &cfg.va_device {
+        parse_va_device(device)?;
+    }
+    if cfg.rt_priority.is_some_and(|p| !(1..=99).contains(&p)) {
+        return Err("rt_priority must be between 1 and 99".to_string());
+    }
+    if let Some(cpus) = &cfg.cpu_affinity {
+        parse_cpu_affinity(cpus)?;
+    }
+    Ok(())
+}
+
+/// Reads the config file for a reload. Unlike `load_config`, a missing or broken
+/// file (e.g. half-written by an editor) is an error instead of the defaults.
+fn read_settings(profile: Option<&str>) -> Result<SenderConfig, String> {
+    let path = config_path()?;
+    let data = fs::read_to_string(&path).map_err(|e| format!("read {}: {e}", path.display()))?;
+    let cfg = toml::from_str::<SenderConfig>(&data)
+        .map_err(|e| format!("parse {}: {e}", path.display()))?;
+    let cfg = match profile {
+        Some(name) => apply_profile(&cfg, name)?,
+        None => cfg,
+    };
+    check_settings(&cfg)?;
+    Ok(cfg)
+}
+
+/// Top-level config keys whose values differ between `old` and `new`.
+fn changed_settings(old: &SenderConfig, new: &SenderConfig) -> Vec<String> {
+    let (Ok(serde_json::Value::Object(old)), Ok(serde_json::Value::Object(new))) =
+        (serde_json::to_value(old), serde_json::to_value(new))
+    else {
+        return Vec::new();
+    };
+    new.into_iter()
+        .filter(|(key, value)| old.get(key) != Some(value))
+        .map(|(key, _)| key)
+        .collect()
+}
+
+/// Stores `cfg` as `[profile.NAME]`. The whole table is written so that a later
+/// plain `send`, which rewrites the top-level settings, does not change the profile.
+fn save_profile(name: &str, cfg: &SenderConfig) -> Result<(), String> {
+    let mut table = toml::Table::try_from(cfg).map_err(|e| format!("serialize config: {e}"))?;
+    table.remove("profile");
+    let mut base = load_config();
+    base.profile.insert(name.to_string(), table);
+    save_config(&base)
+}
+
 fn cfg_from_send(cfg: &SendCfg) -> SenderConfig {
     SenderConfig {
         receiver_ip: cfg.receiver_ip.clone(),
@@ -129,29 +741,142 @@ fn cfg_from_send(cfg: &SendCfg) -> SenderConfig {
         follow_mouse: cfg.follow_mouse,
         smoothing: cfg.smoothing,
         deadzone: cfg.deadzone,
-        encoder: cfg.encoder.clone(),
+        follow_margin: cfg.follow_margin,
+        encoder: if cfg.auto_encoder {
+            "auto".to_string()
+        } else {
+            cfg.encoder.clone()
+        },
         bitrate_kbps: cfg.bitrate_kbps,
+        cursor_mode: cfg.cursor_mode.clone(),
+        temporal_layers: cfg.temporal_layers,
+        transport: cfg.transport.clone(),
+        srt_mode: cfg.srt_mode.clone(),
+        srt_latency_ms: cfg.srt_latency_ms,
+        srt_passphrase: cfg.srt_passphrase.clone(),
+        rtsp_port: cfg.rtsp_port,
+        srtp_key: cfg.srtp_key.clone(),
+        fec_percent: cfg.fec_percent,
+        rtx: cfg.rtx,
+        adaptive_bitrate: cfg.adaptive_bitrate,
+        output: cfg.output.clone(),
+        source: cfg.source.clone(),
+        backend: cfg.backend.clone(),
+        dmabuf: cfg.dmabuf,
+        crop: cfg.crop.clone(),
+        viewports: cfg.viewports.clone(),
+        with_audio: cfg.with_audio,
+        audio_node: cfg.audio_node.clone(),
+        zoom: cfg.zoom,
+        cursor_backend: cfg.cursor_backend.clone(),
+        follow_window: cfg.follow_window,
+        follow_predict_ms: cfg.follow_predict_ms,
+        roi_radius: cfg.roi_radius,
+        click_highlight: cfg.click_highlight,
+        draw_cursor: cfg.draw_cursor,
+        deadzone_y: cfg.deadzone_y,
+        follow_margin_y: cfg.follow_margin_y,
+        follow_shortcut: cfg.follow_shortcut.clone(),
+        no_restart: cfg.no_restart,
+        stats_interval: cfg.stats_interval,
+        metrics_addr: cfg.metrics_addr.clone(),
+        insert_before_encoder: cfg.insert_before_encoder.clone(),
+        va_device: cfg.va_device.clone(),
+        enc_opts: cfg.enc_opts.clone(),
+        rt_priority: cfg.rt_priority,
+        cpu_affinity: cfg.cpu_affinity.clone(),
+        queue_buffers: cfg.queue_buffers,
+        leaky: cfg.leaky.clone(),
+        overlay_clock: cfg.overlay_clock,
+        overlay_clock_position: cfg.overlay_clock_position.clone(),
+        overlay_clock_format: cfg.overlay_clock_format.clone(),
+        watermark: cfg.watermark.clone(),
+        watermark_pos: cfg.watermark_pos.clone(),
+        watermark_opacity: cfg.watermark_opacity,
+        masks: cfg.masks.clone(),
+        fit: cfg.fit.clone(),
+        lock_aspect: cfg.lock_aspect,
+        scale_width: cfg.scale_width,
+        scale_height: cfg.scale_height,
+        bit_depth: cfg.bit_depth,
+        colorimetry: cfg.colorimetry.clone(),
+        color_range: cfg.color_range.clone(),
+        fps_mode: cfg.fps_mode.clone(),
+        idle_fps: cfg.idle_fps,
+        idle_bitrate_kbps: cfg.idle_bitrate_kbps,
+        delta_scale: cfg.delta_scale.clone(),
+        // Profiles are only changed by `send --profile`; keep them across the save.
+        profile: load_config().profile,
     }
 }
 
 fn main() -> ExitCode {
-    let args: Vec<String> = env::args().collect();
-    match parse_cli(&args) {
-        Ok(Cli::Help) => {
-            print_help();
-            ExitCode::SUCCESS
-        }
-        Ok(Cli::ConfigPath) => {
+    let Some(command) = Cli::parse().command else {
+        let _ = Cli::command().print_help();
+        return ExitCode::SUCCESS;
+    };
+    match command {
+        CliCommand::Config { action: None } => {
             match config_path() {
                 Ok(path) => println!("{}", path.display()),
                 Err(err) => eprintln!("error: {err}"),
             }
             ExitCode::SUCCESS
         }
-        Ok(Cli::Tray) => run_tray(),
-        Ok(Cli::RunSaved) => {
-            let cfg = load_config();
-            run_send(SendCfg {
+        CliCommand::Config {
+            action: Some(ConfigAction::Get { key }),
+        } => match config_get(&key) {
+            Ok(value) => {
+                println!("{value}");
+                ExitCode::SUCCESS
+            }
+            Err(err) => {
+                eprintln!("error: {err}");
+                ExitCode::from(2)
+            }
+        },
+        CliCommand::Config {
+            action: Some(action),
+        } => {
+            let edited = match action {
+                ConfigAction::Set { key, value } => config_set(&key, Some(&value)),
+                ConfigAction::Unset { key } => config_set(&key, None),
+                ConfigAction::Get { .. } => unreachable!("handled above"),
+            };
+            match edited {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(err) => {
+                    eprintln!("error: {err}");
+                    ExitCode::from(2)
+                }
+            }
+        }
+        CliCommand::Tray => run_tray(),
+        CliCommand::Calibrate {
+            output,
+            duration_secs,
+        } => run_calibrate(output.as_deref(), duration_secs),
+        CliCommand::CursorMode { mode } => {
+            match send_control_command(&format!("cursor-mode {mode}")) {
+                Ok(reply) => {
+                    println!("{reply}");
+                    ExitCode::SUCCESS
+                }
+                Err(err) => {
+                    eprintln!("error: {err}");
+                    ExitCode::from(1)
+                }
+            }
+        }
+        CliCommand::RunSaved { profile, dry_run } => {
+            let cfg = match load_profile(profile.as_deref()) {
+                Ok(cfg) => cfg,
+                Err(err) => {
+                    eprintln!("error: {err}");
+                    return ExitCode::from(2);
+                }
+            };
+            let send_cfg = SendCfg {
                 receiver_ip: cfg.receiver_ip,
                 port: cfg.port,
                 x: cfg.x,
@@ -162,11 +887,771 @@ fn main() -> ExitCode {
                 follow_mouse: cfg.follow_mouse,
                 smoothing: cfg.smoothing,
                 deadzone: cfg.deadzone,
+                follow_margin: cfg.follow_margin,
                 encoder: cfg.encoder,
                 bitrate_kbps: cfg.bitrate_kbps,
-            })
+                cursor_mode: cfg.cursor_mode,
+                temporal_layers: cfg.temporal_layers,
+                // The schedule is for one run only and is never saved.
+                start_at: None,
+                stop_at: None,
+                duration_secs: None,
+                transport: cfg.transport,
+                srt_mode: cfg.srt_mode,
+                srt_latency_ms: cfg.srt_latency_ms,
+                srt_passphrase: cfg.srt_passphrase,
+                rtsp_port: cfg.rtsp_port,
+                srtp_key: cfg.srtp_key,
+                fec_percent: cfg.fec_percent,
+                rtx: cfg.rtx,
+                adaptive_bitrate: cfg.adaptive_bitrate,
+                output: cfg.output,
+                source: cfg.source,
+                backend: cfg.backend,
+                dmabuf: cfg.dmabuf,
+                crop: cfg.crop,
+                viewports: cfg.viewports,
+                with_audio: cfg.with_audio,
+                audio_node: cfg.audio_node,
+                zoom: cfg.zoom,
+                cursor_backend: cfg.cursor_backend,
+                follow_window: cfg.follow_window,
+                follow_predict_ms: cfg.follow_predict_ms,
+                roi_radius: cfg.roi_radius,
+                click_highlight: cfg.click_highlight,
+                draw_cursor: cfg.draw_cursor,
+                deadzone_y: cfg.deadzone_y,
+                follow_margin_y: cfg.follow_margin_y,
+                follow_shortcut: cfg.follow_shortcut,
+                no_restart: cfg.no_restart,
+                stats_interval: cfg.stats_interval,
+                metrics_addr: cfg.metrics_addr,
+                insert_before_encoder: cfg.insert_before_encoder,
+                va_device: cfg.va_device,
+                enc_opts: cfg.enc_opts,
+                rt_priority: cfg.rt_priority,
+                cpu_affinity: cfg.cpu_affinity,
+                queue_buffers: cfg.queue_buffers,
+                leaky: cfg.leaky,
+                overlay_clock: cfg.overlay_clock,
+                overlay_clock_position: cfg.overlay_clock_position,
+                overlay_clock_format: cfg.overlay_clock_format,
+                watermark: cfg.watermark,
+                watermark_pos: cfg.watermark_pos,
+                watermark_opacity: cfg.watermark_opacity,
+                masks: cfg.masks,
+                fit: cfg.fit,
+                lock_aspect: cfg.lock_aspect,
+                scale_width: cfg.scale_width,
+                scale_height: cfg.scale_height,
+                bit_depth: cfg.bit_depth,
+                colorimetry: cfg.colorimetry,
+                color_range: cfg.color_range,
+                fps_mode: cfg.fps_mode,
+                idle_fps: cfg.idle_fps,
+                idle_bitrate_kbps: cfg.idle_bitrate_kbps,
+                delta_scale: cfg.delta_scale,
+                profile,
+                auto_encoder: false,
+            };
+            if dry_run {
+                print_pipelines(&send_cfg)
+            } else {
+                run_send(send_cfg)
+            }
+        }
+        CliCommand::Send(args) => {
+            let dry_run = args.dry_run;
+            let send_cfg = match args.into_send_cfg() {
+                Ok(cfg) => cfg,
+                // Checks across flags, reported like clap's own errors (exit code 2).
+                Err(err) => Cli::command()
+                    .error(clap::error::ErrorKind::ArgumentConflict, err)
+                    .exit(),
+            };
+            if dry_run {
+                return print_pipelines(&send_cfg);
+            }
+            let saved = match &send_cfg.profile {
+                Some(name) => {
+                    println!("Saving settings to [profile.{name}].");
+                    save_profile(name, &cfg_from_send(&send_cfg))
+                }
+                None => save_config(&cfg_from_send(&send_cfg)),
+            };
+            if let Err(err) = saved {
+                eprintln!("WARN: {err}");
+            }
+            run_send(send_cfg)
+        }
+    }
+}
+
+const EXAMPLES: &str = "\
+Examples:
+  vp-sndr send --receiver-ip 192.168.1.50 --port 5000 --x 200 --y 100 --width 1280 --height 720 --fps 60 --follow-mouse --smoothing 4 --deadzone 30 --encoder x265enc --bitrate-kbps 8000
+  vp-sndr send --receiver-ip 192.168.1.50 --port 5000 --fps 60 --encoder x265enc --temporal-layers 2
+  vp-sndr send --receiver-ip 192.168.1.50 --port 5000 --fps 60 --encoder svtav1enc --bitrate-kbps 4000
+  vp-sndr send --receiver-ip 192.168.1.50 --port 5000 --fps 60 --encoder jpegenc
+  vp-sndr send --receiver-ip 192.168.1.50 --start-at 08:55 --stop-at 15:30
+  vp-sndr send --receiver-ip 192.168.1.50 --transport srt --srt-latency-ms 200 --srt-passphrase 'correct horse battery'
+  vp-sndr send --transport srt --srt-mode listener --port 9000
+  vp-sndr send --transport webrtc --encoder x264enc --port 8080
+  vp-sndr send --rtsp-port 8554 --encoder x264enc
+  vp-sndr send --receiver-ip 192.168.1.50 --fec-percent 20
+  vp-sndr send --receiver-ip 192.168.1.50 --rtx
+  vp-sndr send --receiver-ip 192.168.1.50 --bitrate-kbps 8000 --adaptive-bitrate
+  vp-sndr send --receiver-ip 192.168.1.50 --stats-interval 10 | grep --line-buffered '^{'
+  vp-sndr send --receiver-ip 192.168.1.50 --metrics-addr 127.0.0.1:9321
+  vp-sndr send --receiver-ip 192.168.1.50 --encoder x264enc --fec-percent 20 --dry-run
+  vp-sndr send --receiver-ip 192.168.1.50 --srtp-key \"$(cat ~/.config/vp-link/srtp.key)\"
+  vp-sndr send --receiver-ip 192.168.1.50 --width 1920 --height 1080 --encoder vaapih265enc --dmabuf
+  vp-sndr send --receiver-ip 192.168.1.50 --encoder x264enc --crop gl
+  vp-sndr send --receiver-ip 192.168.1.50 --follow-mouse --crop element
+  vp-sndr send --receiver-ip 192.168.1.50 --encoder vah265enc --va-device /dev/dri/renderD129
+  vp-sndr send --receiver-ip 192.168.1.50 --encoder x264enc --enc-opt rc-lookahead=5 --enc-opt speed-preset=faster
+  vp-sndr send --receiver-ip 192.168.1.50 --insert-before-encoder \"videobalance saturation=0 ! timeoverlay\"
+  vp-sndr send --receiver-ip 192.168.1.50 --viewport name=left,x=0,w=1280,h=720,port=5000 --viewport name=right,x=1280,w=1280,h=720,port=5004
+  vp-sndr send --receiver-ip 192.168.1.50 --with-audio
+  vp-sndr send --receiver-ip 192.168.1.50 --rt-priority 20 --cpu-affinity 2,3
+  vp-sndr send --receiver-ip 192.168.1.50 --queue-buffers 2 --leaky downstream
+  vp-sndr send --receiver-ip 192.168.1.50 --overlay-clock --overlay-clock-position bottom-right
+  vp-sndr send --receiver-ip 192.168.1.50 --watermark ~/logo.png --watermark-pos top-right --watermark-opacity 0.5
+  vp-sndr send --receiver-ip 192.168.1.50 --follow-mouse --mask x=3200,y=0,w=640,h=1440,mode=blur
+  vp-sndr send --receiver-ip 192.168.1.50 --width 2560 --height 1440 --fit letterbox
+  vp-sndr send --receiver-ip 192.168.1.50 --width 1920 --height 1080 --lock-aspect
+  vp-sndr send --receiver-ip 192.168.1.50 --width 2560 --height 1440 --scale-width 1280 --scale-height 720
+  vp-sndr send --receiver-ip 192.168.1.50 --encoder vah265enc --bit-depth 10
+  vp-sndr send --receiver-ip 192.168.1.50 --colorimetry bt709 --color-range limited
+  vp-sndr send --receiver-ip 192.168.1.50 --fps 60 --fps-mode duplicate
+  vp-sndr send --receiver-ip 192.168.1.50 --idle-fps 1 --idle-bitrate-kbps 500
+  vp-sndr send --receiver-ip 192.168.1.50 --zoom 2 --follow-mouse
+  vp-sndr send --receiver-ip 192.168.1.50 --follow-mouse --cursor-backend wayland
+  vp-sndr send --receiver-ip 192.168.1.50 --follow-window --smoothing 6
+  vp-sndr send --receiver-ip 192.168.1.50 --width 2560 --height 1080 --follow-mouse --deadzone 60,30 --follow-margin 200,120
+  vp-sndr send --receiver-ip 192.168.1.50 --follow-mouse --follow-predict-ms 80
+  vp-sndr send --receiver-ip 192.168.1.50 --follow-mouse --follow-shortcut CTRL+ALT+f
+  vp-sndr send --receiver-ip 192.168.1.50 --follow-mouse --click-highlight
+  vp-sndr send --receiver-ip 192.168.1.50 --cursor-mode metadata --draw-cursor
+  vp-sndr send --receiver-ip 192.168.1.50 --follow-mouse --encoder vaapih265enc --roi-radius 200 --bitrate-kbps 5000
+  vp-sndr send --receiver-ip 192.168.1.50 --output DP-2
+  vp-sndr send --receiver-ip 192.168.1.50 --backend screencopy --output DP-2
+  vp-sndr send --receiver-ip 192.168.1.50 --source window --follow-mouse
+  vp-sndr send --profile travel --bitrate-kbps 3000
+  vp-sndr cursor-mode hidden
+  vp-sndr tray
+  vp-sndr config
+  vp-sndr config set fps 30
+  vp-sndr config set profile.travel.bitrate_kbps 2500
+  vp-sndr run-saved
+  vp-sndr run-saved --profile travel
+";
+
+#[derive(Parser)]
+#[command(name = "vp-sndr", about = "vp-sndr: HEVC RTP sender", after_help = EXAMPLES)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+    /// Capture a viewport and stream it to a receiver; the settings are saved for
+    /// run-saved.
+    Send(Box<SendArgs>),
+    /// Switch the cursor mode of the running sender.
+    CursorMode {
+        #[arg(value_parser = parse_running_cursor_mode, value_name = "embedded|hidden|metadata")]
+        mode: String,
+    },
+    /// Measure each pointer device's speed for the libinput cursor fallback.
+    Calibrate {
+        /// Monitor to calibrate against.
+        #[arg(long, value_name = "NAME")]
+        output: Option<String>,
+        /// How long to measure.
+        #[arg(
+            long = "duration",
+            value_name = "SECS",
+            default_value_t = DEFAULT_CALIBRATE_SECS,
+            value_parser = clap::value_parser!(u64).range(1..)
+        )]
+        duration_secs: u64,
+    },
+    /// Show the tray icon.
+    Tray,
+    /// Print the config path, or read and change saved settings.
+    Config {
+        #[command(subcommand)]
+        action: Option<ConfigAction>,
+    },
+    /// Stream with the saved settings.
+    RunSaved {
+        /// Use [profile.NAME] from the config.
+        #[arg(long, value_name = "NAME", env = "VP_SNDR_PROFILE")]
+        profile: Option<String>,
+        /// Print the pipelines and exit.
+        #[arg(long, env = "VP_SNDR_DRY_RUN")]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print a setting; dotted keys reach into tables (profile.travel.fps).
+    Get { key: String },
+    /// Change a setting after checking it like the matching flag.
+    Set { key: String, value: String },
+    /// Put a setting back to its default.
+    Unset { key: String },
+}
+
+/// `send` flags. Values left out come from `--profile NAME` when given, and from
+/// the defaults otherwise, so nothing here has a clap default.
+#[derive(clap::Args)]
+struct SendArgs {
+    /// Receiver address; not needed for an SRT listener, WebRTC or RTSP-only.
+    #[arg(long, value_name = "IP", env = "VP_SNDR_RECEIVER_IP")]
+    receiver_ip: Option<String>,
+    /// Start from [profile.NAME] and save the result back there.
+    #[arg(long, value_name = "NAME", env = "VP_SNDR_PROFILE")]
+    profile: Option<String>,
+    /// RTP port; RTCP and stats use the next three (default 5000).
+    #[arg(long, value_name = "N", env = "VP_SNDR_PORT")]
+    port: Option<u16>,
+    /// Viewport left edge (default 0).
+    #[arg(long, value_name = "N", env = "VP_SNDR_X")]
+    x: Option<u32>,
+    /// Viewport top edge (default 0).
+    #[arg(long, value_name = "N", env = "VP_SNDR_Y")]
+    y: Option<u32>,
+    /// Viewport width (default 1280).
+    #[arg(long, value_name = "N", env = "VP_SNDR_WIDTH", value_parser = clap::value_parser!(u32).range(1..))]
+    width: Option<u32>,
+    /// Viewport height (default 720).
+    #[arg(long, value_name = "N", env = "VP_SNDR_HEIGHT", value_parser = clap::value_parser!(u32).range(1..))]
+    height: Option<u32>,
+    /// Frames per second (default 60).
+    #[arg(long, value_name = "N", env = "VP_SNDR_FPS", value_parser = clap::value_parser!(u32).range(1..))]
+    fps: Option<u32>,
+    /// Pan the viewport after the cursor.
+    #[arg(long, env = "VP_SNDR_FOLLOW_MOUSE")]
+    follow_mouse: bool,
+    /// Pan the viewport after the focused window.
+    #[arg(long, env = "VP_SNDR_FOLLOW_WINDOW")]
+    follow_window: bool,
+    /// How fast the viewport catches up (default 8).
+    #[arg(long, value_name = "K", env = "VP_SNDR_SMOOTHING")]
+    smoothing: Option<f64>,
+    /// Share of the viewport the cursor moves in without panning.
+    #[arg(long, value_name = "PCT[,PCT_Y]", env = "VP_SNDR_DEADZONE", value_parser = parse_deadzone)]
+    deadzone: Option<(f64, Option<f64>)>,
+    /// Pan once the cursor is this close to the viewport edge.
+    #[arg(long, value_name = "PX[,PX_Y]", env = "VP_SNDR_FOLLOW_MARGIN", value_parser = parse_follow_margin)]
+    follow_margin: Option<(u32, Option<u32>)>,
+    /// Aim ahead of the cursor by its speed over this many ms.
+    #[arg(long, value_name = "N", env = "VP_SNDR_FOLLOW_PREDICT_MS")]
+    follow_predict_ms: Option<u32>,
+    /// Global shortcut that freezes and resumes following.
+    #[arg(
+        long,
+        value_name = "TRIGGER",
+        env = "VP_SNDR_FOLLOW_SHORTCUT",
+        num_args = 0..=1,
+        default_missing_value = DEFAULT_FOLLOW_SHORTCUT
+    )]
+    follow_shortcut: Option<String>,
+    /// Draw the cursor from metadata into the frame.
+    #[arg(long, env = "VP_SNDR_DRAW_CURSOR")]
+    draw_cursor: bool,
+    /// Show a ring at each click.
+    #[arg(long, env = "VP_SNDR_CLICK_HIGHLIGHT")]
+    click_highlight: bool,
+    /// Spend more bits on this many pixels around the cursor.
+    #[arg(long, value_name = "PX", env = "VP_SNDR_ROI_RADIUS")]
+    roi_radius: Option<u32>,
+    /// Send a region 1/F the viewport size, scaled up.
+    #[arg(long, value_name = "F", env = "VP_SNDR_ZOOM")]
+    zoom: Option<f64>,
+    /// On a monitor smaller than the viewport, shrink the stream or keep its size with
+    /// black bars (default shrink).
+    #[arg(long, value_name = "shrink|letterbox", env = "VP_SNDR_FIT", value_parser = ["shrink", "letterbox"])]
+    fit: Option<String>,
+    /// When capture and --fps differ: send frames as captured, drop those above --fps,
+    /// or repeat frames to a constant --fps (default passthrough).
+    #[arg(long, value_name = "drop|duplicate|passthrough", env = "VP_SNDR_FPS_MODE", value_parser = ["drop", "duplicate", "passthrough"])]
+    fps_mode: Option<String>,
+    /// Keep the output at --width x --height and its aspect ratio when the viewport is
+    /// resized live; the crop is widened or heightened to match and scaled.
+    #[arg(long, env = "VP_SNDR_LOCK_ASPECT")]
+    lock_aspect: bool,
+    /// Bits per color channel; 10 keeps HDR and 10-bit sources free of banding and
+    /// needs an HEVC encoder (default 8).
+    #[arg(long, value_name = "8|10", env = "VP_SNDR_BIT_DEPTH", value_parser = parse_bit_depth)]
+    bit_depth: Option<u32>,
+    /// Color matrix and primaries of the encoded stream (default: GStreamer's choice,
+    /// bt709 for HD).
+    #[arg(long, value_name = "bt709|bt601|bt2020", env = "VP_SNDR_COLORIMETRY", value_parser = ["bt709", "bt601", "bt2020"])]
+    colorimetry: Option<String>,
+    /// Encode full-range (0-255) or limited-range (16-235) YUV (default limited).
+    #[arg(long, value_name = "full|limited", env = "VP_SNDR_COLOR_RANGE", value_parser = ["full", "limited"])]
+    color_range: Option<String>,
+    /// Encode the viewport at this width instead of its own (lanczos scaling).
+    #[arg(long, value_name = "N", env = "VP_SNDR_SCALE_WIDTH", requires = "scale_height", value_parser = clap::value_parser!(u32).range(2..))]
+    scale_width: Option<u32>,
+    /// Encode the viewport at this height instead of its own.
+    #[arg(long, value_name = "N", env = "VP_SNDR_SCALE_HEIGHT", requires = "scale_width", value_parser = clap::value_parser!(u32).range(2..))]
+    scale_height: Option<u32>,
+    /// Where the cursor position comes from without cursor metadata.
+    #[arg(long, value_name = "auto|wayland|libinput", env = "VP_SNDR_CURSOR_BACKEND", value_parser = parse_cursor_backend)]
+    cursor_backend: Option<String>,
+    /// GStreamer encoder element, or auto to pick the first that works (default auto).
+    #[arg(long, value_name = "ELEMENT", env = "VP_SNDR_ENCODER")]
+    encoder: Option<String>,
+    /// DRM render node for the va plugin encoders, e.g. /dev/dri/renderD129.
+    #[arg(long, value_name = "PATH", env = "VP_SNDR_VA_DEVICE", value_parser = parse_va_device)]
+    va_device: Option<String>,
+    /// Extra encoder property, e.g. rc-lookahead=5; repeat for more.
+    #[arg(long = "enc-opt", value_name = "KEY=VALUE", value_parser = parse_enc_opt)]
+    enc_opts: Vec<(String, String)>,
+    /// Extra elements for the raw frames right before the encoder, e.g.
+    /// "videobalance saturation=0".
+    #[arg(long, value_name = "FRAGMENT", env = "VP_SNDR_INSERT_BEFORE_ENCODER", value_parser = parse_pipeline_fragment)]
+    insert_before_encoder: Option<String>,
+    /// Encoder bitrate (default 8000).
+    #[arg(long, value_name = "N", env = "VP_SNDR_BITRATE", value_parser = clap::value_parser!(u32).range(1..))]
+    bitrate_kbps: Option<u32>,
+    /// How the portal delivers the cursor (default auto).
+    #[arg(long, value_name = "auto|embedded|hidden|metadata", env = "VP_SNDR_CURSOR_MODE", value_parser = parse_cursor_mode)]
+    cursor_mode: Option<String>,
+    /// Temporal sub-layers, passed to x265 as `temporal-layers`.
+    #[arg(long, value_name = "N", env = "VP_SNDR_TEMPORAL_LAYERS")]
+    temporal_layers: Option<u32>,
+    /// Wait until this time (HH:MM or YYYY-MM-DD HH:MM).
+    #[arg(long, value_name = "TIME", env = "VP_SNDR_START_AT", value_parser = parse_wall_time)]
+    start_at: Option<String>,
+    /// Flush and exit at this time (HH:MM or YYYY-MM-DD HH:MM).
+    #[arg(long, value_name = "TIME", env = "VP_SNDR_STOP_AT", value_parser = parse_wall_time)]
+    stop_at: Option<String>,
+    /// Flush and exit after this many seconds.
+    #[arg(long = "duration", value_name = "SECS", env = "VP_SNDR_DURATION", value_parser = clap::value_parser!(u64).range(1..))]
+    duration_secs: Option<u64>,
+    /// Exit on pipeline errors instead of restarting.
+    #[arg(long, env = "VP_SNDR_NO_RESTART")]
+    no_restart: bool,
+    /// Run the capture and encode threads as SCHED_RR at this priority, when permitted.
+    #[arg(long, value_name = "1-99", env = "VP_SNDR_RT_PRIORITY", value_parser = clap::value_parser!(u32).range(1..=99))]
+    rt_priority: Option<u32>,
+    /// Pin the capture and encode threads to these cores, e.g. 2,3 or 4-7.
+    #[arg(long, value_name = "CPUS", env = "VP_SNDR_CPU_AFFINITY", value_parser = parse_cpu_affinity)]
+    cpu_affinity: Option<String>,
+    /// Frames each queue between capture and network may hold (default 8). Every queued
+    /// frame can add one frame interval of latency: 8 at 60 fps is up to ~130 ms per queue.
+    #[arg(long, value_name = "N", env = "VP_SNDR_QUEUE_BUFFERS", value_parser = clap::value_parser!(u32).range(1..))]
+    queue_buffers: Option<u32>,
+    /// What a full queue drops (default none, which blocks instead). `downstream` drops the
+    /// oldest frame and keeps latency bounded; `upstream` drops the newest.
+    #[arg(long, value_name = "upstream|downstream|none", env = "VP_SNDR_LEAKY", value_parser = ["upstream", "downstream", "none"])]
+    leaky: Option<String>,
+    /// Burn the wall-clock time into the video.
+    #[arg(long, env = "VP_SNDR_OVERLAY_CLOCK")]
+    overlay_clock: bool,
+    /// Corner for --overlay-clock (default top-left).
+    #[arg(long, value_name = "CORNER", env = "VP_SNDR_OVERLAY_CLOCK_POSITION", value_parser = ["top-left", "top-right", "bottom-left", "bottom-right"])]
+    overlay_clock_position: Option<String>,
+    /// strftime format for --overlay-clock (default "%Y-%m-%d %H:%M:%S").
+    #[arg(long, value_name = "FORMAT", env = "VP_SNDR_OVERLAY_CLOCK_FORMAT")]
+    overlay_clock_format: Option<String>,
+    /// Image (PNG, JPEG, ...) drawn over the video, e.g. a logo.
+    #[arg(long, value_name = "PATH", env = "VP_SNDR_WATERMARK")]
+    watermark: Option<String>,
+    /// Corner for --watermark (default top-right).
+    #[arg(long, value_name = "CORNER", env = "VP_SNDR_WATERMARK_POS", value_parser = ["top-left", "top-right", "bottom-left", "bottom-right"])]
+    watermark_pos: Option<String>,
+    /// Opacity of --watermark from 0 to 1 (default 1).
+    #[arg(long, value_name = "A", env = "VP_SNDR_WATERMARK_OPACITY")]
+    watermark_opacity: Option<f64>,
+    /// Print the pipelines and exit without capturing or saving the settings.
+    #[arg(long, env = "VP_SNDR_DRY_RUN")]
+    dry_run: bool,
+    /// Print JSON stats on stdout every SECS seconds.
+    #[arg(long, value_name = "SECS", env = "VP_SNDR_STATS_INTERVAL", value_parser = clap::value_parser!(u64).range(1..))]
+    stats_interval: Option<u64>,
+    /// Serve Prometheus metrics on this address.
+    #[arg(long, value_name = "ADDR", env = "VP_SNDR_METRICS_ADDR", value_parser = parse_socket_addr)]
+    metrics_addr: Option<String>,
+    /// How the stream leaves the sender (default udp).
+    #[arg(long, value_name = "udp|srt|webrtc", env = "VP_SNDR_TRANSPORT", value_parser = ["udp", "srt", "webrtc"])]
+    transport: Option<String>,
+    /// SRT connection direction (default caller).
+    #[arg(long, value_name = "caller|listener", env = "VP_SNDR_SRT_MODE", value_parser = ["caller", "listener"])]
+    srt_mode: Option<String>,
+    /// SRT retransmission window (default 120).
+    #[arg(long, value_name = "N", env = "VP_SNDR_SRT_LATENCY_MS")]
+    srt_latency_ms: Option<u32>,
+    /// Encrypt the SRT stream; 10 to 79 characters.
+    #[arg(long, value_name = "P", env = "VP_SNDR_SRT_PASSPHRASE", value_parser = parse_srt_passphrase)]
+    srt_passphrase: Option<String>,
+    /// Also serve the stream to RTSP players on this port.
+    #[arg(long, value_name = "N", env = "VP_SNDR_RTSP_PORT")]
+    rtsp_port: Option<u16>,
+    /// Encrypt the RTP stream with this pre-shared key.
+    #[arg(long, value_name = "HEX", env = "VP_SNDR_SRTP_KEY", value_parser = parse_srtp_key)]
+    srtp_key: Option<String>,
+    /// ULPFEC overhead in percent.
+    #[arg(long, value_name = "N", env = "VP_SNDR_FEC_PERCENT", value_parser = clap::value_parser!(u32).range(0..=100))]
+    fec_percent: Option<u32>,
+    /// Resend packets the receiver NACKs.
+    #[arg(long, env = "VP_SNDR_RTX")]
+    rtx: bool,
+    /// Lower the bitrate on receiver-reported loss.
+    #[arg(long, env = "VP_SNDR_ADAPTIVE_BITRATE")]
+    adaptive_bitrate: bool,
+    /// Send only N frames per second while the viewport has not changed for a while.
+    #[arg(long, value_name = "N", env = "VP_SNDR_IDLE_FPS", value_parser = clap::value_parser!(u32).range(1..))]
+    idle_fps: Option<u32>,
+    /// Encode at this bitrate while idle.
+    #[arg(long, value_name = "KBPS", env = "VP_SNDR_IDLE_BITRATE_KBPS", requires = "idle_fps", value_parser = clap::value_parser!(u32).range(1..))]
+    idle_bitrate_kbps: Option<u32>,
+    /// Capture through the portal or COSMIC screencopy (default portal).
+    #[arg(long, value_name = "portal|screencopy", env = "VP_SNDR_BACKEND", value_parser = ["portal", "screencopy"])]
+    backend: Option<String>,
+    /// Capture a monitor or a window (default monitor).
+    #[arg(long, value_name = "monitor|window", env = "VP_SNDR_SOURCE", value_parser = ["monitor", "window"])]
+    source: Option<String>,
+    /// Monitor to capture.
+    #[arg(long, value_name = "NAME", env = "VP_SNDR_OUTPUT")]
+    output: Option<String>,
+    /// Crop DMA-BUF frames on the GPU (VA-API encoders).
+    #[arg(long, env = "VP_SNDR_DMABUF")]
+    dmabuf: bool,
+    /// Crop on the CPU, with OpenGL, or with a videocrop element (default cpu).
+    #[arg(long, alias = "crop-backend", value_name = "cpu|gl|element", env = "VP_SNDR_CROP", value_parser = ["cpu", "gl", "element"])]
+    crop: Option<String>,
+    /// Extra crop sent as its own stream; the first one replaces --x/--y/--width/--height/--port.
+    #[arg(
+        long = "viewport",
+        value_name = "name=N,x=N,y=N,w=N,h=N,port=N[,ip=IP]",
+        value_parser = parse_viewport
+    )]
+    viewports: Vec<ViewportSpec>,
+    /// Region to hide before encoding, in source pixels; repeat for more.
+    #[arg(
+        long = "mask",
+        value_name = "x=N,y=N,w=N,h=N[,mode=blur|black]",
+        value_parser = parse_mask
+    )]
+    masks: Vec<MaskSpec>,
+    /// Send desktop audio, or the PipeWire NODE, as Opus.
+    #[arg(long, value_name = "NODE", env = "VP_SNDR_WITH_AUDIO", num_args = 0..=1)]
+    with_audio: Option<Option<String>>,
+}
+
+impl SendArgs {
+    /// Lays the flags over the profile (or the defaults) and checks the flags that
+    /// depend on each other.
+    fn into_send_cfg(self) -> Result<SendCfg, String> {
+        // A new profile name starts from the defaults and is created when main
+        // saves the settings.
+        let saved = load_config();
+        let stored = self
+            .profile
+            .as_deref()
+            .filter(|name| saved.profile.contains_key(*name));
+        let base = match stored {
+            Some(name) => apply_profile(&saved, name)?,
+            None => SenderConfig::default(),
+        };
+        let mut receiver_ip = self
+            .receiver_ip
+            .or_else(|| stored.is_some().then_some(base.receiver_ip));
+        let mut port = self.port.unwrap_or(base.port);
+        let mut x = self.x.unwrap_or(base.x);
+        let mut y = self.y.unwrap_or(base.y);
+        let mut width = self.width.unwrap_or(base.width);
+        let mut height = self.height.unwrap_or(base.height);
+        let fps = self.fps.unwrap_or(base.fps);
+        let follow_mouse = self.follow_mouse || base.follow_mouse;
+        let smoothing = self.smoothing.unwrap_or(base.smoothing);
+        let (deadzone, deadzone_y) = self.deadzone.unwrap_or((base.deadzone, base.deadzone_y));
+        let (follow_margin, follow_margin_y) = self
+            .follow_margin
+            .unwrap_or((base.follow_margin, base.follow_margin_y));
+        let encoder = self.encoder.unwrap_or(base.encoder);
+        let bitrate_kbps = self.bitrate_kbps.unwrap_or(base.bitrate_kbps);
+        let cursor_mode = self.cursor_mode.unwrap_or(base.cursor_mode);
+        let temporal_layers = self.temporal_layers.unwrap_or(base.temporal_layers);
+        let transport = self.transport.unwrap_or(base.transport);
+        let srt_mode = self.srt_mode.unwrap_or(base.srt_mode);
+        let srt_latency_ms = self.srt_latency_ms.unwrap_or(base.srt_latency_ms);
+        let srt_passphrase = self.srt_passphrase.or(base.srt_passphrase);
+        let rtsp_port = self.rtsp_port.or(base.rtsp_port);
+        let srtp_key = self.srtp_key.or(base.srtp_key);
+        let fec_percent = self.fec_percent.unwrap_or(base.fec_percent);
+        let rtx = self.rtx || base.rtx;
+        let adaptive_bitrate = self.adaptive_bitrate || base.adaptive_bitrate;
+        let output = self.output.or(base.output);
+        let source = self.source.unwrap_or(base.source);
+        let backend = self.backend.unwrap_or(base.backend);
+        let dmabuf = self.dmabuf || base.dmabuf;
+        let crop = self.crop.unwrap_or(base.crop);
+        let mut viewports = self.viewports;
+        let (with_audio, audio_node) = match self.with_audio {
+            Some(node) => (true, node),
+            None => (base.with_audio, base.audio_node),
+        };
+        let zoom = self.zoom.unwrap_or(base.zoom);
+        let cursor_backend = self.cursor_backend.unwrap_or(base.cursor_backend);
+        let follow_window = self.follow_window || base.follow_window;
+        let follow_predict_ms = self.follow_predict_ms.unwrap_or(base.follow_predict_ms);
+        let roi_radius = self.roi_radius.unwrap_or(base.roi_radius);
+        let click_highlight = self.click_highlight || base.click_highlight;
+        let draw_cursor = self.draw_cursor || base.draw_cursor;
+        let follow_shortcut = self.follow_shortcut.or(base.follow_shortcut);
+        let no_restart = self.no_restart || base.no_restart;
+        let stats_interval = self.stats_interval.or(base.stats_interval);
+        let metrics_addr = self.metrics_addr.or(base.metrics_addr);
+        let insert_before_encoder = self.insert_before_encoder.or(base.insert_before_encoder);
+        let va_device = self.va_device.or(base.va_device);
+        // Like --viewport, any --enc-opt replaces the saved set.
+        let enc_opts = if self.enc_opts.is_empty() {
+            base.enc_opts
+        } else {
+            self.enc_opts.into_iter().collect()
+        };
+        let rt_priority = self.rt_priority.or(base.rt_priority);
+        let cpu_affinity = self.cpu_affinity.or(base.cpu_affinity);
+        let queue_buffers = self.queue_buffers.unwrap_or(base.queue_buffers);
+        let leaky = self.leaky.unwrap_or(base.leaky);
+        let overlay_clock = self.overlay_clock || base.overlay_clock;
+        let overlay_clock_position = self
+            .overlay_clock_position
+            .unwrap_or(base.overlay_clock_position);
+        let overlay_clock_format = self
+            .overlay_clock_format
+            .unwrap_or(base.overlay_clock_format);
+        let watermark = self.watermark.or(base.watermark);
+        let watermark_pos = self.watermark_pos.unwrap_or(base.watermark_pos);
+        let watermark_opacity = self.watermark_opacity.unwrap_or(base.watermark_opacity);
+        // Like --viewport, any --mask replaces the saved set.
+        let masks = if self.masks.is_empty() {
+            base.masks
+        } else {
+            self.masks
+        };
+        let fit = self.fit.unwrap_or(base.fit);
+        let lock_aspect = self.lock_aspect || base.lock_aspect;
+        let scale_width = self.scale_width.or(base.scale_width);
+        let scale_height = self.scale_height.or(base.scale_height);
+        let bit_depth = self.bit_depth.unwrap_or(base.bit_depth);
+        let colorimetry = self.colorimetry.or(base.colorimetry);
+        let color_range = self.color_range.or(base.color_range);
+        let fps_mode = self.fps_mode.unwrap_or(base.fps_mode);
+        let idle_fps = self.idle_fps.or(base.idle_fps);
+        let idle_bitrate_kbps = self.idle_bitrate_kbps.or(base.idle_bitrate_kbps);
+        // The first --viewport is the main stream; the rest ride along on the same
+        // capture.
+        if !viewports.is_empty() {
+            let first = viewports.remove(0);
+            x = first.x;
+            y = first.y;
+            width = first.width;
+            height = first.height;
+            port = first.port;
+            if first.receiver_ip.is_some() {
+                receiver_ip = first.receiver_ip;
+            }
+        } else {
+            // The profile's extra streams, unless --viewport replaced them.
+            viewports = base.viewports;
+        }
+        // An SRT listener or WebRTC server waits for the viewer to connect, so it
+        // needs no address.
+        let receiver_ip = match receiver_ip {
+            Some(ip) => ip,
+            None if transport == "srt" && srt_mode == "listener" => "0.0.0.0".to_string(),
+            None if transport == "webrtc" => "0.0.0.0".to_string(),
+            // RTSP-only: players pull from the sender instead of a pushed stream.
+            None if transport == "udp" && rtsp_port.is_some() => "0.0.0.0".to_string(),
+            None => return Err("missing required argument --receiver-ip".to_string()),
+        };
+        if width == 0 || height == 0 {
+            return Err("--width and --height must be > 0".to_string());
+        }
+        if fps == 0 {
+            return Err("--fps must be > 0".to_string());
+        }
+        if fec_percent > 0 && transport != "udp" {
+            return Err("--fec-percent only applies to --transport udp".to_string());
+        }
+        if rtx && transport != "udp" {
+            return Err("--rtx only applies to --transport udp".to_string());
+        }
+        if adaptive_bitrate && transport != "udp" {
+            return Err("--adaptive-bitrate only applies to --transport udp".to_string());
+        }
+        if dmabuf && crop != "cpu" {
+            return Err(format!(
+                "--dmabuf already crops on the GPU; drop --crop {crop}"
+            ));
+        }
+        // videocrop cannot pad, and windows smaller than the viewport are centered on black.
+        if crop == "element" && source == "window" {
+            return Err("--crop element needs --source monitor".to_string());
+        }
+        if dmabuf && backend != "portal" {
+            return Err("--dmabuf needs --backend portal".to_string());
+        }
+        if !viewports.is_empty() {
+            if transport != "udp" || rtsp_port.is_some() {
+                return Err(
+                    "several --viewport streams need --transport udp without --rtsp-port"
+                        .to_string(),
+                );
+            }
+            if dmabuf || crop != "cpu" {
+                return Err("several --viewport streams are cropped on the CPU; use --crop cpu without --dmabuf".to_string());
+            }
+            let mut taken = vec![(receiver_ip.as_str(), port)];
+            for spec in &viewports {
+                let ip = spec.receiver_ip.as_deref().unwrap_or(&receiver_ip);
+                // Each stream also uses port + 1..=3 for RTCP and stats.
+                if taken.iter().any(|(other_ip, other_port)| {
+                    *other_ip == ip && other_port.abs_diff(spec.port) <= STATS_PORT_OFFSET
+                }) {
+                    return Err(format!(
+                        "--viewport {}: port {} overlaps another stream to {ip} (keep ports 4 apart)",
+                        spec.name, spec.port
+                    ));
+                }
+                taken.push((ip, spec.port));
+            }
+        }
+        if backend == "screencopy" && source == "window" {
+            return Err("--source window needs --backend portal".to_string());
+        }
+        if output.is_some() && source == "window" {
+            return Err("--output only applies to --source monitor".to_string());
+        }
+        if with_audio && (transport != "udp" || receiver_ip == "0.0.0.0") {
+            return Err("--with-audio needs --transport udp with --receiver-ip".to_string());
+        }
+        if srtp_key.is_some() && transport != "udp" {
+            return Err(
+                "--srtp-key only applies to --transport udp (SRT and WebRTC have their own encryption)"
+                    .to_string(),
+            );
+        }
+        if smoothing <= 0.0 {
+            return Err("--smoothing must be > 0".to_string());
+        }
+        if follow_predict_ms > 0 && !follow_mouse {
+            return Err("--follow-predict-ms needs --follow-mouse".to_string());
+        }
+        if follow_predict_ms > MAX_FOLLOW_PREDICT_MS {
+            return Err(format!(
+                "--follow-predict-ms must be at most {MAX_FOLLOW_PREDICT_MS}"
+            ));
+        }
+        if roi_radius > 0 {
+            if !follow_mouse {
+                return Err("--roi-radius needs --follow-mouse".to_string());
+            }
+            if dmabuf || crop != "cpu" {
+                return Err("--roi-radius needs --crop cpu without --dmabuf".to_string());
+            }
+        }
+        if follow_shortcut.is_some() && !follow_mouse {
+            return Err("--follow-shortcut needs --follow-mouse".to_string());
+        }
+        if draw_cursor {
+            if cursor_mode == "embedded" {
+                return Err(
+                    "--draw-cursor would draw a second cursor over --cursor-mode embedded"
+                        .to_string(),
+                );
+            }
+            if dmabuf || crop != "cpu" {
+                return Err("--draw-cursor needs --crop cpu without --dmabuf".to_string());
+            }
+        }
+        if click_highlight {
+            if !follow_mouse {
+                return Err("--click-highlight needs --follow-mouse".to_string());
+            }
+            if dmabuf || crop != "cpu" {
+                return Err("--click-highlight needs --crop cpu without --dmabuf".to_string());
+            }
+        }
+        // Both already keep the output size: GL centers the monitor, VA scales it up.
+        if fit == "letterbox" && (dmabuf || crop == "gl") {
+            return Err("--fit letterbox needs --crop cpu or element without --dmabuf".to_string());
+        }
+        if lock_aspect && (dmabuf || crop == "gl") {
+            return Err("--lock-aspect needs --crop cpu or element without --dmabuf".to_string());
+        }
+        if !masks.is_empty() && (dmabuf || crop != "cpu") {
+            return Err("--mask needs --crop cpu without --dmabuf".to_string());
+        }
+        if idle_fps.is_some() {
+            // Only the CPU crop holds the viewport's own pixels to compare.
+            if dmabuf || crop != "cpu" {
+                return Err("--idle-fps needs --crop cpu without --dmabuf".to_string());
+            }
+            if fps_mode == "duplicate" {
+                return Err(
+                    "--fps-mode duplicate would fill the idle gaps again; drop one of them"
+                        .to_string(),
+                );
+            }
+        }
+        if bit_depth == 10 {
+            // All of these draw or crop in 8-bit RGBA.
+            if crop == "gl" {
+                return Err("--bit-depth 10 needs --crop cpu or element".to_string());
+            }
+            if draw_cursor || click_highlight || !masks.is_empty() {
+                return Err(
+                    "--draw-cursor, --click-highlight and --mask work in 8 bits; drop them for --bit-depth 10"
+                        .to_string(),
+                );
+            }
+        }
+        if !(0.0..=100.0).contains(&deadzone)
+            || !(0.0..=100.0).contains(&deadzone_y.unwrap_or(deadzone))
+        {
+            return Err("--deadzone must be between 0 and 100".to_string());
+        }
+        if follow_window && follow_mouse {
+            return Err("--follow-window and --follow-mouse cannot be combined".to_string());
+        }
+        if follow_window && source == "window" {
+            return Err("--follow-window only applies to --source monitor".to_string());
+        }
+        if !(MIN_ZOOM..=MAX_ZOOM).contains(&zoom) {
+            return Err(format!("--zoom must be between {MIN_ZOOM} and {MAX_ZOOM}"));
+        }
+        if zoom != 1.0 && crop == "gl" {
+            return Err("--zoom needs --crop cpu (or --dmabuf)".to_string());
+        }
+        let (region_w, region_h) = zoomed_region(width, height, zoom);
+        if follow_margin.saturating_mul(2) >= region_w
+            || follow_margin_y.unwrap_or(follow_margin).saturating_mul(2) >= region_h
+        {
+            return Err(
+                "--follow-margin must be less than half of --width and --height".to_string(),
+            );
         }
-        Ok(Cli::Send {
+        if bitrate_kbps == 0 {
+            return Err("--bitrate-kbps must be > 0".to_string());
+        }
+        if !(1..=MAX_TEMPORAL_LAYERS).contains(&temporal_layers) {
+            return Err(format!(
+                "--temporal-layers must be between 1 and {MAX_TEMPORAL_LAYERS}"
+            ));
+        }
+
+        let cfg = SendCfg {
             receiver_ip,
             port,
             x,
@@ -177,57 +1662,87 @@ fn main() -> ExitCode {
             follow_mouse,
             smoothing,
             deadzone,
+            follow_margin,
             encoder,
             bitrate_kbps,
-        }) => {
-            let send_cfg = SendCfg {
-                receiver_ip,
-                port,
-                x,
-                y,
-                width,
-                height,
-                fps,
-                follow_mouse,
-                smoothing,
-                deadzone,
-                encoder,
-                bitrate_kbps,
-            };
-            if let Err(err) = save_config(&cfg_from_send(&send_cfg)) {
-                eprintln!("WARN: {err}");
-            }
-            run_send(send_cfg)
-        }
-        Err(err) => {
-            eprintln!("error: {err}");
-            print_help();
-            ExitCode::from(2)
+            cursor_mode,
+            temporal_layers,
+            start_at: self.start_at,
+            stop_at: self.stop_at,
+            duration_secs: self.duration_secs,
+            transport,
+            srt_mode,
+            srt_latency_ms,
+            srt_passphrase,
+            rtsp_port,
+            srtp_key,
+            fec_percent,
+            rtx,
+            adaptive_bitrate,
+            output,
+            source,
+            backend,
+            dmabuf,
+            crop,
+            viewports,
+            with_audio,
+            audio_node,
+            zoom,
+            cursor_backend,
+            follow_window,
+            follow_predict_ms,
+            roi_radius,
+            click_highlight,
+            draw_cursor,
+            deadzone_y,
+            follow_margin_y,
+            follow_shortcut,
+            no_restart,
+            stats_interval,
+            metrics_addr,
+            insert_before_encoder,
+            va_device,
+            enc_opts,
+            rt_priority,
+            cpu_affinity,
+            queue_buffers,
+            leaky,
+            overlay_clock,
+            overlay_clock_position,
+            overlay_clock_format,
+            watermark,
+            watermark_pos,
+            watermark_opacity,
+            masks,
+            fit,
+            lock_aspect,
+            scale_width,
+            scale_height,
+            bit_depth,
+            colorimetry,
+            color_range,
+            fps_mode,
+            idle_fps,
+            idle_bitrate_kbps,
+            // Written by `calibrate`, not a flag, so the saved values are kept
+            // across the save in main even when the other settings start from the
+            // defaults.
+            delta_scale: match stored {
+                Some(_) => base.delta_scale,
+                None => saved.delta_scale,
+            },
+            profile: self.profile,
+            auto_encoder: false,
+        };
+        // `auto` only considers encoders that pass these when the sender starts.
+        if cfg.encoder != "auto" {
+            check_encoder_options(&cfg)?;
         }
+        Ok(cfg)
     }
 }
 
-enum Cli {
-    Help,
-    Tray,
-    ConfigPath,
-    RunSaved,
-    Send {
-        receiver_ip: String,
-        port: u16,
-        x: u32,
-        y: u32,
-        width: u32,
-        height: u32,
-        fps: u32,
-        follow_mouse: bool,
-        smoothing: f64,
-        deadzone: f64,
-        encoder: String,
-        bitrate_kbps: u32,
-    },
-}
-
+#[derive(Clone)]
 struct SendCfg {
     receiver_ip: String,
     port: u16,
@@ -239,8 +1754,73 @@ struct SendCfg {
     follow_mouse: bool,
     smoothing: f64,
     deadzone: f64,
+    follow_margin: u32,
     encoder: String,
     bitrate_kbps: u32,
+    cursor_mode: String,
+    temporal_layers: u32,
+    start_at: Option<String>,
+    stop_at: Option<String>,
+    duration_secs: Option<u64>,
+    transport: String,
+    srt_mode: String,
+    srt_latency_ms: u32,
+    srt_passphrase: Option<String>,
+    rtsp_port: Option<u16>,
+    srtp_key: Option<String>,
+    fec_percent: u32,
+    rtx: bool,
+    adaptive_bitrate: bool,
+    output: Option<String>,
+    source: String,
+    backend: String,
+    dmabuf: bool,
+    crop: String,
+    viewports: Vec<ViewportSpec>,
+    with_audio: bool,
+    audio_node: Option<String>,
+    zoom: f64,
+    cursor_backend: String,
+    follow_window: bool,
+    follow_predict_ms: u32,
+    roi_radius: u32,
+    click_highlight: bool,
+    draw_cursor: bool,
+    deadzone_y: Option<f64>,
+    follow_margin_y: Option<u32>,
+    follow_shortcut: Option<String>,
+    no_restart: bool,
+    stats_interval: Option<u64>,
+    metrics_addr: Option<String>,
+    insert_before_encoder: Option<String>,
+    va_device: Option<String>,
+    enc_opts: BTreeMap<String, String>,
+    rt_priority: Option<u32>,
+    cpu_affinity: Option<String>,
+    queue_buffers: u32,
+    leaky: String,
+    overlay_clock: bool,
+    overlay_clock_position: String,
+    overlay_clock_format: String,
+    watermark: Option<String>,
+    watermark_pos: String,
+    watermark_opacity: f64,
+    masks: Vec<MaskSpec>,
+    fit: String,
+    lock_aspect: bool,
+    scale_width: Option<u32>,
+    scale_height: Option<u32>,
+    bit_depth: u32,
+    colorimetry: Option<String>,
+    color_range: Option<String>,
+    fps_mode: String,
+    idle_fps: Option<u32>,
+    idle_bitrate_kbps: Option<u32>,
+    delta_scale: BTreeMap<String, f64>,
+    // --profile: the settings came from, and are saved to, [profile.NAME].
+    profile: Option<String>,
+    // --encoder auto: `encoder` holds the probed choice and "auto" is what gets saved.
+    auto_encoder: bool,
 }
 
 #[derive(Clone, Default)]
@@ -296,6 +1876,27 @@ impl Tray for SenderTray {
                 activate: Box::new(move |_| tray_stop()),
                 ..Default::default()
             }));
+            let (label, command) = if tray_stream_paused() {
+                ("Resume Stream", "resume")
+            } else {
+                ("Pause Stream", "pause")
+            };
+            items.push(MenuItem::Standard(StandardItem {
+                label: label.to_string(),
+                activate: Box::new(move |_| tray_pause(command)),
+                ..Default::default()
+            }));
+            for (label, mode) in [
+                ("Cursor: Embedded", "embedded"),
+                ("Cursor: Hidden", "hidden"),
+                ("Cursor: Metadata", "metadata"),
+            ] {
+                items.push(MenuItem::Standard(StandardItem {
+                    label: label.to_string(),
+                    activate: Box::new(move |_| tray_cursor_mode(mode)),
+                    ..Default::default()
+                }));
+            }
         } else {
             items.push(MenuItem::Standard(StandardItem {
                 label: "Start Sender".to_string(),
@@ -387,6 +1988,26 @@ fn tray_stop() {
     service_action("vp-sndr.service", "stop");
 }
 
+fn tray_cursor_mode(mode: &str) {
+    if let Err(err) = send_control_command(&format!("cursor-mode {mode}")) {
+        eprintln!("WARN: could not switch cursor mode: {err}");
+    }
+}
+
+fn tray_stream_paused() -> bool {
+    send_control_command("status")
+        .ok()
+        .and_then(|reply| serde_json::from_str::<serde_json::Value>(&reply).ok())
+        .and_then(|status| status.get("paused").and_then(serde_json::Value::as_bool))
+        .unwrap_or(false)
+}
+
+fn tray_pause(command: &str) {
+    if let Err(err) = send_control_command(command) {
+        eprintln!("WARN: could not {command} the stream: {err}");
+    }
+}
+
 fn tray_open_config() {
     let cfg = load_config();
     let _ = save_config(&cfg);
@@ -404,175 +2025,225 @@ fn tray_open_config() {
         .spawn();
 }
 
-fn parse_cli(args: &[String]) -> Result<Cli, String> {
-    if args.len() <= 1 {
-        return Ok(Cli::Help);
-    }
-    match args[1].as_str() {
-        "-h" | "--help" | "help" => Ok(Cli::Help),
-        "tray" => Ok(Cli::Tray),
-        "config" => Ok(Cli::ConfigPath),
-        "run-saved" => Ok(Cli::RunSaved),
-        "send" => {
-            let mut receiver_ip: Option<String> = None;
-            let mut port = 5000u16;
-            let mut x = 0u32;
-            let mut y = 0u32;
-            let mut width = DEFAULT_WIDTH;
-            let mut height = DEFAULT_HEIGHT;
-            let mut fps = 60u32;
-            let mut follow_mouse = false;
-            let mut smoothing = DEFAULT_MOUSE_SMOOTHING;
-            let mut deadzone = 0.0f64;
-            let mut encoder = String::from("x265enc");
-            let mut bitrate_kbps = 8000u32;
-
-            let mut i = 2usize;
-            while i < args.len() {
-                match args[i].as_str() {
-                    "--receiver-ip" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --receiver-ip".to_string())?;
-                        receiver_ip = Some(next.clone());
-                        i += 2;
-                    }
-                    "--port" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --port".to_string())?;
-                        port = next
-                            .parse::<u16>()
-                            .map_err(|_| format!("invalid --port value: {next}"))?;
-                        i += 2;
-                    }
-                    "--x" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --x".to_string())?;
-                        x = next
-                            .parse::<u32>()
-                            .map_err(|_| format!("invalid --x value: {next}"))?;
-                        i += 2;
-                    }
-                    "--y" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --y".to_string())?;
-                        y = next
-                            .parse::<u32>()
-                            .map_err(|_| format!("invalid --y value: {next}"))?;
-                        i += 2;
-                    }
-                    "--width" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --width".to_string())?;
-                        width = next
-                            .parse::<u32>()
-                            .map_err(|_| format!("invalid --width value: {next}"))?;
-                        i += 2;
-                    }
-                    "--height" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --height".to_string())?;
-                        height = next
-                            .parse::<u32>()
-                            .map_err(|_| format!("invalid --height value: {next}"))?;
-                        i += 2;
-                    }
-                    "--fps" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --fps".to_string())?;
-                        fps = next
-                            .parse::<u32>()
-                            .map_err(|_| format!("invalid --fps value: {next}"))?;
-                        i += 2;
-                    }
-                    "--follow-mouse" => {
-                        follow_mouse = true;
-                        i += 1;
-                    }
-                    "--smoothing" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --smoothing".to_string())?;
-                        smoothing = next
-                            .parse::<f64>()
-                            .map_err(|_| format!("invalid --smoothing value: {next}"))?;
-                        i += 2;
-                    }
-                    "--deadzone" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --deadzone".to_string())?;
-                        deadzone = next
-                            .parse::<f64>()
-                            .map_err(|_| format!("invalid --deadzone value: {next}"))?;
-                        i += 2;
-                    }
-                    "--encoder" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --encoder".to_string())?;
-                        encoder = next.clone();
-                        i += 2;
-                    }
-                    "--bitrate-kbps" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --bitrate-kbps".to_string())?;
-                        bitrate_kbps = next
-                            .parse::<u32>()
-                            .map_err(|_| format!("invalid --bitrate-kbps value: {next}"))?;
-                        i += 2;
-                    }
-                    other => return Err(format!("unknown argument: {other}")),
-                }
-            }
-            let receiver_ip =
-                receiver_ip.ok_or_else(|| "missing required argument --receiver-ip".to_string())?;
-            if width == 0 || height == 0 {
-                return Err("--width and --height must be > 0".to_string());
-            }
-            if fps == 0 {
-                return Err("--fps must be > 0".to_string());
-            }
-            if smoothing <= 0.0 {
-                return Err("--smoothing must be > 0".to_string());
-            }
-            if !(0.0..=100.0).contains(&deadzone) {
-                return Err("--deadzone must be between 0 and 100".to_string());
+/// Parses `name=N,x=N,y=N,w=N,h=N,port=N[,ip=IP]`. Only port is required; x/y default
+/// to 0, w/h to the default viewport size and the name to `port<N>`.
+fn parse_viewport(value: &str) -> Result<ViewportSpec, String> {
+    let mut spec = ViewportSpec {
+        name: String::new(),
+        x: 0,
+        y: 0,
+        width: DEFAULT_WIDTH,
+        height: DEFAULT_HEIGHT,
+        port: 0,
+        receiver_ip: None,
+    };
+    for part in value.split(',').filter(|part| !part.is_empty()) {
+        let (key, val) = part.split_once('=').ok_or_else(|| {
+            format!("invalid --viewport value: {value} (expected key=value pairs)")
+        })?;
+        let number = |val: &str| {
+            val.parse::<u32>()
+                .map_err(|_| format!("invalid --viewport {key} value: {val}"))
+        };
+        match key {
+            "name" => spec.name = val.to_string(),
+            "x" => spec.x = number(val)?,
+            "y" => spec.y = number(val)?,
+            "w" | "width" => spec.width = number(val)?,
+            "h" | "height" => spec.height = number(val)?,
+            "port" => {
+                spec.port = val
+                    .parse::<u16>()
+                    .map_err(|_| format!("invalid --viewport port value: {val}"))?
             }
-            if bitrate_kbps == 0 {
-                return Err("--bitrate-kbps must be > 0".to_string());
+            "ip" => spec.receiver_ip = Some(val.to_string()),
+            other => return Err(format!("unknown --viewport key: {other}")),
+        }
+    }
+    if spec.port == 0 {
+        return Err(format!("--viewport {value} needs port=N"));
+    }
+    if spec.width == 0 || spec.height == 0 {
+        return Err(format!("--viewport {value}: w and h must be > 0"));
+    }
+    if spec.name.is_empty() {
+        spec.name = format!("port{}", spec.port);
+    }
+    Ok(spec)
+}
+
+/// Parses `x=N,y=N,w=N,h=N[,mode=blur|black]`; x/y default to 0 and the mode to black.
+fn parse_mask(value: &str) -> Result<MaskSpec, String> {
+    let mut mask = MaskSpec::default();
+    for part in value.split(',').filter(|part| !part.is_empty()) {
+        let (key, val) = part
+            .split_once('=')
+            .ok_or_else(|| format!("invalid --mask value: {value} (expected key=value pairs)"))?;
+        let number = |val: &str| {
+            val.parse::<u32>()
+                .map_err(|_| format!("invalid --mask {key} value: {val}"))
+        };
+        match key {
+            "x" => mask.x = number(val)?,
+            "y" => mask.y = number(val)?,
+            "w" | "width" => mask.w = number(val)?,
+            "h" | "height" => mask.h = number(val)?,
+            "mode" if matches!(val, "blur" | "black") => mask.mode = val.to_string(),
+            "mode" => {
+                return Err(format!(
+                    "invalid --mask mode: {val} (expected blur or black)"
+                ));
             }
+            other => return Err(format!("unknown --mask key: {other}")),
+        }
+    }
+    if mask.w == 0 || mask.h == 0 {
+        return Err(format!("--mask {value} needs w and h > 0"));
+    }
+    Ok(mask)
+}
 
-            Ok(Cli::Send {
-                receiver_ip,
-                port,
-                x,
-                y,
-                width,
-                height,
-                fps,
-                follow_mouse,
-                smoothing,
-                deadzone,
-                encoder,
-                bitrate_kbps,
-            })
+/// `cursor-mode` switches a running sender, where `auto` has already been resolved.
+fn parse_running_cursor_mode(value: &str) -> Result<String, String> {
+    match parse_cursor_mode(value)?.as_str() {
+        "auto" => Err("cursor-mode must be embedded, hidden, or metadata".to_string()),
+        mode => Ok(mode.to_string()),
+    }
+}
+
+fn parse_cursor_backend(value: &str) -> Result<String, String> {
+    match value {
+        "auto" | "wayland" | "libinput" => Ok(value.to_string()),
+        // The raw evdev reader this replaced.
+        "evdev" => Ok("libinput".to_string()),
+        _ => Err(format!(
+            "invalid value: {value} (expected auto, wayland or libinput)"
+        )),
+    }
+}
+
+fn parse_deadzone(value: &str) -> Result<(f64, Option<f64>), String> {
+    parse_axis_pair::<f64>(value).ok_or_else(|| format!("invalid value: {value}"))
+}
+
+fn parse_follow_margin(value: &str) -> Result<(u32, Option<u32>), String> {
+    parse_axis_pair::<u32>(value).ok_or_else(|| format!("invalid value: {value}"))
+}
+
+/// Checked when parsing and resolved again when the sender starts, so `08:55` is the
+/// next 08:55 from then.
+fn parse_wall_time(value: &str) -> Result<String, String> {
+    resolve_wall_time(value).map(|_| value.to_string())
+}
+
+fn parse_socket_addr(value: &str) -> Result<String, String> {
+    value
+        .parse::<std::net::SocketAddr>()
+        .map(|_| value.to_string())
+        .map_err(|_| format!("invalid address: {value} (expected IP:PORT)"))
+}
+
+fn parse_srt_passphrase(value: &str) -> Result<String, String> {
+    if !(10..=79).contains(&value.len()) {
+        return Err("--srt-passphrase must be 10 to 79 characters".to_string());
+    }
+    Ok(value.to_string())
+}
+
+fn parse_srtp_key(value: &str) -> Result<String, String> {
+    validate_srtp_key(value)?;
+    Ok(value.to_ascii_lowercase())
+}
+
+/// Builds the fragment on its own, so a typo is reported against the flag instead
+/// of as a parse error somewhere in the whole output pipeline.
+fn parse_pipeline_fragment(value: &str) -> Result<String, String> {
+    gst::init().map_err(|e| format!("gstreamer init failed: {e}"))?;
+    gst::parse::bin_from_description(value, true)
+        .map_err(|e| format!("invalid pipeline fragment \"{value}\": {e}"))?;
+    Ok(value.trim().to_string())
+}
+
+fn parse_enc_opt(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, val)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), val.trim().to_string()))
+        }
+        _ => Err(format!(
+            "invalid --enc-opt value: {value} (expected KEY=VALUE)"
+        )),
+    }
+}
+
+fn parse_va_device(value: &str) -> Result<String, String> {
+    if !value.starts_with("/dev/dri/renderD") {
+        return Err(format!(
+            "invalid VA device: {value} (expected a render node such as /dev/dri/renderD128)"
+        ));
+    }
+    if !Path::new(value).exists() {
+        return Err(format!("VA device {value} does not exist"));
+    }
+    Ok(value.to_string())
+}
+
+fn parse_cpu_affinity(value: &str) -> Result<String, String> {
+    cpu_list(value)?;
+    Ok(value.trim().to_string())
+}
+
+/// The cores of a `--cpu-affinity` list: comma-separated numbers and `A-B` ranges.
+fn cpu_list(value: &str) -> Result<Vec<usize>, String> {
+    let invalid = || format!("invalid CPU list: {value} (expected e.g. 2,3 or 4-7)");
+    let mut cpus = Vec::new();
+    for part in value.split(',').map(str::trim) {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (first.trim(), last.trim()),
+            None => (part, part),
+        };
+        let first = first.parse::<usize>().map_err(|_| invalid())?;
+        let last = last.parse::<usize>().map_err(|_| invalid())?;
+        if first > last {
+            return Err(invalid());
+        }
+        if last >= libc::CPU_SETSIZE as usize {
+            return Err(format!("CPU {last} is out of range"));
         }
-        other => Err(format!("unknown command: {other}")),
+        cpus.extend(first..=last);
+    }
+    Ok(cpus)
+}
+
+fn parse_bit_depth(value: &str) -> Result<u32, String> {
+    match value {
+        "8" => Ok(8),
+        "10" => Ok(10),
+        _ => Err(format!("invalid bit depth: {value} (expected 8 or 10)")),
+    }
+}
+
+fn parse_cursor_mode(value: &str) -> Result<String, String> {
+    let mode = value.to_ascii_lowercase();
+    match mode.as_str() {
+        "auto" | "embedded" | "hidden" | "metadata" => Ok(mode),
+        _ => Err(format!(
+            "invalid cursor mode: {value} (expected auto, embedded, hidden, or metadata)"
+        )),
     }
 }
 
 fn run_send(cfg: SendCfg) -> ExitCode {
+    let cfg = match resolve_encoder(&cfg) {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            eprintln!("FAIL: {err}");
+            return ExitCode::from(2);
+        }
+    };
     let output_fps = cfg.fps.max(1);
     println!(
-        "Sending to {}:{} capture_fps={} crop={}x{} at x={}, y={}",
+        "Sending {} to {}:{} capture_fps={} crop={}x{} at x={}, y={}",
+        cfg.transport,
         cfg.receiver_ip,
         cfg.port,
         cfg.fps,
@@ -581,22 +2252,339 @@ fn run_send(cfg: SendCfg) -> ExitCode {
         cfg.x,
         cfg.y
     );
+    if cfg.zoom != 1.0 {
+        let (region_w, region_h) = zoomed_region(cfg.width, cfg.height, cfg.zoom);
+        println!(
+            "Zoom {}x: {}x{} region scaled to {}x{}.",
+            cfg.zoom, region_w, region_h, cfg.width, cfg.height
+        );
+    }
+    let (encode_w, encode_h) = encode_size(cfg);
+    if (encode_w, encode_h) != (cfg.width, cfg.height) {
+        println!("Encoding at {encode_w}x{encode_h}.");
+    }
+    if cfg.transport == "webrtc" {
+        println!("WebRTC viewer: http://<this-host>:{}/", cfg.port);
+    }
+    if cfg.follow_window {
+        println!("Window follow enabled (smoothing={}).", cfg.smoothing);
+    }
     if cfg.follow_mouse {
         println!("Mouse follow enabled (smoothing={}).", cfg.smoothing);
-        if cfg.deadzone > 0.0 {
-            println!("Deadzone enabled ({}% x {}%).", cfg.deadzone, cfg.deadzone);
+        let deadzone_y = cfg.deadzone_y.unwrap_or(cfg.deadzone);
+        if cfg.deadzone > 0.0 || deadzone_y > 0.0 {
+            println!("Deadzone enabled ({}% x {}%).", cfg.deadzone, deadzone_y);
+        }
+        let follow_margin_y = cfg.follow_margin_y.unwrap_or(cfg.follow_margin);
+        if cfg.follow_margin > 0 || follow_margin_y > 0 {
+            println!(
+                "Edge margin enabled ({}px x {}px).",
+                cfg.follow_margin, follow_margin_y
+            );
+        }
+        if cfg.follow_predict_ms > 0 {
+            println!("Cursor prediction enabled ({}ms ahead).", cfg.follow_predict_ms);
+        }
+        if cfg.click_highlight {
+            println!("Click highlight enabled.");
         }
+        if let Some(trigger) = &cfg.follow_shortcut {
+            println!("Follow toggle shortcut requested ({trigger}).");
+        }
+        if cfg.roi_radius > 0 {
+            println!(
+                "Cursor ROI enabled ({}px radius, QP {ROI_DELTA_QP}).",
+                cfg.roi_radius
+            );
+        }
+    }
+    if cfg.draw_cursor {
+        println!("Drawing the tracked cursor into the stream.");
+    }
+    if cfg.temporal_layers > 1 {
+        println!("Temporal layers: {} (x265).", cfg.temporal_layers);
     }
-    let sc = match start_portal_screencast() {
+    let (start_at, stop_at) = match resolve_schedule(&cfg) {
         Ok(v) => v,
         Err(err) => {
-            eprintln!("FAIL: portal ScreenCast handshake failed: {err}");
+            eprintln!("FAIL: {err}");
+            return ExitCode::from(2);
+        }
+    };
+    let mut capture = match start_capture(&cfg, &cfg.cursor_mode) {
+        Ok(v) => v,
+        Err(err) => {
+            eprintln!("FAIL: {err}");
             return ExitCode::from(1);
         }
     };
-    println!("Portal stream node id: {}", sc.node_id);
 
-    run_send_live(sc.node_id, cfg, output_fps)
+    // The portal handshake runs first so any source picker appears while someone is
+    // still around; the stream itself waits for the scheduled start.
+    if let Some(wait) = start_at.and_then(|t| t.duration_since(SystemTime::now()).ok()) {
+        println!(
+            "Waiting {}s for --start-at {}...",
+            wait.as_secs(),
+            cfg.start_at.as_deref().unwrap_or_default()
+        );
+        thread::sleep(wait);
+    }
+    let started = Instant::now();
+    let mut stop_deadline = cfg
+        .duration_secs
+        .map(|secs| started + Duration::from_secs(secs));
+    if let Some(stop) = stop_at {
+        let at = started + stop.duration_since(SystemTime::now()).unwrap_or_default();
+        stop_deadline = Some(stop_deadline.map_or(at, |d| d.min(at)));
+    }
+    if let Some(deadline) = stop_deadline {
+        println!(
+            "Streaming will stop after {}s.",
+            deadline.saturating_duration_since(started).as_secs()
+        );
+    }
+
+    let signals = register_signal_flags();
+    watch_config_file(Arc::clone(&signals.reload));
+    // Served across restarts, so a scrape shows the outage instead of failing.
+    let metrics = cfg.metrics_addr.as_deref().and_then(|addr| {
+        let metrics = Metrics::new("vp_sndr");
+        match metrics.serve(addr) {
+            Ok(local) => {
+                println!("Metrics: http://{local}/metrics");
+                Some(metrics)
+            }
+            Err(err) => {
+                eprintln!("WARN: metrics endpoint unavailable: {err}");
+                None
+            }
+        }
+    });
+    let mut backoff = Duration::from_secs(RESTART_BACKOFF_MIN_SECS);
+    loop {
+        let run_started = Instant::now();
+        let code = run_send_live(
+            capture,
+            cfg.clone(),
+            output_fps,
+            stop_deadline,
+            &signals,
+            metrics.as_ref(),
+        );
+        // Pipelines that failed or could not start (exit code 1) are retried; success,
+        // bad configuration (2) and shutdown requests end the sender.
+        if code != ExitCode::from(1)
+            || cfg.no_restart
+            || signals.terminate.load(Ordering::Relaxed)
+            || stop_deadline.is_some_and(|d| Instant::now() >= d)
+        {
+            return code;
+        }
+        if let Some(metrics) = &metrics {
+            metrics.gauge(
+                "pipeline_playing",
+                "1 while the output pipeline is playing.",
+                0.0,
+            );
+        }
+        if run_started.elapsed() >= Duration::from_secs(RESTART_STABLE_SECS) {
+            backoff = Duration::from_secs(RESTART_BACKOFF_MIN_SECS);
+        }
+        // The portal reuses the restore token, so no picker shows up again unless the
+        // monitor or window is gone.
+        capture = loop {
+            println!("Restarting in {}s...", backoff.as_secs());
+            if !sleep_unless_terminated(backoff, &signals.terminate) {
+                return code;
+            }
+            backoff = (backoff * 2).min(Duration::from_secs(RESTART_BACKOFF_MAX_SECS));
+            match start_capture(&cfg, &cfg.cursor_mode) {
+                Ok(v) => break v,
+                Err(err) => eprintln!("WARN: restart failed: {err}"),
+            }
+        };
+    }
+}
+
+/// Process-wide signal flags, registered once so restarts do not stack handlers.
+struct SignalFlags {
+    // SIGTERM/SIGINT: flush and exit; a second signal exits right away.
+    terminate: Arc<AtomicBool>,
+    // SIGHUP or a write to the config file: re-read it and apply what can change live.
+    reload: Arc<AtomicBool>,
+}
+
+fn register_signal_flags() -> SignalFlags {
+    let flags = SignalFlags {
+        terminate: Arc::new(AtomicBool::new(false)),
+        reload: Arc::new(AtomicBool::new(false)),
+    };
+    for signal in [signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+        let registered = signal_hook::flag::register_conditional_shutdown(
+            signal,
+            1,
+            Arc::clone(&flags.terminate),
+        )
+        .and_then(|_| signal_hook::flag::register(signal, Arc::clone(&flags.terminate)));
+        if let Err(err) = registered {
+            eprintln!("WARN: graceful shutdown on signal {signal} unavailable: {err}");
+        }
+    }
+    if let Err(err) =
+        signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&flags.reload))
+    {
+        eprintln!("WARN: SIGHUP reload unavailable: {err}");
+    }
+    flags
+}
+
+/// Sets `reload` whenever the config file is written. The directory is watched
+/// because editors often save by renaming a new file over the old one.
+fn watch_config_file(reload: Arc<AtomicBool>) {
+    let path = match config_path() {
+        Ok(path) => path,
+        Err(err) => {
+            eprintln!("WARN: config file is not watched: {err}");
+            return;
+        }
+    };
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return;
+    };
+    let name = name.to_os_string();
+    let _ = fs::create_dir_all(dir);
+    let watched = Inotify::init().and_then(|inotify| {
+        inotify
+            .watches()
+            .add(dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)?;
+        Ok(inotify)
+    });
+    let mut inotify = match watched {
+        Ok(inotify) => inotify,
+        Err(err) => {
+            eprintln!("WARN: config file is not watched: {err}");
+            return;
+        }
+    };
+    thread::spawn(move || {
+        let mut buffer = [0u8; 4096];
+        loop {
+            match inotify.read_events_blocking(&mut buffer) {
+                Ok(mut events) => {
+                    if events.any(|event| event.name == Some(name.as_os_str())) {
+                        reload.store(true, Ordering::Relaxed);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("WARN: stopped watching the config file: {err}");
+                    return;
+                }
+            }
+        }
+    });
+}
+
+/// Sleeps for `wait`, returning false early once a termination signal arrives.
+fn sleep_unless_terminated(wait: Duration, terminate: &AtomicBool) -> bool {
+    let until = Instant::now() + wait;
+    while Instant::now() < until {
+        if terminate.load(Ordering::Relaxed) {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(100).min(until - Instant::now()));
+    }
+    !terminate.load(Ordering::Relaxed)
+}
+
+/// Resolves a local wall-clock time: `HH:MM` is its next occurrence, so a time
+/// already past today means tomorrow, and `YYYY-MM-DD HH:MM` is that minute as given
+/// (possibly in the past).
+fn resolve_wall_time(value: &str) -> Result<SystemTime, String> {
+    let invalid = || format!("invalid time: {value} (expected HH:MM or YYYY-MM-DD HH:MM)");
+    let number = |text: &str, len: usize| {
+        if text.len() == len && text.bytes().all(|b| b.is_ascii_digit()) {
+            text.parse::<i32>().ok()
+        } else {
+            None
+        }
+    };
+    let (date, clock) = match value.trim().split_once(' ') {
+        Some((date, clock)) => (Some(date), clock.trim_start()),
+        None => (None, value.trim()),
+    };
+    let (hour, minute) = clock
+        .split_once(':')
+        .and_then(|(h, m)| Some((number(h, 2)?, number(m, 2)?)))
+        .filter(|&(h, m)| h < 24 && m < 60)
+        .ok_or_else(invalid)?;
+    let parts = date.map(|date| date.split('-').collect::<Vec<_>>());
+    let date = match parts.as_deref() {
+        Some(&[year, month, day]) => Some(
+            number(year, 4)
+                .zip(number(month, 2))
+                .zip(number(day, 2))
+                .map(|((year, month), day)| (year, month, day))
+                .ok_or_else(invalid)?,
+        ),
+        Some(_) => return Err(invalid()),
+        None => None,
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as libc::time_t;
+    // SAFETY: localtime_r and mktime only touch the tm passed to them.
+    let mut today: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&now, &mut today) }.is_null() {
+        return Err("could not read the local time".to_string());
+    }
+    let local = |day_offset: i32| {
+        let mut tm = today;
+        if let Some((year, month, day)) = date {
+            (tm.tm_year, tm.tm_mon, tm.tm_mday) = (year - 1900, month - 1, day);
+        }
+        tm.tm_mday += day_offset;
+        (tm.tm_hour, tm.tm_min, tm.tm_sec, tm.tm_isdst) = (hour, minute, 0, -1);
+        let mut normalized = tm;
+        let secs = unsafe { libc::mktime(&mut normalized) };
+        // mktime moves an impossible date such as 02-30 on instead of failing.
+        let exact = (normalized.tm_year, normalized.tm_mon, normalized.tm_mday)
+            == (tm.tm_year, tm.tm_mon, tm.tm_mday);
+        (secs >= 0 && (date.is_none() || exact)).then_some(secs)
+    };
+    let mut secs = local(0).ok_or_else(invalid)?;
+    if date.is_none() && secs <= now {
+        secs = local(1).ok_or_else(invalid)?;
+    }
+    Ok(UNIX_EPOCH + Duration::from_secs(secs as u64))
+}
+
+/// Resolves --start-at and --stop-at. Both treat a past `HH:MM` as the next day's and
+/// refuse a full date that has already passed; a stop given as `HH:MM` that lands at
+/// or before the start moves on a day too (e.g. 22:00 -> 02:00).
+fn resolve_schedule(cfg: &SendCfg) -> Result<(Option<SystemTime>, Option<SystemTime>), String> {
+    let resolve = |flag: &str, value: Option<&str>| {
+        let Some(value) = value else {
+            return Ok(None);
+        };
+        let at = resolve_wall_time(value)?;
+        if at <= SystemTime::now() {
+            return Err(format!("{flag} {value} has already passed"));
+        }
+        Ok(Some(at))
+    };
+    let start_at = resolve("--start-at", cfg.start_at.as_deref())?;
+    let mut stop_at = resolve("--stop-at", cfg.stop_at.as_deref())?;
+    if let (Some(start), Some(stop)) = (start_at, stop_at)
+        && stop <= start
+    {
+        if cfg.stop_at.as_deref().is_some_and(|stop| stop.contains('-')) {
+            return Err("--stop-at is not after --start-at".to_string());
+        }
+        stop_at = Some(stop + Duration::from_secs(24 * 60 * 60));
+    }
+    Ok((start_at, stop_at))
 }
 
 #[derive(Clone, Copy)]
@@ -607,11 +2595,135 @@ struct FollowState {
     cursor_y: f64,
     target_x: f64,
     target_y: f64,
+    // Smoothed cursor velocity in source pixels per second.
+    velocity_x: f64,
+    velocity_y: f64,
     is_lerping: bool,
     last_frame_at: Instant,
+    // Viewport center without following; moved by SetViewport.
+    home_x: f64,
+    home_y: f64,
+    // Cropped region size; SetViewport can change it on the CPU crop.
+    width: u32,
+    height: u32,
+    // Where the last cursor position came from: metadata, wayland, libinput or none.
+    cursor_source: &'static str,
+    // --smoothing and --deadzone (x, y); a config reload can change them.
+    smoothing: f64,
+    deadzone: (f64, f64),
+}
+
+/// The size the encoder gets: --scale-width x --scale-height, or the viewport's own.
+fn encode_size(cfg: &SendCfg) -> (u32, u32) {
+    (
+        cfg.scale_width.unwrap_or(cfg.width),
+        cfg.scale_height.unwrap_or(cfg.height),
+    )
+}
+
+/// `--lock-aspect`: grows the shorter side of a requested viewport around its center
+/// until it has the output's aspect ratio. Sides are rounded up to even, which
+/// videoscale absorbs with at most a pixel of border.
+fn lock_aspect_rect(
+    (x, y, width, height): (u32, u32, u32, u32),
+    (out_w, out_h): (u32, u32),
+) -> (u32, u32, u32, u32) {
+    let (w, h) = (width as u64, height as u64);
+    let (new_w, new_h) = if w * out_h as u64 >= h * out_w as u64 {
+        (w, (w * out_h as u64).div_ceil(out_w as u64))
+    } else {
+        ((h * out_w as u64).div_ceil(out_h as u64), h)
+    };
+    let new_w = (new_w as u32).next_multiple_of(2);
+    let new_h = (new_h as u32).next_multiple_of(2);
+    (
+        (x + width / 2).saturating_sub(new_w / 2),
+        (y + height / 2).saturating_sub(new_h / 2),
+        new_w,
+        new_h,
+    )
+}
+
+/// Pulls the viewport back inside a source that changed size, dropping any pan that
+/// was heading for the old bounds.
+fn reclamp_follow_state(
+    st: &mut FollowState,
+    (src_w, src_h): (usize, usize),
+    (out_w, out_h): (usize, usize),
+) {
+    let clamp_center = |v: f64, src: usize, out: usize| {
+        let half = out as f64 / 2.0;
+        v.clamp(half, (src as f64 - half).max(half))
+    };
+    st.center_x = clamp_center(st.center_x, src_w, out_w);
+    st.center_y = clamp_center(st.center_y, src_h, out_h);
+    st.target_x = st.center_x;
+    st.target_y = st.center_y;
+    st.cursor_x = st.cursor_x.clamp(0.0, src_w.saturating_sub(1) as f64);
+    st.cursor_y = st.cursor_y.clamp(0.0, src_h.saturating_sub(1) as f64);
+    st.velocity_x = 0.0;
+    st.velocity_y = 0.0;
+    st.is_lerping = false;
+}
+
+/// The part of the source a `--zoom`ed viewport captures before it is scaled to
+/// `width`x`height`; kept even for the 4:2:0 encoders.
+fn zoomed_region(width: u32, height: u32, zoom: f64) -> (u32, u32) {
+    if zoom == 1.0 {
+        return (width, height);
+    }
+    let axis = |len: u32| (((len as f64 / zoom) / 2.0).round() as u32 * 2).max(2);
+    (axis(width), axis(height))
+}
+
+/// Parses `A` or `A,B` for options that take one value per axis; `B` is `None` when
+/// both axes share `A`.
+fn parse_axis_pair<T: std::str::FromStr>(value: &str) -> Option<(T, Option<T>)> {
+    match value.split_once(',') {
+        Some((x, y)) => Some((x.trim().parse().ok()?, Some(y.trim().parse().ok()?))),
+        None => Some((value.trim().parse().ok()?, None)),
+    }
 }
 
-fn encoder_stage(encoder: &str, fps: u32, bitrate_kbps: u32) -> Result<String, String> {
+/// Half-size of the region around the viewport center the cursor can move in without
+/// panning. The deadzone and the edge margin both bound it; the tighter one wins.
+/// Each axis is handled on its own, so a wide viewport can pan sideways later than
+/// it pans vertically.
+fn follow_zone_half_extent(
+    width: u32,
+    height: u32,
+    (deadzone_x, deadzone_y): (f64, f64),
+    (margin_x, margin_y): (u32, u32),
+) -> (f64, f64) {
+    let axis = |extent: u32, deadzone: f64, margin: u32| {
+        let half = extent as f64 / 2.0;
+        let margin_zone = (half - margin as f64).max(0.0);
+        match (deadzone > 0.0, margin > 0) {
+            (true, true) => (half * deadzone / 100.0).min(margin_zone),
+            (true, false) => half * deadzone / 100.0,
+            (false, true) => margin_zone,
+            (false, false) => 0.0,
+        }
+    };
+    (
+        axis(width, deadzone_x, margin_x),
+        axis(height, deadzone_y, margin_y),
+    )
+}
+
+fn encoder_stage(
+    encoder: &str,
+    fps: u32,
+    bitrate_kbps: u32,
+    temporal_layers: u32,
+) -> Result<String, String> {
+    // Only x265 exposes temporal sub-layers. Which frames it puts above the base layer
+    // follows from its own GOP structure; the option is passed on as it is.
+    if temporal_layers > 1 && encoder != "x265enc" {
+        return Err(format!(
+            "--temporal-layers is not supported by {encoder} (use x265enc)"
+        ));
+    }
     match encoder {
         "x264enc" => Ok(format!(
             "x264enc tune=zerolatency speed-preset=ultrafast key-int-max={} bitrate={}",
@@ -625,10 +2737,15 @@ fn encoder_stage(encoder: &str, fps: u32, bitrate_kbps: u32) -> Result<String, S
         )),
         "x265enc" => {
             let gop = (fps.max(1) * 2).max(30);
+            let mut options = String::from("repeat-headers=1:aud=1:scenecut=0");
+            if temporal_layers > 1 {
+                options.push_str(&format!(":temporal-layers={temporal_layers}"));
+            }
             Ok(format!(
-                "x265enc speed-preset=veryfast key-int-max={} bitrate={} option-string=\"repeat-headers=1:aud=1:scenecut=0\"",
+                "x265enc speed-preset=veryfast key-int-max={} bitrate={} option-string=\"{}\"",
                 gop,
-                bitrate_kbps
+                bitrate_kbps,
+                options
             ))
         }
         "nvh265enc" => Ok(format!(
@@ -641,468 +2758,4367 @@ fn encoder_stage(encoder: &str, fps: u32, bitrate_kbps: u32) -> Result<String, S
             bitrate_kbps,
             fps.max(1)
         )),
+        // va plugin. b-frames=0 and a single reference frame keep AMD's encoder (which
+        // has no low-power entry point) from buffering frames; the lp variants use
+        // Intel's VDEnc.
+        "vah264enc" | "vah265enc" | "vah264lpenc" | "vah265lpenc" => Ok(format!(
+            "{} rate-control=cbr bitrate={} key-int-max={} b-frames=0 ref-frames=1 target-usage=7",
+            encoder,
+            bitrate_kbps,
+            fps.max(1)
+        )),
+        // QuickSync: low-power runs on the fixed-function VDEnc block, target-usage=7
+        // is the fastest preset, and b-frames are off so nothing is reordered.
+        "qsvh264enc" | "qsvh265enc" => Ok(format!(
+            "{} low-power=on target-usage=7 rate-control=cbr bitrate={} gop-size={} bframes=0",
+            encoder,
+            bitrate_kbps,
+            fps.max(1)
+        )),
+        "qsvav1enc" => Ok(format!(
+            "qsvav1enc low-power=on target-usage=7 rate-control=cbr bitrate={} gop-size={}",
+            bitrate_kbps,
+            fps.max(1) * 2
+        )),
         "v4l2h265enc" => Ok(format!(
             "v4l2h265enc extra-controls=\"controls,video_bitrate={}000\"",
             bitrate_kbps
         )),
+        // pred-struct=1 is SVT-AV1's low-delay mode (no reordering).
+        "svtav1enc" => Ok(format!(
+            "svtav1enc preset=10 target-bitrate={} intra-period-length={} parameters-string=\"pred-struct=1\"",
+            bitrate_kbps,
+            fps.max(1) * 2
+        )),
+        "rav1enc" => Ok(format!(
+            "rav1enc speed-preset=10 low-latency=true bitrate={} max-key-frame-interval={}",
+            bitrate_kbps * 1000,
+            fps.max(1) * 2
+        )),
+        "vaav1enc" => Ok(format!(
+            "vaav1enc rate-control=cbr bitrate={} key-int-max={}",
+            bitrate_kbps,
+            fps.max(1) * 2
+        )),
+        "vp9enc" => Ok(format!(
+            "vp9enc deadline=1 cpu-used=8 end-usage=cbr lag-in-frames=0 row-mt=true target-bitrate={} keyframe-max-dist={}",
+            bitrate_kbps * 1000,
+            fps.max(1) * 2
+        )),
+        "vaapivp9enc" => Ok(format!(
+            "vaapivp9enc rate-control=cbr bitrate={} keyframe-period={}",
+            bitrate_kbps,
+            fps.max(1) * 2
+        )),
+        // Every frame is a standalone JPEG with no rate control, so --bitrate-kbps
+        // does not apply; expect roughly 50-150 Mbit/s at 1280x720@60.
+        "jpegenc" => Ok("jpegenc quality=85".to_string()),
         other => Err(format!("unsupported --encoder '{other}'")),
     }
 }
 
-fn rtp_video_stage(encoder: &str) -> Result<&'static str, String> {
-    match encoder {
-        "x264enc" | "nvh264enc" => {
-            Ok("h264parse config-interval=1 ! rtph264pay pt=96 config-interval=1 mtu=1200")
-        }
-        "x265enc" | "nvh265enc" | "vaapih265enc" | "v4l2h265enc" => {
-            Ok("h265parse config-interval=1 ! rtph265pay pt=96 config-interval=1 mtu=1200")
-        }
-        other => Err(format!("unsupported --encoder '{other}'")),
+fn validate_srtp_key(value: &str) -> Result<(), String> {
+    if value.len() != SRTP_KEY_LEN * 2 || !value.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!(
+            "--srtp-key must be {} hex characters (e.g. from `openssl rand -hex {}`)",
+            SRTP_KEY_LEN * 2,
+            SRTP_KEY_LEN
+        ));
     }
+    Ok(())
 }
 
-fn run_send_live(node_id: u32, cfg: SendCfg, output_fps: u32) -> ExitCode {
-    if let Err(err) = gst::init() {
-        eprintln!("FAIL: gstreamer init failed: {err}");
-        return ExitCode::from(1);
+/// The options that only some encoders support. `--encoder auto` skips encoders
+/// that fail this rather than rejecting the options.
+fn check_encoder_options(cfg: &SendCfg) -> Result<(), String> {
+    let encoder = &cfg.encoder;
+    if cfg.adaptive_bitrate && encoder_bitrate_property(encoder).is_none() {
+        return Err(format!("--adaptive-bitrate is not supported by {encoder}"));
+    }
+    if cfg.idle_bitrate_kbps.is_some() && encoder_bitrate_property(encoder).is_none() {
+        return Err(format!("--idle-bitrate-kbps is not supported by {encoder}"));
+    }
+    if cfg.dmabuf && dmabuf_postproc(encoder).is_none() {
+        return Err(format!(
+            "--dmabuf needs a VA-API encoder (vaapih265enc, vaapivp9enc, vah265enc or vaav1enc), not {encoder}"
+        ));
+    }
+    if cfg.va_device.is_some() && !encoder.starts_with("va") {
+        return Err(format!("--va-device does not apply to {encoder}"));
+    }
+    if cfg.va_device.is_some() && encoder.starts_with("vaapi") {
+        return Err(format!(
+            "--va-device needs a va plugin encoder (e.g. vah265enc); {encoder} follows GST_VAAPI_DRM_DEVICE"
+        ));
+    }
+    if cfg.roi_radius > 0 && !encoder_supports_roi(encoder) {
+        return Err(format!(
+            "--roi-radius is not supported by {encoder} (use vaapih265enc)"
+        ));
+    }
+    if cfg.bit_depth == 10 && !encoder_supports_10bit(encoder) {
+        return Err(format!(
+            "--bit-depth 10 is not supported by {encoder} (use an HEVC encoder such as x265enc or vah265enc)"
+        ));
     }
+    Ok(())
+}
 
-    let enc = match encoder_stage(&cfg.encoder, output_fps, cfg.bitrate_kbps) {
-        Ok(v) => v,
-        Err(err) => {
-            eprintln!("FAIL: {err}");
-            return ExitCode::from(2);
-        }
-    };
-    let rtp_stage = match rtp_video_stage(&cfg.encoder) {
-        Ok(v) => v,
-        Err(err) => {
-            eprintln!("FAIL: {err}");
-            return ExitCode::from(2);
-        }
-    };
+// HEVC encoders that take 10-bit frames and write the Main 10 profile.
+fn encoder_supports_10bit(encoder: &str) -> bool {
+    matches!(
+        encoder,
+        "x265enc" | "nvh265enc" | "vaapih265enc" | "vah265enc" | "vah265lpenc" | "qsvh265enc"
+    )
+}
 
-    let is_nvenc = matches!(cfg.encoder.as_str(), "nvh264enc" | "nvh265enc");
+/// `--encoder auto`: the first of AUTO_ENCODERS that is installed, suits the other
+/// settings and encodes a test frame at the stream size. Other encoders pass through,
+/// with their render node picked if they need one.
+fn resolve_encoder(cfg: &SendCfg) -> Result<SendCfg, String> {
+    if cfg.encoder != "auto" {
+        return resolve_va_device(cfg);
+    }
+    gst::init().map_err(|e| format!("gstreamer init failed: {e}"))?;
+    for &encoder in AUTO_ENCODERS {
+        let candidate = SendCfg {
+            encoder: encoder.to_string(),
+            auto_encoder: true,
+            ..cfg.clone()
+        };
+        if gst::ElementFactory::find(encoder).is_none() {
+            println!("Encoder auto: {encoder} is not installed.");
+            continue;
+        }
+        let checked = check_encoder_options(&candidate)
+            .and_then(|_| resolve_va_device(&candidate))
+            .and_then(|candidate| {
+                output_pipeline_desc(&candidate, cfg.fps.max(1))?;
+                probe_encoder(&candidate)?;
+                Ok(candidate)
+            });
+        match checked {
+            Ok(candidate) => {
+                println!("Encoder auto: using {encoder}.");
+                return Ok(candidate);
+            }
+            Err(err) => println!("Encoder auto: skipping {encoder}: {err}"),
+        }
+    }
+    Err(format!(
+        "--encoder auto: none of {} works here; pass --encoder explicitly",
+        AUTO_ENCODERS.join(", ")
+    ))
+}
 
-    let input_desc = format!(
-        "pipewiresrc path={} do-timestamp=true ! videoconvert ! video/x-raw,format=RGBA,framerate={}/1 ! appsink name=sink max-buffers=1 drop=true emit-signals=true sync=false",
-        node_id, cfg.fps
-    );
+/// Without `--va-device`, a va plugin encoder runs on the first render node whose
+/// element encodes a test frame, so a machine whose first node is a display-only or
+/// encoder-less GPU still finds the one that can encode. With one node, or with
+/// `--va-device`, nothing is probed.
+fn resolve_va_device(cfg: &SendCfg) -> Result<SendCfg, String> {
+    let va_plugin = cfg.encoder.starts_with("va") && !cfg.encoder.starts_with("vaapi");
+    if cfg.va_device.is_some() || !va_plugin {
+        return Ok(cfg.clone());
+    }
+    let nodes = render_nodes();
+    if nodes.len() < 2 {
+        return Ok(cfg.clone());
+    }
+    for node in &nodes {
+        let candidate = SendCfg {
+            va_device: Some(node.clone()),
+            ..cfg.clone()
+        };
+        match probe_encoder(&candidate) {
+            Ok(()) => {
+                println!("VA device: using {node} for {}.", cfg.encoder);
+                return Ok(candidate);
+            }
+            Err(err) => println!("VA device: skipping {node}: {err}"),
+        }
+    }
+    Err(format!(
+        "{} works on none of {}; pass --va-device or another --encoder",
+        cfg.encoder,
+        nodes.join(", ")
+    ))
+}
 
-    let pre_encode = if is_nvenc {
-        "cudaupload".to_string()
+/// The DRM render nodes, renderD128 first.
+fn render_nodes() -> Vec<String> {
+    let mut nodes: Vec<(u32, String)> = fs::read_dir("/dev/dri")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let number = name.strip_prefix("renderD")?.parse().ok()?;
+            Some((number, format!("/dev/dri/{name}")))
+        })
+        .collect();
+    nodes.sort();
+    nodes.into_iter().map(|(_, path)| path).collect()
+}
+
+/// Runs one raw test frame through the encoder of `cfg` and waits for it to reach PAUSED,
+/// which needs the caps to negotiate and the encoder to open (e.g. a GPU session).
+fn probe_encoder(cfg: &SendCfg) -> Result<(), String> {
+    let ((width, height), fps) = (encode_size(cfg), cfg.fps.max(1));
+    let upload = if cfg.encoder.starts_with("nv") {
+        "cudaupload ! "
     } else {
-        format!(
-            "videoconvert ! video/x-raw,format=I420 ! queue max-size-buffers={} max-size-bytes=0 max-size-time=0",
-            DEFAULT_QUEUE_BUFFERS
-        )
+        ""
     };
-    let output_desc = format!(
-        "appsrc name=src is-live=true format=time do-timestamp=true block=true \
-         caps=video/x-raw,format=RGBA,width={},height={},framerate={}/1 ! \
-         queue max-size-buffers={} max-size-bytes=0 max-size-time=0 ! \
-         {} ! {} ! \
-         queue max-size-buffers={} max-size-bytes=0 max-size-time=0 ! {} ! \
-         queue max-size-buffers={} max-size-bytes=0 max-size-time=0 ! \
-         udpsink host={} port={} sync=false async=false",
-        cfg.width, cfg.height, output_fps,
-        DEFAULT_QUEUE_BUFFERS,
-        pre_encode, enc,
-        DEFAULT_QUEUE_BUFFERS, rtp_stage,
-        DEFAULT_QUEUE_BUFFERS,
-        cfg.receiver_ip, cfg.port
+    let format = encoder_raw_format(&cfg.encoder, cfg.bit_depth);
+    let encoder = va_element_name(&cfg.encoder, cfg.va_device.as_deref())?;
+    // The profile caps make a GPU without Main 10 support fail here, not mid-stream.
+    let desc = format!(
+        "videotestsrc num-buffers=1 ! video/x-raw,format={format},width={width},height={height},framerate={fps}/1 ! \
+         {upload}{encoder}{} ! fakesink",
+        profile_caps(cfg)
     );
+    let pipeline = gst::parse::launch(&desc).map_err(|e| e.to_string())?;
+    let _ = pipeline.set_state(gst::State::Paused);
+    let (result, state, _) =
+        pipeline.state(gst::ClockTime::from_seconds(ENCODER_PROBE_TIMEOUT_SECS));
+    let _ = pipeline.set_state(gst::State::Null);
+    match (result, state) {
+        (Ok(_), gst::State::Paused) => Ok(()),
+        (Ok(_), _) => Err(format!(
+            "did not preroll within {ENCODER_PROBE_TIMEOUT_SECS}s"
+        )),
+        (Err(_), _) => Err(format!("could not encode a {width}x{height} test frame")),
+    }
+}
 
-    let input_pipeline = match gst::parse::launch(&input_desc) {
-        Ok(p) => match p.downcast::<gst::Pipeline>() {
-            Ok(v) => v,
-            Err(_) => {
-                eprintln!("FAIL: input pipeline is not a gst::Pipeline");
-                return ExitCode::from(1);
+/// The property that sets an encoder's bitrate while playing, and how many of its
+/// units make one kbps.
+fn encoder_bitrate_property(encoder: &str) -> Option<(&'static str, u32)> {
+    match encoder {
+        "x264enc" | "nvh264enc" | "x265enc" | "nvh265enc" | "vaapih265enc" | "vaav1enc"
+        | "vaapivp9enc" | "qsvh264enc" | "qsvh265enc" | "qsvav1enc" | "vah264enc" | "vah265enc"
+        | "vah264lpenc" | "vah265lpenc" => Some(("bitrate", 1)),
+        "svtav1enc" => Some(("target-bitrate", 1)),
+        "rav1enc" => Some(("bitrate", 1000)),
+        "vp9enc" => Some(("target-bitrate", 1000)),
+        _ => None,
+    }
+}
+
+/// The raw format handed to `encoder` from system memory. The va plugin and QSV
+/// encoders only take NV12, and P010 at 10 bits; x265 takes planar I420 either way.
+fn encoder_raw_format(encoder: &str, bit_depth: u32) -> &'static str {
+    match (encoder, bit_depth) {
+        ("x265enc", 10) => "I420_10LE",
+        (_, 10) => "P010_10LE",
+        (
+            "vaav1enc" | "vah264enc" | "vah265enc" | "vah264lpenc" | "vah265lpenc" | "qsvh264enc"
+            | "qsvh265enc" | "qsvav1enc",
+            _,
+        ) => "NV12",
+        _ => "I420",
+    }
+}
+
+/// Caps right after the encoder that hold it to the Main 10 profile at 10 bits.
+fn profile_caps(cfg: &SendCfg) -> &'static str {
+    if cfg.bit_depth == 10 {
+        " ! video/x-h265,profile=main-10"
+    } else {
+        ""
+    }
+}
+
+/// The raw formats the capture is converted to ahead of the crop.
+fn crop_formats(cfg: &SendCfg) -> &'static str {
+    if cfg.bit_depth == 10 {
+        CROP_FORMATS_10BIT
+    } else {
+        CROP_FORMATS
+    }
+}
+
+/// `--enc-opt` keys must be writable properties of the encoder; the pipeline parser
+/// would otherwise fail with a less specific error.
+fn check_enc_opts(element: &str, opts: &BTreeMap<String, String>) -> Result<(), String> {
+    if opts.is_empty() {
+        return Ok(());
+    }
+    gst::init().map_err(|e| format!("gstreamer init failed: {e}"))?;
+    let encoder = gst::ElementFactory::make(element)
+        .build()
+        .map_err(|_| format!("{element} is not installed"))?;
+    for key in opts.keys() {
+        match encoder.find_property(key) {
+            Some(pspec) if pspec.flags().contains(gst::glib::ParamFlags::WRITABLE) => {}
+            Some(_) => return Err(format!("--enc-opt {key}: {element}'s {key} is read-only")),
+            None => {
+                return Err(format!(
+                    "--enc-opt {key}: {element} has no such property (see gst-inspect-1.0 {element})"
+                ));
             }
-        },
-        Err(err) => {
-            eprintln!("FAIL: could not build input pipeline: {err}");
-            return ExitCode::from(1);
         }
+    }
+    Ok(())
+}
+
+/// The name of a va plugin element on the render node `device`. The plugin
+/// registers the first node's elements under the plain name (vah265enc) and every
+/// other node's with the node in it (varenderD129h265enc). Other elements, and the
+/// legacy vaapi ones, pass through.
+fn va_element_name(element: &str, device: Option<&str>) -> Result<String, String> {
+    let (Some(device), Some(rest)) = (device, element.strip_prefix("va")) else {
+        return Ok(element.to_string());
     };
-    let output_pipeline = match gst::parse::launch(&output_desc) {
-        Ok(p) => match p.downcast::<gst::Pipeline>() {
-            Ok(v) => v,
-            Err(_) => {
-                eprintln!("FAIL: output pipeline is not a gst::Pipeline");
-                return ExitCode::from(1);
+    if element.starts_with("vaapi") {
+        return Ok(element.to_string());
+    }
+    gst::init().map_err(|e| format!("gstreamer init failed: {e}"))?;
+    let node = Path::new(device)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(device);
+    let per_node = format!("va{node}{rest}");
+    if gst::ElementFactory::find(&per_node).is_some() {
+        return Ok(per_node);
+    }
+    let plain = gst::ElementFactory::make(element)
+        .build()
+        .map_err(|_| format!("{element} is not installed"))?;
+    if plain.find_property("device-path").is_some()
+        && plain.property::<Option<String>>("device-path").as_deref() == Some(device)
+    {
+        return Ok(element.to_string());
+    }
+    Err(format!(
+        "{element} is not available on {device} (see `gst-inspect-1.0 va`)"
+    ))
+}
+
+/// The VA post-processor that crops a DMA-buf frame (from its `VideoCropMeta`) on the
+/// GPU for `encoder`, and the caps feature of the surfaces it hands over.
+fn dmabuf_postproc(encoder: &str) -> Option<(&'static str, &'static str)> {
+    match encoder {
+        "vaapih265enc" | "vaapivp9enc" => Some(("vaapipostproc", "memory:VASurface")),
+        "vaav1enc" | "vah264enc" | "vah265enc" | "vah264lpenc" | "vah265lpenc" => {
+            Some(("vapostproc", "memory:VAMemory"))
+        }
+        _ => None,
+    }
+}
+
+// Arrow drawn by --draw-cursor, hotspot at the top-left: 'X' outline, '.' fill.
+// pipewiresrc does not pass on the cursor bitmap of metadata streams, so the
+// real cursor shape is not available.
+const CURSOR_SPRITE: [&str; 20] = [
+    "X           ",
+    "XX          ",
+    "X.X         ",
+    "X..X        ",
+    "X...X       ",
+    "X....X      ",
+    "X.....X     ",
+    "X......X    ",
+    "X.......X   ",
+    "X........X  ",
+    "X.........X ",
+    "X..........X",
+    "X......XXXXX",
+    "X...X..X    ",
+    "X..XX..X    ",
+    "X.X  X..X   ",
+    "XX   X..X   ",
+    "X     X..X  ",
+    "      X..X  ",
+    "       XX   ",
+];
+
+fn draw_cursor_sprite(data: &mut [u8], (width, height): (usize, usize), (x, y): (f64, f64)) {
+    let (x, y) = (x.round() as i64, y.round() as i64);
+    for (row, line) in CURSOR_SPRITE.iter().enumerate() {
+        let py = y + row as i64;
+        if py < 0 || py >= height as i64 {
+            continue;
+        }
+        for (col, ch) in line.bytes().enumerate() {
+            let px = x + col as i64;
+            if px < 0 || px >= width as i64 {
+                continue;
             }
-        },
-        Err(err) => {
-            eprintln!("FAIL: could not build output pipeline: {err}");
-            return ExitCode::from(1);
+            let value = match ch {
+                b'X' => 0,
+                b'.' => 255,
+                _ => continue,
+            };
+            let off = (py as usize * width + px as usize) * 4;
+            data[off..off + 4].copy_from_slice(&[value, value, value, 255]);
         }
-    };
+    }
+}
 
-    let appsink = match input_pipeline
-        .by_name("sink")
-        .and_then(|e| e.downcast::<AppSink>().ok())
-    {
-        Some(v) => v,
-        None => {
-            eprintln!("FAIL: could not find appsink in input pipeline");
-            return ExitCode::from(1);
+// Blends the click ring into an RGBA frame; `progress` runs from 0 at the press to 1
+// when the ring has faded out.
+fn draw_click_highlight(
+    data: &mut [u8],
+    (width, height): (usize, usize),
+    (cx, cy): (f64, f64),
+    progress: f64,
+) {
+    let radius = CLICK_HIGHLIGHT_RADIUS_PX * (0.5 + 0.5 * progress);
+    let alpha = 0.8 * (1.0 - progress);
+    let half = CLICK_HIGHLIGHT_THICKNESS_PX / 2.0;
+    let reach = radius + half + 1.0;
+    let x0 = (cx - reach).floor().max(0.0) as usize;
+    let y0 = (cy - reach).floor().max(0.0) as usize;
+    let x1 = ((cx + reach).ceil().max(0.0) as usize).min(width);
+    let y1 = ((cy + reach).ceil().max(0.0) as usize).min(height);
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let dist = ((x as f64 + 0.5 - cx).powi(2) + (y as f64 + 0.5 - cy).powi(2)).sqrt();
+            // One pixel of falloff on each side keeps the edge from stair-stepping.
+            let cover = (half + 0.5 - (dist - radius).abs()).clamp(0.0, 1.0);
+            if cover <= 0.0 {
+                continue;
+            }
+            let a = alpha * cover;
+            let px = (y * width + x) * 4;
+            for (c, target) in CLICK_HIGHLIGHT_RGB.iter().enumerate() {
+                let v = data[px + c] as f64;
+                data[px + c] = (v + (*target as f64 - v) * a).round() as u8;
+            }
         }
-    };
-    let appsrc = match output_pipeline
-        .by_name("src")
-        .and_then(|e| e.downcast::<AppSrc>().ok())
-    {
-        Some(v) => v,
-        None => {
-            eprintln!("FAIL: could not find appsrc in output pipeline");
-            return ExitCode::from(1);
+    }
+}
+
+// Hides the masks in an RGBA frame whose top-left pixel sits at `origin` in source
+// pixels. Blur blocks are laid out from each mask's own corner, so the pattern stays
+// put on screen while the viewport pans over it.
+fn draw_masks(
+    data: &mut [u8],
+    (width, height): (usize, usize),
+    (origin_x, origin_y): (i64, i64),
+    masks: &[MaskSpec],
+) {
+    for mask in masks {
+        let left = mask.x as i64 - origin_x;
+        let top = mask.y as i64 - origin_y;
+        let x0 = left.clamp(0, width as i64);
+        let y0 = top.clamp(0, height as i64);
+        let x1 = (left + mask.w as i64).clamp(0, width as i64);
+        let y1 = (top + mask.h as i64).clamp(0, height as i64);
+        if x0 >= x1 || y0 >= y1 {
+            continue;
         }
-    };
+        if mask.mode != "blur" {
+            for y in y0 as usize..y1 as usize {
+                let row = &mut data[(y * width + x0 as usize) * 4..(y * width + x1 as usize) * 4];
+                for px in row.chunks_exact_mut(4) {
+                    px.copy_from_slice(&[0, 0, 0, 255]);
+                }
+            }
+            continue;
+        }
+        let block = MASK_BLOCK_PX as i64;
+        let mut by = y0 - (y0 - top).rem_euclid(block);
+        while by < y1 {
+            let mut bx = x0 - (x0 - left).rem_euclid(block);
+            while bx < x1 {
+                pixelate_block(
+                    data,
+                    width,
+                    (bx.max(x0) as usize, by.max(y0) as usize),
+                    ((bx + block).min(x1) as usize, (by + block).min(y1) as usize),
+                );
+                bx += block;
+            }
+            by += block;
+        }
+    }
+}
 
-    let cosmic_cursor = start_cosmic_cursor_tracker().ok();
-    let mouse_deltas = start_mouse_delta_tracker().ok();
-    let saw_cosmic_cursor = Arc::new(AtomicBool::new(false));
+// Paints the RGBA pixels from `(x0, y0)` up to `(x1, y1)` in their average color.
+fn pixelate_block(
+    data: &mut [u8],
+    width: usize,
+    (x0, y0): (usize, usize),
+    (x1, y1): (usize, usize),
+) {
+    let mut sum = [0u64; 3];
+    for y in y0..y1 {
+        for px in data[(y * width + x0) * 4..(y * width + x1) * 4].chunks_exact(4) {
+            for (total, value) in sum.iter_mut().zip(px) {
+                *total += *value as u64;
+            }
+        }
+    }
+    let count = ((x1 - x0) * (y1 - y0)) as u64;
+    let average = sum.map(|total| (total / count) as u8);
+    for y in y0..y1 {
+        for px in data[(y * width + x0) * 4..(y * width + x1) * 4].chunks_exact_mut(4) {
+            px[..3].copy_from_slice(&average);
+        }
+    }
+}
 
-    let follow_state = Arc::new(Mutex::new(FollowState {
-        center_x: cfg.x as f64 + cfg.width as f64 / 2.0,
-        center_y: cfg.y as f64 + cfg.height as f64 / 2.0,
-        cursor_x: cfg.x as f64 + cfg.width as f64 / 2.0,
-        cursor_y: cfg.y as f64 + cfg.height as f64 / 2.0,
-        target_x: cfg.x as f64 + cfg.width as f64 / 2.0,
-        target_y: cfg.y as f64 + cfg.height as f64 / 2.0,
-        is_lerping: false,
-        last_frame_at: Instant::now(),
-    }));
-    let out_idx = Arc::new(Mutex::new(0u64));
+// Encoders that read GstVideoRegionOfInterestMeta. x264enc, x265enc and the
+// nvcodec encoders ignore it, so --roi-radius would silently do nothing there.
+fn encoder_supports_roi(encoder: &str) -> bool {
+    matches!(encoder, "vaapih265enc")
+}
 
-    let follow_state_cb = Arc::clone(&follow_state);
-    let out_idx_cb = Arc::clone(&out_idx);
-    let appsrc_cb = appsrc.clone();
-    let saw_cosmic_cursor_cb = Arc::clone(&saw_cosmic_cursor);
-    let cfg_follow = cfg.follow_mouse;
-    let cfg_width = cfg.width;
-    let cfg_height = cfg.height;
-    let cfg_x = cfg.x;
-    let cfg_y = cfg.y;
-    let cfg_output_fps = output_fps;
-    let cfg_smoothing = cfg.smoothing;
-    let cfg_deadzone = cfg.deadzone;
+// Square of side 2 * radius around the cursor, clipped to the frame. None when the
+// cursor is far enough outside the viewport that nothing is left.
+fn roi_rect(
+    (x, y): (f64, f64),
+    radius: f64,
+    (width, height): (usize, usize),
+) -> Option<(u32, u32, u32, u32)> {
+    let left = (x - radius).max(0.0);
+    let top = (y - radius).max(0.0);
+    let right = (x + radius).min(width as f64);
+    let bottom = (y + radius).min(height as f64);
+    if right <= left || bottom <= top {
+        return None;
+    }
+    Some((
+        left as u32,
+        top as u32,
+        (right - left) as u32,
+        (bottom - top) as u32,
+    ))
+}
 
-    appsink.set_callbacks(
-        AppSinkCallbacks::builder()
-            .new_sample(move |sink| {
-                let sample = sink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
-                let caps = sample.caps().ok_or(gst::FlowError::Error)?;
-                let s = caps.structure(0).ok_or(gst::FlowError::Error)?;
-                let src_w = s.get::<i32>("width").map_err(|_| gst::FlowError::Error)? as usize;
-                let src_h = s.get::<i32>("height").map_err(|_| gst::FlowError::Error)? as usize;
-                let out_w = cfg_width as usize;
-                let out_h = cfg_height as usize;
-                if src_w < out_w || src_h < out_h {
-                    return Err(gst::FlowError::Error);
-                }
+fn encoder_codec(encoder: &str) -> Result<&'static str, String> {
+    match encoder {
+        "x264enc" | "nvh264enc" | "qsvh264enc" | "vah264enc" | "vah264lpenc" => Ok("h264"),
+        "x265enc" | "nvh265enc" | "vaapih265enc" | "qsvh265enc" | "vah265enc" | "vah265lpenc"
+        | "v4l2h265enc" => Ok("h265"),
+        "svtav1enc" | "rav1enc" | "vaav1enc" | "qsvav1enc" => Ok("av1"),
+        "vp9enc" | "vaapivp9enc" => Ok("vp9"),
+        "jpegenc" => Ok("mjpeg"),
+        other => Err(format!("unsupported --encoder '{other}'")),
+    }
+}
 
-                let now = Instant::now();
-                let (crop_x, crop_y) = {
-                    let mut st = follow_state_cb.lock().map_err(|_| gst::FlowError::Error)?;
-                    let prev_cursor_x = st.cursor_x;
-                    let prev_cursor_y = st.cursor_y;
-
-                    if cfg_follow {
-                        let mut used_stream_meta = false;
-                        if let Some((mx, my)) = extract_cursor_from_sample(&sample, src_w as u32, src_h as u32) {
-                            st.cursor_x = mx;
-                            st.cursor_y = my;
-                            used_stream_meta = true;
-                        }
+/// Parses the encoded stream into whole access units for the RTSP branch, which
+/// hands them to the server's own payloader.
+fn rtsp_feed_stage(encoder: &str) -> Result<&'static str, String> {
+    Ok(match encoder_codec(encoder)? {
+        "h264" => "h264parse config-interval=-1 ! video/x-h264,stream-format=byte-stream,alignment=au",
+        "h265" => "h265parse config-interval=-1 ! video/x-h265,stream-format=byte-stream,alignment=au",
+        "vp9" => "vp9parse",
+        "mjpeg" => "jpegparse",
+        _ => "av1parse ! video/x-av1,stream-format=obu-stream,alignment=tu",
+    })
+}
 
-                        let mut used_cosmic = false;
-                        if !used_stream_meta {
-                            if let Some(cosmic_xy) = &cosmic_cursor {
-                                if let Ok(guard) = cosmic_xy.lock() {
-                                    if let Some((mx, my)) = *guard {
-                                        st.cursor_x = mx;
-                                        st.cursor_y = my;
-                                        saw_cosmic_cursor_cb.store(true, Ordering::Relaxed);
-                                        used_cosmic = true;
-                                    }
-                                }
-                            }
-                        }
+fn rtsp_payloader(encoder: &str) -> Result<&'static str, String> {
+    Ok(match encoder_codec(encoder)? {
+        "h264" => "h264parse ! rtph264pay name=pay0 pt=96 config-interval=-1",
+        "h265" => "h265parse ! rtph265pay name=pay0 pt=96 config-interval=-1",
+        "vp9" => "rtpvp9pay name=pay0 pt=96",
+        "mjpeg" => "rtpjpegpay name=pay0 pt=96",
+        _ => "av1parse ! rtpav1pay name=pay0 pt=96",
+    })
+}
 
-                        if !used_stream_meta && !used_cosmic {
-                            if let Some(deltas) = &mouse_deltas {
-                                let mut d = deltas.lock().map_err(|_| gst::FlowError::Error)?;
-                                st.cursor_x += d.0;
-                                st.cursor_y += d.1;
-                                d.0 = 0.0;
-                                d.1 = 0.0;
-                            }
-                        }
-                    }
+fn rtsp_media_desc(encoder: &str) -> Result<String, String> {
+    Ok(format!(
+        "( appsrc name=rtspsrc is-live=true format=time do-timestamp=true ! {} )",
+        rtsp_payloader(encoder)?
+    ))
+}
 
-                    let max_cursor_x = (src_w.saturating_sub(1)) as f64;
-                    let max_cursor_y = (src_h.saturating_sub(1)) as f64;
-                    st.cursor_x = st.cursor_x.clamp(0.0, max_cursor_x);
-                    st.cursor_y = st.cursor_y.clamp(0.0, max_cursor_y);
-                    if cfg_follow {
-                        let cursor_changed = (st.cursor_x - prev_cursor_x).abs() > DEFAULT_CURSOR_CHANGE_EPSILON_PX
-                            || (st.cursor_y - prev_cursor_y).abs() > DEFAULT_CURSOR_CHANGE_EPSILON_PX;
-                        if cursor_changed {
-                            if cfg_deadzone > 0.0 {
-                                let dz_half_w = (cfg_width as f64) * (cfg_deadzone / 100.0) / 2.0;
-                                let dz_half_h = (cfg_height as f64) * (cfg_deadzone / 100.0) / 2.0;
-                                let left = st.center_x - dz_half_w;
-                                let right = st.center_x + dz_half_w;
-                                let top = st.center_y - dz_half_h;
-                                let bottom = st.center_y + dz_half_h;
-
-                                let target_x = if st.cursor_x < left {
-                                    st.cursor_x + dz_half_w
-                                } else if st.cursor_x > right {
-                                    st.cursor_x - dz_half_w
-                                } else {
-                                    st.center_x
-                                };
-                                let target_y = if st.cursor_y < top {
-                                    st.cursor_y + dz_half_h
-                                } else if st.cursor_y > bottom {
-                                    st.cursor_y - dz_half_h
-                                } else {
-                                    st.center_y
-                                };
-                                st.target_x = target_x;
-                                st.target_y = target_y;
-                            } else {
-                                st.target_x = st.cursor_x;
-                                st.target_y = st.cursor_y;
-                            }
-                            st.is_lerping = true;
-                        }
-                    } else {
-                        st.center_x = cfg_x as f64 + cfg_width as f64 / 2.0;
-                        st.center_y = cfg_y as f64 + cfg_height as f64 / 2.0;
-                        st.target_x = st.center_x;
-                        st.target_y = st.center_y;
-                        st.is_lerping = false;
-                    }
+/// The appsrc of the currently prepared RTSP media, if any client is watching.
+type RtspFeed = Arc<Mutex<Option<AppSrc>>>;
 
-                    let dt = (now - st.last_frame_at).as_secs_f64().max(0.000_001);
-                    st.last_frame_at = now;
-                    if st.is_lerping {
-                        let alpha = 1.0 - (-cfg_smoothing * dt).exp();
-                        st.center_x += (st.target_x - st.center_x) * alpha;
-                        st.center_y += (st.target_y - st.center_y) * alpha;
-                        let dx = st.target_x - st.center_x;
-                        let dy = st.target_y - st.center_y;
-                        let settle2 = DEFAULT_SETTLE_EPSILON_PX * DEFAULT_SETTLE_EPSILON_PX;
-                        if dx * dx + dy * dy <= settle2 {
-                            st.center_x = st.target_x;
-                            st.center_y = st.target_y;
-                            st.is_lerping = false;
-                        }
+/// Runs gst-rtsp-server on its own GLib main loop. The media is shared, so every
+/// client sees the same encode; the output pipeline pushes access units into the
+/// media's appsrc while it exists.
+fn start_rtsp_server(port: u16, encoder: &str) -> Result<RtspFeed, String> {
+    let launch = rtsp_media_desc(encoder)?;
+    let feed: RtspFeed = Arc::new(Mutex::new(None));
+    let feed_cb = Arc::clone(&feed);
+    let (ready_tx, ready_rx) = mpsc::channel::<Result<(), String>>();
+    thread::spawn(move || {
+        let ctx = gst::glib::MainContext::new();
+        let started = ctx.with_thread_default(|| {
+            let server = gst_rtsp_server::RTSPServer::new();
+            server.set_service(&port.to_string());
+            let factory = gst_rtsp_server::RTSPMediaFactory::new();
+            factory.set_launch(&launch);
+            factory.set_shared(true);
+            factory.connect_media_configure(move |_, media| {
+                let appsrc = media
+                    .element()
+                    .downcast::<gst::Bin>()
+                    .ok()
+                    .and_then(|bin| bin.by_name("rtspsrc"))
+                    .and_then(|e| e.downcast::<AppSrc>().ok());
+                if let Ok(mut slot) = feed_cb.lock() {
+                    *slot = appsrc;
+                }
+                let feed_unprepared = Arc::clone(&feed_cb);
+                media.connect_unprepared(move |_| {
+                    if let Ok(mut slot) = feed_unprepared.lock() {
+                        *slot = None;
                     }
-                    let max_x = (src_w - out_w) as f64;
-                    let max_y = (src_h - out_h) as f64;
-                    let cx = (st.center_x - cfg_width as f64 / 2.0).clamp(0.0, max_x).round() as usize;
-                    let cy = (st.center_y - cfg_height as f64 / 2.0).clamp(0.0, max_y).round() as usize;
-                    (cx, cy)
-                };
+                });
+            });
+            let mounts = server
+                .mount_points()
+                .ok_or_else(|| "RTSP server has no mount points".to_string())?;
+            mounts.add_factory(RTSP_MOUNT_PATH, factory);
+            server
+                .attach(Some(&ctx))
+                .map_err(|e| format!("bind RTSP port {port}: {e}"))?;
+            Ok::<_, String>((server, gst::glib::MainLoop::new(Some(&ctx), false)))
+        });
+        match started {
+            Ok(Ok((_server, main_loop))) => {
+                let _ = ready_tx.send(Ok(()));
+                main_loop.run();
+            }
+            Ok(Err(err)) => {
+                let _ = ready_tx.send(Err(err));
+            }
+            Err(err) => {
+                let _ = ready_tx.send(Err(format!("RTSP main context: {err}")));
+            }
+        }
+    });
+    ready_rx
+        .recv()
+        .map_err(|_| "RTSP server thread exited".to_string())??;
+    Ok(feed)
+}
 
-                let buffer = sample.buffer().ok_or(gst::FlowError::Error)?;
-                let (plane0_offset, src_stride) = if let Some(meta) = buffer.meta::<gst_video::VideoMeta>() {
-                    let offset = meta.offset().first().copied().unwrap_or(0);
-                    let stride = meta
-                        .stride()
-                        .first()
-                        .copied()
-                        .filter(|v| *v > 0)
-                        .map(|v| v as usize)
-                        .unwrap_or(src_w * 4);
-                    (offset, stride)
-                } else {
-                    (0usize, src_w * 4)
-                };
-                let map = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
-                let src = map.as_slice();
-                let mut out_data = vec![0u8; out_w * out_h * 4];
-                for row in 0..out_h {
-                    let src_off = plane0_offset + (crop_y + row) * src_stride + crop_x * 4;
-                    let dst_off = row * out_w * 4;
-                    let src_end = src_off + out_w * 4;
-                    if src_end > src.len() {
-                        return Err(gst::FlowError::Error);
-                    }
-                    out_data[dst_off..dst_off + out_w * 4]
-                        .copy_from_slice(&src[src_off..src_end]);
-                }
+fn rtp_video_stage(encoder: &str) -> Result<&'static str, String> {
+    Ok(match encoder_codec(encoder)? {
+        "h264" => {
+            "h264parse config-interval=1 ! rtph264pay name=pay pt=96 config-interval=1 mtu=1200"
+        }
+        "h265" => {
+            "h265parse config-interval=1 ! rtph265pay name=pay pt=96 config-interval=1 mtu=1200"
+        }
+        "vp9" => "rtpvp9pay name=pay pt=96 mtu=1200",
+        "mjpeg" => "rtpjpegpay name=pay pt=96 mtu=1200",
+        // AV1 keyframes carry their own sequence header, so there is no config-interval.
+        _ => "av1parse ! video/x-av1,stream-format=obu-stream ! rtpav1pay name=pay pt=96 mtu=1200",
+    })
+}
 
-                let mut out_buf = gst::Buffer::from_mut_slice(out_data);
-                {
-                    let idx = {
-                        let mut c = out_idx_cb.lock().map_err(|_| gst::FlowError::Error)?;
-                        let v = *c;
-                        *c += 1;
-                        v
-                    };
-                    let dur =
-                        gst::ClockTime::from_nseconds(1_000_000_000u64 / cfg_output_fps as u64);
-                    let pts = gst::ClockTime::from_nseconds(
-                        (1_000_000_000u64 * idx) / cfg_output_fps as u64,
-                    );
-                    let b = out_buf.get_mut().ok_or(gst::FlowError::Error)?;
-                    b.set_pts(pts);
-                    b.set_duration(dur);
-                }
+/// SRT carries MPEG-TS rather than RTP, which is what SRT consumers (OBS, ffmpeg,
+/// srtsrc ! tsdemux) expect.
+fn ts_video_stage(encoder: &str) -> Result<&'static str, String> {
+    match encoder_codec(encoder)? {
+        "h264" => Ok("h264parse config-interval=-1 ! mpegtsmux alignment=7"),
+        "h265" => Ok("h265parse config-interval=-1 ! mpegtsmux alignment=7"),
+        _ => Err(format!(
+            "--transport srt only carries H.264/H.265, not {encoder}; use --transport udp"
+        )),
+    }
+}
 
-                appsrc_cb.push_buffer(out_buf).map_err(|_| gst::FlowError::Error)?;
-                Ok(gst::FlowSuccess::Ok)
-            })
-            .eos(move |_| {
-                let _ = appsrc.end_of_stream();
-            })
-            .build(),
-    );
+/// Browsers only reliably decode constrained-baseline H.264 or VP9 over WebRTC, and
+/// every viewer joins mid-stream, so SPS/PPS go with each keyframe.
+fn webrtc_video_stage(encoder: &str) -> Result<&'static str, String> {
+    match encoder_codec(encoder)? {
+        "h264" => Ok(
+            "video/x-h264,profile=constrained-baseline ! h264parse config-interval=-1 ! \
+             rtph264pay pt=96 config-interval=-1 mtu=1200 aggregate-mode=zero-latency",
+        ),
+        "vp9" => Ok("rtpvp9pay pt=96 mtu=1200 picture-id-mode=15-bit"),
+        _ => Err(format!(
+            "--transport webrtc needs an H.264 or VP9 encoder, not {encoder}"
+        )),
+    }
+}
 
-    if output_pipeline.set_state(gst::State::Playing).is_err() {
-        eprintln!("FAIL: could not set output pipeline to Playing");
-        return ExitCode::from(1);
+/// RTP over UDP goes through an rtpbin session (RTCP, FEC); everything else links
+/// straight from the payloader queue to its sink.
+fn uses_rtp_session(cfg: &SendCfg) -> bool {
+    cfg.transport == "udp" && !(cfg.rtsp_port.is_some() && cfg.receiver_ip == "0.0.0.0")
+}
+
+/// The tail of the output pipeline after the `netq` queue. Sinks linked directly
+/// start with `!`; the RTP session sinks are separate chains that
+/// `attach_rtp_session` links through rtpbin.
+fn network_sink_stage(cfg: &SendCfg) -> String {
+    if cfg.transport == "webrtc" {
+        // Each browser gets its own queue ! webrtcbin branch off this tee; the fakesink
+        // keeps data flowing while nobody is watching.
+        " ! tee name=webrtc_tee allow-not-linked=true ! queue leaky=downstream ! fakesink sync=false async=false"
+            .to_string()
+    } else if cfg.transport == "srt" {
+        let host = if cfg.srt_mode == "listener" {
+            ""
+        } else {
+            cfg.receiver_ip.as_str()
+        };
+        format!(
+            " ! srtsink name=netsink uri=srt://{}:{} mode={} latency={} wait-for-connection=false sync=false async=false",
+            host, cfg.port, cfg.srt_mode, cfg.srt_latency_ms
+        )
+    } else if !uses_rtp_session(cfg) {
+        " ! fakesink sync=false async=false".to_string()
+    } else {
+        // srtpenc parses the key property from hex, like gst-launch does.
+        let srtp_stage = cfg
+            .srtp_key
+            .as_ref()
+            .map(|key| format!("srtpenc name=srtp key={key} ! "))
+            .unwrap_or_default();
+        // RTCP: sender reports go to port + 1, receiver reports and NACKs come back
+        // on port + 2.
+        format!(
+            " {}udpsink name=netsink host={} port={} sync=false async=false \
+             udpsink name=rtcpsink host={} port={} sync=false async=false \
+             udpsrc name=rtcpsrc port={} caps=application/x-rtcp",
+            srtp_stage,
+            cfg.receiver_ip,
+            cfg.port,
+            cfg.receiver_ip,
+            cfg.port.saturating_add(1),
+            cfg.port.saturating_add(2)
+        )
     }
-    if input_pipeline.set_state(gst::State::Playing).is_err() {
-        let _ = output_pipeline.set_state(gst::State::Null);
-        eprintln!("FAIL: could not set input pipeline to Playing");
-        return ExitCode::from(1);
+}
+
+/// The fixed crop of a passthrough pipeline. `videocrop` takes margins, so they are
+/// worked out from the viewport once the source size is known, and again when it
+/// changes or the viewport is moved.
+struct StaticCrop {
+    element: gst::Element,
+    // x, y, width, height of the viewport in source pixels.
+    rect: (u32, u32, u32, u32),
+    source: Option<(u32, u32)>,
+}
+
+impl StaticCrop {
+    /// Keeps the viewport inside the source, like the CPU crop does.
+    fn apply(&self) {
+        let Some((src_w, src_h)) = self.source else {
+            return;
+        };
+        let (x, y, w, h) = self.rect;
+        let (w, h) = (w.min(src_w), h.min(src_h));
+        let (x, y) = (x.min(src_w - w), y.min(src_h - h));
+        self.element.set_property("left", x as i32);
+        self.element.set_property("top", y as i32);
+        self.element.set_property("right", (src_w - x - w) as i32);
+        self.element.set_property("bottom", (src_h - y - h) as i32);
     }
+}
 
-    let in_bus = match input_pipeline.bus() {
-        Some(v) => v,
-        None => {
-            let _ = input_pipeline.set_state(gst::State::Null);
-            let _ = output_pipeline.set_state(gst::State::Null);
-            eprintln!("FAIL: could not get input bus");
-            return ExitCode::from(1);
+/// Sets the `vcrop` margins from each caps event, before `videocrop` sees the new
+/// source size.
+fn attach_static_crop(
+    pipeline: &gst::Pipeline,
+    rect: (u32, u32, u32, u32),
+) -> Result<Arc<Mutex<StaticCrop>>, String> {
+    let element = pipeline
+        .by_name("vcrop")
+        .ok_or_else(|| "could not find videocrop in passthrough pipeline".to_string())?;
+    let pad = element
+        .static_pad("sink")
+        .ok_or_else(|| "videocrop has no sink pad".to_string())?;
+    let crop = Arc::new(Mutex::new(StaticCrop {
+        element,
+        rect,
+        source: None,
+    }));
+    let crop_cb = Arc::clone(&crop);
+    pad.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+        if let Some(gst::PadProbeData::Event(ref event)) = info.data
+            && let gst::EventView::Caps(caps) = event.view()
+            && let Ok(video) = gst_video::VideoInfo::from_caps(caps.caps())
+            && let Ok(mut crop) = crop_cb.lock()
+        {
+            crop.source = Some((video.width(), video.height()));
+            crop.apply();
         }
+        gst::PadProbeReturn::Ok
+    });
+    Ok(crop)
+}
+
+/// Counts frames for the stats where no sample handler sees them, and stamps the
+/// capture time for `vp-rcvr --measure-latency`. Source and encoder share a clock
+/// here, so a frame's age is simply the running time minus its PTS.
+fn count_passthrough_frames(
+    pipeline: &gst::Pipeline,
+    counters: Arc<FrameCounters>,
+    capture_time: bool,
+) -> Result<(), String> {
+    let valve = pipeline
+        .by_name("pausevalve")
+        .ok_or_else(|| "could not find the pause valve in passthrough pipeline".to_string())?;
+    let (Some(capture_pad), Some(valve_pad)) = (valve.static_pad("sink"), valve.static_pad("src"))
+    else {
+        return Err("the pause valve has no pads".to_string());
     };
-    let out_bus = match output_pipeline.bus() {
-        Some(v) => v,
-        None => {
-            let _ = input_pipeline.set_state(gst::State::Null);
-            let _ = output_pipeline.set_state(gst::State::Null);
-            eprintln!("FAIL: could not get output bus");
-            return ExitCode::from(1);
+    let captured = Arc::clone(&counters);
+    capture_pad.add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+        captured.captured.fetch_add(1, Ordering::Relaxed);
+        gst::PadProbeReturn::Ok
+    });
+    valve_pad.add_probe(gst::PadProbeType::BUFFER, move |pad, info| {
+        if capture_time
+            && let Some(now) = pad.parent_element().and_then(|e| e.current_running_time())
+            && let Some(gst::PadProbeData::Buffer(ref mut buffer)) = info.data
+            && let Some(pts) = buffer.pts()
+        {
+            add_capture_time_meta(buffer.make_mut(), now.saturating_sub(pts));
         }
-    };
+        counters.sent.fetch_add(1, Ordering::Relaxed);
+        let sent_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        counters.last_sent_ms.store(sent_ms, Ordering::Relaxed);
+        gst::PadProbeReturn::Ok
+    });
+    Ok(())
+}
 
-    let mut done = false;
-    let deadline = Instant::now() + Duration::from_secs(8 * 60 * 60);
-    while Instant::now() < deadline {
-        if let Some(msg) = in_bus.timed_pop(gst::ClockTime::from_mseconds(50)) {
-            match msg.view() {
-                gst::MessageView::Error(e) => {
-                    eprintln!(
-                        "FAIL: input pipeline error from {}: {}",
-                        e.src().map(|s| s.path_string()).unwrap_or_else(|| "<unknown>".into()),
-                        e.error()
-                    );
-                    done = true;
-                }
-                gst::MessageView::Eos(..) => done = true,
-                _ => {}
+/// `--crop element`: moves `vcrop` to the viewport each frame carries as a
+/// VideoCropMeta. The probe runs on videocrop's streaming thread right before the
+/// frame is cropped, so all four margins change together and match the frame.
+fn attach_element_crop(pipeline: &gst::Pipeline) -> Result<(), String> {
+    let pad = pipeline
+        .by_name("vcrop")
+        .and_then(|vcrop| vcrop.static_pad("sink"))
+        .ok_or_else(|| "could not find videocrop in output pipeline".to_string())?;
+    pad.add_probe(gst::PadProbeType::BUFFER, |pad, info| {
+        let Some(source) = pad
+            .current_caps()
+            .and_then(|caps| gst_video::VideoInfo::from_caps(&caps).ok())
+        else {
+            return gst::PadProbeReturn::Ok;
+        };
+        if let Some(gst::PadProbeData::Buffer(ref mut buffer)) = info.data
+            && let Some(rect) = buffer
+                .meta::<gst_video::VideoCropMeta>()
+                .map(|meta| meta.rect())
+            && let Some(vcrop) = pad.parent_element()
+        {
+            // videocrop would otherwise crop the crop.
+            if let Some(meta) = buffer.make_mut().meta_mut::<gst_video::VideoCropMeta>() {
+                let _ = meta.remove();
             }
+            set_element_crop(&vcrop, &source, rect);
         }
-        if let Some(msg) = out_bus.timed_pop(gst::ClockTime::from_mseconds(0)) {
-            match msg.view() {
+        gst::PadProbeReturn::Ok
+    });
+    Ok(())
+}
+
+/// `--crop gl`: points `glcrop` at the viewport each frame carries as a
+/// VideoCropMeta. glupload does not pass the meta on, so the probe sits on its sink
+/// pad; upload and transformation run on the same streaming thread, so all four
+/// properties change between two frames and the next frame drawn is this one.
+fn attach_gl_crop(pipeline: &gst::Pipeline) -> Result<(), String> {
+    let glcrop = pipeline
+        .by_name("glcrop")
+        .ok_or_else(|| "could not find gltransformation in output pipeline".to_string())?
+        .downgrade();
+    let pad = pipeline
+        .by_name("glcropup")
+        .and_then(|upload| upload.static_pad("sink"))
+        .ok_or_else(|| "could not find glupload in output pipeline".to_string())?;
+    pad.add_probe(gst::PadProbeType::BUFFER, move |pad, info| {
+        let Some(source) = pad
+            .current_caps()
+            .and_then(|caps| gst_video::VideoInfo::from_caps(&caps).ok())
+        else {
+            return gst::PadProbeReturn::Ok;
+        };
+        if let Some(gst::PadProbeData::Buffer(ref mut buffer)) = info.data
+            && let Some((x, y, w, h)) = buffer
+                .meta::<gst_video::VideoCropMeta>()
+                .map(|meta| meta.rect())
+            && let Some(glcrop) = glcrop.upgrade()
+        {
+            if let Some(meta) = buffer.make_mut().meta_mut::<gst_video::VideoCropMeta>() {
+                let _ = meta.remove();
+            }
+            set_gl_crop(
+                &glcrop,
+                (source.width() as usize, source.height() as usize),
+                (w as usize, h as usize),
+                (x as usize, y as usize),
+            );
+        }
+        gst::PadProbeReturn::Ok
+    });
+    Ok(())
+}
+
+/// Sets the `videocrop` margins that cut `(x, y, w, h)` out of `source`. Only
+/// changed margins are set, since each change makes videocrop renegotiate.
+fn set_element_crop(
+    vcrop: &gst::Element,
+    source: &gst_video::VideoInfo,
+    (x, y, w, h): (u32, u32, u32, u32),
+) {
+    let left = align_to_chroma(x as usize, source.format()) as u32;
+    let top = align_to_chroma(y as usize, source.format()) as u32;
+    let margins = [
+        ("left", left),
+        ("top", top),
+        ("right", source.width().saturating_sub(left + w)),
+        ("bottom", source.height().saturating_sub(top + h)),
+    ];
+    for (name, value) in margins {
+        let value = value as i32;
+        if vcrop.property::<i32>(name) != value {
+            vcrop.set_property(name, value);
+        }
+    }
+}
+
+/// Has the payloader write each frame's capture time (its `timestamp/x-ntp`
+/// reference timestamp meta) into an RFC 6051 header extension on the first packet
+/// of the frame.
+fn add_capture_time_extension(pipeline: &gst::Pipeline) -> Result<(), String> {
+    let pay = pipeline
+        .by_name("pay")
+        .ok_or_else(|| "could not find the RTP payloader in output pipeline".to_string())?;
+    let ext = gst::ElementFactory::make("rtphdrextntp64")
+        .build()
+        .map_err(|e| format!("create rtphdrextntp64 (needs GStreamer 1.22): {e}"))?
+        .downcast::<gst_rtp::RTPHeaderExtension>()
+        .map_err(|_| "rtphdrextntp64 is not an RTP header extension".to_string())?;
+    ext.set_id(CAPTURE_TIME_EXT_ID);
+    pay.emit_by_name::<()>("add-extension", &[&ext]);
+    Ok(())
+}
+
+/// Tags a frame with its capture time as NTP wall-clock time, `age` ago; encoders
+/// and payloaders carry the meta through to the header extension.
+fn add_capture_time_meta(buffer: &mut gst::BufferRef, age: gst::ClockTime) {
+    let Some(since_unix) = SystemTime::now()
+        .checked_sub(Duration::from_nanos(age.nseconds()))
+        .and_then(|at| at.duration_since(UNIX_EPOCH).ok())
+    else {
+        return;
+    };
+    let ntp = gst::ClockTime::from_seconds(NTP_UNIX_OFFSET_SECS)
+        + gst::ClockTime::from_nseconds(since_unix.as_nanos() as u64);
+    let caps = gst::Caps::new_empty_simple("timestamp/x-ntp");
+    gst::ReferenceTimestampMeta::add(buffer, &caps, ntp, gst::ClockTime::NONE);
+}
+
+/// Routes the RTP stream through an rtpbin session, which adds RTCP sender reports
+/// on port + 1 and, with --fec-percent, ULPFEC packets (RFC 5109) on the same SSRC.
+/// With --rtx, packets NACKed by the receiver are resent on a separate RTX stream.
+fn attach_rtp_session(pipeline: &gst::Pipeline, fec_percent: u32, rtx: bool) -> Result<(), String> {
+    let rtpbin = gst::ElementFactory::make("rtpbin")
+        .name("rtpbin")
+        .build()
+        .map_err(|e| format!("create rtpbin: {e}"))?;
+    // AVPF: receiver PLI/FIR feedback is acted on as soon as it arrives; the session
+    // turns it into a force-keyunit event that travels upstream to the encoder.
+    rtpbin.set_property_from_str("rtp-profile", "avpf");
+    if rtx {
+        rtpbin.connect("request-aux-sender", false, |values| {
+            let session = values[1].get::<u32>().ok()?;
+            let rtx_send = gst::ElementFactory::make("rtprtxsend")
+                .property("payload-type-map", rtx_payload_type_map())
+                .property("max-size-time", RTX_HISTORY_MS)
+                .property("max-size-packets", 0u32)
+                .build()
+                .ok()?;
+            wrap_aux_element(rtx_send, session).map(|bin| bin.to_value())
+        });
+    }
+    if fec_percent > 0 {
+        rtpbin.connect("request-fec-encoder", false, move |_| {
+            let encoder = gst::ElementFactory::make("rtpulpfecenc")
+                .property("pt", FEC_PAYLOAD_TYPE)
+                .property("percentage", fec_percent)
+                .build()
+                .ok()?;
+            Some(encoder.to_value())
+        });
+    }
+    pipeline
+        .add(&rtpbin)
+        .map_err(|e| format!("add rtpbin: {e}"))?;
+
+    let netq = pipeline
+        .by_name("netq")
+        .ok_or_else(|| "could not find netq in output pipeline".to_string())?;
+    netq.link_pads(Some("src"), &rtpbin, Some("send_rtp_sink_0"))
+        .map_err(|e| format!("link rtpbin input: {e}"))?;
+    let rtp_head = pipeline
+        .by_name("srtp")
+        .or_else(|| pipeline.by_name("netsink"))
+        .ok_or_else(|| "could not find udpsink in output pipeline".to_string())?;
+    rtpbin
+        .link_pads(Some("send_rtp_src_0"), &rtp_head, None)
+        .map_err(|e| format!("link rtpbin output: {e}"))?;
+    let rtcp_sink = pipeline
+        .by_name("rtcpsink")
+        .ok_or_else(|| "could not find RTCP udpsink in output pipeline".to_string())?;
+    rtpbin
+        .link_pads(Some("send_rtcp_src_0"), &rtcp_sink, None)
+        .map_err(|e| format!("link rtpbin RTCP output: {e}"))?;
+    let rtcp_src = pipeline
+        .by_name("rtcpsrc")
+        .ok_or_else(|| "could not find RTCP udpsrc in output pipeline".to_string())?;
+    rtcp_src
+        .link_pads(Some("src"), &rtpbin, Some("recv_rtcp_sink_0"))
+        .map_err(|e| format!("link rtpbin RTCP input: {e}"))?;
+    Ok(())
+}
+
+fn rtx_payload_type_map() -> gst::Structure {
+    gst::Structure::builder("application/x-rtp-pt-map")
+        .field(MEDIA_PAYLOAD_TYPE.to_string(), RTX_PAYLOAD_TYPE)
+        .build()
+}
+
+/// rtpbin wants aux elements as a bin with `sink_N` / `src_N` pads for session N.
+fn wrap_aux_element(element: gst::Element, session: u32) -> Option<gst::Element> {
+    let bin = gst::Bin::new();
+    bin.add(&element).ok()?;
+    for (pad_name, ghost_name) in [("sink", format!("sink_{session}")), ("src", format!("src_{session}"))] {
+        let target = element.static_pad(pad_name)?;
+        let ghost = gst::GhostPad::builder_with_target(&target)
+            .ok()?
+            .name(ghost_name)
+            .build();
+        bin.add_pad(&ghost).ok()?;
+    }
+    Some(bin.upcast())
+}
+
+/// `--dry-run`: prints every pipeline `send` would launch, with the same encoder,
+/// caps and queue settings, without opening the portal. GStreamer is only started
+/// to probe for `--encoder auto`.
+fn print_pipelines(cfg: &SendCfg) -> ExitCode {
+    let cfg = &match resolve_encoder(cfg) {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            eprintln!("FAIL: {err}");
+            return ExitCode::from(2);
+        }
+    };
+    let output_fps = cfg.fps.max(1);
+    let node_id = (cfg.backend != "screencopy").then_some("<portal-node>");
+    let mut pipelines = Vec::new();
+    match node_id {
+        Some(node_id) if static_passthrough(cfg) => pipelines.push((
+            "Passthrough".to_string(),
+            passthrough_pipeline_desc(node_id, cfg, output_fps),
+        )),
+        _ => {
+            pipelines.push(("Input".to_string(), Ok(input_pipeline_desc(node_id, cfg))));
+            pipelines.push(("Output".to_string(), output_pipeline_desc(cfg, output_fps)));
+        }
+    }
+    for spec in &cfg.viewports {
+        pipelines.push((
+            format!("Viewport {}", spec.name),
+            output_pipeline_desc(&viewport_cfg(cfg, spec), output_fps),
+        ));
+    }
+    if cfg.rtsp_port.is_some() {
+        pipelines.push(("RTSP media".to_string(), rtsp_media_desc(&cfg.encoder)));
+    }
+    if cfg.with_audio {
+        pipelines.push(("Audio".to_string(), Ok(audio_pipeline_desc(cfg))));
+    }
+    for (name, desc) in pipelines {
+        match desc {
+            Ok(desc) => println!("{name} pipeline:\n  {desc}\n"),
+            Err(err) => {
+                eprintln!("FAIL: {err}");
+                return ExitCode::from(2);
+            }
+        }
+    }
+    if uses_rtp_session(cfg) {
+        println!(
+            "The RTP chains are linked through rtpbin (RTCP on port + 1{}{}).",
+            if cfg.fec_percent > 0 { ", ULPFEC" } else { "" },
+            if cfg.rtx { ", RTX" } else { "" }
+        );
+    }
+    if cfg.transport == "webrtc" {
+        println!("Each browser gets a queue ! webrtcbin branch off webrtc_tee.");
+    }
+    ExitCode::SUCCESS
+}
+
+/// Builds the appsrc-to-network half of the sender for one viewport of `cfg`.
+fn output_pipeline_desc(cfg: &SendCfg, output_fps: u32) -> Result<String, String> {
+    Ok(format!(
+        "appsrc name=src is-live=true format=time do-timestamp=true block=true{}",
+        encode_chain_desc(cfg, output_fps)?
+    ))
+}
+
+/// With a fixed viewport nothing needs to look at the frames on the way to the
+/// encoder: no follow, no overlays, one viewport, a monitor that does not resize.
+/// Such a viewport is cut with `videocrop` in a single capture-to-network pipeline.
+fn static_passthrough(cfg: &SendCfg) -> bool {
+    cfg.backend == "portal"
+        && cfg.source == "monitor"
+        && !cfg.follow_mouse
+        && !cfg.follow_window
+        && !cfg.dmabuf
+        && cfg.crop != "gl"
+        && !cfg.draw_cursor
+        && !cfg.click_highlight
+        && cfg.roi_radius == 0
+        && cfg.viewports.is_empty()
+        && cfg.masks.is_empty()
+        && cfg.idle_fps.is_none()
+}
+
+/// The single pipeline for `static_passthrough`: the capture links straight into the
+/// encode chain, so frames skip the appsink/appsrc copy and share one clock. The
+/// chain crops with `--crop element`'s `videocrop`, which gets its margins once the
+/// source size is known (`attach_static_crop`); `pausevalve` drops frames while the
+/// stream is paused.
+fn passthrough_pipeline_desc(
+    node_id: &str,
+    cfg: &SendCfg,
+    output_fps: u32,
+) -> Result<String, String> {
+    let chain_cfg = SendCfg {
+        crop: "element".to_string(),
+        ..cfg.clone()
+    };
+    Ok(format!(
+        "pipewiresrc path={} do-timestamp=true ! videoconvert ! \
+         video/x-raw,format={},framerate={}/1 ! valve name=pausevalve drop=false{}",
+        node_id,
+        crop_formats(cfg),
+        cfg.fps,
+        encode_chain_desc(&chain_cfg, output_fps)?
+    ))
+}
+
+/// The encode-to-network chain behind the frame source, starting with its ` ! `.
+fn encode_chain_desc(cfg: &SendCfg, output_fps: u32) -> Result<String, String> {
+    let va_device = cfg.va_device.as_deref();
+    let enc = encoder_stage(&cfg.encoder, output_fps, cfg.bitrate_kbps, cfg.temporal_layers)?;
+    // On a second GPU the va plugin's element carries the render node in its name.
+    let element = va_element_name(&cfg.encoder, va_device)?;
+    let mut enc = enc.replacen(&cfg.encoder, &element, 1);
+    // --enc-opt goes last, so it overrides the built-in settings above.
+    check_enc_opts(&element, &cfg.enc_opts)?;
+    for (key, value) in &cfg.enc_opts {
+        enc.push_str(&format!(" {key}=\"{}\"", value.replace('"', "\\\"")));
+    }
+    let rtp_stage = match cfg.transport.as_str() {
+        "srt" => ts_video_stage(&cfg.encoder)?,
+        "webrtc" => webrtc_video_stage(&cfg.encoder)?,
+        _ => rtp_video_stage(&cfg.encoder)?,
+    };
+
+    let is_nvenc = matches!(cfg.encoder.as_str(), "nvh264enc" | "nvh265enc");
+
+    let (encode_w, encode_h) = encode_size(cfg);
+    let dmabuf_stage = if cfg.dmabuf {
+        dmabuf_postproc(&cfg.encoder)
+    } else {
+        None
+    };
+    // User elements get system-memory frames in whatever raw format they negotiate;
+    // pre_encode converts back to what the encoder takes.
+    let user_stage = match &cfg.insert_before_encoder {
+        Some(_) if dmabuf_stage.is_some() => {
+            return Err(
+                "--insert-before-encoder needs system-memory frames; drop --dmabuf".to_string(),
+            );
+        }
+        Some(fragment) => format!("videoconvert ! {fragment} ! "),
+        None => String::new(),
+    };
+    // The masks are drawn by the CPU crop; any other crop would stream them unmasked.
+    if !cfg.masks.is_empty() && (cfg.dmabuf || cfg.crop != "cpu") {
+        return Err("--mask needs --crop cpu without --dmabuf".to_string());
+    }
+    let mut overlay_stage = String::new();
+    if let Some(path) = &cfg.watermark {
+        if dmabuf_stage.is_some() {
+            return Err("--watermark needs system-memory frames; drop --dmabuf".to_string());
+        }
+        overlay_stage.push_str(&watermark_stage(
+            path,
+            &cfg.watermark_pos,
+            cfg.watermark_opacity,
+        ));
+    }
+    if cfg.overlay_clock {
+        if dmabuf_stage.is_some() {
+            return Err("--overlay-clock needs system-memory frames; drop --dmabuf".to_string());
+        }
+        overlay_stage.push_str(&clock_overlay_stage(
+            &cfg.overlay_clock_position,
+            &cfg.overlay_clock_format,
+        ));
+    }
+    let queue = queue_props(cfg);
+    let raw_format = encoder_raw_format(&cfg.encoder, cfg.bit_depth);
+    let colorimetry = colorimetry_field(cfg.colorimetry.as_deref(), cfg.color_range.as_deref());
+    let pre_encode = if let Some((postproc, feature)) = dmabuf_stage {
+        let postproc = va_element_name(postproc, va_device)?;
+        // The post-processor converts to P010 on the GPU for 10-bit encoding.
+        let format = if cfg.bit_depth == 10 {
+            format!(",format={raw_format}")
+        } else {
+            String::new()
+        };
+        format!(
+            "{} ! video/x-raw({}),width={},height={}{}{} ! queue {}",
+            postproc, feature, encode_w, encode_h, format, colorimetry, queue
+        )
+    } else if is_nvenc && (cfg.bit_depth == 10 || !colorimetry.is_empty()) {
+        format!("videoconvert ! video/x-raw,format={raw_format}{colorimetry} ! cudaupload")
+    } else if is_nvenc {
+        "cudaupload".to_string()
+    } else {
+        // The encoders write the caps colorimetry into the stream's VUI, so the
+        // receiver's decoder reports the same matrix and range.
+        format!(
+            "videoconvert ! video/x-raw,format={}{} ! queue {}",
+            raw_format, colorimetry, queue
+        )
+    };
+    let (encoded_tee, rtsp_branch) = if cfg.rtsp_port.is_some() {
+        let feed_stage = rtsp_feed_stage(&cfg.encoder)?;
+        (
+            " ! tee name=encoded".to_string(),
+            format!(
+                " encoded. ! queue leaky=downstream max-size-buffers={} max-size-bytes=0 max-size-time=0 ! \
+                 {} ! appsink name=rtspsink sync=false drop=true max-buffers={}",
+                cfg.queue_buffers, feed_stage, cfg.queue_buffers
+            ),
+        )
+    } else {
+        (String::new(), String::new())
+    };
+    // The appsrc caps come from the frames: DMA-buf, GL- and element-cropped frames
+    // arrive at source size with their viewport as a VideoCropMeta, and CPU crops keep
+    // the source's raw format.
+    let (region_w, region_h) = zoomed_region(cfg.width, cfg.height, cfg.zoom);
+    // A zoomed viewport is cropped at region size and scaled to the output size.
+    // With --fit letterbox a monitor smaller than the viewport arrives at its own size,
+    // and videoscale fits it in with black bars instead of the encoder restarting;
+    // --lock-aspect does the same for live resizes.
+    let method = if cfg.scale_width.is_some() {
+        " method=lanczos"
+    } else {
+        ""
+    };
+    let scale_stage = if cfg.fit == "letterbox" || cfg.lock_aspect {
+        format!(
+            " ! videoscale add-borders=true{} ! \
+             video/x-raw,width={},height={},pixel-aspect-ratio=1/1",
+            method, encode_w, encode_h
+        )
+    } else if (region_w, region_h) != (encode_w, encode_h) {
+        format!(
+            " ! videoscale{} ! video/x-raw,width={},height={}",
+            method, encode_w, encode_h
+        )
+    } else {
+        String::new()
+    };
+    let rate_stage = fps_mode_stage(&cfg.fps_mode, output_fps);
+    let crop_stage = if dmabuf_stage.is_some() {
+        String::new()
+    } else if cfg.crop == "gl" {
+        format!(
+            " ! glupload name=glcropup ! gltransformation name=glcrop ortho=true ! \
+             video/x-raw(memory:GLMemory),width={},height={} ! gldownload ! \
+             video/x-raw,format=RGBA,width={},height={}",
+            encode_w, encode_h, encode_w, encode_h
+        )
+    } else if cfg.crop == "element" {
+        format!(" ! videocrop name=vcrop{scale_stage}")
+    } else {
+        scale_stage
+    };
+    Ok(format!(
+        "{}{} ! \
+         queue name=encq {} ! \
+         {}{}{} ! {} name=venc{}{} ! \
+         queue {} ! {} ! \
+         queue name=netq {}{}{}",
+        crop_stage,
+        rate_stage,
+        queue,
+        user_stage, overlay_stage, pre_encode, enc, profile_caps(cfg), encoded_tee,
+        queue, rtp_stage,
+        queue,
+        network_sink_stage(cfg), rtsp_branch
+    ))
+}
+
+/// `videorate` for --fps-mode, starting with its ` ! `. Frames arrive stamped with
+/// their capture time, at whatever rate the compositor sends them: passthrough
+/// keeps that, drop caps it at the output rate, and duplicate fills the gaps with
+/// copies of the previous frame for a constant rate. videorate fills a gap when the
+/// frame after it arrives, so a screen that stops changing altogether still pauses
+/// the stream until it changes again. The caps keep any memory feature, so the
+/// stage also works on DMA-bufs.
+fn fps_mode_stage(mode: &str, output_fps: u32) -> String {
+    match mode {
+        "drop" => format!(" ! videorate drop-only=true max-rate={output_fps}"),
+        "duplicate" => format!(" ! videorate ! video/x-raw(ANY),framerate={output_fps}/1"),
+        _ => String::new(),
+    }
+}
+
+/// `clockoverlay` in one corner. It comes after --insert-before-encoder and the
+/// watermark, so user filters do not touch the time.
+fn clock_overlay_stage(position: &str, format: &str) -> String {
+    let (valign, halign) = position.split_once('-').unwrap_or(("top", "left"));
+    format!(
+        "clockoverlay name=clock valignment={} halignment={} shaded-background=true \
+         time-format=\"{}\" ! ",
+        valign,
+        halign,
+        quote_property(format)
+    )
+}
+
+/// `gdkpixbufoverlay` with the image `WATERMARK_MARGIN_PX` inside one corner;
+/// negative offsets count from the right and bottom edges.
+fn watermark_stage(path: &str, position: &str, opacity: f64) -> String {
+    let (valign, halign) = position.split_once('-').unwrap_or(("top", "right"));
+    let offset_x = if halign == "right" {
+        -WATERMARK_MARGIN_PX
+    } else {
+        WATERMARK_MARGIN_PX
+    };
+    let offset_y = if valign == "bottom" {
+        -WATERMARK_MARGIN_PX
+    } else {
+        WATERMARK_MARGIN_PX
+    };
+    format!(
+        "gdkpixbufoverlay name=watermark location=\"{}\" alpha={} offset-x={} offset-y={} ! ",
+        quote_property(path),
+        opacity,
+        offset_x,
+        offset_y
+    )
+}
+
+/// Escapes a string for a double-quoted property in a pipeline description.
+fn quote_property(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Size and leak policy shared by the queues between capture and network, as
+/// `queue` properties. `--leaky none` is the enum's `no`.
+fn queue_props(cfg: &SendCfg) -> String {
+    let leaky = if cfg.leaky == "none" {
+        "no"
+    } else {
+        cfg.leaky.as_str()
+    };
+    format!(
+        "leaky={} max-size-buffers={} max-size-bytes=0 max-size-time=0",
+        leaky, cfg.queue_buffers
+    )
+}
+
+/// A `--viewport` stream beyond the first: its own encoder and RTP session, fed
+/// with crops of the frames the main viewport is cut from.
+struct ExtraViewport {
+    name: String,
+    pipeline: gst::Pipeline,
+    feed: ViewportFeed,
+}
+
+#[derive(Clone)]
+struct ViewportFeed {
+    appsrc: AppSrc,
+    rect: (usize, usize, usize, usize),
+    pool: Arc<Mutex<FramePool>>,
+}
+
+/// Recycles the CPU-cropped output frames: a buffer goes back to the pool once the
+/// encoder is done with it, so steady streaming does not allocate (and page-fault
+/// in) a whole frame per sample. The pool is rebuilt when the frame size changes.
+#[derive(Default)]
+struct FramePool {
+    pool: Option<(gst::BufferPool, usize)>,
+}
+
+impl FramePool {
+    fn acquire(&mut self, size: usize) -> Result<gst::Buffer, gst::FlowError> {
+        if self.pool.as_ref().map(|(_, current)| *current) != Some(size) {
+            if let Some((old, _)) = self.pool.take() {
+                // Buffers still in flight are freed instead of returned.
+                let _ = old.set_active(false);
+            }
+            let pool = gst::BufferPool::new();
+            let mut config = pool.config();
+            config.set_params(None, size as u32, 0, 0);
+            pool.set_config(config).map_err(|_| gst::FlowError::Error)?;
+            pool.set_active(true).map_err(|_| gst::FlowError::Error)?;
+            self.pool = Some((pool, size));
+        }
+        let (pool, _) = self.pool.as_ref().ok_or(gst::FlowError::Error)?;
+        pool.acquire_buffer(None)
+    }
+}
+
+/// --idle-fps: compares each CPU-cropped frame with the one sent before it. After
+/// `IDLE_AFTER_MS` without a change only `idle_fps` frames a second go out; the
+/// first changed frame goes out at once and ends the idle state.
+struct IdleDetector {
+    idle_fps: u32,
+    changed_at: Instant,
+    idle: Arc<AtomicBool>,
+}
+
+impl IdleDetector {
+    /// Whether to drop `frame`, given the last frame pushed and when.
+    fn skip(&mut self, frame: &gst::Buffer, last: Option<&(gst::Buffer, Instant)>) -> bool {
+        let unchanged = last.is_some_and(|(prev, _)| same_pixels(prev, frame));
+        if !unchanged {
+            self.changed_at = Instant::now();
+            if self.idle.swap(false, Ordering::Relaxed) {
+                println!("Viewport changed; back to full rate.");
+            }
+            return false;
+        }
+        if self.changed_at.elapsed() < Duration::from_millis(IDLE_AFTER_MS) {
+            return false;
+        }
+        if !self.idle.swap(true, Ordering::Relaxed) {
+            println!("Viewport idle; sending {} fps.", self.idle_fps);
+        }
+        let interval = Duration::from_secs_f64(1.0 / self.idle_fps as f64);
+        last.is_some_and(|(_, at)| at.elapsed() < interval)
+    }
+}
+
+fn same_pixels(a: &gst::Buffer, b: &gst::Buffer) -> bool {
+    match (a.map_readable(), b.map_readable()) {
+        (Ok(a), Ok(b)) => a.as_slice() == b.as_slice(),
+        _ => false,
+    }
+}
+
+/// The settings of one extra viewport: the main stream's, with its own rectangle,
+/// port and receiver.
+fn viewport_cfg(cfg: &SendCfg, spec: &ViewportSpec) -> SendCfg {
+    SendCfg {
+        receiver_ip: spec
+            .receiver_ip
+            .clone()
+            .unwrap_or_else(|| cfg.receiver_ip.clone()),
+        port: spec.port,
+        x: spec.x,
+        y: spec.y,
+        width: spec.width,
+        height: spec.height,
+        crop: "cpu".to_string(),
+        viewports: Vec::new(),
+        zoom: 1.0,
+        scale_width: None,
+        scale_height: None,
+        ..cfg.clone()
+    }
+}
+
+fn start_extra_viewport(
+    cfg: &SendCfg,
+    spec: &ViewportSpec,
+    output_fps: u32,
+) -> Result<ExtraViewport, String> {
+    let vp_cfg = viewport_cfg(cfg, spec);
+    let desc = output_pipeline_desc(&vp_cfg, output_fps)?;
+    let pipeline = gst::parse::launch(&desc)
+        .map_err(|e| format!("could not build viewport {} pipeline: {e}", spec.name))?
+        .downcast::<gst::Pipeline>()
+        .map_err(|_| format!("viewport {} pipeline is not a gst::Pipeline", spec.name))?;
+    if uses_rtp_session(&vp_cfg) {
+        attach_rtp_session(&pipeline, vp_cfg.fec_percent, vp_cfg.rtx)?;
+    }
+    tune_streaming_threads(&pipeline, &vp_cfg)?;
+    let appsrc = pipeline
+        .by_name("src")
+        .and_then(|e| e.downcast::<AppSrc>().ok())
+        .ok_or_else(|| format!("could not find appsrc in viewport {} pipeline", spec.name))?;
+    pipeline
+        .set_state(gst::State::Playing)
+        .map_err(|e| format!("could not start viewport {} pipeline: {e:?}", spec.name))?;
+    println!(
+        "Viewport {}: {}x{} at x={}, y={} -> {}:{}",
+        spec.name, spec.width, spec.height, spec.x, spec.y, vp_cfg.receiver_ip, spec.port
+    );
+    Ok(ExtraViewport {
+        name: spec.name.clone(),
+        pipeline,
+        feed: ViewportFeed {
+            appsrc,
+            rect: (
+                spec.x as usize,
+                spec.y as usize,
+                spec.width as usize,
+                spec.height as usize,
+            ),
+            pool: Arc::new(Mutex::new(FramePool::default())),
+        },
+    })
+}
+
+/// Copies the `(x, y, w, h)` rectangle of `src` to `(pad_x, pad_y)` in `dst`, plane
+/// by plane in the frames' shared raw format. Chroma planes are addressed at their
+/// subsampled size, so NV12 and I420 need even `x`, `y`, `pad_x` and `pad_y`.
+fn crop_frame(
+    src: &gst_video::VideoFrameRef<&gst::BufferRef>,
+    (x, y, w, h): (usize, usize, usize, usize),
+    dst: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>,
+    (pad_x, pad_y): (usize, usize),
+) -> bool {
+    let finfo = src.format_info();
+    for plane in 0..src.n_planes() {
+        let Some(component) =
+            (0..finfo.n_components() as usize).find(|&c| finfo.plane()[c] == plane)
+        else {
+            continue;
+        };
+        let (w_sub, h_sub) = (finfo.w_sub()[component], finfo.h_sub()[component]);
+        let pixel_bytes = finfo.pixel_stride()[component] as usize;
+        let src_stride = src.plane_stride()[plane as usize] as usize;
+        let dst_stride = dst.plane_stride()[plane as usize] as usize;
+        let (Ok(src_data), Ok(dst_data)) = (src.plane_data(plane), dst.plane_data_mut(plane))
+        else {
+            return false;
+        };
+        // Offsets round down and sizes up, so an odd edge keeps its chroma.
+        let row_bytes = w.div_ceil(1 << w_sub) * pixel_bytes;
+        let rows = h.div_ceil(1 << h_sub);
+        let dst_off = (pad_y >> h_sub) * dst_stride + (pad_x >> w_sub) * pixel_bytes;
+        let Some(copied) = copy_rows(
+            src_data,
+            (
+                (y >> h_sub) * src_stride + (x >> w_sub) * pixel_bytes,
+                src_stride,
+            ),
+            dst_data,
+            (dst_off, dst_stride),
+            (row_bytes, rows),
+        ) else {
+            return false;
+        };
+        // A source frame shorter than the crop leaves the missing rows black instead
+        // of the recycled buffer's old picture.
+        for row in copied..rows {
+            let start = dst_off + row * dst_stride;
+            paint_black(finfo, plane, &mut dst_data[start..start + row_bytes]);
+        }
+    }
+    true
+}
+
+/// Paints a whole frame black.
+fn fill_black(frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>) {
+    let finfo = frame.format_info();
+    for plane in 0..frame.n_planes() {
+        if let Ok(data) = frame.plane_data_mut(plane) {
+            paint_black(finfo, plane, data);
+        }
+    }
+}
+
+/// Fills `data`, part of `plane` in `finfo`'s format, with black: zero for RGB, and
+/// limited-range black (Y 16, chroma 128, scaled up at 10 bits) for YUV.
+fn paint_black(finfo: gst_video::VideoFormatInfo, plane: u32, data: &mut [u8]) {
+    let value: u16 = if finfo.is_yuv() && finfo.plane()[0] != plane {
+        128
+    } else if finfo.is_yuv() {
+        16
+    } else {
+        0
+    };
+    let component = finfo.plane()[..finfo.n_components() as usize]
+        .iter()
+        .position(|&p| p == plane)
+        .unwrap_or(0);
+    let depth = finfo.depth()[component];
+    if depth <= 8 {
+        data.fill(value as u8);
+    } else {
+        // 16-bit little-endian samples, e.g. P010 keeps its 10 bits at the top.
+        let sample = (value << (depth - 8)) << finfo.shift()[component];
+        for pair in data.chunks_exact_mut(2) {
+            pair.copy_from_slice(&sample.to_le_bytes());
+        }
+    }
+}
+
+/// Carries the source's colorimetry and HDR mastering metadata over to the cropped
+/// frames' caps, so the encoder can signal them (e.g. PQ with SMPTE ST 2086 SEI).
+/// Sources that do not set them leave the defaults.
+fn copy_hdr_fields(src: &gst::StructureRef, caps: &mut gst::CapsRef) {
+    let Some(dst) = caps.structure_mut(0) else {
+        return;
+    };
+    for field in [
+        "colorimetry",
+        "mastering-display-info",
+        "content-light-level",
+    ] {
+        if let Ok(value) = src.value(field) {
+            dst.set_value(field, value.clone());
+        }
+    }
+}
+
+/// The layout of a `width`x`height` frame in `format` as this sender pushes it.
+fn crop_info(
+    format: gst_video::VideoFormat,
+    (width, height): (usize, usize),
+    fps: u32,
+) -> Result<gst_video::VideoInfo, gst::FlowError> {
+    gst_video::VideoInfo::builder(format, width as u32, height as u32)
+        .fps(gst::Fraction::new(fps as i32, 1))
+        .build()
+        .map_err(|_| gst::FlowError::NotNegotiated)
+}
+
+/// Even-aligns a crop coordinate for formats with subsampled chroma.
+fn align_to_chroma(value: usize, format: gst_video::VideoFormat) -> usize {
+    let finfo = gst_video::VideoFormatInfo::from_format(format);
+    let sub = finfo
+        .w_sub()
+        .iter()
+        .chain(finfo.h_sub())
+        .copied()
+        .max()
+        .unwrap_or(0);
+    value & !((1usize << sub) - 1)
+}
+
+/// Copies `rows` rows of `row_bytes` each between two strided images, given as
+/// (offset of the first row, stride). Large crops are split into bands copied on the
+/// crop copy pool, since a single core cannot keep up with memory bandwidth at 1440p
+/// and above. Returns the number of rows copied, fewer than `rows` when `src` ends
+/// early, or None with nothing copied when `dst` is too short.
+fn copy_rows(
+    src: &[u8],
+    (src_off, src_stride): (usize, usize),
+    dst: &mut [u8],
+    (dst_off, dst_stride): (usize, usize),
+    (row_bytes, rows): (usize, usize),
+) -> Option<usize> {
+    if rows == 0 {
+        return Some(0);
+    }
+    if dst_off + (rows - 1) * dst_stride + row_bytes > dst.len() {
+        return None;
+    }
+    // The rows the source holds in full.
+    let rows = match src.len().checked_sub(src_off + row_bytes) {
+        Some(rest) => rows.min(rest / src_stride.max(1) + 1),
+        None => 0,
+    };
+    if rows == 0 {
+        return Some(0);
+    }
+    let (src, dst) = (&src[src_off..], &mut dst[dst_off..]);
+    let copy_band = |first_row: usize, band: &mut [u8], band_rows: usize| {
+        for row in 0..band_rows {
+            let from = (first_row + row) * src_stride;
+            let to = row * dst_stride;
+            band[to..to + row_bytes].copy_from_slice(&src[from..from + row_bytes]);
+        }
+    };
+    match crop_copy_pool() {
+        Some(pool) if row_bytes * rows >= CROP_PARALLEL_MIN_BYTES => {
+            let band_rows = rows.div_ceil(pool.current_num_threads());
+            pool.install(|| {
+                dst.par_chunks_mut(band_rows * dst_stride)
+                    .enumerate()
+                    .for_each(|(index, band)| {
+                        let first_row = index * band_rows;
+                        if first_row < rows {
+                            copy_band(first_row, band, band_rows.min(rows - first_row));
+                        }
+                    });
+            });
+        }
+        _ => copy_band(0, dst, rows),
+    }
+    Some(rows)
+}
+
+/// The threads that copy the bands of large crops. They are started with the first
+/// large crop and kept for the rest of the run; None on a single core.
+fn crop_copy_pool() -> Option<&'static rayon::ThreadPool> {
+    static POOL: OnceLock<Option<rayon::ThreadPool>> = OnceLock::new();
+    POOL.get_or_init(|| {
+        let threads = thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(CROP_COPY_THREADS);
+        if threads < 2 {
+            return None;
+        }
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|index| format!("vp-crop-{index}"))
+            .build()
+            .ok()
+    })
+    .as_ref()
+}
+
+/// How long ago a sample was captured, from its PTS on the input pipeline's clock.
+/// The input and output pipelines run on separate clocks, so frames are carried
+/// across as an age rather than as a timestamp.
+fn capture_age(sink: &AppSink, sample: &gst::Sample) -> gst::ClockTime {
+    let captured = sample
+        .buffer()
+        .and_then(|buffer| buffer.pts())
+        .and_then(|pts| {
+            sample
+                .segment()?
+                .downcast_ref::<gst::ClockTime>()?
+                .to_running_time(pts)
+        });
+    match (captured, sink.current_running_time()) {
+        (Some(captured), Some(now)) => now.saturating_sub(captured),
+        _ => gst::ClockTime::ZERO,
+    }
+}
+
+/// Keeps output timestamps strictly increasing: a frame stamped at or before the
+/// previous one (capture jitter, a pause repeat) is moved just after it.
+fn monotonic_pts(
+    last: &Mutex<Option<gst::ClockTime>>,
+    pts: Option<gst::ClockTime>,
+) -> Result<Option<gst::ClockTime>, gst::FlowError> {
+    let mut last = last.lock().map_err(|_| gst::FlowError::Error)?;
+    let Some(mut pts) = pts else {
+        return Ok(None);
+    };
+    if let Some(prev) = *last
+        && pts <= prev
+    {
+        pts = prev + gst::ClockTime::from_useconds(1);
+    }
+    *last = Some(pts);
+    Ok(Some(pts))
+}
+
+/// Cuts each fixed viewport out of a source frame, in its own raw format, and pushes
+/// it with the main frame's timestamps. Viewports that do not fit the current source
+/// size are skipped until it grows back. The masks are drawn into each crop.
+fn push_viewport_crops(
+    feeds: &[ViewportFeed],
+    src: &gst_video::VideoFrameRef<&gst::BufferRef>,
+    fps: u32,
+    (pts, duration): (Option<gst::ClockTime>, Option<gst::ClockTime>),
+    masks: &[MaskSpec],
+) {
+    let (src_w, src_h) = (src.width() as usize, src.height() as usize);
+    let format = src.format();
+    for feed in feeds {
+        let (x, y, w, h) = feed.rect;
+        if x + w > src_w || y + h > src_h {
+            continue;
+        }
+        let Ok(info) = crop_info(format, (w, h), fps) else {
+            continue;
+        };
+        if let Ok(caps) = info.to_caps()
+            && feed.appsrc.caps().as_ref() != Some(&caps)
+        {
+            feed.appsrc.set_caps(Some(&caps));
+        }
+        let Ok(mut buffer) = feed
+            .pool
+            .lock()
+            .map_err(|_| gst::FlowError::Error)
+            .and_then(|mut pool| pool.acquire(info.size()))
+        else {
+            continue;
+        };
+        let Some(out) = buffer.get_mut() else {
+            continue;
+        };
+        out.set_pts(pts);
+        out.set_duration(duration);
+        let Ok(mut frame) = gst_video::VideoFrameRef::from_buffer_ref_writable(out, &info) else {
+            continue;
+        };
+        let origin = (align_to_chroma(x, format), align_to_chroma(y, format));
+        if !crop_frame(src, (origin.0, origin.1, w, h), &mut frame, (0, 0)) {
+            continue;
+        }
+        // Masks make the source RGBA, like the main stream's overlays.
+        if !masks.is_empty()
+            && let Ok(data) = frame.plane_data_mut(0)
+        {
+            draw_masks(data, (w, h), (origin.0 as i64, origin.1 as i64), masks);
+        }
+        drop(frame);
+        // A viewport whose pipeline has stopped only loses its own frames.
+        let _ = feed.appsrc.push_buffer(buffer);
+    }
+}
+
+fn run_send_live(
+    mut capture: Capture,
+    cfg: SendCfg,
+    output_fps: u32,
+    stop_deadline: Option<Instant>,
+    signals: &SignalFlags,
+    metrics: Option<&Metrics>,
+) -> ExitCode {
+    if let Err(err) = gst::init() {
+        eprintln!("FAIL: gstreamer init failed: {err}");
+        return ExitCode::from(1);
+    }
+
+    let passthrough_node = match &capture {
+        Capture::Portal(sc) if static_passthrough(&cfg) => Some(sc.node_id.to_string()),
+        _ => None,
+    };
+    let output_desc = match &passthrough_node {
+        Some(node_id) => passthrough_pipeline_desc(node_id, &cfg, output_fps),
+        None => output_pipeline_desc(&cfg, output_fps),
+    };
+    let output_desc = match output_desc {
+        Ok(v) => v,
+        Err(err) => {
+            eprintln!("FAIL: {err}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let output_pipeline = match gst::parse::launch(&output_desc) {
+        Ok(p) => match p.downcast::<gst::Pipeline>() {
+            Ok(v) => v,
+            Err(_) => {
+                eprintln!("FAIL: output pipeline is not a gst::Pipeline");
+                return ExitCode::from(1);
+            }
+        },
+        Err(err) => {
+            eprintln!("FAIL: could not build output pipeline: {err}");
+            return ExitCode::from(1);
+        }
+    };
+
+    if uses_rtp_session(&cfg)
+        && let Err(err) = attach_rtp_session(&output_pipeline, cfg.fec_percent, cfg.rtx)
+    {
+        eprintln!("FAIL: {err}");
+        return ExitCode::from(1);
+    }
+
+    if uses_rtp_session(&cfg)
+        && let Err(err) = add_capture_time_extension(&output_pipeline)
+    {
+        eprintln!("WARN: capture times are not sent, so vp-rcvr cannot measure latency: {err}");
+    }
+
+    if let Err(err) = tune_streaming_threads(&output_pipeline, &cfg) {
+        eprintln!("FAIL: {err}");
+        return ExitCode::from(1);
+    }
+
+    if let Some(passphrase) = cfg.srt_passphrase.as_deref().filter(|_| cfg.transport == "srt") {
+        match output_pipeline.by_name("netsink") {
+            Some(sink) => sink.set_property("passphrase", passphrase),
+            None => {
+                eprintln!("FAIL: could not find srtsink in output pipeline");
+                return ExitCode::from(1);
+            }
+        }
+    }
+
+    if let Some(rtsp_port) = cfg.rtsp_port {
+        let feed = match start_rtsp_server(rtsp_port, &cfg.encoder) {
+            Ok(v) => v,
+            Err(err) => {
+                eprintln!("FAIL: {err}");
+                return ExitCode::from(1);
+            }
+        };
+        let Some(rtsp_sink) = output_pipeline
+            .by_name("rtspsink")
+            .and_then(|e| e.downcast::<AppSink>().ok())
+        else {
+            eprintln!("FAIL: could not find rtsp appsink in output pipeline");
+            return ExitCode::from(1);
+        };
+        rtsp_sink.set_callbacks(
+            AppSinkCallbacks::builder()
+                .new_sample(move |sink| {
+                    let sample = sink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                    // Without a client the sample is simply dropped.
+                    if let Some(src) = feed.lock().ok().and_then(|slot| slot.clone()) {
+                        let _ = src.push_sample(&sample);
+                    }
+                    Ok(gst::FlowSuccess::Ok)
+                })
+                .build(),
+        );
+        println!("RTSP: rtsp://<this-host>:{rtsp_port}{RTSP_MOUNT_PATH}");
+    }
+
+    // A passthrough pipeline captures by itself and has no appsrc to feed.
+    let appsrc = match output_pipeline
+        .by_name("src")
+        .and_then(|e| e.downcast::<AppSrc>().ok())
+    {
+        Some(v) => Some(v),
+        None if passthrough_node.is_some() => None,
+        None => {
+            eprintln!("FAIL: could not find appsrc in output pipeline");
+            return ExitCode::from(1);
+        }
+    };
+    let static_crop = if passthrough_node.is_some() {
+        let (region_w, region_h) = zoomed_region(cfg.width, cfg.height, cfg.zoom);
+        match attach_static_crop(&output_pipeline, (cfg.x, cfg.y, region_w, region_h)) {
+            Ok(v) => {
+                println!("Fixed viewport: cropping with videocrop in a single pipeline.");
+                Some(v)
+            }
+            Err(err) => {
+                eprintln!("FAIL: {err}");
+                return ExitCode::from(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    let gl_crop = output_pipeline.by_name("glcrop").is_some();
+    if appsrc.is_some()
+        && cfg.crop == "element"
+        && let Err(err) = attach_element_crop(&output_pipeline)
+    {
+        eprintln!("FAIL: {err}");
+        return ExitCode::from(1);
+    }
+    if gl_crop && let Err(err) = attach_gl_crop(&output_pipeline) {
+        eprintln!("FAIL: {err}");
+        return ExitCode::from(1);
+    }
+
+    let mut extra_viewports: Vec<ExtraViewport> = Vec::new();
+    for spec in &cfg.viewports {
+        match start_extra_viewport(&cfg, spec, output_fps) {
+            Ok(v) => extra_viewports.push(v),
+            Err(err) => {
+                for extra in &extra_viewports {
+                    let _ = extra.pipeline.set_state(gst::State::Null);
+                }
+                eprintln!("FAIL: {err}");
+                return ExitCode::from(1);
+            }
+        }
+    }
+    let extra_feeds: Vec<ViewportFeed> = extra_viewports.iter().map(|v| v.feed.clone()).collect();
+
+    // A window stream has its own coordinate space: --x/--y and the cursor position
+    // are window-local, so the desktop-wide trackers cannot be used as a fallback.
+    let window_source = cfg.source == "window";
+    let (cosmic_cursor, mouse_deltas) = if window_source {
+        if cfg.follow_mouse {
+            println!("Window source: following the cursor from stream metadata only.");
+        }
+        (None, None)
+    } else {
+        match start_cursor_trackers(&cfg.cursor_backend, cfg.output.as_deref(), &cfg.delta_scale) {
+            Ok(v) => v,
+            Err(err) if cfg.follow_mouse || cfg.draw_cursor => {
+                for extra in &extra_viewports {
+                    let _ = extra.pipeline.set_state(gst::State::Null);
+                }
+                eprintln!("FAIL: --cursor-backend {}: {err}", cfg.cursor_backend);
+                return ExitCode::from(1);
+            }
+            Err(err) => {
+                eprintln!("WARN: --cursor-backend {}: {err}", cfg.cursor_backend);
+                (None, None)
+            }
+        }
+    };
+    let last_click = if cfg.click_highlight {
+        match start_click_tracker() {
+            Ok(v) => Some(v),
+            Err(err) => {
+                for extra in &extra_viewports {
+                    let _ = extra.pipeline.set_state(gst::State::Null);
+                }
+                eprintln!("FAIL: --click-highlight: {err}");
+                return ExitCode::from(1);
+            }
+        }
+    } else {
+        None
+    };
+    let focused_window = if cfg.follow_window {
+        match start_focused_window_tracker(cfg.output.as_deref()) {
+            Ok(v) => Some(v),
+            Err(err) => {
+                for extra in &extra_viewports {
+                    let _ = extra.pipeline.set_state(gst::State::Null);
+                }
+                eprintln!("FAIL: --follow-window: {err}");
+                return ExitCode::from(1);
+            }
+        }
+    } else {
+        None
+    };
+    let saw_cosmic_cursor = Arc::new(AtomicBool::new(false));
+
+    // Everything below crops at region size; only the output pipeline knows about zoom.
+    let (region_w, region_h) = zoomed_region(cfg.width, cfg.height, cfg.zoom);
+    let follow_state = Arc::new(Mutex::new(FollowState {
+        center_x: cfg.x as f64 + region_w as f64 / 2.0,
+        center_y: cfg.y as f64 + region_h as f64 / 2.0,
+        cursor_x: cfg.x as f64 + region_w as f64 / 2.0,
+        cursor_y: cfg.y as f64 + region_h as f64 / 2.0,
+        target_x: cfg.x as f64 + region_w as f64 / 2.0,
+        target_y: cfg.y as f64 + region_h as f64 / 2.0,
+        velocity_x: 0.0,
+        velocity_y: 0.0,
+        is_lerping: false,
+        last_frame_at: Instant::now(),
+        home_x: cfg.x as f64 + region_w as f64 / 2.0,
+        home_y: cfg.y as f64 + region_h as f64 / 2.0,
+        width: region_w,
+        height: region_h,
+        cursor_source: "none",
+        smoothing: cfg.smoothing,
+        deadzone: (cfg.deadzone, cfg.deadzone_y.unwrap_or(cfg.deadzone)),
+    }));
+    let last_pts = Mutex::new(None::<gst::ClockTime>);
+    let frame_counters = Arc::new(FrameCounters::default());
+    if let Some(pad) = output_pipeline
+        .by_name("venc")
+        .and_then(|encoder| encoder.static_pad("src"))
+    {
+        let counters = Arc::clone(&frame_counters);
+        pad.add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+            counters.encoded.fetch_add(1, Ordering::Relaxed);
+            gst::PadProbeReturn::Ok
+        });
+    }
+    if uses_rtp_session(&cfg)
+        && let Err(err) = count_keyframe_requests(&output_pipeline, Arc::clone(&frame_counters))
+    {
+        eprintln!("WARN: keyframe requests are not counted: {err}");
+    }
+
+    let follow_state_cb = Arc::clone(&follow_state);
+    let appsrc_cb = appsrc.clone();
+    let saw_cosmic_cursor_cb = Arc::clone(&saw_cosmic_cursor);
+    let frame_counters_cb = Arc::clone(&frame_counters);
+    let cfg_follow = cfg.follow_mouse;
+    let follow_paused = Arc::new(AtomicBool::new(false));
+    if let Some(trigger) = &cfg.follow_shortcut {
+        start_follow_shortcut(trigger.clone(), Arc::clone(&follow_paused));
+    }
+    let follow_paused_cb = Arc::clone(&follow_paused);
+    let stream_paused = Arc::new(AtomicBool::new(false));
+    let stream_paused_cb = Arc::clone(&stream_paused);
+    let last_pushed = Mutex::new(None::<(gst::Buffer, Instant)>);
+    let cfg_track_cursor = cfg.follow_mouse || cfg.draw_cursor;
+    let cfg_draw_cursor = cfg.draw_cursor;
+    let cfg_masks = cfg.masks.clone();
+    let cfg_bit_depth = cfg.bit_depth;
+    // Both pass the whole frame on with the viewport as a VideoCropMeta.
+    let cfg_crop_meta = cfg.dmabuf || cfg.crop == "element";
+    let cfg_output_fps = output_fps;
+    let cfg_capture_time = uses_rtp_session(&cfg);
+    let cfg_predict_secs = cfg.follow_predict_ms as f64 / 1000.0;
+    let cfg_roi_radius = cfg.roi_radius as f64;
+    let cfg_follow_margin = (
+        cfg.follow_margin,
+        cfg.follow_margin_y.unwrap_or(cfg.follow_margin),
+    );
+    // Only the CPU crop feeds the encoder at viewport size through the appsrc caps;
+    // the GPU paths scale to a fixed size and windows are padded instead.
+    let cpu_crop = !cfg.dmabuf && cfg.crop == "cpu";
+    let shrink_viewport = !window_source && (cpu_crop || cfg.crop == "element");
+    let last_sizes = Mutex::new(None::<((usize, usize), (usize, usize), gst_video::VideoFormat)>);
+    let frame_pool = Mutex::new(FramePool::default());
+    let stream_idle = Arc::new(AtomicBool::new(false));
+    let idle_detector = cfg.idle_fps.filter(|_| cpu_crop).map(|idle_fps| IdleDetector {
+        idle_fps,
+        changed_at: Instant::now(),
+        idle: Arc::clone(&stream_idle),
+    });
+    let idle_detector = Mutex::new(idle_detector);
+
+    let on_sample: SampleHandler = Arc::new(move |sink: &AppSink| {
+        let Some(appsrc_cb) = &appsrc_cb else {
+            return Err(gst::FlowError::NotLinked);
+        };
+        let sample = sink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+        frame_counters_cb.captured.fetch_add(1, Ordering::Relaxed);
+        if stream_paused_cb.load(Ordering::Relaxed) {
+            // Paused: drop the new frame and repeat the last one sent before the pause
+            // once a second, stamped with the output's current running time.
+            let mut last = last_pushed.lock().map_err(|_| gst::FlowError::Error)?;
+            if let Some((buffer, at)) = last.as_mut()
+                && at.elapsed() >= Duration::from_millis(PAUSE_FRAME_INTERVAL_MS)
+            {
+                let pts = monotonic_pts(&last_pts, appsrc_cb.current_running_time())?;
+                // Without its metas: the capture time would count the pause as latency.
+                let mut frozen = buffer
+                    .copy_region(
+                        gst::BufferCopyFlags::FLAGS | gst::BufferCopyFlags::MEMORY,
+                        ..,
+                    )
+                    .map_err(|_| gst::FlowError::Error)?;
+                let b = frozen.get_mut().ok_or(gst::FlowError::Error)?;
+                b.set_pts(pts);
+                b.set_duration(gst::ClockTime::from_mseconds(PAUSE_FRAME_INTERVAL_MS));
+                appsrc_cb
+                    .push_buffer(frozen)
+                    .map_err(|_| gst::FlowError::Error)?;
+                *at = Instant::now();
+            }
+            return Ok(gst::FlowSuccess::Ok);
+        }
+        let caps = sample.caps().ok_or(gst::FlowError::Error)?;
+        let s = caps.structure(0).ok_or(gst::FlowError::Error)?;
+        let src_w = s.get::<i32>("width").map_err(|_| gst::FlowError::Error)? as usize;
+        let src_h = s.get::<i32>("height").map_err(|_| gst::FlowError::Error)? as usize;
+        let (view_w, view_h) = {
+            let st = follow_state_cb.lock().map_err(|_| gst::FlowError::Error)?;
+            (st.width, st.height)
+        };
+        // A monitor smaller than the viewport (resolution switch, hotplug) shrinks the
+        // viewport to fit; windows are centered on black instead, since they resize
+        // far too often to re-initialize the encoder every time.
+        let (out_w, out_h) = if shrink_viewport {
+            ((view_w as usize).min(src_w), (view_h as usize).min(src_h))
+        } else {
+            (view_w as usize, view_h as usize)
+        };
+        let copy_w = out_w.min(src_w);
+        let copy_h = out_h.min(src_h);
+        let format = s
+            .get::<&str>("format")
+            .map(gst_video::VideoFormat::from_string)
+            .unwrap_or(gst_video::VideoFormat::Unknown);
+        {
+            // A new source size or format, or a SetViewport resize, renegotiates the
+            // appsrc caps; the encoder restarts at the new size with a keyframe.
+            let mut last = last_sizes.lock().map_err(|_| gst::FlowError::Error)?;
+            let sizes = ((src_w, src_h), (out_w, out_h), format);
+            if *last != Some(sizes) {
+                if let Some((last_src, _, _)) = *last
+                    && last_src != (src_w, src_h)
+                {
+                    println!(
+                        "Source resolution changed to {src_w}x{src_h}; viewport {out_w}x{out_h}"
+                    );
+                    let mut st = follow_state_cb.lock().map_err(|_| gst::FlowError::Error)?;
+                    reclamp_follow_state(&mut st, (src_w, src_h), (out_w, out_h));
+                }
+                if cpu_crop {
+                    let mut viewport_caps = crop_info(format, (out_w, out_h), cfg_output_fps)?
+                        .to_caps()
+                        .map_err(|_| gst::FlowError::NotNegotiated)?;
+                    if cfg_bit_depth == 10 {
+                        copy_hdr_fields(s, viewport_caps.make_mut());
+                    }
+                    if appsrc_cb.caps().as_ref() != Some(&viewport_caps) {
+                        appsrc_cb.set_caps(Some(&viewport_caps));
+                    }
+                }
+                *last = Some(sizes);
+            }
+        }
+
+        let now = Instant::now();
+        let (crop_x, crop_y, cursor) = {
+            let mut st = follow_state_cb.lock().map_err(|_| gst::FlowError::Error)?;
+            let prev_cursor_x = st.cursor_x;
+            let prev_cursor_y = st.cursor_y;
+
+            if cfg_track_cursor {
+                let mut used_stream_meta = false;
+                if let Some((mx, my)) = extract_cursor_from_sample(&sample, src_w as u32, src_h as u32) {
+                    st.cursor_x = mx;
+                    st.cursor_y = my;
+                    st.cursor_source = "metadata";
+                    used_stream_meta = true;
+                }
+
+                let mut used_cosmic = false;
+                if !used_stream_meta {
+                    if let Some(cosmic_xy) = &cosmic_cursor {
+                        if let Ok(guard) = cosmic_xy.lock() {
+                            if let Some((mx, my)) = *guard {
+                                st.cursor_x = mx;
+                                st.cursor_y = my;
+                                st.cursor_source = "wayland";
+                                saw_cosmic_cursor_cb.store(true, Ordering::Relaxed);
+                                used_cosmic = true;
+                            }
+                        }
+                    }
+                }
+
+                if !used_stream_meta && !used_cosmic {
+                    if let Some(deltas) = &mouse_deltas {
+                        let mut d = deltas.lock().map_err(|_| gst::FlowError::Error)?;
+                        st.cursor_x += d.0;
+                        st.cursor_y += d.1;
+                        st.cursor_source = "libinput";
+                        d.0 = 0.0;
+                        d.1 = 0.0;
+                    }
+                }
+            }
+
+            let max_cursor_x = (src_w.saturating_sub(1)) as f64;
+            let max_cursor_y = (src_h.saturating_sub(1)) as f64;
+            st.cursor_x = st.cursor_x.clamp(0.0, max_cursor_x);
+            st.cursor_y = st.cursor_y.clamp(0.0, max_cursor_y);
+            if cfg_follow {
+                let cursor_changed = (st.cursor_x - prev_cursor_x).abs() > DEFAULT_CURSOR_CHANGE_EPSILON_PX
+                    || (st.cursor_y - prev_cursor_y).abs() > DEFAULT_CURSOR_CHANGE_EPSILON_PX;
+                // Aim ahead along the cursor's recent motion so fast flicks do not park
+                // it at the viewport edge while the smoothing catches up. One more
+                // update after the cursor slows down brings the aim back onto it.
+                let (aim_x, aim_y, predicting) = if cfg_predict_secs > 0.0 {
+                    let dt = (now - st.last_frame_at).as_secs_f64().max(0.000_001);
+                    let was_predicting =
+                        st.velocity_x.hypot(st.velocity_y) >= FOLLOW_PREDICT_MIN_SPEED_PX_S;
+                    st.velocity_x += ((st.cursor_x - prev_cursor_x) / dt - st.velocity_x)
+                        * FOLLOW_VELOCITY_SMOOTHING;
+                    st.velocity_y += ((st.cursor_y - prev_cursor_y) / dt - st.velocity_y)
+                        * FOLLOW_VELOCITY_SMOOTHING;
+                    if st.velocity_x.hypot(st.velocity_y) >= FOLLOW_PREDICT_MIN_SPEED_PX_S {
+                        (
+                            (st.cursor_x + st.velocity_x * cfg_predict_secs)
+                                .clamp(0.0, max_cursor_x),
+                            (st.cursor_y + st.velocity_y * cfg_predict_secs)
+                                .clamp(0.0, max_cursor_y),
+                            true,
+                        )
+                    } else {
+                        (st.cursor_x, st.cursor_y, was_predicting)
+                    }
+                } else {
+                    (st.cursor_x, st.cursor_y, false)
+                };
+                if follow_paused_cb.load(Ordering::Relaxed) {
+                    // Frozen by the shortcut: stop where the viewport is right now.
+                    st.target_x = st.center_x;
+                    st.target_y = st.center_y;
+                    st.is_lerping = false;
+                } else if cursor_changed || predicting {
+                    let (zone_half_w, zone_half_h) =
+                        follow_zone_half_extent(view_w, view_h, st.deadzone, cfg_follow_margin);
+                    if zone_half_w > 0.0 || zone_half_h > 0.0 {
+                        let left = st.center_x - zone_half_w;
+                        let right = st.center_x + zone_half_w;
+                        let top = st.center_y - zone_half_h;
+                        let bottom = st.center_y + zone_half_h;
+
+                        let target_x = if aim_x < left {
+                            aim_x + zone_half_w
+                        } else if aim_x > right {
+                            aim_x - zone_half_w
+                        } else {
+                            st.center_x
+                        };
+                        let target_y = if aim_y < top {
+                            aim_y + zone_half_h
+                        } else if aim_y > bottom {
+                            aim_y - zone_half_h
+                        } else {
+                            st.center_y
+                        };
+                        st.target_x = target_x;
+                        st.target_y = target_y;
+                    } else {
+                        st.target_x = aim_x;
+                        st.target_y = aim_y;
+                    }
+                    st.is_lerping = true;
+                }
+            } else if let Some(focused) = &focused_window {
+                // The rect is a fraction of the output, so HiDPI scaling drops out. While
+                // the focused window is on another output the viewport stays put.
+                let rect = *focused.lock().map_err(|_| gst::FlowError::Error)?;
+                if let Some((fx, fy, fw, fh)) = rect {
+                    let half_w = out_w as f64 / 2.0;
+                    let half_h = out_h as f64 / 2.0;
+                    let target_x = ((fx + fw / 2.0) * src_w as f64)
+                        .clamp(half_w, (src_w as f64 - half_w).max(half_w));
+                    let target_y = ((fy + fh / 2.0) * src_h as f64)
+                        .clamp(half_h, (src_h as f64 - half_h).max(half_h));
+                    if (target_x - st.target_x).abs() > DEFAULT_CURSOR_CHANGE_EPSILON_PX
+                        || (target_y - st.target_y).abs() > DEFAULT_CURSOR_CHANGE_EPSILON_PX
+                    {
+                        st.target_x = target_x;
+                        st.target_y = target_y;
+                        st.is_lerping = true;
+                    }
+                }
+            } else {
+                st.center_x = st.home_x;
+                st.center_y = st.home_y;
+                st.target_x = st.center_x;
+                st.target_y = st.center_y;
+                st.is_lerping = false;
+            }
+
+            let dt = (now - st.last_frame_at).as_secs_f64().max(0.000_001);
+            st.last_frame_at = now;
+            if st.is_lerping {
+                let alpha = 1.0 - (-st.smoothing * dt).exp();
+                st.center_x += (st.target_x - st.center_x) * alpha;
+                st.center_y += (st.target_y - st.center_y) * alpha;
+                let dx = st.target_x - st.center_x;
+                let dy = st.target_y - st.center_y;
+                let settle2 = DEFAULT_SETTLE_EPSILON_PX * DEFAULT_SETTLE_EPSILON_PX;
+                if dx * dx + dy * dy <= settle2 {
+                    st.center_x = st.target_x;
+                    st.center_y = st.target_y;
+                    st.is_lerping = false;
+                }
+            }
+            let max_x = src_w.saturating_sub(out_w) as f64;
+            let max_y = src_h.saturating_sub(out_h) as f64;
+            let cx = (st.center_x - view_w as f64 / 2.0).clamp(0.0, max_x).round() as usize;
+            let cy = (st.center_y - view_h as f64 / 2.0).clamp(0.0, max_y).round() as usize;
+            (cx, cy, (st.cursor_x, st.cursor_y))
+        };
+
+        let mut out_buf = if cfg_crop_meta {
+            // Zero-copy: pass the frame on untouched and let the VA post-processor crop
+            // the DMA-buf on the GPU, or videocrop the frame (`attach_element_crop`).
+            if appsrc_cb.caps().as_deref() != Some(caps) {
+                appsrc_cb.set_caps(Some(&caps.to_owned()));
+            }
+            let mut buffer = sample.buffer_owned().ok_or(gst::FlowError::Error)?;
+            gst_video::VideoCropMeta::add(
+                buffer.make_mut(),
+                (crop_x as u32, crop_y as u32, copy_w as u32, copy_h as u32),
+            );
+            buffer
+        } else if gl_crop {
+            if appsrc_cb.caps().as_deref() != Some(caps) {
+                appsrc_cb.set_caps(Some(&caps.to_owned()));
+            }
+            // The whole viewport, also where it overhangs a smaller source; see
+            // `attach_gl_crop`. The pixels stay shared.
+            let mut buffer = sample.buffer_owned().ok_or(gst::FlowError::Error)?;
+            gst_video::VideoCropMeta::add(
+                buffer.make_mut(),
+                (crop_x as u32, crop_y as u32, out_w as u32, out_h as u32),
+            );
+            buffer
+        } else {
+            let buffer = sample.buffer().ok_or(gst::FlowError::Error)?;
+            let src_info =
+                gst_video::VideoInfo::from_caps(caps).map_err(|_| gst::FlowError::NotNegotiated)?;
+            // Honors the buffer's VideoMeta, so padded PipeWire strides work.
+            let src = gst_video::VideoFrameRef::from_buffer_ref_readable(buffer, &src_info)
+                .map_err(|_| gst::FlowError::Error)?;
+            let out_info = crop_info(format, (out_w, out_h), cfg_output_fps)?;
+            let mut out_buf = frame_pool
+                .lock()
+                .map_err(|_| gst::FlowError::Error)?
+                .acquire(out_info.size())?;
+            let mut out_frame = gst_video::VideoFrameRef::from_buffer_ref_writable(
+                out_buf.get_mut().ok_or(gst::FlowError::Error)?,
+                &out_info,
+            )
+            .map_err(|_| gst::FlowError::Error)?;
+            let pad_x = align_to_chroma((out_w - copy_w) / 2, format);
+            let pad_y = align_to_chroma((out_h - copy_h) / 2, format);
+            // Recycled buffers hold the previous frame, so the padding is cleared.
+            if copy_w < out_w || copy_h < out_h {
+                fill_black(&mut out_frame);
+            }
+            let crop_x = align_to_chroma(crop_x, format);
+            let crop_y = align_to_chroma(crop_y, format);
+            if !crop_frame(
+                &src,
+                (crop_x, crop_y, copy_w, copy_h),
+                &mut out_frame,
+                (pad_x, pad_y),
+            ) {
+                return Err(gst::FlowError::Error);
+            }
+            // The overlays only run on RGBA input (see input_pipeline_desc).
+            let out_data = out_frame
+                .plane_data_mut(0)
+                .map_err(|_| gst::FlowError::Error)?;
+            if !cfg_masks.is_empty() {
+                let origin = (crop_x as i64 - pad_x as i64, crop_y as i64 - pad_y as i64);
+                draw_masks(out_data, (out_w, out_h), origin, &cfg_masks);
+            }
+            if let Some(last_click) = &last_click
+                && let Some(at) = *last_click.lock().map_err(|_| gst::FlowError::Error)?
+            {
+                let age = now.saturating_duration_since(at).as_secs_f64() * 1000.0;
+                if age < CLICK_HIGHLIGHT_MS as f64 {
+                    let local = (
+                        cursor.0 - crop_x as f64 + pad_x as f64,
+                        cursor.1 - crop_y as f64 + pad_y as f64,
+                    );
+                    draw_click_highlight(
+                        out_data,
+                        (out_w, out_h),
+                        local,
+                        age / CLICK_HIGHLIGHT_MS as f64,
+                    );
+                }
+            }
+            if cfg_draw_cursor {
+                let local = (
+                    cursor.0 - crop_x as f64 + pad_x as f64,
+                    cursor.1 - crop_y as f64 + pad_y as f64,
+                );
+                draw_cursor_sprite(out_data, (out_w, out_h), local);
+            }
+            drop(out_frame);
+            out_buf
+        };
+        {
+            // The capture PTS rather than a frame count, so stalls and dropped frames
+            // show up as gaps instead of drifting the video away from the audio.
+            let age = capture_age(sink, &sample);
+            let pts = appsrc_cb
+                .current_running_time()
+                .map(|now| now.saturating_sub(age));
+            let pts = monotonic_pts(&last_pts, pts)?;
+            let dur =
+                gst::ClockTime::from_nseconds(1_000_000_000u64 / cfg_output_fps as u64);
+            let b = out_buf.get_mut().ok_or(gst::FlowError::Error)?;
+            b.set_pts(pts);
+            b.set_duration(dur);
+            if cfg_capture_time {
+                add_capture_time_meta(b, age);
+            }
+            if cfg_roi_radius > 0.0 {
+                let pad_x = (out_w - copy_w) / 2;
+                let pad_y = (out_h - copy_h) / 2;
+                let local_x = cursor.0 - crop_x as f64 + pad_x as f64;
+                let local_y = cursor.1 - crop_y as f64 + pad_y as f64;
+                if let Some(rect) = roi_rect((local_x, local_y), cfg_roi_radius, (out_w, out_h)) {
+                    let mut roi = gst_video::VideoRegionOfInterestMeta::add(b, "cursor", rect);
+                    roi.add_param(
+                        gst::Structure::builder("roi/vaapi")
+                            .field("delta-qp", ROI_DELTA_QP)
+                            .build(),
+                    );
+                }
+            }
+        }
+
+        let timing = (out_buf.pts(), out_buf.duration());
+        let mut last = last_pushed.lock().map_err(|_| gst::FlowError::Error)?;
+        let idle_skip = idle_detector
+            .lock()
+            .map_err(|_| gst::FlowError::Error)?
+            .as_mut()
+            .is_some_and(|detector| detector.skip(&out_buf, last.as_ref()));
+        if !idle_skip {
+            *last = Some((out_buf.clone(), Instant::now()));
+            drop(last);
+            appsrc_cb
+                .push_buffer(out_buf)
+                .map_err(|_| gst::FlowError::Error)?;
+            frame_counters_cb.sent.fetch_add(1, Ordering::Relaxed);
+            let sent_ms = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            frame_counters_cb
+                .last_sent_ms
+                .store(sent_ms, Ordering::Relaxed);
+        }
+        if !extra_feeds.is_empty()
+            && let Some(buffer) = sample.buffer()
+            && let Ok(info) = gst_video::VideoInfo::from_caps(caps)
+            && let Ok(frame) = gst_video::VideoFrameRef::from_buffer_ref_readable(buffer, &info)
+        {
+            push_viewport_crops(&extra_feeds, &frame, cfg_output_fps, timing, &cfg_masks);
+        }
+        Ok(gst::FlowSuccess::Ok)
+    });
+
+    if output_pipeline.set_state(gst::State::Playing).is_err() {
+        eprintln!("FAIL: could not set output pipeline to Playing");
+        return ExitCode::from(1);
+    }
+    let out_bus = match output_pipeline.bus() {
+        Some(v) => v,
+        None => {
+            let _ = output_pipeline.set_state(gst::State::Null);
+            eprintln!("FAIL: could not get output bus");
+            return ExitCode::from(1);
+        }
+    };
+    let (mut input_pipeline, mut in_bus) = match &appsrc {
+        Some(appsrc) => match start_input_pipeline(&capture, &cfg, &on_sample, appsrc) {
+            Ok(v) => v,
+            Err(err) => {
+                let _ = output_pipeline.set_state(gst::State::Null);
+                eprintln!("FAIL: {err}");
+                return ExitCode::from(1);
+            }
+        },
+        // The passthrough pipeline is both; its messages come up on either bus.
+        None => {
+            if let Err(err) = count_passthrough_frames(
+                &output_pipeline,
+                Arc::clone(&frame_counters),
+                cfg_capture_time,
+            ) {
+                let _ = output_pipeline.set_state(gst::State::Null);
+                eprintln!("FAIL: {err}");
+                return ExitCode::from(1);
+            }
+            (output_pipeline.clone(), out_bus.clone())
+        }
+    };
+
+    let (control_tx, control_rx) = mpsc::channel::<ControlRequest>();
+    let control_socket = match start_control_listener(control_tx.clone()) {
+        Ok(()) => true,
+        Err(err) => {
+            eprintln!("WARN: control socket unavailable: {err}");
+            false
+        }
+    };
+    // Dropping the connection releases the bus name.
+    let _dbus = match start_dbus_service(control_tx.clone()) {
+        Ok(conn) => Some(conn),
+        Err(err) => {
+            eprintln!("WARN: D-Bus interface unavailable: {err}");
+            None
+        }
+    };
+
+    let signal_rx = if cfg.transport == "webrtc" {
+        match signaling::start_signaling_server(cfg.port) {
+            Ok(rx) => Some(rx),
+            Err(err) => {
+                let _ = input_pipeline.set_state(gst::State::Null);
+                let _ = output_pipeline.set_state(gst::State::Null);
+                eprintln!("FAIL: {err}");
+                return ExitCode::from(1);
+            }
+        }
+    } else {
+        None
+    };
+    let mut webrtc_peers: HashMap<u64, WebRtcPeer> = HashMap::new();
+
+    let mut abr = if cfg.adaptive_bitrate {
+        match AdaptiveBitrate::new(&output_pipeline, &cfg) {
+            Ok(v) => Some(v),
+            Err(err) => {
+                eprintln!("WARN: adaptive bitrate disabled: {err}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut audio = if cfg.with_audio {
+        match start_audio_pipeline(&cfg) {
+            Ok(v) => {
+                println!(
+                    "Audio: {} as Opus to {}:{}",
+                    cfg.audio_node.as_deref().unwrap_or("desktop audio"),
+                    cfg.receiver_ip,
+                    cfg.port.saturating_add(AUDIO_PORT_OFFSET)
+                );
+                Some(v)
+            }
+            Err(err) => {
+                eprintln!("WARN: audio disabled: {err}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let stats_target = match stats_target(&cfg.receiver_ip, cfg.port) {
+        Ok(v) => Some(v),
+        Err(err) => {
+            eprintln!("WARN: sender stats are not sent: {err}");
+            None
+        }
+    };
+    let mut stats = StatsReporter::new(stats_target);
+    let mut json_stats = cfg
+        .stats_interval
+        .map(|secs| JsonStats::new(Duration::from_secs(secs)));
+    let mut bitrate_kbps = cfg.bitrate_kbps;
+    let mut was_idle = false;
+    // What the last config reload saw, so the next one only acts on new edits.
+    let mut live_settings = cfg_from_send(&cfg);
+
+    let mut done = false;
+    let mut failed = false;
+    let mut stop_deadline = stop_deadline;
+    let mut flush_deadline: Option<Instant> = None;
+    let deadline = match stop_deadline {
+        Some(stop) => stop + Duration::from_secs(EOS_FLUSH_TIMEOUT_SECS + 1),
+        None => Instant::now() + Duration::from_secs(8 * 60 * 60),
+    };
+    while Instant::now() < deadline {
+        // SIGTERM/SIGINT flush the encoder like Stop so `systemctl --user stop` does
+        // not cut off the last GOP.
+        if signals.terminate.load(Ordering::Relaxed) && flush_deadline.is_none() {
+            println!("Termination requested; flushing...");
+            stop_deadline = Some(Instant::now());
+        }
+        if stop_deadline.is_some_and(|d| Instant::now() >= d) {
+            // Stop capturing and push EOS through the encoder so the last frames
+            // are flushed to the receiver before the pipelines are torn down.
+            println!("Scheduled stop reached; flushing...");
+            match &appsrc {
+                Some(appsrc) => {
+                    let _ = input_pipeline.set_state(gst::State::Null);
+                    let _ = appsrc.end_of_stream();
+                }
+                // EOS from the capture source flushes the encoder behind it.
+                None => {
+                    output_pipeline.send_event(gst::event::Eos::new());
+                }
+            }
+            for extra in &extra_viewports {
+                let _ = extra.feed.appsrc.end_of_stream();
+            }
+            if let Some((audio_pipeline, _)) = audio.take() {
+                let _ = audio_pipeline.set_state(gst::State::Null);
+            }
+            stop_deadline = None;
+            flush_deadline = Some(Instant::now() + Duration::from_secs(EOS_FLUSH_TIMEOUT_SECS));
+        }
+        if flush_deadline.is_some_and(|d| Instant::now() >= d) {
+            eprintln!("WARN: output pipeline did not reach EOS within {EOS_FLUSH_TIMEOUT_SECS}s");
+            done = true;
+            break;
+        }
+        if let Some(msg) = in_bus.timed_pop(gst::ClockTime::from_mseconds(50)) {
+            match msg.view() {
+                gst::MessageView::Error(e) => {
+                    eprintln!(
+                        "FAIL: input pipeline error from {}: {}",
+                        e.src().map(|s| s.path_string()).unwrap_or_else(|| "<unknown>".into()),
+                        e.error()
+                    );
+                    failed = true;
+                    done = true;
+                }
+                gst::MessageView::Eos(..) => done = true,
+                _ => {}
+            }
+        }
+        if let Some(msg) = out_bus.timed_pop(gst::ClockTime::from_mseconds(0)) {
+            match msg.view() {
                 gst::MessageView::Error(e) => {
                     eprintln!(
-                        "FAIL: output pipeline error from {}: {}",
-                        e.src().map(|s| s.path_string()).unwrap_or_else(|| "<unknown>".into()),
-                        e.error()
+                        "FAIL: output pipeline error from {}: {}",
+                        e.src().map(|s| s.path_string()).unwrap_or_else(|| "<unknown>".into()),
+                        e.error()
+                    );
+                    failed = true;
+                    done = true;
+                }
+                gst::MessageView::Eos(..) => done = true,
+                _ => {}
+            }
+        }
+        // Losing the audio leaves the video running.
+        if let Some((audio_pipeline, audio_bus)) = &audio
+            && let Some(msg) = audio_bus.timed_pop(gst::ClockTime::from_mseconds(0))
+            && let gst::MessageView::Error(e) = msg.view()
+        {
+            eprintln!("WARN: audio stopped: {}", e.error());
+            let _ = audio_pipeline.set_state(gst::State::Null);
+            audio = None;
+        }
+        // A failing extra viewport is reported but leaves the others streaming.
+        for extra in &extra_viewports {
+            if let Some(msg) = extra
+                .pipeline
+                .bus()
+                .and_then(|bus| bus.timed_pop(gst::ClockTime::from_mseconds(0)))
+                && let gst::MessageView::Error(e) = msg.view()
+            {
+                eprintln!("WARN: viewport {} stopped: {}", extra.name, e.error());
+            }
+        }
+        if signals.reload.swap(false, Ordering::Relaxed) {
+            match read_settings(cfg.profile.as_deref()) {
+                Ok(new) => {
+                    let mut restart = Vec::new();
+                    for key in changed_settings(&live_settings, &new) {
+                        match key.as_str() {
+                            "bitrate_kbps" => {
+                                // Nobody waits for the reply; failures are reported by
+                                // the handler.
+                                let (reply, _) = mpsc::channel();
+                                let _ = control_tx.send(ControlRequest {
+                                    command: ControlCommand::Bitrate(new.bitrate_kbps),
+                                    reply,
+                                });
+                            }
+                            "smoothing" | "deadzone" | "deadzone_y" => {
+                                if let Ok(mut st) = follow_state.lock() {
+                                    st.smoothing = new.smoothing;
+                                    st.deadzone =
+                                        (new.deadzone, new.deadzone_y.unwrap_or(new.deadzone));
+                                }
+                                println!("Config reload: {key} applied.");
+                            }
+                            "follow_mouse" if cfg.follow_mouse => {
+                                follow_paused.store(!new.follow_mouse, Ordering::Relaxed);
+                                println!("Config reload: follow_mouse = {}.", new.follow_mouse);
+                            }
+                            // Other profiles do not affect this run.
+                            "profile" => {}
+                            _ => restart.push(key),
+                        }
+                    }
+                    if !restart.is_empty() {
+                        println!(
+                            "Config reload: restart the sender to apply {}.",
+                            restart.join(", ")
+                        );
+                    }
+                    live_settings = new;
+                }
+                Err(err) => eprintln!("WARN: config not reloaded: {err}"),
+            }
+        }
+        while let Ok(req) = control_rx.try_recv() {
+            let result = match req.command {
+                ControlCommand::CursorMode(_) | ControlCommand::Stop
+                    if flush_deadline.is_some() =>
+                {
+                    Err("sender is stopping".to_string())
+                }
+                ControlCommand::CursorMode(mode) => match &appsrc {
+                    None => Err(
+                        "the cursor mode of a fixed viewport is set with --cursor-mode at start"
+                            .to_string(),
+                    ),
+                    Some(appsrc) => {
+                        let _ = input_pipeline.set_state(gst::State::Null);
+                        match restart_capture(&cfg, &mode, &on_sample, appsrc) {
+                            Ok((new_capture, pipeline, bus)) => {
+                                std::mem::replace(&mut capture, new_capture).close();
+                                input_pipeline = pipeline;
+                                in_bus = bus;
+                                Ok(format!("cursor mode {}", capture.cursor_mode_name()).into())
+                            }
+                            Err(err) => {
+                                eprintln!("WARN: cursor mode switch failed: {err}");
+                                let _ = input_pipeline.set_state(gst::State::Playing);
+                                Err(err)
+                            }
+                        }
+                    }
+                },
+                ControlCommand::Viewport {
+                    x,
+                    y,
+                    width,
+                    height,
+                } => {
+                    // Resizing only works where the appsrc or videocrop caps carry the
+                    // viewport size, and the crop is scaled back to the output size.
+                    let resizable = shrink_viewport && (cfg.zoom == 1.0 || cfg.lock_aspect);
+                    let (x, y, width, height) = if cfg.lock_aspect {
+                        lock_aspect_rect((x, y, width, height), encode_size(&cfg))
+                    } else {
+                        (x, y, width, height)
+                    };
+                    match follow_state.lock() {
+                        Ok(_) if width < 2 || height < 2 || width % 2 != 0 || height % 2 != 0 => {
+                            Err("the viewport size must be even and at least 2x2".to_string())
+                        }
+                        Ok(st) if !resizable && (width, height) != (st.width, st.height) => {
+                            Err(format!(
+                                "the viewport is {}x{}; resizing it live needs --crop cpu or element on a monitor, and --lock-aspect with --zoom",
+                                st.width, st.height
+                            ))
+                        }
+                        Ok(mut st) => {
+                            st.width = width;
+                            st.height = height;
+                            st.home_x = x as f64 + width as f64 / 2.0;
+                            st.home_y = y as f64 + height as f64 / 2.0;
+                            st.center_x = st.home_x;
+                            st.center_y = st.home_y;
+                            st.target_x = st.home_x;
+                            st.target_y = st.home_y;
+                            st.is_lerping = false;
+                            if let Some(crop) = &static_crop
+                                && let Ok(mut crop) = crop.lock()
+                            {
+                                crop.rect = (x, y, width, height);
+                                crop.apply();
+                            }
+                            Ok(format!("viewport {width}x{height} at {x},{y}").into())
+                        }
+                        Err(_) => Err("follow state is poisoned".to_string()),
+                    }
+                }
+                ControlCommand::Bitrate(kbps) => {
+                    let applied = match &mut abr {
+                        Some(abr) => abr.set_ceiling(kbps),
+                        None => set_encoder_bitrate(&output_pipeline, &cfg.encoder, kbps),
+                    };
+                    applied
+                        .map(|()| {
+                            bitrate_kbps = kbps;
+                            println!("Bitrate set to {kbps} kbps.");
+                            format!("bitrate {kbps} kbps").into()
+                        })
+                        .inspect_err(|err| eprintln!("WARN: bitrate change failed: {err}"))
+                }
+                ControlCommand::FollowMouse(_) if !cfg.follow_mouse => {
+                    Err("the sender was started without --follow-mouse".to_string())
+                }
+                ControlCommand::FollowMouse(enabled) => {
+                    follow_paused.store(!enabled, Ordering::Relaxed);
+                    Ok(format!("follow-mouse {}", if enabled { "on" } else { "off" }).into())
+                }
+                ControlCommand::Pause(pause) => {
+                    let reply = if pause { "paused" } else { "resumed" };
+                    if stream_paused.swap(pause, Ordering::Relaxed) != pause {
+                        println!("Stream {reply}.");
+                    }
+                    if let Some(valve) = output_pipeline.by_name("pausevalve") {
+                        valve.set_property("drop", pause);
+                    }
+                    Ok(reply.into())
+                }
+                ControlCommand::Stop => {
+                    println!("Stop requested; flushing...");
+                    stop_deadline = Some(Instant::now());
+                    Ok("stopping".into())
+                }
+                ControlCommand::Stats => stats
+                    .last_line
+                    .strip_prefix("vp-stats ")
+                    .map(serde_json::Value::from)
+                    .ok_or_else(|| "no stats yet".to_string()),
+                ControlCommand::Status => {
+                    let (center_x, center_y, region_w, region_h) = follow_state
+                        .lock()
+                        .map(|st| (st.center_x, st.center_y, st.width, st.height))
+                        .unwrap_or_default();
+                    Ok(serde_json::json!({
+                        "state": if flush_deadline.is_some() { "stopping" } else { "streaming" },
+                        "transport": cfg.transport,
+                        "receiver_ip": cfg.receiver_ip,
+                        "port": cfg.port,
+                        "encoder": cfg.encoder,
+                        "bitrate_kbps": abr.as_ref().map_or(bitrate_kbps, |a| a.current_kbps),
+                        "fps": cfg.fps,
+                        "cursor_mode": capture.cursor_mode_name(),
+                        "follow_mouse": cfg.follow_mouse && !follow_paused.load(Ordering::Relaxed),
+                        "paused": stream_paused.load(Ordering::Relaxed),
+                        "viewport": {
+                            "x": (center_x - region_w as f64 / 2.0).round(),
+                            "y": (center_y - region_h as f64 / 2.0).round(),
+                            "width": region_w,
+                            "height": region_h,
+                        },
+                        "frames_sent": frame_counters.sent.load(Ordering::Relaxed),
+                    }))
+                }
+                ControlCommand::Keyframe => {
+                    request_keyframe(&output_pipeline).map(|()| "keyframe requested".into())
+                }
+            };
+            let _ = req.reply.send(result);
+        }
+        if let Some(rx) = &signal_rx {
+            while let Ok(event) = rx.try_recv() {
+                if let Err(err) = handle_signal_event(&output_pipeline, &mut webrtc_peers, event) {
+                    eprintln!("WARN: webrtc: {err}");
+                }
+            }
+        }
+        if let Some(abr) = &mut abr {
+            abr.tick();
+        }
+        let idle = stream_idle.load(Ordering::Relaxed);
+        if idle != was_idle {
+            was_idle = idle;
+            if let Some(idle_kbps) = cfg.idle_bitrate_kbps {
+                let kbps = if idle {
+                    idle_kbps.min(bitrate_kbps)
+                } else {
+                    bitrate_kbps
+                };
+                let applied = match &mut abr {
+                    Some(abr) => abr.set_ceiling(kbps),
+                    None => set_encoder_bitrate(&output_pipeline, &cfg.encoder, kbps),
+                };
+                if let Err(err) = applied {
+                    eprintln!("WARN: idle bitrate change failed: {err}");
+                }
+            }
+        }
+        {
+            let follow = follow_state.lock().ok().map(|st| *st);
+            let bitrate_kbps = abr.as_ref().map_or(bitrate_kbps, |a| a.current_kbps);
+            if stats.tick(&frame_counters, &cfg, bitrate_kbps, follow)
+                && let Some(metrics) = metrics
+            {
+                export_metrics(
+                    metrics,
+                    &frame_counters,
+                    &stats,
+                    bitrate_kbps,
+                    &output_pipeline,
+                    stream_paused.load(Ordering::Relaxed),
+                );
+            }
+            if let Some(json_stats) = &mut json_stats {
+                json_stats.tick(&frame_counters, &output_pipeline, bitrate_kbps, follow);
+            }
+        }
+        if done {
+            break;
+        }
+    }
+
+    let _ = input_pipeline.set_state(gst::State::Null);
+    let _ = output_pipeline.set_state(gst::State::Null);
+    for extra in &extra_viewports {
+        let _ = extra.pipeline.set_state(gst::State::Null);
+    }
+    if let Some((audio_pipeline, _)) = &audio {
+        let _ = audio_pipeline.set_state(gst::State::Null);
+    }
+    webrtc_peers.clear();
+    capture.close();
+    if control_socket {
+        let _ = fs::remove_file(control_socket_path());
+    }
+    if failed {
+        ExitCode::from(1)
+    } else if done {
+        ExitCode::SUCCESS
+    } else {
+        eprintln!("FAIL: sender timed out");
+        ExitCode::from(1)
+    }
+}
+
+#[derive(Default)]
+struct FrameCounters {
+    captured: AtomicU64,
+    sent: AtomicU64,
+    // Buffers that left the encoder.
+    encoded: AtomicU64,
+    // Unix time in milliseconds of the last frame pushed to the encoder.
+    last_sent_ms: AtomicU64,
+    // PLI/FIR keyframe requests received over RTCP.
+    keyframe_requests: AtomicU64,
+}
+
+/// Periodic `vp-stats key=value ...` datagrams for the receiver, so it can show
+/// sender-side health next to its own network stats.
+/// The newest report block a receiver sent about our stream.
+#[derive(Clone, Copy, PartialEq)]
+struct ReceiverReport {
+    highest_seq: u32,
+    loss_pct: f64,
+    jitter_ms: u32,
+}
+
+/// Lowers the encoder bitrate when receiver reports show loss or jitter, and raises
+/// it back towards --bitrate-kbps while they stay clean.
+struct AdaptiveBitrate {
+    encoder: gst::Element,
+    property: &'static str,
+    units_per_kbps: u32,
+    session: gst::glib::Object,
+    min_kbps: u32,
+    max_kbps: u32,
+    current_kbps: u32,
+    last_report: Option<ReceiverReport>,
+    last_at: Instant,
+}
+
+impl AdaptiveBitrate {
+    fn new(pipeline: &gst::Pipeline, cfg: &SendCfg) -> Result<Self, String> {
+        let (property, units_per_kbps) = encoder_bitrate_property(&cfg.encoder)
+            .ok_or_else(|| format!("{} has no runtime bitrate control", cfg.encoder))?;
+        let encoder = pipeline
+            .by_name("venc")
+            .ok_or_else(|| "could not find encoder in output pipeline".to_string())?;
+        let rtpbin = pipeline
+            .by_name("rtpbin")
+            .ok_or_else(|| "no RTP session (needs --transport udp with --receiver-ip)".to_string())?;
+        let session = rtpbin
+            .emit_by_name::<Option<gst::glib::Object>>("get-internal-session", &[&0u32])
+            .ok_or_else(|| "rtpbin has no session 0".to_string())?;
+        Ok(Self {
+            encoder,
+            property,
+            units_per_kbps,
+            session,
+            min_kbps: (cfg.bitrate_kbps / ABR_MIN_DIVISOR).max(1),
+            max_kbps: cfg.bitrate_kbps,
+            current_kbps: cfg.bitrate_kbps,
+            last_report: None,
+            last_at: Instant::now(),
+        })
+    }
+
+    fn tick(&mut self) {
+        if self.last_at.elapsed() < Duration::from_millis(ABR_POLL_MS) {
+            return;
+        }
+        self.last_at = Instant::now();
+        let Some(report) = latest_receiver_report(&self.session) else {
+            return;
+        };
+        // Report blocks stay in the stats until the next RTCP packet; act once per report.
+        if self
+            .last_report
+            .is_some_and(|last| last.highest_seq == report.highest_seq)
+        {
+            return;
+        }
+        self.last_report = Some(report);
+
+        let congested =
+            report.loss_pct > ABR_LOSS_THRESHOLD_PCT || report.jitter_ms > ABR_JITTER_THRESHOLD_MS;
+        let target = if congested {
+            (self.current_kbps as f64 * ABR_STEP_DOWN) as u32
+        } else {
+            (self.current_kbps as f64 * ABR_STEP_UP).ceil() as u32
+        }
+        .clamp(self.min_kbps, self.max_kbps);
+        if target == self.current_kbps {
+            return;
+        }
+        let value = (target * self.units_per_kbps).to_string();
+        self.encoder.set_property_from_str(self.property, &value);
+        println!(
+            "Bitrate: {} -> {} kbps (loss {:.1}%, jitter {} ms)",
+            self.current_kbps, target, report.loss_pct, report.jitter_ms
+        );
+        self.current_kbps = target;
+    }
+
+    /// A new --bitrate-kbps from the control interface: adapt below it from now on.
+    fn set_ceiling(&mut self, kbps: u32) -> Result<(), String> {
+        let value = (kbps * self.units_per_kbps).to_string();
+        self.encoder.set_property_from_str(self.property, &value);
+        self.min_kbps = (kbps / ABR_MIN_DIVISOR).max(1);
+        self.max_kbps = kbps;
+        self.current_kbps = kbps;
+        Ok(())
+    }
+}
+
+/// Asks the encoder for an IDR frame with fresh parameter sets, e.g. for a receiver
+/// that joined mid-stream.
+fn request_keyframe(pipeline: &gst::Pipeline) -> Result<(), String> {
+    let pad = pipeline
+        .by_name("venc")
+        .and_then(|encoder| encoder.static_pad("src"))
+        .ok_or_else(|| "could not find encoder in output pipeline".to_string())?;
+    let event = gst_video::UpstreamForceKeyUnitEvent::builder()
+        .all_headers(true)
+        .build();
+    if pad.send_event(event) {
+        Ok(())
+    } else {
+        Err("the encoder did not accept the keyframe request".to_string())
+    }
+}
+
+fn set_encoder_bitrate(pipeline: &gst::Pipeline, encoder: &str, kbps: u32) -> Result<(), String> {
+    let (property, units_per_kbps) = encoder_bitrate_property(encoder)
+        .ok_or_else(|| format!("{encoder} has no runtime bitrate control"))?;
+    let element = pipeline
+        .by_name("venc")
+        .ok_or_else(|| "could not find encoder in output pipeline".to_string())?;
+    element.set_property_from_str(property, &(kbps * units_per_kbps).to_string());
+    Ok(())
+}
+
+/// Reads the receiver's report block out of the RTP session stats. Remote sources
+/// carry the `rb-*` fields of the last RR they sent about our SSRC.
+fn latest_receiver_report(session: &gst::glib::Object) -> Option<ReceiverReport> {
+    let stats = session.property::<gst::Structure>("stats");
+    let sources = stats.get::<gst::glib::ValueArray>("source-stats").ok()?;
+    sources.iter().find_map(|value| {
+        let source = value.get::<gst::Structure>().ok()?;
+        if source.get::<bool>("internal").unwrap_or(true)
+            || !source.get::<bool>("have-rb").unwrap_or(false)
+        {
+            return None;
+        }
+        let fraction_lost = source.get::<u32>("rb-fractionlost").ok()?;
+        let jitter = source.get::<u32>("rb-jitter").ok()?;
+        Some(ReceiverReport {
+            highest_seq: source.get::<u32>("rb-exthighestseq").ok()?,
+            loss_pct: fraction_lost as f64 * 100.0 / 256.0,
+            // RTP timestamp units at the 90 kHz video clock.
+            jitter_ms: jitter / 90,
+        })
+    })
+}
+
+/// Counts the receiver's keyframe requests (PLI and FIR) for the stats; the RTP
+/// session already forwards them to the encoder.
+fn count_keyframe_requests(
+    pipeline: &gst::Pipeline,
+    counters: Arc<FrameCounters>,
+) -> Result<(), String> {
+    let rtpbin = pipeline
+        .by_name("rtpbin")
+        .ok_or_else(|| "could not find rtpbin in output pipeline".to_string())?;
+    let session = rtpbin
+        .emit_by_name::<Option<gst::glib::Object>>("get-internal-session", &[&0u32])
+        .ok_or_else(|| "rtpbin has no session 0".to_string())?;
+    session.connect("on-feedback-rtcp", false, move |values| {
+        let kind = values[1].get::<u32>().ok()?;
+        let fbtype = values[2].get::<u32>().ok()?;
+        // Payload-specific feedback (206): 1 = PLI, 4 = FIR.
+        if kind == 206 && matches!(fbtype, 1 | 4) {
+            counters.keyframe_requests.fetch_add(1, Ordering::Relaxed);
+        }
+        None
+    });
+    Ok(())
+}
+
+/// `--stats-interval`: one JSON object per line on stdout, for monitoring scripts.
+/// Counters are totals since the start, so a stall shows as a counter that stops.
+struct JsonStats {
+    interval: Duration,
+    last_at: Instant,
+}
+
+impl JsonStats {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_at: Instant::now(),
+        }
+    }
+
+    fn tick(
+        &mut self,
+        counters: &FrameCounters,
+        pipeline: &gst::Pipeline,
+        bitrate_kbps: u32,
+        follow: Option<FollowState>,
+    ) {
+        if self.last_at.elapsed() < self.interval {
+            return;
+        }
+        self.last_at = Instant::now();
+        let captured = counters.captured.load(Ordering::Relaxed);
+        let sent = counters.sent.load(Ordering::Relaxed);
+        let queue_depth = |name: &str| {
+            pipeline
+                .by_name(name)
+                .map(|queue| queue.property::<u32>("current-level-buffers"))
+        };
+        let mut line = serde_json::json!({
+            "timestamp": SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            "frames_captured": captured,
+            "frames_sent": sent,
+            "frames_encoded": counters.encoded.load(Ordering::Relaxed),
+            "frames_dropped": captured.saturating_sub(sent),
+            "bitrate_kbps": bitrate_kbps,
+            "queue_depth": {
+                "encoder": queue_depth("encq"),
+                "network": queue_depth("netq"),
+            },
+            "keyframe_requests": counters.keyframe_requests.load(Ordering::Relaxed),
+        });
+        if let Some(st) = follow {
+            line["cursor_backend"] = st.cursor_source.into();
+            line["crop"] = serde_json::json!({
+                "x": (st.center_x - st.width as f64 / 2.0).round(),
+                "y": (st.center_y - st.height as f64 / 2.0).round(),
+                "width": st.width,
+                "height": st.height,
+            });
+        }
+        println!("{line}");
+    }
+}
+
+/// Where the stats datagrams go: the receiver's RTP port + 3.
+fn stats_target(receiver_ip: &str, rtp_port: u16) -> Result<(UdpSocket, String, u16), String> {
+    if receiver_ip == "0.0.0.0" {
+        return Err("no --receiver-ip to send stats to".to_string());
+    }
+    let port = rtp_port
+        .checked_add(STATS_PORT_OFFSET)
+        .ok_or_else(|| format!("no stats port above RTP port {rtp_port}"))?;
+    let socket = UdpSocket::bind(("0.0.0.0", 0)).map_err(|e| format!("bind stats socket: {e}"))?;
+    Ok((socket, receiver_ip.to_string(), port))
+}
+
+struct StatsReporter {
+    // Without a receiver the line is still kept for the control interface.
+    target: Option<(UdpSocket, String, u16)>,
+    last_line: String,
+    last_at: Instant,
+    last_captured: u64,
+    last_sent: u64,
+    drops_total: u64,
+    // Frames sent per second over the last interval.
+    fps: f64,
+}
+
+impl StatsReporter {
+    fn new(target: Option<(UdpSocket, String, u16)>) -> Self {
+        Self {
+            target,
+            last_line: String::new(),
+            last_at: Instant::now(),
+            last_captured: 0,
+            last_sent: 0,
+            drops_total: 0,
+            fps: 0.0,
+        }
+    }
+
+    fn tick(
+        &mut self,
+        counters: &FrameCounters,
+        cfg: &SendCfg,
+        bitrate_kbps: u32,
+        follow: Option<FollowState>,
+    ) -> bool {
+        let elapsed = self.last_at.elapsed();
+        if elapsed < Duration::from_millis(STATS_INTERVAL_MS) {
+            return false;
+        }
+        let secs = elapsed.as_secs_f64();
+        let captured = counters.captured.load(Ordering::Relaxed);
+        let sent = counters.sent.load(Ordering::Relaxed);
+        let captured_delta = captured - self.last_captured;
+        let sent_delta = sent - self.last_sent;
+        let drops = captured_delta.saturating_sub(sent_delta);
+        self.drops_total += drops;
+        self.last_at = Instant::now();
+        self.last_captured = captured;
+        self.last_sent = sent;
+
+        let mut line = format!(
+            "vp-stats fps={:.1} capture_fps={:.1} target_fps={} bitrate_kbps={} encoder={} drops={} drops_total={} keyframe_requests={}",
+            sent_delta as f64 / secs,
+            captured_delta as f64 / secs,
+            cfg.fps,
+            bitrate_kbps,
+            cfg.encoder,
+            drops,
+            self.drops_total,
+            counters.keyframe_requests.load(Ordering::Relaxed)
+        );
+        let follow_label = match follow {
+            _ if !cfg.follow_mouse && !cfg.follow_window => "off",
+            Some(st) if st.is_lerping => "panning",
+            _ => "idle",
+        };
+        line.push_str(&format!(" follow={follow_label}"));
+        if let Some(st) = follow {
+            line.push_str(&format!(
+                " view_x={:.0} view_y={:.0} cursor_x={:.0} cursor_y={:.0}",
+                st.center_x - st.width as f64 / 2.0,
+                st.center_y - st.height as f64 / 2.0,
+                st.cursor_x,
+                st.cursor_y
+            ));
+        }
+        // Best effort: the receiver may not be up yet.
+        if let Some((socket, receiver_ip, port)) = &self.target {
+            let _ = socket.send_to(line.as_bytes(), (receiver_ip.as_str(), *port));
+        }
+        self.last_line = line;
+        self.fps = sent_delta as f64 / secs;
+        true
+    }
+}
+
+fn export_metrics(
+    metrics: &Metrics,
+    counters: &FrameCounters,
+    stats: &StatsReporter,
+    bitrate_kbps: u32,
+    pipeline: &gst::Pipeline,
+    paused: bool,
+) {
+    let captured = counters.captured.load(Ordering::Relaxed);
+    let sent = counters.sent.load(Ordering::Relaxed);
+    metrics.counter(
+        "frames_captured_total",
+        "Frames delivered by the capture.",
+        captured,
+    );
+    metrics.counter("frames_sent_total", "Frames pushed to the encoder.", sent);
+    metrics.counter(
+        "frames_encoded_total",
+        "Frames that left the encoder.",
+        counters.encoded.load(Ordering::Relaxed),
+    );
+    metrics.counter(
+        "frames_dropped_total",
+        "Captured frames that were not sent.",
+        captured.saturating_sub(sent),
+    );
+    metrics.counter(
+        "keyframe_requests_total",
+        "PLI/FIR keyframe requests from the receiver.",
+        counters.keyframe_requests.load(Ordering::Relaxed),
+    );
+    metrics.gauge("fps", "Frames sent per second.", stats.fps);
+    metrics.gauge(
+        "bitrate_kbps",
+        "Encoder target bitrate.",
+        bitrate_kbps as f64,
+    );
+    metrics.gauge(
+        "pipeline_playing",
+        "1 while the output pipeline is playing.",
+        (pipeline.current_state() == gst::State::Playing) as u8 as f64,
+    );
+    metrics.gauge(
+        "paused",
+        "1 while the stream is paused.",
+        paused as u8 as f64,
+    );
+    let last_sent_ms = counters.last_sent_ms.load(Ordering::Relaxed);
+    if last_sent_ms > 0 {
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        metrics.gauge(
+            "last_frame_age_seconds",
+            "Seconds since the last frame was sent.",
+            now_ms.saturating_sub(last_sent_ms) as f64 / 1000.0,
+        );
+    }
+}
+
+// One warning per run is enough when --rt-priority is not permitted; every
+// streaming thread of every (re)started pipeline would fail the same way.
+static THREAD_TUNING_WARNED: AtomicBool = AtomicBool::new(false);
+
+/// `--rt-priority` and `--cpu-affinity` for the streaming threads of `pipeline`.
+/// GStreamer posts a stream-status message from each new streaming thread, so the
+/// bus sync handler runs on the thread that needs tuning.
+fn tune_streaming_threads(pipeline: &gst::Pipeline, cfg: &SendCfg) -> Result<(), String> {
+    if cfg.rt_priority.is_none() && cfg.cpu_affinity.is_none() {
+        return Ok(());
+    }
+    let cpus = cfg
+        .cpu_affinity
+        .as_deref()
+        .map(cpu_list)
+        .transpose()?
+        .unwrap_or_default();
+    let rt_priority = cfg.rt_priority;
+    let bus = pipeline
+        .bus()
+        .ok_or_else(|| "could not get pipeline bus".to_string())?;
+    bus.set_sync_handler(move |_, msg| {
+        if let gst::MessageView::StreamStatus(status) = msg.view()
+            && status.get().0 == gst::StreamStatusType::Enter
+            && let Err(err) = tune_current_thread(rt_priority, &cpus)
+            && !THREAD_TUNING_WARNED.swap(true, Ordering::Relaxed)
+        {
+            eprintln!("WARN: {err}");
+        }
+        gst::BusSyncReply::Pass
+    });
+    Ok(())
+}
+
+fn tune_current_thread(rt_priority: Option<u32>, cpus: &[usize]) -> Result<(), String> {
+    if !cpus.is_empty() {
+        // SAFETY: cpu_set_t is plain data; CPU_SET stays in bounds because cpu_list
+        // rejects cores at or above CPU_SETSIZE. Pid 0 is the calling thread.
+        let rc = unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            for &cpu in cpus {
+                libc::CPU_SET(cpu, &mut set);
+            }
+            libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set)
+        };
+        if rc != 0 {
+            return Err(format!(
+                "--cpu-affinity: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+    }
+    if let Some(priority) = rt_priority {
+        let param = libc::sched_param {
+            sched_priority: priority as i32,
+        };
+        // SAFETY: plain syscall wrapper on the calling thread with a valid param.
+        let rc =
+            unsafe { libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_RR, &param) };
+        if rc != 0 {
+            return Err(format!(
+                "--rt-priority {priority}: {} (needs CAP_SYS_NICE or an rtprio limit in /etc/security/limits.conf)",
+                std::io::Error::from_raw_os_error(rc)
+            ));
+        }
+    }
+    Ok(())
+}
+
+type SampleHandler = Arc<dyn Fn(&AppSink) -> Result<gst::FlowSuccess, gst::FlowError> + Send + Sync>;
+
+/// `node_id` is the portal's PipeWire node; without one the frames come from the
+/// screencopy thread, which already paces them to --fps.
+fn input_pipeline_desc(node_id: Option<&str>, cfg: &SendCfg) -> String {
+    // videoconvert passes a source already in one of the formats through untouched.
+    let formats = if cfg.draw_cursor || cfg.click_highlight || !cfg.masks.is_empty() {
+        "RGBA"
+    } else {
+        crop_formats(cfg)
+    };
+    match node_id {
+        // always-copy=false keeps PipeWire's own DMA-bufs instead of copying into shm.
+        Some(node_id) if cfg.dmabuf => format!(
+            "pipewiresrc path={} do-timestamp=true always-copy=false ! video/x-raw(memory:DMABuf),framerate={}/1 ! appsink name=sink max-buffers=1 drop=true emit-signals=true sync=false",
+            node_id, cfg.fps
+        ),
+        Some(node_id) => format!(
+            "pipewiresrc path={} do-timestamp=true ! videoconvert ! video/x-raw,format={},framerate={}/1 ! appsink name=sink max-buffers=1 drop=true emit-signals=true sync=false",
+            node_id, formats, cfg.fps
+        ),
+        None => format!(
+            "appsrc name=capsrc is-live=true do-timestamp=true format=time ! videoconvert ! video/x-raw,format={} ! appsink name=sink max-buffers=1 drop=true emit-signals=true sync=false",
+            formats
+        ),
+    }
+}
+
+fn start_input_pipeline(
+    capture: &Capture,
+    cfg: &SendCfg,
+    on_sample: &SampleHandler,
+    appsrc: &AppSrc,
+) -> Result<(gst::Pipeline, gst::Bus), String> {
+    let node_id = match capture {
+        Capture::Portal(sc) => Some(sc.node_id.to_string()),
+        Capture::Screencopy(_) => None,
+    };
+    let input_desc = input_pipeline_desc(node_id.as_deref(), cfg);
+    let input_pipeline = gst::parse::launch(&input_desc)
+        .map_err(|e| format!("could not build input pipeline: {e}"))?
+        .downcast::<gst::Pipeline>()
+        .map_err(|_| "input pipeline is not a gst::Pipeline".to_string())?;
+    tune_streaming_threads(&input_pipeline, cfg)?;
+    if let Capture::Screencopy(sc) = capture {
+        let capsrc = input_pipeline
+            .by_name("capsrc")
+            .and_then(|e| e.downcast::<AppSrc>().ok())
+            .ok_or_else(|| "could not find capsrc in input pipeline".to_string())?;
+        sc.attach(capsrc);
+    }
+    let appsink = input_pipeline
+        .by_name("sink")
+        .and_then(|e| e.downcast::<AppSink>().ok())
+        .ok_or_else(|| "could not find appsink in input pipeline".to_string())?;
+
+    let on_sample = Arc::clone(on_sample);
+    let appsrc = appsrc.clone();
+    appsink.set_callbacks(
+        AppSinkCallbacks::builder()
+            .new_sample(move |sink| on_sample(sink))
+            .eos(move |_| {
+                let _ = appsrc.end_of_stream();
+            })
+            .build(),
+    );
+
+    if input_pipeline.set_state(gst::State::Playing).is_err() {
+        let _ = input_pipeline.set_state(gst::State::Null);
+        return Err("could not set input pipeline to Playing".to_string());
+    }
+    let in_bus = match input_pipeline.bus() {
+        Some(v) => v,
+        None => {
+            let _ = input_pipeline.set_state(gst::State::Null);
+            return Err("could not get input bus".to_string());
+        }
+    };
+    Ok((input_pipeline, in_bus))
+}
+
+/// Captures a PipeWire audio node and sends it as Opus over RTP to port + 2. Without
+/// a node name the monitor of the default output (desktop audio) is captured.
+fn audio_pipeline_desc(cfg: &SendCfg) -> String {
+    let source = match &cfg.audio_node {
+        Some(node) => format!("pipewiresrc target-object=\"{node}\""),
+        None => "pipewiresrc stream-properties=\"props,stream.capture.sink=true\"".to_string(),
+    };
+    let srtp_stage = cfg
+        .srtp_key
+        .as_ref()
+        .map(|key| format!("srtpenc key={key} ! "))
+        .unwrap_or_default();
+    format!(
+        "{} do-timestamp=true ! audioconvert ! audioresample ! audio/x-raw,rate=48000,channels=2 ! \
+         queue leaky=downstream max-size-buffers=0 max-size-bytes=0 max-size-time=200000000 ! \
+         opusenc bitrate={} frame-size=10 ! rtpopuspay pt={} ! \
+         {}udpsink host={} port={} sync=false async=false",
+        source,
+        AUDIO_BITRATE_BPS,
+        AUDIO_PAYLOAD_TYPE,
+        srtp_stage,
+        cfg.receiver_ip,
+        cfg.port.saturating_add(AUDIO_PORT_OFFSET)
+    )
+}
+
+fn start_audio_pipeline(cfg: &SendCfg) -> Result<(gst::Pipeline, gst::Bus), String> {
+    let pipeline = gst::parse::launch(&audio_pipeline_desc(cfg))
+        .map_err(|e| format!("could not build audio pipeline: {e}"))?
+        .downcast::<gst::Pipeline>()
+        .map_err(|_| "audio pipeline is not a gst::Pipeline".to_string())?;
+    if pipeline.set_state(gst::State::Playing).is_err() {
+        let _ = pipeline.set_state(gst::State::Null);
+        return Err("could not set audio pipeline to Playing".to_string());
+    }
+    let bus = match pipeline.bus() {
+        Some(v) => v,
+        None => {
+            let _ = pipeline.set_state(gst::State::Null);
+            return Err("could not get audio bus".to_string());
+        }
+    };
+    Ok((pipeline, bus))
+}
+
+/// Points `gltransformation` at the viewport. With `ortho=true` the frame fills the
+/// output, so scaling by source/output size restores 1:1 pixels and the translation
+/// (in output widths/heights) moves the crop origin to the top-left corner. Sources
+/// smaller than the viewport end up centered, matching the CPU path.
+fn set_gl_crop(
+    glcrop: &gst::Element,
+    (src_w, src_h): (usize, usize),
+    (out_w, out_h): (usize, usize),
+    (crop_x, crop_y): (usize, usize),
+) {
+    let axis = |src: usize, out: usize, crop: usize| {
+        let (src, out) = (src as f64, out as f64);
+        let origin = crop as f64 - ((out - src) / 2.0).max(0.0);
+        ((src / out) as f32, ((src - 2.0 * origin - out) / (2.0 * out)) as f32)
+    };
+    let (scale_x, translation_x) = axis(src_w, out_w, crop_x);
+    let (scale_y, translation_y) = axis(src_h, out_h, crop_y);
+    glcrop.set_property("scale-x", scale_x);
+    glcrop.set_property("scale-y", scale_y);
+    glcrop.set_property("translation-x", translation_x);
+    glcrop.set_property("translation-y", translation_y);
+}
+
+fn restart_capture(
+    cfg: &SendCfg,
+    cursor_mode: &str,
+    on_sample: &SampleHandler,
+    appsrc: &AppSrc,
+) -> Result<(Capture, gst::Pipeline, gst::Bus), String> {
+    let capture = start_capture(cfg, cursor_mode)?;
+    match start_input_pipeline(&capture, cfg, on_sample, appsrc) {
+        Ok((pipeline, bus)) => Ok((capture, pipeline, bus)),
+        Err(err) => {
+            capture.close();
+            Err(err)
+        }
+    }
+}
+
+enum ControlCommand {
+    CursorMode(String),
+    Viewport {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    Bitrate(u32),
+    FollowMouse(bool),
+    Pause(bool),
+    Stop,
+    Stats,
+    Status,
+    Keyframe,
+}
+
+type ControlRequest = control::Request<ControlCommand>;
+
+fn control_socket_path() -> PathBuf {
+    control::socket_path("vp-sndr.sock")
+}
+
+fn parse_control_command(line: &str) -> Result<ControlCommand, String> {
+    let mut parts = line.split_whitespace();
+    match parts.next() {
+        Some("cursor-mode") => {
+            let mode = parts
+                .next()
+                .ok_or_else(|| "missing value after cursor-mode".to_string())?;
+            Ok(ControlCommand::CursorMode(parse_cursor_mode(mode)?))
+        }
+        Some("viewport") => {
+            let mut next = |name: &str| {
+                let value = parts
+                    .next()
+                    .ok_or_else(|| format!("missing {name} after viewport"))?;
+                value
+                    .parse::<u32>()
+                    .map_err(|_| format!("invalid viewport {name}: {value}"))
+            };
+            Ok(ControlCommand::Viewport {
+                x: next("x")?,
+                y: next("y")?,
+                width: next("width")?,
+                height: next("height")?,
+            })
+        }
+        Some("bitrate") => {
+            let value = parts
+                .next()
+                .ok_or_else(|| "missing value after bitrate".to_string())?;
+            match value.parse::<u32>() {
+                Ok(kbps) if kbps > 0 => Ok(ControlCommand::Bitrate(kbps)),
+                _ => Err(format!("invalid bitrate: {value}")),
+            }
+        }
+        Some("follow") => match parts.next() {
+            Some("on") => Ok(ControlCommand::FollowMouse(true)),
+            Some("off") => Ok(ControlCommand::FollowMouse(false)),
+            _ => Err("follow takes on or off".to_string()),
+        },
+        Some("pause") => Ok(ControlCommand::Pause(true)),
+        Some("resume") => Ok(ControlCommand::Pause(false)),
+        Some("stop") => Ok(ControlCommand::Stop),
+        Some("stats") => Ok(ControlCommand::Stats),
+        Some("status") => Ok(ControlCommand::Status),
+        Some("keyframe") => Ok(ControlCommand::Keyframe),
+        Some(other) => Err(format!("unknown command: {other}")),
+        None => Err("empty command".to_string()),
+    }
+}
+
+/// JSON-RPC methods of the control socket.
+fn parse_control_method(
+    method: &str,
+    params: &serde_json::Value,
+) -> Option<Result<ControlCommand, String>> {
+    let command = match method {
+        "get-status" => Ok(ControlCommand::Status),
+        "get-stats" => Ok(ControlCommand::Stats),
+        "set-viewport" => (|| {
+            Ok(ControlCommand::Viewport {
+                x: control::param_u32(params, "x")?,
+                y: control::param_u32(params, "y")?,
+                width: control::param_u32(params, "width")?,
+                height: control::param_u32(params, "height")?,
+            })
+        })(),
+        "set-bitrate" => match control::param_u32(params, "kbps") {
+            Ok(0) => Err("params.kbps must be > 0".to_string()),
+            Ok(kbps) => Ok(ControlCommand::Bitrate(kbps)),
+            Err(err) => Err(err),
+        },
+        "set-follow-mouse" => params
+            .get("enabled")
+            .and_then(serde_json::Value::as_bool)
+            .map(ControlCommand::FollowMouse)
+            .ok_or_else(|| "params.enabled must be a boolean".to_string()),
+        "set-cursor-mode" => params
+            .get("mode")
+            .and_then(serde_json::Value::as_str)
+            .ok_or_else(|| "params.mode must be a string".to_string())
+            .and_then(parse_cursor_mode)
+            .map(ControlCommand::CursorMode),
+        "request-keyframe" => Ok(ControlCommand::Keyframe),
+        "pause" => Ok(ControlCommand::Pause(true)),
+        "resume" => Ok(ControlCommand::Pause(false)),
+        "quit" => Ok(ControlCommand::Stop),
+        _ => return None,
+    };
+    Some(command)
+}
+
+struct WebRtcPeer {
+    queue: gst::Element,
+    webrtc: gst::Element,
+    tee_pad: gst::Pad,
+    outgoing: mpsc::Sender<String>,
+}
+
+fn handle_signal_event(
+    pipeline: &gst::Pipeline,
+    peers: &mut HashMap<u64, WebRtcPeer>,
+    event: SignalEvent,
+) -> Result<(), String> {
+    match event {
+        SignalEvent::Connected { peer, outgoing } => {
+            let added = add_webrtc_peer(pipeline, outgoing)?;
+            println!("WebRTC viewer {peer} connected ({} watching)", peers.len() + 1);
+            peers.insert(peer, added);
+            Ok(())
+        }
+        SignalEvent::Offer { peer, sdp } => {
+            let p = peers
+                .get(&peer)
+                .ok_or_else(|| format!("offer from unknown viewer {peer}"))?;
+            answer_webrtc_offer(p, &sdp)
+        }
+        SignalEvent::Ice {
+            peer,
+            mline,
+            candidate,
+        } => {
+            let p = peers
+                .get(&peer)
+                .ok_or_else(|| format!("ICE candidate from unknown viewer {peer}"))?;
+            p.webrtc
+                .emit_by_name::<()>("add-ice-candidate", &[&mline, &candidate]);
+            Ok(())
+        }
+        SignalEvent::Disconnected { peer } => {
+            if let Some(p) = peers.remove(&peer) {
+                remove_webrtc_peer(pipeline, p);
+                println!("WebRTC viewer {peer} left ({} watching)", peers.len());
+            }
+            Ok(())
+        }
+    }
+}
+
+fn add_webrtc_peer(
+    pipeline: &gst::Pipeline,
+    outgoing: mpsc::Sender<String>,
+) -> Result<WebRtcPeer, String> {
+    let tee = pipeline
+        .by_name("webrtc_tee")
+        .ok_or_else(|| "could not find webrtc_tee in output pipeline".to_string())?;
+    let queue = gst::ElementFactory::make("queue")
+        .property_from_str("leaky", "downstream")
+        .property("max-size-buffers", DEFAULT_QUEUE_BUFFERS)
+        .property("max-size-bytes", 0u32)
+        .property("max-size-time", 0u64)
+        .build()
+        .map_err(|e| format!("create queue: {e}"))?;
+    let webrtc = gst::ElementFactory::make("webrtcbin")
+        .property_from_str("bundle-policy", "max-bundle")
+        .build()
+        .map_err(|e| format!("create webrtcbin (is gst-plugins-bad installed?): {e}"))?;
+
+    let ice_out = outgoing.clone();
+    webrtc.connect("on-ice-candidate", false, move |values| {
+        let mline = values[1].get::<u32>().ok()?;
+        let candidate = values[2].get::<String>().ok()?;
+        let _ = ice_out.send(signaling::ice_message(mline, &candidate));
+        None
+    });
+
+    pipeline
+        .add_many([&queue, &webrtc])
+        .map_err(|e| format!("add webrtc branch: {e}"))?;
+    queue
+        .link(&webrtc)
+        .map_err(|e| format!("link queue to webrtcbin: {e}"))?;
+    webrtc
+        .sync_state_with_parent()
+        .map_err(|e| format!("start webrtcbin: {e}"))?;
+    queue
+        .sync_state_with_parent()
+        .map_err(|e| format!("start webrtc queue: {e}"))?;
+
+    let tee_pad = tee
+        .request_pad_simple("src_%u")
+        .ok_or_else(|| "could not get a tee pad for the viewer".to_string())?;
+    let queue_sink = queue
+        .static_pad("sink")
+        .ok_or_else(|| "webrtc queue has no sink pad".to_string())?;
+    tee_pad
+        .link(&queue_sink)
+        .map_err(|e| format!("link tee to webrtc queue: {e:?}"))?;
+
+    // The new viewer can only start decoding at a keyframe.
+    let force_key = gst_video::UpstreamForceKeyUnitEvent::builder()
+        .all_headers(true)
+        .build();
+    queue_sink.push_event(force_key);
+
+    Ok(WebRtcPeer {
+        queue,
+        webrtc,
+        tee_pad,
+        outgoing,
+    })
+}
+
+fn answer_webrtc_offer(peer: &WebRtcPeer, sdp: &str) -> Result<(), String> {
+    let message = gst_sdp::SDPMessage::parse_buffer(sdp.as_bytes())
+        .map_err(|_| "could not parse viewer SDP offer".to_string())?;
+    let offer =
+        gst_webrtc::WebRTCSessionDescription::new(gst_webrtc::WebRTCSDPType::Offer, message);
+    peer.webrtc
+        .emit_by_name::<()>("set-remote-description", &[&offer, &None::<gst::Promise>]);
+
+    let webrtc = peer.webrtc.clone();
+    let outgoing = peer.outgoing.clone();
+    let promise = gst::Promise::with_change_func(move |reply| {
+        let answer = match reply {
+            Ok(Some(reply)) => reply
+                .get::<gst_webrtc::WebRTCSessionDescription>("answer")
+                .ok(),
+            _ => None,
+        };
+        let Some(answer) = answer else {
+            eprintln!("WARN: webrtc: could not create an answer for the viewer");
+            return;
+        };
+        webrtc.emit_by_name::<()>("set-local-description", &[&answer, &None::<gst::Promise>]);
+        match answer.sdp().as_text() {
+            Ok(text) => {
+                let _ = outgoing.send(signaling::answer_message(&text));
+            }
+            Err(err) => eprintln!("WARN: webrtc: could not serialize answer: {err}"),
+        }
+    });
+    peer.webrtc
+        .emit_by_name::<()>("create-answer", &[&None::<gst::Structure>, &promise]);
+    Ok(())
+}
+
+fn remove_webrtc_peer(pipeline: &gst::Pipeline, peer: WebRtcPeer) {
+    if let Some(queue_sink) = peer.queue.static_pad("sink") {
+        let _ = peer.tee_pad.unlink(&queue_sink);
+    }
+    if let Some(tee) = pipeline.by_name("webrtc_tee") {
+        tee.release_request_pad(&peer.tee_pad);
+    }
+    let _ = peer.webrtc.set_state(gst::State::Null);
+    let _ = peer.queue.set_state(gst::State::Null);
+    let _ = pipeline.remove_many([&peer.queue, &peer.webrtc]);
+}
+
+fn start_control_listener(tx: mpsc::Sender<ControlRequest>) -> Result<(), String> {
+    let path = control_socket_path();
+    control::listen(
+        &path,
+        control::Protocol {
+            name: "sender",
+            parse_line: parse_control_command,
+            parse_method: parse_control_method,
+            reply_timeout: Duration::from_secs(CONTROL_REPLY_TIMEOUT_SECS),
+        },
+        tx,
+    )?;
+    println!("Control socket: {}", path.display());
+    Ok(())
+}
+
+/// Hands a command to the streaming loop and waits for its answer.
+fn forward_control(tx: &mpsc::Sender<ControlRequest>, command: ControlCommand) -> control::Reply {
+    control::forward(
+        tx,
+        command,
+        "sender",
+        Duration::from_secs(CONTROL_REPLY_TIMEOUT_SECS),
+    )
+}
+
+/// `org.vplink.Sender1` on the session bus; every call goes through the same queue
+/// as the control socket.
+struct SenderBus {
+    tx: Mutex<mpsc::Sender<ControlRequest>>,
+}
+
+impl SenderBus {
+    fn call(&self, command: ControlCommand) -> zbus::fdo::Result<String> {
+        let tx = self
+            .tx
+            .lock()
+            .map_err(|_| zbus::fdo::Error::Failed("control queue is poisoned".to_string()))?
+            .clone();
+        match forward_control(&tx, command) {
+            Ok(serde_json::Value::String(msg)) => Ok(msg),
+            Ok(other) => Ok(other.to_string()),
+            Err(err) => Err(zbus::fdo::Error::Failed(err)),
+        }
+    }
+}
+
+#[zbus::interface(name = "org.vplink.Sender1")]
+impl SenderBus {
+    /// Moves the viewport; the size must stay the same while streaming.
+    fn set_viewport(&self, x: u32, y: u32, width: u32, height: u32) -> zbus::fdo::Result<String> {
+        self.call(ControlCommand::Viewport {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+
+    fn set_bitrate(&self, kbps: u32) -> zbus::fdo::Result<String> {
+        if kbps == 0 {
+            return Err(zbus::fdo::Error::InvalidArgs(
+                "kbps must be > 0".to_string(),
+            ));
+        }
+        self.call(ControlCommand::Bitrate(kbps))
+    }
+
+    fn set_follow_mouse(&self, enabled: bool) -> zbus::fdo::Result<String> {
+        self.call(ControlCommand::FollowMouse(enabled))
+    }
+
+    fn pause(&self) -> zbus::fdo::Result<String> {
+        self.call(ControlCommand::Pause(true))
+    }
+
+    fn resume(&self) -> zbus::fdo::Result<String> {
+        self.call(ControlCommand::Pause(false))
+    }
+
+    fn stop(&self) -> zbus::fdo::Result<String> {
+        self.call(ControlCommand::Stop)
+    }
+
+    /// The fields of the last `vp-stats` line, read fresh on every Get. No
+    /// PropertiesChanged is sent for it, so clients must not cache it.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn stats(&self) -> zbus::fdo::Result<HashMap<String, String>> {
+        let line = self.call(ControlCommand::Stats)?;
+        Ok(line
+            .split_whitespace()
+            .filter_map(|field| field.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect())
+    }
+}
+
+fn start_dbus_service(
+    tx: mpsc::Sender<ControlRequest>,
+) -> Result<zbus::blocking::Connection, String> {
+    let conn = zbus::blocking::connection::Builder::session()
+        .and_then(|b| b.name(DBUS_NAME))
+        .and_then(|b| b.serve_at(DBUS_PATH, SenderBus { tx: Mutex::new(tx) }))
+        .and_then(|b| b.build())
+        .map_err(|e| format!("cannot own {DBUS_NAME} on the session bus: {e}"))?;
+    println!("D-Bus: {DBUS_NAME} at {DBUS_PATH}");
+    Ok(conn)
+}
+
+fn send_control_command(line: &str) -> Result<String, String> {
+    control::send_line(&control_socket_path(), "sender", line)
+}
+
+/// Where frames come from: a portal PipeWire stream or a direct screencopy session.
+enum Capture {
+    Portal(PortalScreenCast),
+    Screencopy(screencopy::ScreencopyCapture),
+}
+
+impl Capture {
+    fn cursor_mode_name(&self) -> String {
+        match self {
+            Capture::Portal(sc) => format!("{:?}", sc.cursor_mode),
+            Capture::Screencopy(sc) if sc.paint_cursor => "Embedded".to_string(),
+            Capture::Screencopy(_) => "Hidden".to_string(),
+        }
+    }
+
+    fn close(self) {
+        match self {
+            Capture::Portal(sc) => sc.close(),
+            Capture::Screencopy(sc) => sc.close(),
+        }
+    }
+}
+
+fn start_capture(cfg: &SendCfg, cursor_mode: &str) -> Result<Capture, String> {
+    let output = cfg.output.as_deref();
+    if cfg.backend == "screencopy" {
+        // No cursor metadata without the portal: the cursor is either painted into
+        // the frames or left out, and follow mode tracks it separately.
+        let sc = screencopy::start_screencopy_capture(output, cursor_mode == "embedded", cfg.fps)
+            .map_err(|e| format!("screencopy capture failed: {e}"))?;
+        println!("Screencopy capturing output {}", sc.output_name);
+        return Ok(Capture::Screencopy(sc));
+    }
+    let sc = start_portal_screencast(cursor_mode, &cfg.source, output)
+        .map_err(|e| format!("portal ScreenCast handshake failed: {e}"))?;
+    println!("Portal stream node id: {}", sc.node_id);
+    Ok(Capture::Portal(sc))
+}
+
+struct PortalScreenCast {
+    node_id: u32,
+    cursor_mode: CursorMode,
+    rt: tokio::runtime::Runtime,
+    session: Session<'static, Screencast<'static>>,
+}
+
+impl PortalScreenCast {
+    fn close(self) {
+        let PortalScreenCast { rt, session, .. } = self;
+        if let Err(err) = rt.block_on(session.close()) {
+            eprintln!("WARN: could not close portal session: {err}");
+        }
+    }
+}
+
+fn start_portal_screencast(
+    requested_cursor_mode: &str,
+    source: &str,
+    output: Option<&str>,
+) -> Result<PortalScreenCast, String> {
+    // Resolve --output before the portal so a typo fails without a picker popping up.
+    let target = match output {
+        Some(wanted) => Some(find_wayland_output(&list_wayland_outputs()?, wanted)?),
+        None => None,
+    };
+    let source_type = if source == "window" {
+        SourceType::Window
+    } else {
+        SourceType::Monitor
+    };
+    let token_path = match restore_token_path(source, output) {
+        Ok(p) => Some(p),
+        Err(err) => {
+            eprintln!("WARN: {err}; the source picker will appear every time");
+            None
+        }
+    };
+    let restore_token = token_path.as_deref().and_then(load_restore_token);
+    if restore_token.is_some() {
+        println!("Portal: restoring the previous source selection");
+    }
+    println!("Portal: CreateSession...");
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| format!("failed to create tokio runtime: {e}"))?;
+    let (node_id, cursor_mode, session) = rt.block_on(async {
+        let portal = Screencast::new()
+            .await
+            .map_err(|e| format!("failed to connect to ScreenCast portal: {e}"))?;
+        let session =
+            tokio::time::timeout(Duration::from_secs(PORTAL_TIMEOUT_SECS), portal.create_session())
+                .await
+                .map_err(|_| "CreateSession timed out".to_string())?
+                .map_err(|e| format!("CreateSession failed: {e}"))?;
+        let available_cursor_modes = portal
+            .available_cursor_modes()
+            .await
+            .map_err(|e| format!("Failed to query available cursor modes: {e}"))?;
+        let requested = match requested_cursor_mode {
+            "embedded" => Some(CursorMode::Embedded),
+            "hidden" => Some(CursorMode::Hidden),
+            "metadata" => Some(CursorMode::Metadata),
+            _ => None,
+        };
+        let cursor_mode = match requested {
+            Some(mode) if available_cursor_modes.contains(mode) => mode,
+            _ => {
+                if requested.is_some() {
+                    eprintln!(
+                        "WARN: portal does not offer cursor mode '{requested_cursor_mode}', picking automatically"
                     );
-                    done = true;
                 }
-                gst::MessageView::Eos(..) => done = true,
-                _ => {}
+                if available_cursor_modes.contains(CursorMode::Metadata) {
+                    CursorMode::Metadata
+                } else if available_cursor_modes.contains(CursorMode::Embedded) {
+                    CursorMode::Embedded
+                } else {
+                    CursorMode::Hidden
+                }
             }
+        };
+        println!("Portal: SelectSources ({source}, cursor mode {cursor_mode:?})...");
+        tokio::time::timeout(
+            Duration::from_secs(PORTAL_TIMEOUT_SECS),
+            portal.select_sources(
+                &session,
+                cursor_mode,
+                source_type.into(),
+                // With --output, let the picker offer every monitor and choose below.
+                target.is_some(),
+                restore_token.as_deref(),
+                PersistMode::ExplicitlyRevoked,
+            ),
+        )
+        .await
+        .map_err(|_| "SelectSources timed out".to_string())?
+        .map_err(|e| format!("SelectSources failed: {e}"))?;
+        println!("Portal: Start (watch for COSMIC picker popup)...");
+        let request =
+            tokio::time::timeout(Duration::from_secs(PORTAL_TIMEOUT_SECS), portal.start(&session, None))
+                .await
+                .map_err(|_| "Start timed out".to_string())?
+                .map_err(|e| format!("Start failed: {e}"))?;
+        let response = request
+            .response()
+            .map_err(|e| format!("Start response failed: {e}"))?;
+        let streams = response.streams();
+        let stream = match &target {
+            Some(target) => select_output_stream(streams, target)?,
+            None => streams
+                .first()
+                .ok_or_else(|| "Start returned no streams".to_string())?,
+        };
+        // Tokens are single-use: the portal hands out a fresh one on every Start.
+        let saved = match (&token_path, response.restore_token()) {
+            (Some(path), Some(token)) => save_restore_token(path, token),
+            _ => Ok(()),
+        };
+        if let Err(err) = saved {
+            eprintln!("WARN: could not save portal restore token: {err}");
         }
-        if done {
-            break;
+        Ok::<_, String>((stream.pipe_wire_node_id(), cursor_mode, session))
+    })?;
+    Ok(PortalScreenCast {
+        node_id,
+        cursor_mode,
+        rt,
+        session,
+    })
+}
+
+#[derive(Clone)]
+struct WaylandOutput {
+    name: String,
+    description: String,
+    position: (i32, i32),
+    size: (i32, i32),
+}
+
+struct OutputListApp {
+    registry_state: RegistryState,
+    output_state: OutputState,
+}
+
+impl ProvidesRegistryState for OutputListApp {
+    fn registry(&mut self) -> &mut RegistryState {
+        &mut self.registry_state
+    }
+    sctk::registry_handlers!(OutputState);
+}
+
+impl OutputHandler for OutputListApp {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.output_state
+    }
+    fn new_output(&mut self, _: &WlConnection, _: &WlQueueHandle<Self>, _: wl_output::WlOutput) {}
+    fn update_output(
+        &mut self,
+        _: &WlConnection,
+        _: &WlQueueHandle<Self>,
+        _: wl_output::WlOutput,
+    ) {
+    }
+    fn output_destroyed(
+        &mut self,
+        _: &WlConnection,
+        _: &WlQueueHandle<Self>,
+        _: wl_output::WlOutput,
+    ) {
+    }
+}
+
+sctk::delegate_registry!(OutputListApp);
+sctk::delegate_output!(OutputListApp);
+
+fn list_wayland_outputs() -> Result<Vec<WaylandOutput>, String> {
+    let conn = WlConnection::connect_to_env()
+        .map_err(|e| format!("wayland connect failed for output list: {e}"))?;
+    let (globals, mut event_queue) =
+        wl_registry_queue_init(&conn).map_err(|e| format!("wayland registry init failed: {e}"))?;
+    let qh = event_queue.handle();
+    let mut app = OutputListApp {
+        registry_state: RegistryState::new(&globals),
+        output_state: OutputState::new(&globals, &qh),
+    };
+    // The first roundtrip binds the outputs, the second collects their xdg-output info.
+    for _ in 0..2 {
+        event_queue
+            .roundtrip(&mut app)
+            .map_err(|e| format!("wayland roundtrip failed: {e}"))?;
+    }
+    Ok(app
+        .output_state
+        .outputs()
+        .filter_map(|o| app.output_state.info(&o))
+        .map(|info| WaylandOutput {
+            name: info.name.unwrap_or_default(),
+            description: info.description.unwrap_or_default(),
+            position: info.logical_position.unwrap_or(info.location),
+            size: info.logical_size.unwrap_or_default(),
+        })
+        .collect())
+}
+
+/// Matches the connector name exactly (case-insensitive), then falls back to a
+/// substring of the description so `--output "Dell"` works too.
+fn find_wayland_output(outputs: &[WaylandOutput], wanted: &str) -> Result<WaylandOutput, String> {
+    let wanted_lc = wanted.to_ascii_lowercase();
+    let by_name = outputs
+        .iter()
+        .position(|o| o.name.eq_ignore_ascii_case(wanted));
+    let by_description = || {
+        let hits: Vec<usize> = outputs
+            .iter()
+            .enumerate()
+            .filter(|(_, o)| o.description.to_ascii_lowercase().contains(&wanted_lc))
+            .map(|(i, _)| i)
+            .collect();
+        if hits.len() == 1 { Some(hits[0]) } else { None }
+    };
+    match by_name.or_else(by_description) {
+        Some(i) => {
+            let o = &outputs[i];
+            println!(
+                "Output {} ({}) at {},{} size {}x{}",
+                o.name, o.description, o.position.0, o.position.1, o.size.0, o.size.1
+            );
+            Ok(o.clone())
         }
+        None => Err(format!(
+            "no single output matches '{wanted}'; known outputs: {}",
+            describe_wayland_outputs(outputs)
+        )),
     }
+}
 
-    let _ = input_pipeline.set_state(gst::State::Null);
-    let _ = output_pipeline.set_state(gst::State::Null);
-    if done {
-        ExitCode::SUCCESS
-    } else {
-        eprintln!("FAIL: sender timed out");
-        ExitCode::from(1)
+/// Picks the bound `wl_output` for `--output` (or the first one) on a connection that
+/// already has its xdg-output info.
+fn select_wl_output(
+    output_state: &OutputState,
+    wanted: Option<&str>,
+) -> Result<(wl_output::WlOutput, String), String> {
+    let outputs: Vec<(wl_output::WlOutput, WaylandOutput)> = output_state
+        .outputs()
+        .filter_map(|o| {
+            let info = output_state.info(&o)?;
+            Some((
+                o,
+                WaylandOutput {
+                    name: info.name.unwrap_or_default(),
+                    description: info.description.unwrap_or_default(),
+                    position: info.logical_position.unwrap_or(info.location),
+                    size: info.logical_size.unwrap_or_default(),
+                },
+            ))
+        })
+        .collect();
+    match wanted {
+        Some(wanted) => {
+            let infos: Vec<WaylandOutput> = outputs.iter().map(|(_, info)| info.clone()).collect();
+            let found = find_wayland_output(&infos, wanted)?;
+            outputs
+                .into_iter()
+                .find(|(_, info)| info.name == found.name)
+                .map(|(o, info)| (o, info.name))
+                .ok_or_else(|| format!("output {} disappeared", found.name))
+        }
+        None => outputs
+            .into_iter()
+            .next()
+            .map(|(o, info)| (o, info.name))
+            .ok_or_else(|| "no wl_output available".to_string()),
     }
 }
 
-struct PortalScreenCast {
-    node_id: u32,
+fn describe_wayland_outputs(outputs: &[WaylandOutput]) -> String {
+    if outputs.is_empty() {
+        return "none".to_string();
+    }
+    outputs
+        .iter()
+        .map(|o| format!("{} ({})", o.name, o.description))
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
-fn start_portal_screencast() -> Result<PortalScreenCast, String> {
-    println!("Portal: CreateSession...");
-    let rt = tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()
-        .map_err(|e| format!("failed to create tokio runtime: {e}"))?;
-    rt.block_on(async {
-        let portal = Screencast::new()
-            .await
-            .map_err(|e| format!("failed to connect to ScreenCast portal: {e}"))?;
-        let session =
-            tokio::time::timeout(Duration::from_secs(PORTAL_TIMEOUT_SECS), portal.create_session())
-                .await
-                .map_err(|_| "CreateSession timed out".to_string())?
-                .map_err(|e| format!("CreateSession failed: {e}"))?;
-        let available_cursor_modes = portal
-            .available_cursor_modes()
-            .await
-            .map_err(|e| format!("Failed to query available cursor modes: {e}"))?;
-        let cursor_mode = if available_cursor_modes.contains(CursorMode::Metadata) {
-            CursorMode::Metadata
-        } else if available_cursor_modes.contains(CursorMode::Embedded) {
-            CursorMode::Embedded
-        } else {
-            CursorMode::Hidden
-        };
-        println!("Portal: SelectSources...");
-        tokio::time::timeout(
-            Duration::from_secs(PORTAL_TIMEOUT_SECS),
-            portal.select_sources(
-                &session,
-                cursor_mode,
-                SourceType::Monitor.into(),
-                false,
-                None,
-                PersistMode::DoNot,
-            ),
-        )
-        .await
-        .map_err(|_| "SelectSources timed out".to_string())?
-        .map_err(|e| format!("SelectSources failed: {e}"))?;
-        println!("Portal: Start (watch for COSMIC picker popup)...");
-        let request =
-            tokio::time::timeout(Duration::from_secs(PORTAL_TIMEOUT_SECS), portal.start(&session, None))
-                .await
-                .map_err(|_| "Start timed out".to_string())?
-                .map_err(|e| format!("Start failed: {e}"))?;
-        let response = request
-            .response()
-            .map_err(|e| format!("Start response failed: {e}"))?;
-        let streams = response.streams();
-        let stream = streams
-            .first()
-            .ok_or_else(|| "Start returned no streams".to_string())?;
-        Ok(PortalScreenCast {
-            node_id: stream.pipe_wire_node_id(),
+/// Picks the portal stream covering `target`. Streams carry their logical position
+/// and size but no connector name, so geometry is the only link back to the output.
+fn select_output_stream<'a>(
+    streams: &'a [Stream],
+    target: &WaylandOutput,
+) -> Result<&'a Stream, String> {
+    if let Some(stream) = streams
+        .iter()
+        .find(|s| s.position() == Some(target.position) && s.size() == Some(target.size))
+    {
+        return Ok(stream);
+    }
+    if let Some(stream) = streams
+        .iter()
+        .find(|s| s.position() == Some(target.position))
+    {
+        return Ok(stream);
+    }
+    if streams.len() == 1 && streams[0].position().is_none() {
+        eprintln!(
+            "WARN: portal did not report stream geometry, using the only selected monitor for {}",
+            target.name
+        );
+        return Ok(&streams[0]);
+    }
+    let selected = streams
+        .iter()
+        .map(|s| match (s.position(), s.size()) {
+            (Some((x, y)), Some((w, h))) => format!("{w}x{h} at {x},{y}"),
+            _ => "unknown geometry".to_string(),
         })
-    })
+        .collect::<Vec<_>>()
+        .join(", ");
+    Err(format!(
+        "none of the selected monitors is {} ({}x{} at {},{}); selected: {}",
+        target.name, target.size.0, target.size.1, target.position.0, target.position.1,
+        if selected.is_empty() { "none" } else { &selected }
+    ))
 }
 
 #[derive(Default)]
@@ -1235,12 +7251,106 @@ impl ScreencopyHandler for CosmicCursorApp {
     }
 }
 
-fn start_cosmic_cursor_tracker() -> Result<Arc<Mutex<Option<(f64, f64)>>>, String> {
+/// Binds the `--follow-shortcut` key through the GlobalShortcuts portal and flips
+/// `paused` on every press. The portal may ask the user to confirm or pick another
+/// key, so this runs in the background and only warns if it fails.
+fn start_follow_shortcut(trigger: String, paused: Arc<AtomicBool>) {
+    thread::spawn(move || {
+        let rt = match tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(rt) => rt,
+            Err(err) => {
+                eprintln!("WARN: --follow-shortcut: failed to create tokio runtime: {err}");
+                return;
+            }
+        };
+        let result = rt.block_on(async {
+            let portal = GlobalShortcuts::new()
+                .await
+                .map_err(|e| format!("failed to connect to GlobalShortcuts portal: {e}"))?;
+            let session = portal
+                .create_session()
+                .await
+                .map_err(|e| format!("CreateSession failed: {e}"))?;
+            let shortcut =
+                NewShortcut::new(FOLLOW_SHORTCUT_ID, "Pause or resume following the mouse")
+                    .preferred_trigger(trigger.as_str());
+            let bound = portal
+                .bind_shortcuts(&session, &[shortcut], None)
+                .await
+                .and_then(|request| request.response())
+                .map_err(|e| format!("BindShortcuts failed: {e}"))?;
+            match bound
+                .shortcuts()
+                .iter()
+                .find(|s| s.id() == FOLLOW_SHORTCUT_ID)
+            {
+                Some(s) => println!("Follow toggle shortcut bound: {}", s.trigger_description()),
+                None => return Err("the shortcut was not bound".to_string()),
+            }
+            let mut activated = portal
+                .receive_activated()
+                .await
+                .map_err(|e| format!("cannot listen for shortcuts: {e}"))?;
+            while let Some(event) = activated.next().await {
+                if event.shortcut_id() != FOLLOW_SHORTCUT_ID {
+                    continue;
+                }
+                if paused.fetch_xor(true, Ordering::Relaxed) {
+                    println!("Mouse follow resumed.");
+                } else {
+                    println!("Mouse follow paused; viewport frozen.");
+                }
+            }
+            // Closing the session unbinds the key.
+            session
+                .close()
+                .await
+                .map_err(|e| format!("closing the shortcut session failed: {e}"))?;
+            Ok::<_, String>(())
+        });
+        if let Err(err) = result {
+            eprintln!("WARN: --follow-shortcut: {err}");
+        }
+    });
+}
+
+/// Latest absolute cursor position from the COSMIC cursor session.
+type CursorPosition = Arc<Mutex<Option<(f64, f64)>>>;
+/// Accelerated libinput motion accumulated since the last frame consumed it.
+type MouseDeltas = Arc<Mutex<(f64, f64)>>;
+
+/// Starts the desktop-wide trackers `--cursor-backend` asks for. `auto` tries both and
+/// prefers the COSMIC cursor session per frame; an explicit backend must start.
+fn start_cursor_trackers(
+    backend: &str,
+    output: Option<&str>,
+    delta_scale: &BTreeMap<String, f64>,
+) -> Result<(Option<CursorPosition>, Option<MouseDeltas>), String> {
+    match backend {
+        "wayland" => Ok((Some(start_cosmic_cursor_tracker(output)?), None)),
+        "libinput" => Ok((None, Some(start_mouse_delta_tracker(delta_scale.clone())?))),
+        _ => Ok((
+            start_cosmic_cursor_tracker(output).ok(),
+            start_mouse_delta_tracker(delta_scale.clone()).ok(),
+        )),
+    }
+}
+
+/// Follows the pointer through an `ext-image-copy-capture` cursor session, which
+/// reports the position the compositor draws (acceleration applied) relative to the
+/// captured output.
+fn start_cosmic_cursor_tracker(output: Option<&str>) -> Result<CursorPosition, String> {
     let cursor_xy = Arc::new(Mutex::new(None));
     let cursor_xy_thread = Arc::clone(&cursor_xy);
+    let output = output.map(str::to_string);
     let (ready_tx, ready_rx) = mpsc::channel::<Result<(), String>>();
     thread::spawn(move || {
-        if let Err(err) = run_cosmic_cursor_tracker_loop(cursor_xy_thread, ready_tx.clone()) {
+        if let Err(err) =
+            run_cosmic_cursor_tracker_loop(output.as_deref(), cursor_xy_thread, ready_tx.clone())
+        {
             let _ = ready_tx.send(Err(err));
         }
     });
@@ -1252,6 +7362,7 @@ fn start_cosmic_cursor_tracker() -> Result<Arc<Mutex<Option<(f64, f64)>>>, Strin
 }
 
 fn run_cosmic_cursor_tracker_loop(
+    wanted: Option<&str>,
     cursor_xy: Arc<Mutex<Option<(f64, f64)>>>,
     ready_tx: mpsc::Sender<Result<(), String>>,
 ) -> Result<(), String> {
@@ -1270,15 +7381,15 @@ fn run_cosmic_cursor_tracker_loop(
         _cursor_session: None,
         cursor_xy,
     };
-    event_queue
-        .roundtrip(&mut app)
-        .map_err(|e| format!("initial wayland roundtrip failed: {e}"))?;
+    // The first roundtrip binds the outputs, the second collects their xdg-output info.
+    for _ in 0..2 {
+        event_queue
+            .roundtrip(&mut app)
+            .map_err(|e| format!("initial wayland roundtrip failed: {e}"))?;
+    }
 
-    let output = app
-        .output_state
-        .outputs()
-        .next()
-        .ok_or_else(|| "no wl_output available for cursor tracker".to_string())?;
+    let (output, _) =
+        select_wl_output(&app.output_state, wanted).map_err(|e| format!("cursor tracker: {e}"))?;
     let wait_deadline = Instant::now() + Duration::from_secs(3);
     while app.pointer.is_none() && Instant::now() < wait_deadline {
         event_queue
@@ -1316,52 +7427,382 @@ sctk::delegate_pointer!(CosmicCursorApp);
 delegate_screencopy!(CosmicCursorApp);
 delegate_noop!(CosmicCursorApp: ignore wl_buffer::WlBuffer);
 
-fn start_mouse_delta_tracker() -> Result<Arc<Mutex<(f64, f64)>>, String> {
-    let mut devices: VecDeque<Device> = VecDeque::new();
-    let entries = std::fs::read_dir("/dev/input")
-        .map_err(|e| format!("failed to scan /dev/input: {e}"))?;
-    for entry in entries.flatten() {
-        let path = entry.path();
-        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
-        if !name.starts_with("event") {
-            continue;
+/// Opens the input devices libinput asks for; the sender needs read access to
+/// `/dev/input` (the `input` group) just like the compositor's seat does.
+struct LibinputOpener;
+
+impl LibinputInterface for LibinputOpener {
+    fn open_restricted(&mut self, path: &Path, flags: i32) -> Result<OwnedFd, i32> {
+        let access = flags & libc::O_ACCMODE;
+        fs::OpenOptions::new()
+            .custom_flags(flags)
+            .read(access == libc::O_RDONLY || access == libc::O_RDWR)
+            .write(access == libc::O_WRONLY || access == libc::O_RDWR)
+            .open(path)
+            .map(OwnedFd::from)
+            .map_err(|e| e.raw_os_error().unwrap_or(libc::EIO))
+    }
+
+    fn close_restricted(&mut self, fd: OwnedFd) {
+        drop(fs::File::from(fd));
+    }
+}
+
+/// Sums pointer motion from a private libinput context on seat0. libinput applies the
+/// same acceleration profile the compositor uses by default, so mice, touchpads and
+/// trackpoints move the tracked cursor about as far as the real one.
+/// Sums pointer motion, scaled per device by the factors `calibrate` measured
+/// (1.0 for devices it has not seen).
+fn start_mouse_delta_tracker(scales: BTreeMap<String, f64>) -> Result<MouseDeltas, String> {
+    let deltas = Arc::new(Mutex::new((0.0f64, 0.0f64)));
+    let deltas_thread = Arc::clone(&deltas);
+    spawn_libinput_thread("mouse tracker", move |event| {
+        if let LibinputEvent::Pointer(LibinputPointerEvent::Motion(motion)) = event
+            && let Ok(mut d) = deltas_thread.lock()
+        {
+            let scale = scales.get(motion.device().name()).copied().unwrap_or(1.0);
+            d.0 += motion.dx() * scale;
+            d.1 += motion.dy() * scale;
+        }
+    })?;
+    Ok(deltas)
+}
+
+/// Raw libinput motion per device name, for `calibrate`.
+type DeviceDeltas = Arc<Mutex<BTreeMap<String, (f64, f64)>>>;
+
+fn start_device_delta_tracker() -> Result<DeviceDeltas, String> {
+    let deltas = Arc::new(Mutex::new(BTreeMap::new()));
+    let deltas_thread = Arc::clone(&deltas);
+    spawn_libinput_thread("calibration tracker", move |event| {
+        if let LibinputEvent::Pointer(LibinputPointerEvent::Motion(motion)) = event
+            && let Ok(mut map) = deltas_thread.lock()
+        {
+            let d = map
+                .entry(motion.device().name().to_string())
+                .or_insert((0.0, 0.0));
+            d.0 += motion.dx();
+            d.1 += motion.dy();
+        }
+    })?;
+    Ok(deltas)
+}
+
+/// `vp-sndr calibrate`: while the user moves the pointer around, compares how far
+/// each libinput device says it moved with how far the COSMIC cursor session saw
+/// the cursor go, and stores the ratio as that device's `delta_scale`.
+fn run_calibrate(output: Option<&str>, duration_secs: u64) -> ExitCode {
+    let cursor = match start_cosmic_cursor_tracker(output) {
+        Ok(v) => v,
+        Err(err) => {
+            eprintln!("FAIL: calibrate needs the COSMIC cursor session: {err}");
+            return ExitCode::from(1);
+        }
+    };
+    let device_deltas = match start_device_delta_tracker() {
+        Ok(v) => v,
+        Err(err) => {
+            eprintln!("FAIL: {err}");
+            return ExitCode::from(1);
         }
-        if let Ok(dev) = Device::open(&path) {
-            let has_relative = dev.supported_events().contains(EventType::RELATIVE);
-            if has_relative {
-                let _ = dev.set_nonblocking(true);
-                devices.push_back(dev);
+    };
+    println!(
+        "Move the pointer around the screen for {duration_secs}s, away from the edges. \
+         Use each mouse or touchpad you want calibrated."
+    );
+    // Distance the cursor moved and the device reported, per device. Only intervals
+    // where a single device moved and the cursor was not pinned at an edge count.
+    let mut travel: BTreeMap<String, (f64, f64)> = BTreeMap::new();
+    let mut last_pos = None;
+    let deadline = Instant::now() + Duration::from_secs(duration_secs);
+    while Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(CALIBRATE_SAMPLE_MS));
+        let pos = cursor.lock().ok().and_then(|guard| *guard);
+        let moved: Vec<(String, (f64, f64))> = match device_deltas.lock() {
+            Ok(mut map) => std::mem::take(&mut *map)
+                .into_iter()
+                .filter(|(_, (dx, dy))| *dx != 0.0 || *dy != 0.0)
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        if let (Some((x0, y0)), Some((x1, y1)), [(name, (dx, dy))]) =
+            (last_pos, pos, moved.as_slice())
+        {
+            let cursor_dist = (x1 - x0).hypot(y1 - y0);
+            if cursor_dist > 0.0 {
+                let entry = travel.entry(name.clone()).or_insert((0.0, 0.0));
+                entry.0 += cursor_dist;
+                entry.1 += dx.hypot(*dy);
             }
         }
+        last_pos = pos;
     }
-    if devices.is_empty() {
-        return Err("no relative mouse devices found in /dev/input/event*".to_string());
+
+    let mut cfg = load_config();
+    let mut calibrated = 0;
+    for (name, (cursor_dist, delta_dist)) in &travel {
+        if *cursor_dist < CALIBRATE_MIN_TRAVEL_PX || *delta_dist <= 0.0 {
+            println!("{name}: not enough movement, skipped");
+            continue;
+        }
+        let scale = cursor_dist / delta_dist;
+        println!("{name}: scale {scale:.3}");
+        cfg.delta_scale.insert(name.clone(), scale);
+        calibrated += 1;
     }
-    let deltas = Arc::new(Mutex::new((0.0f64, 0.0f64)));
-    let deltas_thread = Arc::clone(&deltas);
-    thread::spawn(move || loop {
-        for dev in &mut devices {
-            if let Ok(events) = dev.fetch_events() {
-                for ev in events {
-                    match ev.destructure() {
-                        EventSummary::RelativeAxis(_, RelativeAxisCode::REL_X, v) => {
-                            if let Ok(mut d) = deltas_thread.lock() {
-                                d.0 += v as f64;
-                            }
-                        }
-                        EventSummary::RelativeAxis(_, RelativeAxisCode::REL_Y, v) => {
-                            if let Ok(mut d) = deltas_thread.lock() {
-                                d.1 += v as f64;
-                            }
-                        }
-                        _ => {}
-                    }
-                }
+    if calibrated == 0 {
+        eprintln!("FAIL: no pointer device moved far enough; nothing was saved");
+        return ExitCode::from(1);
+    }
+    if let Err(err) = save_config(&cfg) {
+        eprintln!("FAIL: {err}");
+        return ExitCode::from(1);
+    }
+    println!("Saved {calibrated} device scale(s) to the config.");
+    ExitCode::SUCCESS
+}
+
+/// Time of the most recent pointer button press.
+type LastClick = Arc<Mutex<Option<Instant>>>;
+
+/// Watches button presses for `--click-highlight` on a libinput context of its own, so
+/// it works whichever `--cursor-backend` tracks the position.
+fn start_click_tracker() -> Result<LastClick, String> {
+    let last_click = Arc::new(Mutex::new(None));
+    let last_click_thread = Arc::clone(&last_click);
+    spawn_libinput_thread("click tracker", move |event| {
+        if let LibinputEvent::Pointer(LibinputPointerEvent::Button(button)) = event
+            && button.button_state() == ButtonState::Pressed
+            && let Ok(mut at) = last_click_thread.lock()
+        {
+            *at = Some(Instant::now());
+        }
+    })?;
+    Ok(last_click)
+}
+
+/// Runs a libinput context on `seat0` in its own thread and hands every event to
+/// `on_event`. Returns once the context is up and has found a pointer device.
+fn spawn_libinput_thread(
+    what: &'static str,
+    mut on_event: impl FnMut(LibinputEvent) + Send + 'static,
+) -> Result<(), String> {
+    let (ready_tx, ready_rx) = mpsc::channel::<Result<(), String>>();
+    // A libinput context cannot leave the thread that created it.
+    thread::spawn(move || {
+        let mut input = Libinput::new_with_udev(LibinputOpener);
+        if input.udev_assign_seat("seat0").is_err() {
+            let _ = ready_tx.send(Err("libinput could not assign seat0".to_string()));
+            return;
+        }
+        if let Err(err) = input.dispatch() {
+            let _ = ready_tx.send(Err(format!("libinput dispatch failed: {err}")));
+            return;
+        }
+        let mut has_pointer = false;
+        for event in &mut input {
+            if let LibinputEvent::Device(_) = &event {
+                has_pointer |= event.device().has_capability(DeviceCapability::Pointer);
             }
         }
-        thread::sleep(Duration::from_millis(2));
+        if !has_pointer {
+            let _ = ready_tx.send(Err(
+                "libinput found no pointer devices (is the user in the input group?)".to_string(),
+            ));
+            return;
+        }
+        let _ = ready_tx.send(Ok(()));
+        loop {
+            if input.dispatch().is_err() {
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+            for event in &mut input {
+                on_event(event);
+            }
+            thread::sleep(Duration::from_millis(2));
+        }
     });
-    Ok(deltas)
+    match ready_rx.recv_timeout(Duration::from_secs(4)) {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(err)) => Err(err),
+        Err(_) => Err(format!("timed out initializing libinput {what}")),
+    }
+}
+
+/// Geometry of the focused toplevel as fractions (x, y, width, height) of the tracked
+/// output; `None` while the focused window is elsewhere.
+type FocusedWindow = Arc<Mutex<Option<(f64, f64, f64, f64)>>>;
+
+struct FocusedWindowApp {
+    registry_state: RegistryState,
+    output_state: OutputState,
+    toplevel_info_state: ToplevelInfoState,
+    output: Option<wl_output::WlOutput>,
+    output_size: (f64, f64),
+    focused: FocusedWindow,
+}
+
+impl FocusedWindowApp {
+    fn refresh_focus(&mut self) {
+        let Some(output) = &self.output else {
+            return;
+        };
+        let (out_w, out_h) = self.output_size;
+        let rect = self
+            .toplevel_info_state
+            .toplevels()
+            .find(|info| {
+                info.state
+                    .contains(&zcosmic_toplevel_handle_v1::State::Activated)
+            })
+            .and_then(|info| info.geometry.get(output))
+            .map(|g| {
+                (
+                    g.x as f64 / out_w,
+                    g.y as f64 / out_h,
+                    g.width as f64 / out_w,
+                    g.height as f64 / out_h,
+                )
+            });
+        if let Ok(mut focused) = self.focused.lock() {
+            *focused = rect;
+        }
+    }
+}
+
+impl ProvidesRegistryState for FocusedWindowApp {
+    fn registry(&mut self) -> &mut RegistryState {
+        &mut self.registry_state
+    }
+    sctk::registry_handlers!(OutputState);
+}
+
+impl OutputHandler for FocusedWindowApp {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.output_state
+    }
+    fn new_output(&mut self, _: &WlConnection, _: &WlQueueHandle<Self>, _: wl_output::WlOutput) {}
+    fn update_output(
+        &mut self,
+        _: &WlConnection,
+        _: &WlQueueHandle<Self>,
+        _: wl_output::WlOutput,
+    ) {
+    }
+    fn output_destroyed(
+        &mut self,
+        _: &WlConnection,
+        _: &WlQueueHandle<Self>,
+        _: wl_output::WlOutput,
+    ) {
+    }
+}
+
+impl ToplevelInfoHandler for FocusedWindowApp {
+    fn toplevel_info_state(&mut self) -> &mut ToplevelInfoState {
+        &mut self.toplevel_info_state
+    }
+    fn new_toplevel(
+        &mut self,
+        _: &WlConnection,
+        _: &WlQueueHandle<Self>,
+        _: &ext_foreign_toplevel_handle_v1::ExtForeignToplevelHandleV1,
+    ) {
+        self.refresh_focus();
+    }
+    fn update_toplevel(
+        &mut self,
+        _: &WlConnection,
+        _: &WlQueueHandle<Self>,
+        _: &ext_foreign_toplevel_handle_v1::ExtForeignToplevelHandleV1,
+    ) {
+        self.refresh_focus();
+    }
+    fn toplevel_closed(
+        &mut self,
+        _: &WlConnection,
+        _: &WlQueueHandle<Self>,
+        _: &ext_foreign_toplevel_handle_v1::ExtForeignToplevelHandleV1,
+    ) {
+        self.refresh_focus();
+    }
+}
+
+sctk::delegate_registry!(FocusedWindowApp);
+sctk::delegate_output!(FocusedWindowApp);
+delegate_toplevel_info!(FocusedWindowApp);
+
+/// Watches which toplevel is activated through `cosmic-toplevel-info` (v2 carries the
+/// state and per-output geometry on top of `ext-foreign-toplevel-list`).
+fn start_focused_window_tracker(output: Option<&str>) -> Result<FocusedWindow, String> {
+    let focused = Arc::new(Mutex::new(None));
+    let focused_thread = Arc::clone(&focused);
+    let output = output.map(str::to_string);
+    let (ready_tx, ready_rx) = mpsc::channel::<Result<(), String>>();
+    thread::spawn(move || {
+        if let Err(err) =
+            run_focused_window_tracker_loop(output.as_deref(), focused_thread, ready_tx.clone())
+        {
+            let _ = ready_tx.send(Err(err));
+        }
+    });
+    match ready_rx.recv_timeout(Duration::from_secs(4)) {
+        Ok(Ok(())) => Ok(focused),
+        Ok(Err(err)) => Err(err),
+        Err(_) => Err("timed out initializing focused window tracker".to_string()),
+    }
+}
+
+fn run_focused_window_tracker_loop(
+    wanted: Option<&str>,
+    focused: FocusedWindow,
+    ready_tx: mpsc::Sender<Result<(), String>>,
+) -> Result<(), String> {
+    let conn = WlConnection::connect_to_env()
+        .map_err(|e| format!("wayland connect failed for window tracker: {e}"))?;
+    let (globals, mut event_queue) =
+        wl_registry_queue_init(&conn).map_err(|e| format!("wayland registry init failed: {e}"))?;
+    let qh = event_queue.handle();
+    let registry_state = RegistryState::new(&globals);
+    let toplevel_info_state = ToplevelInfoState::try_new(&registry_state, &qh)
+        .ok_or_else(|| "compositor does not offer ext-foreign-toplevel-list".to_string())?;
+    if toplevel_info_state.cosmic_toplevel_info.is_none() {
+        return Err(
+            "compositor does not offer cosmic-toplevel-info v2 (window geometry)".to_string(),
+        );
+    }
+    let mut app = FocusedWindowApp {
+        registry_state,
+        output_state: OutputState::new(&globals, &qh),
+        toplevel_info_state,
+        output: None,
+        output_size: (1.0, 1.0),
+        focused,
+    };
+    // The first roundtrip binds the outputs, the second collects their xdg-output info.
+    for _ in 0..2 {
+        event_queue
+            .roundtrip(&mut app)
+            .map_err(|e| format!("initial wayland roundtrip failed: {e}"))?;
+    }
+    let (output, _) =
+        select_wl_output(&app.output_state, wanted).map_err(|e| format!("window tracker: {e}"))?;
+    let (w, h) = app
+        .output_state
+        .info(&output)
+        .and_then(|info| info.logical_size)
+        .filter(|(w, h)| *w > 0 && *h > 0)
+        .ok_or_else(|| "window tracker: output has no logical size".to_string())?;
+    app.output = Some(output);
+    app.output_size = (w as f64, h as f64);
+    app.refresh_focus();
+    let _ = ready_tx.send(Ok(()));
+    loop {
+        event_queue
+            .blocking_dispatch(&mut app)
+            .map_err(|e| format!("window tracker dispatch failed: {e}"))?;
+    }
 }
 
 fn extract_cursor_from_sample(sample: &gst::Sample, src_w: u32, src_h: u32) -> Option<(f64, f64)> {
@@ -1394,18 +7835,3 @@ fn read_xy_from_structure(st: &gst::StructureRef, src_w: u32, src_h: u32) -> Opt
     }
 }
 
-fn print_help() {
-    println!("vp-sndr: HEVC RTP sender");
-    println!();
-    println!("Usage:");
-    println!("  vp-sndr send --receiver-ip IP [--port N] [--x N] [--y N] [--width N] [--height N] [--fps N] [--follow-mouse] [--smoothing K] [--deadzone PCT] [--encoder x264enc|nvh264enc|x265enc|nvh265enc|vaapih265enc|v4l2h265enc] [--bitrate-kbps N]");
-    println!("  vp-sndr tray");
-    println!("  vp-sndr config");
-    println!("  vp-sndr run-saved");
-    println!();
-    println!("Examples:");
-    println!("  vp-sndr send --receiver-ip 192.168.1.50 --port 5000 --x 200 --y 100 --width 1280 --height 720 --fps 60 --follow-mouse --smoothing 4 --deadzone 30 --encoder x265enc --bitrate-kbps 8000");
-    println!("  vp-sndr tray");
-    println!("  vp-sndr config");
-    println!("  vp-sndr run-saved");
-}
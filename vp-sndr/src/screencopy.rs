@@ -0,0 +1,426 @@
+//! Direct capture for `--backend screencopy`.
+//!
+//! Talks to the compositor over `ext-image-copy-capture` (as exposed by COSMIC) instead
+//! of the ScreenCast portal, so there is no picker dialog and no PipeWire stream. A
+//! Wayland thread copies each frame into a shared-memory buffer and pushes it into an
+//! `appsrc`, which feeds the same crop callback as the portal stream (the shm formats
+//! below are ones the crop takes as they are).
+//! Only one frame is in flight at a time; the compositor answers a capture request
+//! once the output has new content, so a static screen produces no frames. Frames
+//! are copied out of the shm buffer into recycled GStreamer buffers. DMA-buf capture
+//! is not implemented.
+
+use cosmic_client_toolkit::delegate_screencopy;
+use cosmic_client_toolkit::screencopy::{
+    CaptureFrame, CaptureOptions, CaptureSession, CaptureSource, FailureReason, Formats, Frame,
+    Rect, ScreencopyFrameData, ScreencopyHandler, ScreencopySessionData, ScreencopyState,
+};
+use cosmic_client_toolkit::sctk;
+use cosmic_client_toolkit::sctk::output::{OutputHandler, OutputState};
+use cosmic_client_toolkit::sctk::registry::{ProvidesRegistryState, RegistryState};
+use cosmic_client_toolkit::sctk::shm::raw::RawPool;
+use cosmic_client_toolkit::sctk::shm::{Shm, ShmHandler};
+use cosmic_client_toolkit::wayland_client::backend::WaylandError;
+use cosmic_client_toolkit::wayland_client::globals::registry_queue_init;
+use cosmic_client_toolkit::wayland_client::protocol::{wl_buffer, wl_output, wl_shm};
+use cosmic_client_toolkit::wayland_client::{
+    Connection, EventQueue, QueueHandle, WEnum, delegate_noop,
+};
+use gstreamer as gst;
+use gstreamer_app::AppSrc;
+use std::os::fd::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SESSION_READY_TIMEOUT_SECS: u64 = 4;
+// How long the capture thread waits for Wayland events before it checks whether it
+// was closed, since a static screen sends none.
+const STOP_POLL_MS: i32 = 100;
+
+/// Handle to the capture thread. Frames are dropped until an `appsrc` is attached.
+pub struct ScreencopyCapture {
+    pub output_name: String,
+    pub paint_cursor: bool,
+    target: Arc<Mutex<Option<AppSrc>>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl ScreencopyCapture {
+    pub fn attach(&self, appsrc: AppSrc) {
+        if let Ok(mut target) = self.target.lock() {
+            *target = Some(appsrc);
+        }
+    }
+
+    /// Stops capturing. Within STOP_POLL_MS the thread destroys the session and its
+    /// shm buffer and exits.
+    pub fn close(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Ok(mut target) = self.target.lock() {
+            *target = None;
+        }
+    }
+}
+
+pub fn start_screencopy_capture(
+    output: Option<&str>,
+    paint_cursor: bool,
+    fps: u32,
+) -> Result<ScreencopyCapture, String> {
+    let target = Arc::new(Mutex::new(None));
+    let stop = Arc::new(AtomicBool::new(false));
+    let (ready_tx, ready_rx) = mpsc::channel::<Result<String, String>>();
+    let wanted = output.map(str::to_string);
+    let target_thread = Arc::clone(&target);
+    let stop_thread = Arc::clone(&stop);
+    let frame_interval = Duration::from_nanos(1_000_000_000 / fps.max(1) as u64);
+    thread::spawn(move || {
+        let result = run_capture_loop(
+            wanted.as_deref(),
+            paint_cursor,
+            frame_interval,
+            target_thread,
+            stop_thread,
+            &ready_tx,
+        );
+        if let Err(err) = result {
+            // Before the session is up this reaches start_screencopy_capture; after
+            // that it is the only trace, so log it as well.
+            eprintln!("WARN: screencopy capture stopped: {err}");
+            let _ = ready_tx.send(Err(err));
+        }
+    });
+    match ready_rx.recv_timeout(Duration::from_secs(SESSION_READY_TIMEOUT_SECS)) {
+        Ok(Ok(output_name)) => Ok(ScreencopyCapture {
+            output_name,
+            paint_cursor,
+            target,
+            stop,
+        }),
+        Ok(Err(err)) => Err(err),
+        Err(_) => Err("timed out starting screencopy session".to_string()),
+    }
+}
+
+struct ShmFrame {
+    pool: RawPool,
+    buffer: wl_buffer::WlBuffer,
+    caps: gst::Caps,
+    size: (u32, u32),
+    // The compositor only copies damaged regions into a reused buffer, so the
+    // first capture into a new buffer has to ask for everything.
+    needs_full_damage: bool,
+}
+
+struct ScreencopyApp {
+    registry_state: RegistryState,
+    output_state: OutputState,
+    shm: Shm,
+    screencopy_state: ScreencopyState,
+    session: Option<CaptureSession>,
+    frame: Option<ShmFrame>,
+    in_flight: bool,
+    last_capture: Option<Instant>,
+    stopped: Option<String>,
+    target: Arc<Mutex<Option<AppSrc>>>,
+    buffers: super::FramePool,
+}
+
+impl ScreencopyApp {
+    fn request_capture(&mut self, qh: &QueueHandle<Self>) {
+        let (Some(session), Some(frame)) = (&self.session, &mut self.frame) else {
+            return;
+        };
+        if self.in_flight {
+            return;
+        }
+        let damage = if frame.needs_full_damage {
+            frame.needs_full_damage = false;
+            vec![Rect {
+                x: 0,
+                y: 0,
+                width: frame.size.0 as i32,
+                height: frame.size.1 as i32,
+            }]
+        } else {
+            Vec::new()
+        };
+        session.capture(&frame.buffer, &damage, qh, ScreencopyFrameData::default());
+        self.in_flight = true;
+        self.last_capture = Some(Instant::now());
+    }
+
+    fn push_frame(&mut self) {
+        let Some(frame) = &mut self.frame else {
+            return;
+        };
+        let Ok(target) = self.target.lock() else {
+            return;
+        };
+        let Some(appsrc) = target.as_ref() else {
+            return;
+        };
+        if appsrc.caps().as_ref() != Some(&frame.caps) {
+            appsrc.set_caps(Some(&frame.caps));
+        }
+        let len = frame.size.0 as usize * frame.size.1 as usize * 4;
+        let Ok(mut buffer) = self.buffers.acquire(len) else {
+            return;
+        };
+        let Some(mut map) = buffer.get_mut().and_then(|b| b.map_writable().ok()) else {
+            return;
+        };
+        map[..len].copy_from_slice(&frame.pool.mmap()[..len]);
+        drop(map);
+        let _ = appsrc.push_buffer(buffer);
+    }
+}
+
+impl ProvidesRegistryState for ScreencopyApp {
+    fn registry(&mut self) -> &mut RegistryState {
+        &mut self.registry_state
+    }
+    sctk::registry_handlers!(OutputState);
+}
+
+impl OutputHandler for ScreencopyApp {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.output_state
+    }
+    fn new_output(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_output::WlOutput) {}
+    fn update_output(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_output::WlOutput) {}
+    fn output_destroyed(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_output::WlOutput) {}
+}
+
+impl ShmHandler for ScreencopyApp {
+    fn shm_state(&mut self) -> &mut Shm {
+        &mut self.shm
+    }
+}
+
+impl ScreencopyHandler for ScreencopyApp {
+    fn screencopy_state(&mut self) -> &mut ScreencopyState {
+        &mut self.screencopy_state
+    }
+
+    // Sent once the session starts and again whenever the buffer constraints change
+    // (resolution switch, scale change), so the buffer is rebuilt every time.
+    fn init_done(
+        &mut self,
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+        _: &CaptureSession,
+        formats: &Formats,
+    ) {
+        let Some((shm_format, gst_format)) = pick_shm_format(&formats.shm_formats) else {
+            self.stopped = Some(format!(
+                "compositor offers no 32-bit RGB shm format (offered: {:?})",
+                formats.shm_formats
+            ));
+            return;
+        };
+        let (width, height) = formats.buffer_size;
+        let mut pool = match RawPool::new(width as usize * height as usize * 4, &self.shm) {
+            Ok(v) => v,
+            Err(err) => {
+                self.stopped = Some(format!(
+                    "could not allocate {width}x{height} shm pool: {err}"
+                ));
+                return;
+            }
+        };
+        let buffer = pool.create_buffer(
+            0,
+            width as i32,
+            height as i32,
+            width as i32 * 4,
+            shm_format,
+            (),
+            qh,
+        );
+        if let Some(old) = self.frame.take() {
+            old.buffer.destroy();
+        }
+        println!("Screencopy: {width}x{height} {gst_format}");
+        self.frame = Some(ShmFrame {
+            pool,
+            buffer,
+            caps: gst::Caps::builder("video/x-raw")
+                .field("format", gst_format)
+                .field("width", width as i32)
+                .field("height", height as i32)
+                .field("framerate", gst::Fraction::new(0, 1))
+                .build(),
+            size: (width, height),
+            needs_full_damage: true,
+        });
+        self.in_flight = false;
+    }
+
+    fn stopped(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &CaptureSession) {
+        self.stopped = Some("compositor ended the capture session (output removed?)".to_string());
+    }
+
+    fn ready(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &CaptureFrame, _: Frame) {
+        self.in_flight = false;
+        self.push_frame();
+    }
+
+    fn failed(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &CaptureFrame,
+        reason: WEnum<FailureReason>,
+    ) {
+        self.in_flight = false;
+        match reason {
+            // A new init_done with the new constraints follows.
+            WEnum::Value(FailureReason::BufferConstraints) => {}
+            WEnum::Value(FailureReason::Stopped) => {
+                self.stopped = Some("capture session stopped".to_string());
+            }
+            // Retried by the capture loop at the next frame slot.
+            _ => {
+                if let Some(frame) = &mut self.frame {
+                    frame.needs_full_damage = true;
+                }
+            }
+        }
+    }
+}
+
+/// Prefers formats GStreamer reads without a swizzle; little-endian `Xrgb8888` is
+/// BGRx in memory.
+fn pick_shm_format(offered: &[wl_shm::Format]) -> Option<(wl_shm::Format, &'static str)> {
+    [
+        (wl_shm::Format::Xrgb8888, "BGRx"),
+        (wl_shm::Format::Argb8888, "BGRA"),
+        (wl_shm::Format::Xbgr8888, "RGBx"),
+        (wl_shm::Format::Abgr8888, "RGBA"),
+    ]
+    .into_iter()
+    .find(|(format, _)| offered.contains(format))
+}
+
+fn run_capture_loop(
+    wanted: Option<&str>,
+    paint_cursor: bool,
+    frame_interval: Duration,
+    target: Arc<Mutex<Option<AppSrc>>>,
+    stop: Arc<AtomicBool>,
+    ready_tx: &mpsc::Sender<Result<String, String>>,
+) -> Result<(), String> {
+    let conn = Connection::connect_to_env()
+        .map_err(|e| format!("wayland connect failed for screencopy: {e}"))?;
+    let (globals, mut event_queue) =
+        registry_queue_init(&conn).map_err(|e| format!("wayland registry init failed: {e}"))?;
+    let qh = event_queue.handle();
+    let mut app = ScreencopyApp {
+        registry_state: RegistryState::new(&globals),
+        output_state: OutputState::new(&globals, &qh),
+        shm: Shm::bind(&globals, &qh).map_err(|e| format!("wl_shm unavailable: {e}"))?,
+        screencopy_state: ScreencopyState::new(&globals, &qh),
+        session: None,
+        frame: None,
+        in_flight: false,
+        last_capture: None,
+        stopped: None,
+        target,
+        buffers: super::FramePool::default(),
+    };
+    // The first roundtrip binds the outputs, the second collects their xdg-output info.
+    for _ in 0..2 {
+        event_queue
+            .roundtrip(&mut app)
+            .map_err(|e| format!("wayland roundtrip failed: {e}"))?;
+    }
+
+    let (output, output_name) = super::select_wl_output(&app.output_state, wanted)?;
+
+    let options = if paint_cursor {
+        CaptureOptions::PaintCursors
+    } else {
+        CaptureOptions::empty()
+    };
+    let session = app
+        .screencopy_state
+        .capturer()
+        .create_session(
+            &CaptureSource::Output(output),
+            options,
+            &qh,
+            ScreencopySessionData::default(),
+        )
+        .map_err(|e| format!("create_session failed: {e}"))?;
+    app.session = Some(session);
+    let _ = ready_tx.send(Ok(output_name));
+
+    while !stop.load(Ordering::Relaxed) {
+        dispatch_or_wait(&mut event_queue, &mut app)?;
+        if let Some(reason) = app.stopped.take() {
+            if let Ok(target) = app.target.lock() {
+                if let Some(appsrc) = target.as_ref() {
+                    let _ = appsrc.end_of_stream();
+                }
+            }
+            return Err(reason);
+        }
+        if !app.in_flight && app.frame.is_some() {
+            // Pace requests to --fps; the compositor holds each one until the
+            // next damage anyway.
+            if let Some(wait) = app
+                .last_capture
+                .map(|at| frame_interval.saturating_sub(at.elapsed()))
+            {
+                thread::sleep(wait);
+            }
+            app.request_capture(&qh);
+        }
+    }
+    // Release the compositor's side of the capture now rather than when the
+    // connection goes.
+    app.session = None;
+    if let Some(frame) = app.frame.take() {
+        frame.buffer.destroy();
+    }
+    let _ = event_queue.flush();
+    Ok(())
+}
+
+/// Like `blocking_dispatch`, but returns after STOP_POLL_MS without events.
+fn dispatch_or_wait(
+    event_queue: &mut EventQueue<ScreencopyApp>,
+    app: &mut ScreencopyApp,
+) -> Result<(), String> {
+    let failed = |e: &dyn std::fmt::Display| format!("screencopy dispatch failed: {e}");
+    if event_queue.dispatch_pending(app).map_err(|e| failed(&e))? > 0 {
+        return Ok(());
+    }
+    event_queue.flush().map_err(|e| failed(&e))?;
+    if let Some(guard) = event_queue.prepare_read() {
+        let mut fd = libc::pollfd {
+            fd: guard.connection_fd().as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // Dropping the guard without reading cancels the read.
+        if unsafe { libc::poll(&mut fd, 1, STOP_POLL_MS) } > 0 {
+            match guard.read() {
+                Ok(_) => {}
+                Err(WaylandError::Io(err)) if err.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(err) => return Err(failed(&err)),
+            }
+        }
+    }
+    event_queue.dispatch_pending(app).map_err(|e| failed(&e))?;
+    Ok(())
+}
+
+sctk::delegate_registry!(ScreencopyApp);
+sctk::delegate_output!(ScreencopyApp);
+sctk::delegate_shm!(ScreencopyApp);
+delegate_screencopy!(ScreencopyApp);
+delegate_noop!(ScreencopyApp: ignore wl_buffer::WlBuffer);
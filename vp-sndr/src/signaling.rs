@@ -0,0 +1,223 @@
+//! Built-in signaling for `--transport webrtc`.
+//!
+//! One TCP port serves both the viewer page (`GET /`) and a WebSocket (`/ws`) that
+//! relays SDP and ICE candidates between the browser and the sender. Messages are
+//! small JSON objects: `{"type":"offer","sdp":..}`, `{"type":"answer","sdp":..}` and
+//! `{"type":"ice","candidate":..,"sdpMLineIndex":N}`. The browser always makes the
+//! offer, so the sender only ever answers.
+
+use serde_json::{Value, json};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tungstenite::{Message, WebSocket};
+
+const SOCKET_POLL_MS: u64 = 50;
+const MAX_REQUEST_HEAD_BYTES: usize = 8192;
+
+pub enum SignalEvent {
+    Connected {
+        peer: u64,
+        outgoing: mpsc::Sender<String>,
+    },
+    Offer {
+        peer: u64,
+        sdp: String,
+    },
+    Ice {
+        peer: u64,
+        mline: u32,
+        candidate: String,
+    },
+    Disconnected {
+        peer: u64,
+    },
+}
+
+pub fn start_signaling_server(port: u16) -> Result<mpsc::Receiver<SignalEvent>, String> {
+    let listener = TcpListener::bind(("0.0.0.0", port))
+        .map_err(|e| format!("bind signaling port {port}: {e}"))?;
+    let (tx, rx) = mpsc::channel::<SignalEvent>();
+    let next_peer = AtomicU64::new(1);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let tx = tx.clone();
+            let peer = next_peer.fetch_add(1, Ordering::Relaxed);
+            thread::spawn(move || handle_client(stream, peer, &tx));
+        }
+    });
+    Ok(rx)
+}
+
+pub fn answer_message(sdp: &str) -> String {
+    json!({ "type": "answer", "sdp": sdp }).to_string()
+}
+
+pub fn ice_message(mline: u32, candidate: &str) -> String {
+    json!({ "type": "ice", "candidate": candidate, "sdpMLineIndex": mline }).to_string()
+}
+
+fn handle_client(stream: TcpStream, peer: u64, tx: &mpsc::Sender<SignalEvent>) {
+    let mut head = vec![0u8; MAX_REQUEST_HEAD_BYTES];
+    let n = match stream.peek(&mut head) {
+        Ok(n) => n,
+        Err(_) => return,
+    };
+    let head = String::from_utf8_lossy(&head[..n]).to_ascii_lowercase();
+    if head.contains("upgrade: websocket") {
+        match tungstenite::accept(stream) {
+            Ok(ws) => run_peer(ws, peer, tx),
+            Err(err) => eprintln!("WARN: webrtc signaling handshake failed: {err}"),
+        }
+    } else {
+        serve_viewer_page(stream, &head);
+    }
+}
+
+fn serve_viewer_page(mut stream: TcpStream, head: &str) {
+    // Drain the request so the browser does not see a reset before the reply.
+    let mut buf = [0u8; MAX_REQUEST_HEAD_BYTES];
+    let _ = stream.read(&mut buf);
+    let path = head.split_whitespace().nth(1).unwrap_or("/");
+    let (status, content_type, body) = if path == "/" || path == "/index.html" {
+        ("200 OK", "text/html; charset=utf-8", VIEWER_PAGE)
+    } else {
+        ("404 Not Found", "text/plain", "not found\n")
+    };
+    let reply = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    let _ = stream.write_all(reply.as_bytes());
+}
+
+fn run_peer(mut ws: WebSocket<TcpStream>, peer: u64, tx: &mpsc::Sender<SignalEvent>) {
+    let _ = ws
+        .get_ref()
+        .set_read_timeout(Some(Duration::from_millis(SOCKET_POLL_MS)));
+    let (out_tx, out_rx) = mpsc::channel::<String>();
+    if tx
+        .send(SignalEvent::Connected {
+            peer,
+            outgoing: out_tx,
+        })
+        .is_err()
+    {
+        return;
+    }
+    'session: loop {
+        while let Ok(text) = out_rx.try_recv() {
+            if ws.send(Message::text(text)).is_err() {
+                break 'session;
+            }
+        }
+        match ws.read() {
+            Ok(Message::Text(text)) => match parse_signal(peer, text.as_ref()) {
+                Ok(event) => {
+                    if tx.send(event).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => eprintln!("WARN: webrtc peer {peer}: {err}"),
+            },
+            Ok(Message::Close(_)) => break,
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e))
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(_) => break,
+        }
+    }
+    let _ = tx.send(SignalEvent::Disconnected { peer });
+}
+
+fn parse_signal(peer: u64, text: &str) -> Result<SignalEvent, String> {
+    let msg: Value = serde_json::from_str(text).map_err(|e| format!("bad message: {e}"))?;
+    match msg["type"].as_str() {
+        Some("offer") => {
+            let sdp = msg["sdp"]
+                .as_str()
+                .ok_or_else(|| "offer without sdp".to_string())?;
+            Ok(SignalEvent::Offer {
+                peer,
+                sdp: sdp.to_string(),
+            })
+        }
+        Some("ice") => {
+            let candidate = msg["candidate"]
+                .as_str()
+                .ok_or_else(|| "ice message without candidate".to_string())?;
+            let mline = msg["sdpMLineIndex"]
+                .as_u64()
+                .ok_or_else(|| "ice message without sdpMLineIndex".to_string())?;
+            Ok(SignalEvent::Ice {
+                peer,
+                mline: mline as u32,
+                candidate: candidate.to_string(),
+            })
+        }
+        Some(other) => Err(format!("unknown message type '{other}'")),
+        None => Err("message without type".to_string()),
+    }
+}
+
+const VIEWER_PAGE: &str = r#"<!doctype html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>vp-sndr</title>
+<style>
+  html, body { margin: 0; height: 100%; background: #000; }
+  video { width: 100%; height: 100%; object-fit: contain; }
+  #status { position: fixed; top: 8px; left: 8px; color: #ccc; font: 14px sans-serif; }
+</style>
+</head>
+<body>
+<video id="video" autoplay muted playsinline></video>
+<div id="status">connecting...</div>
+<script>
+const video = document.getElementById("video");
+const status = document.getElementById("status");
+const pc = new RTCPeerConnection();
+const ws = new WebSocket(`ws://${location.host}/ws`);
+
+pc.addTransceiver("video", { direction: "recvonly" });
+pc.ontrack = (e) => {
+  video.srcObject = e.streams[0] || new MediaStream([e.track]);
+  status.textContent = "";
+};
+pc.onicecandidate = (e) => {
+  if (e.candidate) {
+    ws.send(JSON.stringify({
+      type: "ice",
+      candidate: e.candidate.candidate,
+      sdpMLineIndex: e.candidate.sdpMLineIndex,
+    }));
+  }
+};
+pc.onconnectionstatechange = () => {
+  if (pc.connectionState === "failed" || pc.connectionState === "closed") {
+    status.textContent = `connection ${pc.connectionState}`;
+  }
+};
+
+ws.onopen = async () => {
+  const offer = await pc.createOffer();
+  await pc.setLocalDescription(offer);
+  ws.send(JSON.stringify({ type: "offer", sdp: offer.sdp }));
+};
+ws.onmessage = async (m) => {
+  const msg = JSON.parse(m.data);
+  if (msg.type === "answer") {
+    await pc.setRemoteDescription({ type: "answer", sdp: msg.sdp });
+  } else if (msg.type === "ice") {
+    await pc.addIceCandidate({ candidate: msg.candidate, sdpMLineIndex: msg.sdpMLineIndex });
+  }
+};
+ws.onclose = () => { status.textContent = "sender disconnected"; };
+</script>
+</body>
+</html>
+"#;
@@ -1,5 +1,6 @@
-use ashpd::desktop::screencast::{CursorMode, Screencast, SourceType};
+use ashpd::desktop::screencast::{CursorMode, Screencast, SourceType, Stream};
 use ashpd::desktop::PersistMode;
+use clap::{CommandFactory, Parser, Subcommand};
 use cosmic_client_toolkit::screencopy::{
     CaptureCursorSession, CaptureFrame, CaptureSession, CaptureSource, FailureReason, Formats,
     Frame, ScreencopyCursorSessionData, ScreencopyCursorSessionDataExt, ScreencopyHandler,
@@ -22,6 +23,7 @@ use std::collections::VecDeque;
 use std::env;
 use std::ffi::OsStr;
 use std::fs;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 use std::process::{Command, ExitCode, Stdio};
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -36,24 +38,25 @@ const DEFAULT_HEIGHT: u32 = 720;
 const PORTAL_TIMEOUT_SECS: u64 = 15;
 const DEFAULT_MOUSE_SAMPLE_INTERVAL_SECS: f64 = 0.5;
 const DEFAULT_MOUSE_SMOOTHING: f64 = 8.0;
+const DEFAULT_LOOPBACK_VIDEO_NR: u32 = 10;
 
 fn main() -> ExitCode {
-    let args: Vec<String> = env::args().collect();
-    match parse_cli(&args) {
-        Ok(Cli::Help) => {
-            print_help();
-            ExitCode::SUCCESS
-        }
-        Ok(Cli::Check) => run_check(),
-        Ok(Cli::Capture { timeout_secs }) => run_capture(timeout_secs),
-        Ok(Cli::Frame {
+    let Some(command) = Cli::parse().command else {
+        let _ = Cli::command().print_help();
+        return ExitCode::SUCCESS;
+    };
+    match command {
+        CliCommand::Check => run_check(),
+        CliCommand::Doctor { assume_yes } => run_doctor(assume_yes),
+        CliCommand::Capture { timeout_secs } => run_capture(timeout_secs),
+        CliCommand::Frame {
             x,
             y,
             width,
             height,
             out,
-        }) => run_frame(x, y, width, height, &out),
-        Ok(Cli::Record {
+        } => run_frame(x, y, width, height, &out),
+        CliCommand::Record {
             x,
             y,
             width,
@@ -65,7 +68,8 @@ fn main() -> ExitCode {
             follow_mouse,
             sample_interval_secs,
             smoothing,
-        }) => run_record(
+            output,
+        } => run_record(
             x,
             y,
             width,
@@ -77,262 +81,130 @@ fn main() -> ExitCode {
             follow_mouse,
             sample_interval_secs,
             smoothing,
+            output.as_deref(),
         ),
-        Err(err) => {
-            eprintln!("error: {err}");
-            print_help();
-            ExitCode::from(2)
-        }
     }
 }
 
-enum Cli {
-    Help,
+#[derive(Parser)]
+#[command(name = "vp-test", about = "vp-test: COSMIC/Wayland screencast probe")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+    /// Validate session, tools, pipewire plugin, and portal presence.
     Check,
-    Capture { timeout_secs: u64 },
+    /// Check sender/receiver prerequisites and apply or print safe fixes.
+    Doctor {
+        /// Apply the fixes without asking.
+        #[arg(short = 'y', long = "yes", env = "VP_TEST_YES")]
+        assume_yes: bool,
+    },
+    /// Attempt to pull 120 frames from pipewiresrc.
+    Capture {
+        /// Give up when no frame arrives for this long.
+        #[arg(
+            long,
+            value_name = "N",
+            env = "VP_TEST_TIMEOUT_SECS",
+            default_value_t = DEFAULT_CAPTURE_TIMEOUT_SECS
+        )]
+        timeout_secs: u64,
+    },
+    /// Capture one screenshot and crop a viewport frame.
     Frame {
+        /// Viewport left edge.
+        #[arg(long, value_name = "N", env = "VP_TEST_X", default_value_t = 0)]
         x: u32,
+        /// Viewport top edge.
+        #[arg(long, value_name = "N", env = "VP_TEST_Y", default_value_t = 0)]
         y: u32,
+        /// Viewport width.
+        #[arg(long, value_name = "N", env = "VP_TEST_WIDTH", default_value_t = DEFAULT_WIDTH, value_parser = clap::value_parser!(u32).range(1..))]
         width: u32,
+        /// Viewport height.
+        #[arg(long, value_name = "N", env = "VP_TEST_HEIGHT", default_value_t = DEFAULT_HEIGHT, value_parser = clap::value_parser!(u32).range(1..))]
         height: u32,
+        /// PNG to write.
+        #[arg(
+            long,
+            value_name = "PATH",
+            env = "VP_TEST_OUT",
+            default_value = "vp-frame.png"
+        )]
         out: PathBuf,
     },
+    /// Record a short cropped video (.webm), using PipeWire when available.
     Record {
+        /// Viewport left edge.
+        #[arg(long, value_name = "N", env = "VP_TEST_X", default_value_t = 0)]
         x: u32,
+        /// Viewport top edge.
+        #[arg(long, value_name = "N", env = "VP_TEST_Y", default_value_t = 0)]
         y: u32,
+        /// Viewport width.
+        #[arg(long, value_name = "N", env = "VP_TEST_WIDTH", default_value_t = DEFAULT_WIDTH, value_parser = clap::value_parser!(u32).range(1..))]
         width: u32,
+        /// Viewport height.
+        #[arg(long, value_name = "N", env = "VP_TEST_HEIGHT", default_value_t = DEFAULT_HEIGHT, value_parser = clap::value_parser!(u32).range(1..))]
         height: u32,
+        /// Recording length.
+        #[arg(long, value_name = "N", env = "VP_TEST_DURATION_SECS", default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
         duration_secs: u32,
+        /// Frames per second.
+        #[arg(long, value_name = "N", env = "VP_TEST_FPS", default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
         fps: u32,
+        /// Drop this many frames after each kept one.
+        #[arg(
+            long,
+            value_name = "N",
+            env = "VP_TEST_FRAME_SKIP",
+            default_value_t = 0
+        )]
         frame_skip: u32,
+        /// WebM to write.
+        #[arg(
+            long,
+            value_name = "PATH",
+            env = "VP_TEST_OUT",
+            default_value = "vp-record.webm"
+        )]
         out: PathBuf,
+        /// Pan the viewport after the cursor.
+        #[arg(long, env = "VP_TEST_FOLLOW_MOUSE")]
         follow_mouse: bool,
+        /// Seconds between cursor samples.
+        #[arg(
+            long = "sample-interval",
+            value_name = "S",
+            env = "VP_TEST_SAMPLE_INTERVAL",
+            default_value_t = DEFAULT_MOUSE_SAMPLE_INTERVAL_SECS,
+            value_parser = parse_positive
+        )]
         sample_interval_secs: f64,
+        /// How fast the viewport catches up.
+        #[arg(
+            long,
+            value_name = "K",
+            env = "VP_TEST_SMOOTHING",
+            default_value_t = DEFAULT_MOUSE_SMOOTHING,
+            value_parser = parse_positive
+        )]
         smoothing: f64,
+        /// Monitor to record.
+        #[arg(long, value_name = "NAME", env = "VP_TEST_OUTPUT")]
+        output: Option<String>,
     },
 }
 
-fn parse_cli(args: &[String]) -> Result<Cli, String> {
-    if args.len() <= 1 {
-        return Ok(Cli::Help);
-    }
-
-    match args[1].as_str() {
-        "-h" | "--help" | "help" => Ok(Cli::Help),
-        "check" => Ok(Cli::Check),
-        "capture" => {
-            let mut timeout_secs = DEFAULT_CAPTURE_TIMEOUT_SECS;
-            let mut i = 2usize;
-            while i < args.len() {
-                match args[i].as_str() {
-                    "--timeout-secs" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --timeout-secs".to_string())?;
-                        timeout_secs = next
-                            .parse::<u64>()
-                            .map_err(|_| format!("invalid --timeout-secs value: {next}"))?;
-                        i += 2;
-                    }
-                    unknown => return Err(format!("unknown argument: {unknown}")),
-                }
-            }
-            Ok(Cli::Capture { timeout_secs })
-        }
-        "frame" => {
-            let mut x = 0u32;
-            let mut y = 0u32;
-            let mut width = DEFAULT_WIDTH;
-            let mut height = DEFAULT_HEIGHT;
-            let mut out = PathBuf::from("vp-frame.png");
-
-            let mut i = 2usize;
-            while i < args.len() {
-                match args[i].as_str() {
-                    "--x" => {
-                        let next = args.get(i + 1).ok_or_else(|| "missing value after --x".to_string())?;
-                        x = next.parse::<u32>().map_err(|_| format!("invalid --x value: {next}"))?;
-                        i += 2;
-                    }
-                    "--y" => {
-                        let next = args.get(i + 1).ok_or_else(|| "missing value after --y".to_string())?;
-                        y = next.parse::<u32>().map_err(|_| format!("invalid --y value: {next}"))?;
-                        i += 2;
-                    }
-                    "--width" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --width".to_string())?;
-                        width = next
-                            .parse::<u32>()
-                            .map_err(|_| format!("invalid --width value: {next}"))?;
-                        i += 2;
-                    }
-                    "--height" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --height".to_string())?;
-                        height = next
-                            .parse::<u32>()
-                            .map_err(|_| format!("invalid --height value: {next}"))?;
-                        i += 2;
-                    }
-                    "--out" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --out".to_string())?;
-                        out = PathBuf::from(next);
-                        i += 2;
-                    }
-                    unknown => return Err(format!("unknown argument: {unknown}")),
-                }
-            }
-
-            if width == 0 || height == 0 {
-                return Err("--width and --height must be > 0".to_string());
-            }
-
-            Ok(Cli::Frame {
-                x,
-                y,
-                width,
-                height,
-                out,
-            })
-        }
-        "record" => {
-            let mut x = 0u32;
-            let mut y = 0u32;
-            let mut width = DEFAULT_WIDTH;
-            let mut height = DEFAULT_HEIGHT;
-            let mut duration_secs = 5u32;
-            let mut fps = 10u32;
-            let mut frame_skip = 0u32;
-            let mut out = PathBuf::from("vp-record.webm");
-            let mut follow_mouse = false;
-            let mut sample_interval_secs = DEFAULT_MOUSE_SAMPLE_INTERVAL_SECS;
-            let mut smoothing = DEFAULT_MOUSE_SMOOTHING;
-
-            let mut i = 2usize;
-            while i < args.len() {
-                match args[i].as_str() {
-                    "--x" => {
-                        let next = args.get(i + 1).ok_or_else(|| "missing value after --x".to_string())?;
-                        x = next.parse::<u32>().map_err(|_| format!("invalid --x value: {next}"))?;
-                        i += 2;
-                    }
-                    "--y" => {
-                        let next = args.get(i + 1).ok_or_else(|| "missing value after --y".to_string())?;
-                        y = next.parse::<u32>().map_err(|_| format!("invalid --y value: {next}"))?;
-                        i += 2;
-                    }
-                    "--width" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --width".to_string())?;
-                        width = next
-                            .parse::<u32>()
-                            .map_err(|_| format!("invalid --width value: {next}"))?;
-                        i += 2;
-                    }
-                    "--height" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --height".to_string())?;
-                        height = next
-                            .parse::<u32>()
-                            .map_err(|_| format!("invalid --height value: {next}"))?;
-                        i += 2;
-                    }
-                    "--duration-secs" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --duration-secs".to_string())?;
-                        duration_secs = next
-                            .parse::<u32>()
-                            .map_err(|_| format!("invalid --duration-secs value: {next}"))?;
-                        i += 2;
-                    }
-                    "--fps" => {
-                        let next = args.get(i + 1).ok_or_else(|| "missing value after --fps".to_string())?;
-                        fps = next
-                            .parse::<u32>()
-                            .map_err(|_| format!("invalid --fps value: {next}"))?;
-                        i += 2;
-                    }
-                    "--frame-skip" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --frame-skip".to_string())?;
-                        frame_skip = next
-                            .parse::<u32>()
-                            .map_err(|_| format!("invalid --frame-skip value: {next}"))?;
-                        i += 2;
-                    }
-                    "--out" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --out".to_string())?;
-                        out = PathBuf::from(next);
-                        i += 2;
-                    }
-                    "--follow-mouse" => {
-                        follow_mouse = true;
-                        i += 1;
-                    }
-                    "--sample-interval" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --sample-interval".to_string())?;
-                        sample_interval_secs = next
-                            .parse::<f64>()
-                            .map_err(|_| format!("invalid --sample-interval value: {next}"))?;
-                        i += 2;
-                    }
-                    "--smoothing" => {
-                        let next = args
-                            .get(i + 1)
-                            .ok_or_else(|| "missing value after --smoothing".to_string())?;
-                        smoothing = next
-                            .parse::<f64>()
-                            .map_err(|_| format!("invalid --smoothing value: {next}"))?;
-                        i += 2;
-                    }
-                    unknown => return Err(format!("unknown argument: {unknown}")),
-                }
-            }
-
-            if width == 0 || height == 0 {
-                return Err("--width and --height must be > 0".to_string());
-            }
-            if duration_secs == 0 {
-                return Err("--duration-secs must be > 0".to_string());
-            }
-            if fps == 0 {
-                return Err("--fps must be > 0".to_string());
-            }
-            if sample_interval_secs <= 0.0 {
-                return Err("--sample-interval must be > 0".to_string());
-            }
-            if smoothing <= 0.0 {
-                return Err("--smoothing must be > 0".to_string());
-            }
-
-            Ok(Cli::Record {
-                x,
-                y,
-                width,
-                height,
-                duration_secs,
-                fps,
-                frame_skip,
-                out,
-                follow_mouse,
-                sample_interval_secs,
-                smoothing,
-            })
-        }
-        unknown => Err(format!("unknown command: {unknown}")),
+fn parse_positive(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(number) if number > 0.0 => Ok(number),
+        Ok(_) => Err("must be > 0".to_string()),
+        Err(_) => Err(format!("invalid number: {value}")),
     }
 }
 
@@ -371,57 +243,458 @@ fn run_check() -> ExitCode {
     }
 
     println!("\n== Portal Service (best effort) ==");
-    match Command::new("gdbus")
-        .args([
-            "call",
-            "--session",
-            "--dest",
-            "org.freedesktop.DBus",
-            "--object-path",
-            "/org/freedesktop/DBus",
-            "--method",
-            "org.freedesktop.DBus.NameHasOwner",
-            "org.freedesktop.portal.Desktop",
-        ])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output()
-    {
-        Ok(out) if out.status.success() => {
-            let text = String::from_utf8_lossy(&out.stdout);
-            if text.contains("true") {
-                println!("PASS: org.freedesktop.portal.Desktop is active.");
+    match query_portal_active() {
+        Ok(true) => println!("PASS: org.freedesktop.portal.Desktop is active."),
+        Ok(false) => {
+            println!("FAIL: org.freedesktop.portal.Desktop is not active.");
+            failures += 1;
+        }
+        Err(err) => println!("WARN: {err}"),
+    }
+
+    println!("\n== Result ==");
+    if failures == 0 {
+        println!("PASS: Basic capture prerequisites look good.");
+        println!("Next: run `cargo run --release -- capture` to attempt real frame capture.");
+        ExitCode::SUCCESS
+    } else {
+        println!("FAIL: {failures} prerequisite checks failed.");
+        ExitCode::from(1)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PackageManager {
+    Apt,
+    Dnf,
+    Pacman,
+    Zypper,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Package {
+    GstTools,
+    GlibTools,
+    GstPipewire,
+    GstBase,
+    GstGood,
+    GstBad,
+    GstUgly,
+    GstLibav,
+    V4l2Loopback,
+    CosmicScreenshot,
+}
+
+#[derive(Clone, Copy)]
+enum DoctorCheck {
+    WaylandSession,
+    Command(&'static str, Package),
+    GstElement(&'static str, Package),
+    Portal,
+    ConfigDir,
+    V4l2Loopback,
+}
+
+impl DoctorCheck {
+    /// Optional checks cover the OBS loopback path, alternative codecs, and COSMIC-only
+    /// tools; fixes are still offered but they do not fail the doctor run.
+    fn optional(&self) -> bool {
+        matches!(
+            self,
+            DoctorCheck::V4l2Loopback
+                | DoctorCheck::Command(_, Package::CosmicScreenshot)
+                | DoctorCheck::GstElement(_, Package::GstUgly | Package::GstLibav)
+        )
+    }
+}
+
+fn doctor_checks() -> Vec<DoctorCheck> {
+    vec![
+        DoctorCheck::WaylandSession,
+        DoctorCheck::Command("gst-launch-1.0", Package::GstTools),
+        DoctorCheck::Command("gst-inspect-1.0", Package::GstTools),
+        DoctorCheck::Command("gst-discoverer-1.0", Package::GstTools),
+        DoctorCheck::Command("gdbus", Package::GlibTools),
+        DoctorCheck::Command("cosmic-screenshot", Package::CosmicScreenshot),
+        DoctorCheck::GstElement("pipewiresrc", Package::GstPipewire),
+        DoctorCheck::GstElement("videoconvert", Package::GstBase),
+        DoctorCheck::GstElement("vp8enc", Package::GstGood),
+        DoctorCheck::GstElement("rtph265pay", Package::GstGood),
+        DoctorCheck::GstElement("v4l2sink", Package::GstGood),
+        DoctorCheck::GstElement("h265parse", Package::GstBad),
+        DoctorCheck::GstElement("x265enc", Package::GstBad),
+        DoctorCheck::GstElement("fpsdisplaysink", Package::GstBad),
+        DoctorCheck::GstElement("x264enc", Package::GstUgly),
+        DoctorCheck::GstElement("avdec_h264", Package::GstLibav),
+        DoctorCheck::Portal,
+        DoctorCheck::ConfigDir,
+        DoctorCheck::V4l2Loopback,
+    ]
+}
+
+fn run_doctor_check(check: &DoctorCheck) -> bool {
+    match check {
+        DoctorCheck::WaylandSession => {
+            let ok = env::var("XDG_SESSION_TYPE").is_ok_and(|v| v == "wayland");
+            if ok {
+                println!("PASS: Wayland session detected.");
             } else {
+                println!("FAIL: Not in a Wayland session.");
+            }
+            ok
+        }
+        DoctorCheck::Command(cmd, _) => check_command_exists(cmd),
+        DoctorCheck::GstElement(element, _) => {
+            let ok = check_gst_plugin(element);
+            if ok {
+                println!("PASS: GStreamer element `{element}` is installed.");
+            } else {
+                println!("FAIL: GStreamer element `{element}` is missing.");
+            }
+            ok
+        }
+        DoctorCheck::Portal => match query_portal_active() {
+            Ok(true) => {
+                println!("PASS: org.freedesktop.portal.Desktop is active.");
+                true
+            }
+            Ok(false) => {
                 println!("FAIL: org.freedesktop.portal.Desktop is not active.");
-                failures += 1;
+                false
+            }
+            Err(err) => {
+                println!("FAIL: {err}");
+                false
+            }
+        },
+        DoctorCheck::ConfigDir => match vp_link_config_dir() {
+            Some(dir) if dir.is_dir() => {
+                println!("PASS: config directory {} exists.", dir.display());
+                true
+            }
+            Some(dir) => {
+                println!("FAIL: config directory {} is missing.", dir.display());
+                false
             }
+            None => {
+                println!("FAIL: could not resolve config directory (HOME unset).");
+                false
+            }
+        },
+        DoctorCheck::V4l2Loopback => {
+            let ok = Path::new("/sys/module/v4l2loopback").exists();
+            if ok {
+                println!("PASS: v4l2loopback module is loaded.");
+            } else {
+                println!("WARN: v4l2loopback module is not loaded (needed for OBS output).");
+            }
+            ok
         }
-        Ok(out) => {
-            println!(
-                "WARN: Could not query DBus session bus (exit {}).",
-                out.status.code().unwrap_or(-1)
-            );
-            let err = String::from_utf8_lossy(&out.stderr);
-            if !err.trim().is_empty() {
-                println!("dbus stderr: {}", err.trim());
+    }
+}
+
+fn run_doctor(assume_yes: bool) -> ExitCode {
+    let manager = detect_package_manager();
+    println!("== Doctor ==");
+    match manager {
+        Some(pm) => println!("Package manager: {}", package_manager_name(pm)),
+        None => println!("WARN: unknown distro; package names will not be suggested."),
+    }
+
+    println!("\n== Checks ==");
+    let failed: Vec<DoctorCheck> = doctor_checks()
+        .into_iter()
+        .filter(|check| !run_doctor_check(check))
+        .collect();
+    if failed.is_empty() {
+        println!("\n== Result ==");
+        println!("PASS: No problems found.");
+        return ExitCode::SUCCESS;
+    }
+
+    println!("\n== Fixes ==");
+    let mut packages: Vec<Package> = Vec::new();
+    for check in &failed {
+        match check {
+            DoctorCheck::Command(_, pkg) | DoctorCheck::GstElement(_, pkg) => {
+                if !packages.contains(pkg) {
+                    packages.push(*pkg);
+                }
+            }
+            DoctorCheck::WaylandSession => {
+                println!("MANUAL: log in to a Wayland session (COSMIC on Pop!_OS) and re-run.");
+            }
+            DoctorCheck::Portal => {
+                println!("MANUAL: start the desktop portal (`systemctl --user restart xdg-desktop-portal`) from inside the desktop session.");
+            }
+            DoctorCheck::ConfigDir => fix_config_dir(),
+            DoctorCheck::V4l2Loopback => {
+                if module_available("v4l2loopback") {
+                    fix_v4l2loopback(assume_yes);
+                } else if !packages.contains(&Package::V4l2Loopback) {
+                    packages.push(Package::V4l2Loopback);
+                }
             }
         }
-        Err(err) => {
-            println!("WARN: Could not invoke gdbus: {err}");
+    }
+    if !packages.is_empty() {
+        match manager.map(|pm| install_command(pm, &packages)) {
+            Some((cmd, unavailable)) => {
+                if let Some(cmd) = cmd {
+                    println!("Install missing packages with:");
+                    println!("  {cmd}");
+                }
+                for pkg in unavailable {
+                    println!("MANUAL: no known package for {} on this distro.", package_label(pkg));
+                }
+            }
+            None => {
+                for pkg in &packages {
+                    println!("MANUAL: install {} with your package manager.", package_label(*pkg));
+                }
+            }
+        }
+        if packages.contains(&Package::V4l2Loopback) {
+            println!("Then re-run `vp-test doctor` to load v4l2loopback.");
+        }
+    }
+
+    println!("\n== Re-check ==");
+    let mut failures = 0u32;
+    let mut warnings = 0u32;
+    for check in &failed {
+        if !run_doctor_check(check) {
+            if check.optional() {
+                warnings += 1;
+            } else {
+                failures += 1;
+            }
         }
     }
 
     println!("\n== Result ==");
     if failures == 0 {
-        println!("PASS: Basic capture prerequisites look good.");
-        println!("Next: run `cargo run --release -- capture` to attempt real frame capture.");
+        if warnings > 0 {
+            println!("PASS: Required checks pass ({warnings} optional checks still need attention).");
+        } else {
+            println!("PASS: All problems fixed.");
+        }
         ExitCode::SUCCESS
     } else {
-        println!("FAIL: {failures} prerequisite checks failed.");
+        println!("FAIL: {failures} checks still failing; apply the fixes above and re-run.");
         ExitCode::from(1)
     }
 }
 
+fn fix_config_dir() {
+    let Some(dir) = vp_link_config_dir() else {
+        println!("MANUAL: set HOME or XDG_CONFIG_HOME so the config directory can be resolved.");
+        return;
+    };
+    match fs::create_dir_all(&dir) {
+        Ok(()) => println!("FIXED: created {}.", dir.display()),
+        Err(err) => println!("FAIL: could not create {}: {err}", dir.display()),
+    }
+}
+
+fn fix_v4l2loopback(assume_yes: bool) {
+    let video_nr = format!("video_nr={DEFAULT_LOOPBACK_VIDEO_NR}");
+    let args = ["modprobe", "v4l2loopback", &video_nr, "card_label=vp-link", "exclusive_caps=1"];
+    if !confirm(&format!("Load v4l2loopback now (sudo {})?", args.join(" ")), assume_yes) {
+        println!("SKIP: run `sudo {}` when ready.", args.join(" "));
+        return;
+    }
+    match Command::new("sudo").args(args).status() {
+        Ok(status) if status.success() => {
+            println!("FIXED: loaded v4l2loopback (/dev/video{DEFAULT_LOOPBACK_VIDEO_NR}).");
+        }
+        Ok(status) => println!(
+            "FAIL: sudo modprobe exited with code {}.",
+            status.code().unwrap_or(-1)
+        ),
+        Err(err) => println!("FAIL: could not run sudo: {err}"),
+    }
+}
+
+fn confirm(question: &str, assume_yes: bool) -> bool {
+    if assume_yes {
+        println!("{question} [y/N] y");
+        return true;
+    }
+    print!("{question} [y/N] ");
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+fn vp_link_config_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("vp-link"))
+}
+
+/// Keyed by `--output` like vp-sndr, since a token restores exactly what was selected.
+fn restore_token_path(output: Option<&str>) -> Option<PathBuf> {
+    let name = match output {
+        Some(output) => {
+            let output: String = output
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || c == '-' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+            format!("vp-test-{output}.restore-token")
+        }
+        None => "vp-test.restore-token".to_string(),
+    };
+    Some(vp_link_config_dir()?.join(name))
+}
+
+fn load_restore_token(path: &Path) -> Option<String> {
+    let token = fs::read_to_string(path).ok()?;
+    let token = token.trim();
+    if token.is_empty() {
+        None
+    } else {
+        Some(token.to_string())
+    }
+}
+
+fn save_restore_token(path: &Path, token: &str) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("create dir {}: {e}", parent.display()))?;
+    }
+    fs::write(path, format!("{token}\n")).map_err(|e| format!("write {}: {e}", path.display()))
+}
+
+fn module_available(module: &str) -> bool {
+    Command::new("modinfo")
+        .arg(module)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+fn detect_package_manager() -> Option<PackageManager> {
+    let text = fs::read_to_string("/etc/os-release").ok()?;
+    let mut ids: Vec<String> = Vec::new();
+    for line in text.lines() {
+        if let Some(v) = line.strip_prefix("ID=").or_else(|| line.strip_prefix("ID_LIKE=")) {
+            ids.extend(v.trim_matches('"').split_whitespace().map(str::to_string));
+        }
+    }
+    ids.iter().find_map(|id| match id.as_str() {
+        "debian" | "ubuntu" | "pop" => Some(PackageManager::Apt),
+        "fedora" | "rhel" | "centos" => Some(PackageManager::Dnf),
+        "arch" => Some(PackageManager::Pacman),
+        "suse" | "opensuse" | "opensuse-tumbleweed" | "opensuse-leap" => Some(PackageManager::Zypper),
+        _ => None,
+    })
+}
+
+fn package_manager_name(pm: PackageManager) -> &'static str {
+    match pm {
+        PackageManager::Apt => "apt",
+        PackageManager::Dnf => "dnf",
+        PackageManager::Pacman => "pacman",
+        PackageManager::Zypper => "zypper",
+    }
+}
+
+fn package_label(pkg: Package) -> &'static str {
+    match pkg {
+        Package::GstTools => "GStreamer command-line tools",
+        Package::GlibTools => "GLib tools (gdbus)",
+        Package::GstPipewire => "the GStreamer PipeWire plugin",
+        Package::GstBase => "GStreamer base plugins",
+        Package::GstGood => "GStreamer good plugins",
+        Package::GstBad => "GStreamer bad plugins",
+        Package::GstUgly => "GStreamer ugly plugins",
+        Package::GstLibav => "the GStreamer libav plugin",
+        Package::V4l2Loopback => "v4l2loopback",
+        Package::CosmicScreenshot => "cosmic-screenshot",
+    }
+}
+
+fn package_name(pm: PackageManager, pkg: Package) -> Option<&'static str> {
+    use PackageManager::*;
+    let name = match (pm, pkg) {
+        (Apt, Package::GstTools) => "gstreamer1.0-tools",
+        (Apt, Package::GlibTools) => "libglib2.0-bin",
+        (Apt, Package::GstPipewire) => "gstreamer1.0-pipewire",
+        (Apt, Package::GstBase) => "gstreamer1.0-plugins-base",
+        (Apt, Package::GstGood) => "gstreamer1.0-plugins-good",
+        (Apt, Package::GstBad) => "gstreamer1.0-plugins-bad",
+        (Apt, Package::GstUgly) => "gstreamer1.0-plugins-ugly",
+        (Apt, Package::GstLibav) => "gstreamer1.0-libav",
+        (Apt, Package::V4l2Loopback) => "v4l2loopback-dkms",
+        (Apt, Package::CosmicScreenshot) => "cosmic-screenshot",
+        (Dnf, Package::GstTools) => "gstreamer1",
+        (Dnf, Package::GlibTools) => "glib2",
+        (Dnf, Package::GstPipewire) => "pipewire-gstreamer",
+        (Dnf, Package::GstBase) => "gstreamer1-plugins-base",
+        (Dnf, Package::GstGood) => "gstreamer1-plugins-good",
+        (Dnf, Package::GstBad) => "gstreamer1-plugins-bad-free",
+        (Dnf, Package::GstUgly) => "gstreamer1-plugins-ugly",
+        (Dnf, Package::GstLibav) => "gstreamer1-plugin-libav",
+        (Dnf, Package::V4l2Loopback) => "v4l2loopback",
+        (Pacman, Package::GstTools) => "gstreamer",
+        (Pacman, Package::GlibTools) => "glib2",
+        (Pacman, Package::GstPipewire) => "gst-plugin-pipewire",
+        (Pacman, Package::GstBase) => "gst-plugins-base",
+        (Pacman, Package::GstGood) => "gst-plugins-good",
+        (Pacman, Package::GstBad) => "gst-plugins-bad",
+        (Pacman, Package::GstUgly) => "gst-plugins-ugly",
+        (Pacman, Package::GstLibav) => "gst-libav",
+        (Pacman, Package::V4l2Loopback) => "v4l2loopback-dkms",
+        (Zypper, Package::GstTools) => "gstreamer-utils",
+        (Zypper, Package::GlibTools) => "glib2-tools",
+        (Zypper, Package::GstPipewire) => "gstreamer-plugin-pipewire",
+        (Zypper, Package::GstBase) => "gstreamer-plugins-base",
+        (Zypper, Package::GstGood) => "gstreamer-plugins-good",
+        (Zypper, Package::GstBad) => "gstreamer-plugins-bad",
+        (Zypper, Package::GstUgly) => "gstreamer-plugins-ugly",
+        (Zypper, Package::GstLibav) => "gstreamer-plugins-libav",
+        (Zypper, Package::V4l2Loopback) => "v4l2loopback-kmp-default",
+        (_, Package::CosmicScreenshot) => return None,
+    };
+    Some(name)
+}
+
+/// Returns the install command for the packages this distro provides, plus the
+/// packages that have no known name there.
+fn install_command(pm: PackageManager, packages: &[Package]) -> (Option<String>, Vec<Package>) {
+    let mut names: Vec<&str> = Vec::new();
+    let mut unavailable = Vec::new();
+    for pkg in packages {
+        match package_name(pm, *pkg) {
+            Some(name) => names.push(name),
+            None => unavailable.push(*pkg),
+        }
+    }
+    if names.is_empty() {
+        return (None, unavailable);
+    }
+    let prefix = match pm {
+        PackageManager::Apt => "sudo apt install -y",
+        PackageManager::Dnf => "sudo dnf install -y",
+        PackageManager::Pacman => "sudo pacman -S --needed",
+        PackageManager::Zypper => "sudo zypper install",
+    };
+    (Some(format!("{prefix} {}", names.join(" "))), unavailable)
+}
+
 fn run_capture(timeout_secs: u64) -> ExitCode {
     println!("Running capture probe with timeout={timeout_secs}s");
     if !check_gst_plugin("pipewiresrc") {
@@ -614,6 +887,7 @@ fn run_record(
     follow_mouse: bool,
     sample_interval_secs: f64,
     smoothing: f64,
+    output: Option<&str>,
 ) -> ExitCode {
     let frames = duration_secs.saturating_mul(fps);
     if frames == 0 {
@@ -648,7 +922,7 @@ fn run_record(
     }
 
     println!("Using PipeWire recording path via portal ScreenCast handshake.");
-    match start_portal_screencast() {
+    match start_portal_screencast(output) {
         Ok(sc) => {
             println!("Portal stream node id: {}", sc.node_id);
             if follow_mouse {
@@ -858,6 +1132,13 @@ fn run_record_follow_live(
     let frame_count_cb = Arc::clone(&frame_count);
     let input_frame_count_cb = Arc::clone(&input_frame_count);
     let appsrc_cb = appsrc.clone();
+    let frame_pool = match new_frame_pool((out_w * out_h * 4) as usize) {
+        Ok(v) => v,
+        Err(err) => {
+            eprintln!("FAIL: {err}");
+            return ExitCode::from(1);
+        }
+    };
 
     appsink.set_callbacks(
         AppSinkCallbacks::builder()
@@ -1007,15 +1288,21 @@ fn run_record_follow_live(
                     return Ok(gst::FlowSuccess::Ok);
                 }
 
-                let mut out_data = vec![0u8; out_w_us * out_h_us * 4];
-                for row in 0..out_h_us {
-                    let src_off = (crop_y + row) * src_stride + crop_x * 4;
-                    let dst_off = row * out_w_us * 4;
-                    out_data[dst_off..dst_off + out_w_us * 4]
-                        .copy_from_slice(&src[src_off..src_off + out_w_us * 4]);
+                let mut out_buf = frame_pool.acquire_buffer(None)?;
+                {
+                    let mut out_map = out_buf
+                        .get_mut()
+                        .ok_or(gst::FlowError::Error)?
+                        .map_writable()
+                        .map_err(|_| gst::FlowError::Error)?;
+                    let out_data = out_map.as_mut_slice();
+                    for row in 0..out_h_us {
+                        let src_off = (crop_y + row) * src_stride + crop_x * 4;
+                        let dst_off = row * out_w_us * 4;
+                        out_data[dst_off..dst_off + out_w_us * 4]
+                            .copy_from_slice(&src[src_off..src_off + out_w_us * 4]);
+                    }
                 }
-
-                let mut out_buf = gst::Buffer::from_mut_slice(out_data);
                 {
                     let idx = {
                         let mut c = frame_count_cb.lock().map_err(|_| gst::FlowError::Error)?;
@@ -1123,6 +1410,19 @@ fn run_record_follow_live(
     }
 }
 
+/// Output frames come from a pool and go back to it once the encoder is done, instead
+/// of allocating a whole frame per sample.
+fn new_frame_pool(size: usize) -> Result<gst::BufferPool, String> {
+    let pool = gst::BufferPool::new();
+    let mut config = pool.config();
+    config.set_params(None, size as u32, 0, 0);
+    pool.set_config(config)
+        .map_err(|e| format!("could not configure frame pool: {e}"))?;
+    pool.set_active(true)
+        .map_err(|e| format!("could not activate frame pool: {e}"))?;
+    Ok(pool)
+}
+
 fn extract_cursor_from_sample(sample: &gst::Sample, src_w: u32, src_h: u32) -> Option<(f64, f64)> {
     let buffer = sample.buffer()?;
     for meta in buffer.iter_meta::<gst::Meta>() {
@@ -1571,7 +1871,17 @@ struct PortalScreenCast {
     node_id: u32,
 }
 
-fn start_portal_screencast() -> Result<PortalScreenCast, String> {
+fn start_portal_screencast(output: Option<&str>) -> Result<PortalScreenCast, String> {
+    // Resolve --output before the portal so a typo fails without a picker popping up.
+    let target = match output {
+        Some(wanted) => Some(find_wayland_output(&list_wayland_outputs()?, wanted)?),
+        None => None,
+    };
+    let token_path = restore_token_path(output);
+    let restore_token = token_path.as_deref().and_then(load_restore_token);
+    if restore_token.is_some() {
+        println!("Portal: restoring the previous source selection");
+    }
     println!("Portal: CreateSession...");
     let rt = tokio::runtime::Builder::new_current_thread()
         .enable_all()
@@ -1610,9 +1920,10 @@ fn start_portal_screencast() -> Result<PortalScreenCast, String> {
                 &session,
                 cursor_mode,
                 SourceType::Monitor.into(),
-                false,
-                None,
-                PersistMode::DoNot,
+                // With --output, let the picker offer every monitor and choose below.
+                target.is_some(),
+                restore_token.as_deref(),
+                PersistMode::ExplicitlyRevoked,
             ),
         )
         .await
@@ -1632,15 +1943,182 @@ fn start_portal_screencast() -> Result<PortalScreenCast, String> {
             .map_err(|e| format!("Start response failed: {e}"))?;
 
         let streams = response.streams();
-        let stream = streams
-            .first()
-            .ok_or_else(|| "Start returned no streams".to_string())?;
+        let stream = match &target {
+            Some(target) => select_output_stream(streams, target)?,
+            None => streams
+                .first()
+                .ok_or_else(|| "Start returned no streams".to_string())?,
+        };
+        // Tokens are single-use: the portal hands out a fresh one on every Start.
+        let saved = match (&token_path, response.restore_token()) {
+            (Some(path), Some(token)) => save_restore_token(path, token),
+            _ => Ok(()),
+        };
+        if let Err(err) = saved {
+            eprintln!("WARN: could not save portal restore token: {err}");
+        }
         Ok(PortalScreenCast {
             node_id: stream.pipe_wire_node_id(),
         })
     })
 }
 
+#[derive(Clone)]
+struct WaylandOutput {
+    name: String,
+    description: String,
+    position: (i32, i32),
+    size: (i32, i32),
+}
+
+struct OutputListApp {
+    registry_state: RegistryState,
+    output_state: OutputState,
+}
+
+impl ProvidesRegistryState for OutputListApp {
+    fn registry(&mut self) -> &mut RegistryState {
+        &mut self.registry_state
+    }
+    sctk::registry_handlers!(OutputState);
+}
+
+impl OutputHandler for OutputListApp {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.output_state
+    }
+    fn new_output(&mut self, _: &WlConnection, _: &WlQueueHandle<Self>, _: wl_output::WlOutput) {}
+    fn update_output(
+        &mut self,
+        _: &WlConnection,
+        _: &WlQueueHandle<Self>,
+        _: wl_output::WlOutput,
+    ) {
+    }
+    fn output_destroyed(
+        &mut self,
+        _: &WlConnection,
+        _: &WlQueueHandle<Self>,
+        _: wl_output::WlOutput,
+    ) {
+    }
+}
+
+sctk::delegate_registry!(OutputListApp);
+sctk::delegate_output!(OutputListApp);
+
+fn list_wayland_outputs() -> Result<Vec<WaylandOutput>, String> {
+    let conn = WlConnection::connect_to_env()
+        .map_err(|e| format!("wayland connect failed for output list: {e}"))?;
+    let (globals, mut event_queue) =
+        wl_registry_queue_init(&conn).map_err(|e| format!("wayland registry init failed: {e}"))?;
+    let qh = event_queue.handle();
+    let mut app = OutputListApp {
+        registry_state: RegistryState::new(&globals),
+        output_state: OutputState::new(&globals, &qh),
+    };
+    // The first roundtrip binds the outputs, the second collects their xdg-output info.
+    for _ in 0..2 {
+        event_queue
+            .roundtrip(&mut app)
+            .map_err(|e| format!("wayland roundtrip failed: {e}"))?;
+    }
+    Ok(app
+        .output_state
+        .outputs()
+        .filter_map(|o| app.output_state.info(&o))
+        .map(|info| WaylandOutput {
+            name: info.name.unwrap_or_default(),
+            description: info.description.unwrap_or_default(),
+            position: info.logical_position.unwrap_or(info.location),
+            size: info.logical_size.unwrap_or_default(),
+        })
+        .collect())
+}
+
+/// Matches the connector name exactly (case-insensitive), then falls back to a
+/// substring of the description so `--output "Dell"` works too.
+fn find_wayland_output(outputs: &[WaylandOutput], wanted: &str) -> Result<WaylandOutput, String> {
+    let wanted_lc = wanted.to_ascii_lowercase();
+    let by_name = outputs
+        .iter()
+        .position(|o| o.name.eq_ignore_ascii_case(wanted));
+    let by_description = || {
+        let hits: Vec<usize> = outputs
+            .iter()
+            .enumerate()
+            .filter(|(_, o)| o.description.to_ascii_lowercase().contains(&wanted_lc))
+            .map(|(i, _)| i)
+            .collect();
+        if hits.len() == 1 { Some(hits[0]) } else { None }
+    };
+    match by_name.or_else(by_description) {
+        Some(i) => {
+            let o = &outputs[i];
+            println!(
+                "Output {} ({}) at {},{} size {}x{}",
+                o.name, o.description, o.position.0, o.position.1, o.size.0, o.size.1
+            );
+            Ok(o.clone())
+        }
+        None => Err(format!(
+            "no single output matches '{wanted}'; known outputs: {}",
+            describe_wayland_outputs(outputs)
+        )),
+    }
+}
+
+fn describe_wayland_outputs(outputs: &[WaylandOutput]) -> String {
+    if outputs.is_empty() {
+        return "none".to_string();
+    }
+    outputs
+        .iter()
+        .map(|o| format!("{} ({})", o.name, o.description))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Picks the portal stream covering `target`. Streams carry their logical position
+/// and size but no connector name, so geometry is the only link back to the output.
+fn select_output_stream<'a>(
+    streams: &'a [Stream],
+    target: &WaylandOutput,
+) -> Result<&'a Stream, String> {
+    if let Some(stream) = streams
+        .iter()
+        .find(|s| s.position() == Some(target.position) && s.size() == Some(target.size))
+    {
+        return Ok(stream);
+    }
+    if let Some(stream) = streams
+        .iter()
+        .find(|s| s.position() == Some(target.position))
+    {
+        return Ok(stream);
+    }
+    if streams.len() == 1 && streams[0].position().is_none() {
+        eprintln!(
+            "WARN: portal did not report stream geometry, using the only selected monitor for {}",
+            target.name
+        );
+        return Ok(&streams[0]);
+    }
+    let selected = streams
+        .iter()
+        .map(|s| match (s.position(), s.size()) {
+            (Some((x, y)), Some((w, h))) => format!("{w}x{h} at {x},{y}"),
+            _ => "unknown geometry".to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    Err(format!(
+        "none of the selected monitors is {} ({}x{} at {},{}); selected: {}",
+        target.name, target.size.0, target.size.1, target.position.0, target.position.1,
+        if selected.is_empty() { "none" } else { &selected }
+    ))
+}
+
 fn check_command_exists(cmd: &str) -> bool {
     let exists = Command::new("which")
         .arg(cmd)
@@ -1657,6 +2135,37 @@ fn check_command_exists(cmd: &str) -> bool {
     exists
 }
 
+fn query_portal_active() -> Result<bool, String> {
+    let out = Command::new("gdbus")
+        .args([
+            "call",
+            "--session",
+            "--dest",
+            "org.freedesktop.DBus",
+            "--object-path",
+            "/org/freedesktop/DBus",
+            "--method",
+            "org.freedesktop.DBus.NameHasOwner",
+            "org.freedesktop.portal.Desktop",
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|err| format!("Could not invoke gdbus: {err}"))?;
+    if !out.status.success() {
+        let mut msg = format!(
+            "Could not query DBus session bus (exit {}).",
+            out.status.code().unwrap_or(-1)
+        );
+        let err = String::from_utf8_lossy(&out.stderr);
+        if !err.trim().is_empty() {
+            msg.push_str(&format!("\ndbus stderr: {}", err.trim()));
+        }
+        return Err(msg);
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).contains("true"))
+}
+
 fn check_gst_plugin(plugin: &str) -> bool {
     Command::new("gst-inspect-1.0")
         .arg(OsStr::new(plugin))
@@ -1676,19 +2185,3 @@ impl BoolToU32 for bool {
         if self { 1 } else { 0 }
     }
 }
-
-fn print_help() {
-    println!("vp-test: COSMIC/Wayland screencast probe");
-    println!();
-    println!("Usage:");
-    println!("  vp-test check");
-    println!("  vp-test capture [--timeout-secs N]");
-    println!("  vp-test frame [--x N] [--y N] [--width N] [--height N] [--out PATH]");
-    println!("  vp-test record [--x N] [--y N] [--width N] [--height N] [--duration-secs N] [--fps N] [--frame-skip N] [--out PATH] [--follow-mouse] [--sample-interval S] [--smoothing K]");
-    println!();
-    println!("Commands:");
-    println!("  check      Validate session, tools, pipewire plugin, and portal presence.");
-    println!("  capture    Attempt to pull 120 frames from pipewiresrc.");
-    println!("  frame      Capture one screenshot and crop a viewport frame.");
-    println!("  record     Record a short cropped video (.webm), using PipeWire when available.");
-}